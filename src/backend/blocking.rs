@@ -0,0 +1,617 @@
+//! Per-key wait queues for blocking commands such as `BLPOP`.
+//!
+//! A blocked client registers a waiter on every key it is interested in.
+//! Whenever a write may have made a key ready, `signal_ready` serves the
+//! waiters queued on it in arrival order until the key runs dry. A waiter
+//! served through one key stays behind in the queues of its other keys;
+//! those stale entries are skipped and cleaned up when the client's
+//! `Blocked` guard is dropped.
+//!
+//! Each key has a queue of its own, locked only to pick or drop a waiter,
+//! so serving one key never holds up clients blocking or being served on
+//! another. One writer at a time serves a queue: others that find it busy
+//! flag the key as ready again and leave it to that writer.
+
+use super::{
+    group::xreadgroup_serve,
+    list::{blmove_serve, blmpop_serve, bpop_serve},
+    stream::xread_serve,
+    zset::{bzmpop_serve, bzpop_serve},
+    Storage, StorageError,
+};
+use crate::{
+    cmd::{Command, End},
+    resp::{Array, Key, Null, Resp},
+};
+use anyhow::Result;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Tries to serve a waiter from one of its keys. `Ok(None)` means the key had
+/// nothing to offer and the waiter should keep waiting.
+pub(super) type Serve = dyn Fn(&Storage, &Key) -> Result<Option<Resp>, StorageError> + Send + Sync;
+
+type Reply = oneshot::Sender<Result<Resp, StorageError>>;
+
+struct Waiter {
+    serve: Box<Serve>,
+    /// Taken by whoever finishes the wait first: a writer serving the waiter
+    /// or the client giving up.
+    reply: Mutex<Option<Reply>>,
+}
+
+#[derive(Default)]
+pub(super) struct WaitQueues {
+    queues: DashMap<Key, Queue>,
+}
+
+#[derive(Default)]
+struct Queue {
+    waiters: VecDeque<Arc<Waiter>>,
+    /// Set while a writer is serving the queue.
+    serving: bool,
+    /// Set when the key may have become ready while it was being served, so
+    /// that the writer serving it tries again before giving up.
+    ready: bool,
+}
+
+impl WaitQueues {
+    /// Takes `waiter` out of the queue of `key`.
+    fn dequeue(&self, key: &Key, waiter: &Arc<Waiter>) {
+        let Some(mut queue) = self.queues.get_mut(key) else {
+            return;
+        };
+        match queue.waiters.front() {
+            Some(front) if Arc::ptr_eq(front, waiter) => {
+                queue.waiters.pop_front();
+            }
+            _ => queue.waiters.retain(|w| !Arc::ptr_eq(w, waiter)),
+        }
+    }
+
+    /// Drops the queue of `key` if nobody waits on it or is serving it.
+    fn remove_idle(&self, key: &Key) {
+        self.queues
+            .remove_if(key, |_, q| q.waiters.is_empty() && !q.serving);
+    }
+
+    #[cfg(test)]
+    fn waiting(&self, key: &Key) -> usize {
+        self.queues.get(key).map_or(0, |q| q.waiters.len())
+    }
+}
+
+/// Removes a waiter from all its queues when the wait ends, however it ends.
+struct Blocked<'a> {
+    queues: &'a WaitQueues,
+    keys: &'a [Key],
+    waiter: Arc<Waiter>,
+}
+
+impl Blocked<'_> {
+    /// Stops writers from serving this waiter. Returns false if one already
+    /// has.
+    fn cancel(&self) -> bool {
+        self.waiter.reply.lock().unwrap().take().is_some()
+    }
+}
+
+impl Drop for Blocked<'_> {
+    fn drop(&mut self) {
+        self.cancel();
+        for key in self.keys {
+            self.queues.dequeue(key, &self.waiter);
+            self.queues.remove_idle(key);
+        }
+    }
+}
+
+impl Storage {
+    /// Runs a command that may block until the keys it waits on are ready.
+    /// Any other command runs as usual. Dropping the future cancels the
+    /// wait.
+    pub async fn execute_blocking(&self, cmd: Command) -> Result<Resp> {
+        let res = match cmd {
+            Command::BLPop(c) => {
+                self.block_on(&c.keys, c.timeout, bpop_serve(End::Left))
+                    .await?
+            }
+            Command::BRPop(c) => {
+                self.block_on(&c.keys, c.timeout, bpop_serve(End::Right))
+                    .await?
+            }
+            Command::BLMPop(c) => {
+                self.block_on(&c.keys, c.timeout, blmpop_serve(c.end, c.count))
+                    .await?
+            }
+            Command::BZPopMin(c) => {
+                self.block_on(&c.keys, c.timeout, bzpop_serve(false))
+                    .await?
+            }
+            Command::BZPopMax(c) => self.block_on(&c.keys, c.timeout, bzpop_serve(true)).await?,
+            Command::BZMPop(c) => {
+                self.block_on(&c.keys, c.timeout, bzmpop_serve(c.max, c.count))
+                    .await?
+            }
+            Command::XRead(c) => {
+                let after = {
+                    let _shared = self.read_lock()?;
+                    self.xread_ids(&c)?
+                };
+                self.block_on(&c.keys, c.timeout, xread_serve(after, c.count))
+                    .await?
+            }
+            Command::XReadGroup(c) => {
+                let serve = xreadgroup_serve(c.clone());
+                self.block_on(&c.read.keys, c.read.timeout, serve).await?
+            }
+            Command::BLMove(c) => {
+                let source = std::slice::from_ref(&c.lmove.source);
+                let value = self
+                    .block_on(source, c.timeout, blmove_serve(&c.lmove))
+                    .await?;
+                if value.is_some() {
+                    let _shared = self.read_lock()?;
+                    self.signal_ready(&c.lmove.destination);
+                }
+                return Ok(value.unwrap_or(Resp::Null(Null)));
+            }
+            cmd => return cmd.execute(self),
+        };
+        Ok(res.unwrap_or_else(|| Resp::Array(Array::new(vec![], true))))
+    }
+
+    /// Serves from the first of `keys` that can, without blocking.
+    pub(super) fn try_serve(
+        &self,
+        keys: &[Key],
+        serve: &Serve,
+    ) -> Result<Option<Resp>, StorageError> {
+        for key in keys {
+            if let Some(resp) = serve(self, key)? {
+                return Ok(Some(resp));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Serves from the first of `keys` that can, waiting until one becomes
+    /// ready if none is. Waits forever without a timeout and returns `None`
+    /// once it expires.
+    pub(super) async fn block_on(
+        &self,
+        keys: &[Key],
+        timeout: Option<Duration>,
+        serve: Box<Serve>,
+    ) -> Result<Option<Resp>, StorageError> {
+        let (tx, mut rx) = oneshot::channel();
+        let waiter = Arc::new(Waiter {
+            serve,
+            reply: Mutex::new(Some(tx)),
+        });
+        let blocked = {
+            let _shared = self.read_lock()?;
+            if let Some(resp) = self.try_serve(keys, &*waiter.serve)? {
+                return Ok(Some(resp));
+            }
+            let queues = &self.blocked().queues;
+            for key in keys {
+                let mut queue = queues.entry(key.clone()).or_default();
+                queue.waiters.push_back(waiter.clone());
+            }
+            let blocked = Blocked {
+                queues: self.blocked(),
+                keys,
+                waiter,
+            };
+            // A write between the check and now found nobody waiting, so
+            // look at the keys again now that we are queued.
+            for key in keys {
+                self.signal_ready(key);
+            }
+            blocked
+        };
+        let res = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, &mut rx).await {
+                Ok(res) => res.map_err(|_| ()),
+                // A writer may have served us just as the timeout expired.
+                Err(_) if blocked.cancel() => return Ok(None),
+                Err(_) => rx.try_recv().map_err(|_| ()),
+            },
+            None => (&mut rx).await.map_err(|_| ()),
+        };
+        drop(blocked);
+        match res {
+            Ok(res) => res.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Serves the clients blocked on `key`, oldest first, for as long as the
+    /// key has something to give.
+    pub(super) fn signal_ready(&self, key: &Key) {
+        let queues = &self.blocked().queues;
+        match queues.get_mut(key) {
+            Some(mut queue) if queue.serving => {
+                queue.ready = true;
+                return;
+            }
+            Some(mut queue) => queue.serving = true,
+            None => return,
+        }
+        loop {
+            // Our `serving` flag keeps the queue from being removed.
+            let waiter = {
+                let mut queue = queues.get_mut(key).unwrap();
+                queue.ready = false;
+                match queue.waiters.front() {
+                    Some(waiter) => waiter.clone(),
+                    None => {
+                        queue.serving = false;
+                        break;
+                    }
+                }
+            };
+            let mut reply = waiter.reply.lock().unwrap();
+            let Some(tx) = reply.take() else {
+                drop(reply);
+                self.blocked().dequeue(key, &waiter);
+                continue;
+            };
+            let res = match (waiter.serve)(self, key) {
+                Ok(Some(resp)) => Ok(resp),
+                Ok(None) => {
+                    *reply = Some(tx);
+                    drop(reply);
+                    let mut queue = queues.get_mut(key).unwrap();
+                    if queue.ready {
+                        continue;
+                    }
+                    queue.serving = false;
+                    break;
+                }
+                Err(e) => Err(e),
+            };
+            self.blocked().dequeue(key, &waiter);
+            // The client holds the receiver until it has taken the reply
+            // out of our hands, so this can't fail.
+            let _ = tx.send(res);
+        }
+        self.blocked().remove_idle(key);
+    }
+
+    /// Serves the clients blocked on any key of this database, for when
+    /// its whole keyspace changed.
+    pub(super) fn signal_all(&self) {
+        let keys: Vec<Key> = self
+            .blocked()
+            .queues
+            .iter()
+            .map(|queue| queue.key().clone())
+            .collect();
+        for key in keys {
+            self.signal_ready(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::{Array, BulkString};
+    use std::time::Instant;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn bulk(s: &str) -> Resp {
+        Resp::BulkString(BulkString::new(s, false))
+    }
+
+    fn array(items: &[&str]) -> Resp {
+        super::super::array(items.iter().map(|s| bulk(s)))
+    }
+
+    async fn run(storage: &Storage, args: &[&str]) -> Resp {
+        let cmd = crate::cmd::parse_command(args).unwrap();
+        storage.execute_blocking(cmd).await.unwrap()
+    }
+
+    fn spawn(storage: &Storage, args: &[&str]) -> tokio::task::JoinHandle<Resp> {
+        let storage = storage.clone();
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        tokio::spawn(async move {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            run(&storage, &args).await
+        })
+    }
+
+    /// Lets spawned clients run until they block.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn test_serves_immediately() {
+        let storage = Storage::new();
+        run(&storage, &["RPUSH", "b", "x", "y"]).await;
+        assert_eq!(
+            run(&storage, &["BLPOP", "a", "b", "0"]).await,
+            array(&["b", "x"])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["BLMPOP", "0", "2", "a", "b", "RIGHT", "COUNT", "5"]
+            )
+            .await,
+            super::super::array([bulk("b"), array(&["y"])])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fifo_across_keys() {
+        let storage = Storage::new();
+        let first = spawn(&storage, &["BLPOP", "a", "b", "0"]);
+        settle().await;
+        let second = spawn(&storage, &["BRPOP", "b", "0"]);
+        settle().await;
+        let third = spawn(&storage, &["BLPOP", "b", "a", "0"]);
+        settle().await;
+        assert_eq!(storage.blocked().waiting(&key("a")), 2);
+        assert_eq!(storage.blocked().waiting(&key("b")), 3);
+
+        run(&storage, &["RPUSH", "b", "x", "y"]).await;
+        assert_eq!(first.await.unwrap(), array(&["b", "x"]));
+        assert_eq!(second.await.unwrap(), array(&["b", "y"]));
+        assert_eq!(storage.key_type(&key("b")), "none");
+
+        run(&storage, &["LPUSH", "a", "z"]).await;
+        assert_eq!(third.await.unwrap(), array(&["a", "z"]));
+        assert_eq!(storage.blocked().waiting(&key("a")), 0);
+        assert_eq!(storage.blocked().waiting(&key("b")), 0);
+    }
+
+    #[tokio::test]
+    async fn test_push_beyond_waiters() {
+        let storage = Storage::new();
+        let first = spawn(&storage, &["BLMPOP", "0", "1", "l", "LEFT", "COUNT", "2"]);
+        settle().await;
+        let second = spawn(&storage, &["BLPOP", "l", "0"]);
+        settle().await;
+        run(&storage, &["RPUSH", "l", "a", "b", "c", "d"]).await;
+        assert_eq!(
+            first.await.unwrap(),
+            super::super::array([bulk("l"), array(&["a", "b"])])
+        );
+        assert_eq!(second.await.unwrap(), array(&["l", "c"]));
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "0", "-1"]).await,
+            array(&["d"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let storage = Storage::new();
+        let start = Instant::now();
+        assert_eq!(
+            run(&storage, &["BLPOP", "a", "0.1"]).await,
+            Resp::Array(Array::new(vec![], true))
+        );
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_secs(1));
+        assert_eq!(storage.blocked().waiting(&key("a")), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_is_removed() {
+        let storage = Storage::new();
+        let gone = spawn(&storage, &["BLPOP", "a", "b", "0"]);
+        settle().await;
+        let waiting = spawn(&storage, &["BLPOP", "a", "0"]);
+        settle().await;
+        gone.abort();
+        settle().await;
+        assert_eq!(storage.blocked().waiting(&key("a")), 1);
+        assert_eq!(storage.blocked().waiting(&key("b")), 0);
+
+        run(&storage, &["RPUSH", "a", "x"]).await;
+        assert_eq!(waiting.await.unwrap(), array(&["a", "x"]));
+    }
+
+    #[tokio::test]
+    async fn test_blmove_wakes_destination() {
+        let storage = Storage::new();
+        let popper = spawn(&storage, &["BLPOP", "dst", "0"]);
+        settle().await;
+        let mover = spawn(&storage, &["BLMOVE", "src", "dst", "RIGHT", "LEFT", "0"]);
+        settle().await;
+        run(&storage, &["RPUSH", "src", "x"]).await;
+        assert_eq!(mover.await.unwrap(), bulk("x"));
+        assert_eq!(popper.await.unwrap(), array(&["dst", "x"]));
+        assert_eq!(storage.key_type(&key("dst")), "none");
+    }
+
+    #[tokio::test]
+    async fn test_bzpop_woken_by_zadd() {
+        let storage = Storage::new();
+        let first = spawn(&storage, &["BZPOPMIN", "z", "0"]);
+        settle().await;
+        let second = spawn(&storage, &["BZPOPMAX", "y", "z", "0"]);
+        settle().await;
+        let third = spawn(&storage, &["BZMPOP", "0", "1", "z", "MIN", "COUNT", "5"]);
+        settle().await;
+
+        run(
+            &storage,
+            &["ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d"],
+        )
+        .await;
+        let double = |score| Resp::Double(crate::resp::Double::new(score));
+        assert_eq!(
+            first.await.unwrap(),
+            super::super::array([bulk("z"), bulk("a"), double(1.0)])
+        );
+        assert_eq!(
+            second.await.unwrap(),
+            super::super::array([bulk("z"), bulk("d"), double(4.0)])
+        );
+        assert_eq!(
+            third.await.unwrap(),
+            super::super::array([
+                bulk("z"),
+                super::super::array([
+                    super::super::array([bulk("b"), double(2.0)]),
+                    super::super::array([bulk("c"), double(3.0)]),
+                ])
+            ])
+        );
+        assert_eq!(storage.key_type(&key("z")), "none");
+        assert_eq!(storage.blocked().waiting(&key("y")), 0);
+    }
+
+    #[tokio::test]
+    async fn test_bzpop_woken_by_store() {
+        let storage = Storage::new();
+        let popper = spawn(&storage, &["BZPOPMAX", "dst", "0"]);
+        settle().await;
+        run(&storage, &["ZADD", "a", "1", "x"]).await;
+        run(&storage, &["ZADD", "b", "2", "x"]).await;
+        run(&storage, &["ZUNIONSTORE", "dst", "2", "a", "b"]).await;
+        assert_eq!(
+            popper.await.unwrap(),
+            super::super::array([
+                bulk("dst"),
+                bulk("x"),
+                Resp::Double(crate::resp::Double::new(3.0))
+            ])
+        );
+        assert_eq!(
+            run(&storage, &["BZPOPMIN", "dst", "0.05"]).await,
+            Resp::Array(Array::new(vec![], true))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xread_woken_by_xadd() {
+        let storage = Storage::new();
+        run(&storage, &["XADD", "s", "1-0", "f", "old"]).await;
+        let first = spawn(
+            &storage,
+            &["XREAD", "BLOCK", "0", "STREAMS", "t", "s", "0", "$"],
+        );
+        settle().await;
+        let second = spawn(&storage, &["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]);
+        settle().await;
+
+        run(&storage, &["XADD", "s", "2-0", "f", "new"]).await;
+        let reply = super::super::array([super::super::array([
+            bulk("s"),
+            super::super::array([super::super::array([bulk("2-0"), array(&["f", "new"])])]),
+        ])]);
+        assert_eq!(first.await.unwrap(), reply);
+        assert_eq!(second.await.unwrap(), reply);
+        assert_eq!(storage.blocked().waiting(&key("s")), 0);
+        assert_eq!(storage.blocked().waiting(&key("t")), 0);
+
+        assert_eq!(
+            run(&storage, &["XREAD", "BLOCK", "50", "STREAMS", "s", "$"]).await,
+            Resp::Array(Array::new(vec![], true))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xreadgroup_fifo() {
+        let storage = Storage::new();
+        run(&storage, &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]).await;
+        let first = spawn(
+            &storage,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "BLOCK",
+                "0",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        );
+        settle().await;
+        let second = spawn(
+            &storage,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "b",
+                "BLOCK",
+                "0",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        );
+        settle().await;
+
+        run(&storage, &["XADD", "s", "1-0", "f", "v"]).await;
+        let reply = super::super::array([super::super::array([
+            bulk("s"),
+            super::super::array([super::super::array([bulk("1-0"), array(&["f", "v"])])]),
+        ])]);
+        assert_eq!(first.await.unwrap(), reply);
+        assert_eq!(storage.blocked().waiting(&key("s")), 1);
+
+        // Destroying the group wakes the remaining reader with an error.
+        run(&storage, &["XGROUP", "DESTROY", "s", "g"]).await;
+        assert!(second.await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_contention() {
+        let storage = Storage::new();
+        let keys = ["a", "b", "c", "d"];
+        let mut clients = Vec::new();
+        let mut writers = Vec::new();
+        for i in 0..2000 {
+            clients.push(spawn(&storage, &["BLPOP", keys[i % 4], "0"]));
+            writers.push(spawn(&storage, &["RPUSH", keys[i % 4], &i.to_string()]));
+        }
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let mut served = Vec::new();
+        for client in clients {
+            let Resp::Array(reply) = client.await.unwrap() else {
+                panic!("not a pair");
+            };
+            served.push(reply[1].clone());
+        }
+        served.sort_by_key(|v| format!("{v:?}"));
+        served.dedup();
+        assert_eq!(served.len(), 2000, "every element went to one client");
+        for name in keys {
+            assert_eq!(storage.key_type(&key(name)), "none");
+            assert_eq!(storage.blocked().waiting(&key(name)), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() {
+        let storage = Storage::new();
+        storage.set(key("s"), bulk("v"));
+        let cmd = crate::cmd::parse_command(&["BLPOP", "s", "0"]).unwrap();
+        let err = storage.execute_blocking(cmd).await.unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::WrongType
+        );
+    }
+}
@@ -0,0 +1,1135 @@
+//! Per-connection state: the selected database, subscriptions, watched
+//! keys and queued transaction, plus the registry of connected clients
+//! that `CLIENT LIST`, `CLIENT KILL` and `CLIENT PAUSE` work on, and which
+//! delivers client-side caching invalidations.
+
+use super::{bulk_string, integer, ok, Storage, StorageError, Subscriber, Watcher};
+use crate::{
+    cmd::{self, ClientFilter, ClientType, Command, CommandError, CommandExecutor, PauseMode},
+    resp::{Array, BulkString, Key, Null, Protocol, Resp, SimpleString},
+};
+use anyhow::Result;
+use bytes::BytesMut;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender},
+    Notify,
+};
+
+/// Commands queued between `MULTI` and `EXEC`.
+#[derive(Default)]
+pub struct Transaction {
+    pub commands: Vec<Command>,
+    /// Set when a command fails to parse while queuing, which makes `EXEC`
+    /// discard the transaction.
+    pub aborted: bool,
+}
+
+/// What `Client::queue` made of a command.
+pub enum Queued {
+    /// The command was handled, or queued, with these replies.
+    Reply(Result<Vec<Resp>>),
+    /// The command is outside any transaction and should run now.
+    Run(Command),
+}
+
+/// What other connections can see of a client. The connection publishes
+/// its state here after every command.
+struct Info {
+    addr: SocketAddr,
+    laddr: SocketAddr,
+    created: Instant,
+    name: Option<String>,
+    last_interaction: Instant,
+    last_command: &'static str,
+    db: usize,
+    sub: usize,
+    psub: usize,
+    ssub: usize,
+    /// Commands queued in a transaction, or -1 outside one.
+    multi: i64,
+    watch: usize,
+    resp: u8,
+    /// Bytes read but not parsed yet, and the room left for more.
+    qbuf: usize,
+    qbuf_free: usize,
+    /// Bytes of replies not written to the socket yet.
+    obl: usize,
+    no_evict: bool,
+    tracking: bool,
+    /// Where the client's messages go, and whether it is subscribed to
+    /// `INVALIDATE_CHANNEL`, for RESP2 clients that tracking redirects to.
+    sender: UnboundedSender<Resp>,
+    invalidations: bool,
+}
+
+impl Info {
+    fn client_type(&self) -> ClientType {
+        match self.sub + self.psub + self.ssub {
+            0 => ClientType::Normal,
+            _ => ClientType::PubSub,
+        }
+    }
+
+    fn matches(&self, id: u64, filter: &ClientFilter, me: u64) -> bool {
+        (filter.ids.is_empty() || filter.ids.contains(&id))
+            && filter
+                .addr
+                .as_ref()
+                .is_none_or(|a| *a == self.addr.to_string())
+            && filter
+                .laddr
+                .as_ref()
+                .is_none_or(|a| *a == self.laddr.to_string())
+            && filter.client_type.is_none_or(|t| t == self.client_type())
+            && !(filter.skip_me && id == me)
+            && filter
+                .max_age
+                .is_none_or(|age| self.created.elapsed().as_secs() > age)
+    }
+
+    /// The `CLIENT INFO` and `CLIENT LIST` line: space separated
+    /// `field=value` pairs.
+    fn line(&self, id: u64) -> String {
+        let mut flags = String::new();
+        if self.multi >= 0 {
+            flags.push('x');
+        }
+        if self.client_type() == ClientType::PubSub {
+            flags.push('P');
+        }
+        if self.no_evict {
+            flags.push('e');
+        }
+        if self.tracking {
+            flags.push('t');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={id} addr={} laddr={} name={} age={} idle={} flags={flags} db={} sub={} \
+             psub={} ssub={} multi={} watch={} qbuf={} qbuf-free={} obl={} cmd={} \
+             user=default resp={}",
+            self.addr,
+            self.laddr,
+            self.name.as_deref().unwrap_or(""),
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            self.db,
+            self.sub,
+            self.psub,
+            self.ssub,
+            self.multi,
+            self.watch,
+            self.qbuf,
+            self.qbuf_free,
+            self.obl,
+            self.last_command,
+            self.resp,
+        )
+    }
+}
+
+/// The channel RESP2 clients receive invalidations on, redirected from a
+/// tracking client.
+const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// A connected client as the registry holds it.
+struct Entry {
+    info: Mutex<Info>,
+    /// Notified once to close the connection.
+    killed: Notify,
+}
+
+/// The connected clients, and whether `CLIENT PAUSE` holds their commands.
+#[derive(Default)]
+pub struct Clients {
+    last_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<Entry>>>,
+    /// When the pause ends, and what it holds.
+    pause: Mutex<Option<(Instant, PauseMode)>>,
+    unpaused: Notify,
+}
+
+impl Clients {
+    /// Registers a client, giving it an ID that is never reused.
+    fn register(&self, info: Info) -> (u64, Arc<Entry>) {
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let entry = Arc::new(Entry {
+            info: Mutex::new(info),
+            killed: Notify::new(),
+        });
+        self.clients.lock().unwrap().insert(id, entry.clone());
+        (id, entry)
+    }
+
+    fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        self.clients.lock().unwrap().contains_key(&id)
+    }
+
+    /// Sends client `id` an `invalidate` message for `key`, or for every
+    /// key if there is none: a push on RESP3, or a message on
+    /// `INVALIDATE_CHANNEL` on RESP2 if it subscribed to that. Returns
+    /// whether the client is still connected.
+    pub(super) fn invalidate(&self, id: u64, key: Option<&Key>) -> bool {
+        let Some(entry) = self.clients.lock().unwrap().get(&id).cloned() else {
+            return false;
+        };
+        let info = entry.info.lock().unwrap();
+        let keys = Resp::Array(match key {
+            Some(key) => Array::new(vec![key.clone().into()], false),
+            None => Array::new(Vec::new(), true),
+        });
+        let bulk = |s: &str| Resp::BulkString(BulkString::new(s, false));
+        let message = match info.resp {
+            3 => vec![bulk("invalidate"), keys],
+            _ if info.invalidations => vec![bulk("message"), bulk(INVALIDATE_CHANNEL), keys],
+            _ => return true,
+        };
+        // The connection may be closing, in which case there's no one to
+        // tell.
+        let _ = info.sender.send(Resp::Push(Array::new(message, false)));
+        true
+    }
+
+    /// Tells client `id`, on RESP3, that `target`, which it redirects its
+    /// invalidations to, has disconnected.
+    pub(super) fn redirect_broken(&self, id: u64, target: u64) {
+        let Some(entry) = self.clients.lock().unwrap().get(&id).cloned() else {
+            return;
+        };
+        let info = entry.info.lock().unwrap();
+        if info.resp == 3 {
+            let message = vec![bulk_string("tracking-redir-broken"), integer(target as i64)];
+            let _ = info.sender.send(Resp::Push(Array::new(message, false)));
+        }
+    }
+
+    /// The clients matching `filter`, in the order they connected.
+    fn matching(&self, filter: &ClientFilter, me: u64) -> Vec<(u64, Arc<Entry>)> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, entry)| entry.info.lock().unwrap().matches(**id, filter, me))
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect()
+    }
+
+    fn list(&self, filter: &ClientFilter) -> String {
+        self.matching(filter, 0)
+            .into_iter()
+            .map(|(id, entry)| entry.info.lock().unwrap().line(id) + "\n")
+            .collect()
+    }
+
+    /// Closes the connections matching `filter` and returns how many there
+    /// were. Every client is logged in as the default user.
+    fn kill(&self, filter: &ClientFilter, me: u64) -> Result<usize, StorageError> {
+        if let Some(user) = filter.user.as_ref().filter(|u| *u != "default") {
+            return Err(StorageError::NoSuchUser(user.clone()));
+        }
+        let killed = self.matching(filter, me);
+        for (_, entry) in &killed {
+            entry.killed.notify_one();
+        }
+        Ok(killed.len())
+    }
+
+    /// Holds commands for `timeout`. A pause already in effect keeps the
+    /// later end and the more restrictive mode of the two.
+    fn pause(&self, timeout: Duration, mode: PauseMode) {
+        let now = Instant::now();
+        let until = now + timeout;
+        let mut pause = self.pause.lock().unwrap();
+        *pause = Some(match *pause {
+            Some((end, current)) if end > now => (end.max(until), current.max(mode)),
+            _ => (until, mode),
+        });
+    }
+
+    fn unpause(&self) {
+        *self.pause.lock().unwrap() = None;
+        self.unpaused.notify_waiters();
+    }
+
+    /// Waits until a pause no longer holds a command, which `write` tells
+    /// may write. Returns straight away when there is no pause.
+    pub async fn wait_unpaused(&self, write: bool) {
+        loop {
+            // Created before checking, so an unpause in between wakes it.
+            let unpaused = self.unpaused.notified();
+            let until = match *self.pause.lock().unwrap() {
+                Some((until, mode))
+                    if until > Instant::now() && (write || mode == PauseMode::All) =>
+                {
+                    until
+                }
+                _ => return,
+            };
+            tokio::select! {
+                _ = unpaused => {}
+                _ = tokio::time::sleep_until(until.into()) => {}
+            }
+        }
+    }
+}
+
+/// The state of one connection. The fields commands work on are public so
+/// that the connection can borrow them separately.
+pub struct Client {
+    id: u64,
+    entry: Arc<Entry>,
+    /// The selected database.
+    pub storage: Storage,
+    pub subscriber: Subscriber,
+    /// Messages for the subscriber's channels.
+    pub messages: UnboundedReceiver<Resp>,
+    pub watcher: Watcher,
+    pub transaction: Option<Transaction>,
+    tracking: Option<cmd::Tracking>,
+    /// How many commands the client sent.
+    commands: u64,
+    /// The last `CLIENT CACHING` answer, and the command it was sent as,
+    /// since it only applies to the command right after.
+    caching: Option<(bool, u64)>,
+}
+
+impl Client {
+    /// Registers a client connected from `addr` to `laddr`, on database 0
+    /// of `storage`.
+    pub fn new(storage: &Storage, addr: SocketAddr, laddr: SocketAddr) -> Self {
+        let mut storage = storage.select(0).expect("there is always a database 0");
+        let (subscriber, messages) = Subscriber::new(storage.broker());
+        let now = Instant::now();
+        let (id, entry) = storage.clients().register(Info {
+            addr,
+            laddr,
+            created: now,
+            name: None,
+            last_interaction: now,
+            last_command: "NULL",
+            db: 0,
+            sub: 0,
+            psub: 0,
+            ssub: 0,
+            multi: -1,
+            watch: 0,
+            resp: 2,
+            qbuf: 0,
+            qbuf_free: 0,
+            obl: 0,
+            no_evict: false,
+            tracking: false,
+            sender: subscriber.sender(),
+            invalidations: false,
+        });
+        storage.client = id;
+        Client {
+            id,
+            entry,
+            watcher: Watcher::new(&storage),
+            storage,
+            subscriber,
+            messages,
+            transaction: None,
+            tracking: None,
+            commands: 0,
+            caching: None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Records that the client sent `cmd`, for `CLIENT INFO`.
+    pub fn interact(&mut self, cmd: &Command) {
+        self.commands += 1;
+        let mut info = self.entry.info.lock().unwrap();
+        info.last_interaction = Instant::now();
+        info.last_command = cmd.name();
+    }
+
+    /// Remembers the keys `cmd` reads if the client tracks them. This runs
+    /// before the command, so a write racing with it can only cause a
+    /// spurious invalidation, never a missed one.
+    pub fn track(&self, cmd: &Command) {
+        let Some(tracking) = &self.tracking else {
+            return;
+        };
+        let caching = self
+            .caching
+            .filter(|(_, at)| at + 1 == self.commands)
+            .map(|(yes, _)| yes);
+        let track = if tracking.bcast {
+            false
+        } else if tracking.optin {
+            caching == Some(true)
+        } else if tracking.optout {
+            caching != Some(false)
+        } else {
+            true
+        };
+        if track {
+            let keys = cmd.read_keys();
+            if !keys.is_empty() {
+                self.storage.track(self.id, keys);
+            }
+        }
+    }
+
+    /// Resolves once `CLIENT KILL` picks this client. The future doesn't
+    /// borrow the client, so it can be awaited alongside its messages.
+    pub fn killed(&self) -> impl Future<Output = ()> {
+        let entry = self.entry.clone();
+        async move { entry.killed.notified().await }
+    }
+
+    /// Publishes the connection's state, with its read and write buffers,
+    /// for other clients to list.
+    pub fn sync(&self, protocol: Protocol, read: &BytesMut, write: &BytesMut) {
+        self.refresh(protocol);
+        let mut info = self.entry.info.lock().unwrap();
+        info.qbuf = read.len();
+        info.qbuf_free = read.capacity() - read.len();
+        info.obl = write.len();
+    }
+
+    fn refresh(&self, protocol: Protocol) {
+        let (sub, psub, ssub) = self.subscriber.counts();
+        let mut info = self.entry.info.lock().unwrap();
+        info.db = self.storage.db();
+        (info.sub, info.psub, info.ssub) = (sub, psub, ssub);
+        info.multi = match &self.transaction {
+            Some(t) => t.commands.len() as i64,
+            None => -1,
+        };
+        info.watch = self.watcher.len();
+        info.tracking = self.tracking.is_some();
+        info.invalidations = self
+            .subscriber
+            .has_channel(&Key::BulkString(BulkString::new(INVALIDATE_CHANNEL, false)));
+        info.resp = match protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+    }
+
+    /// Returns the connection to the state it had when it connected:
+    /// discards the transaction, unwatches every key, cancels every
+    /// subscription, turns tracking off, selects database 0 and clears the
+    /// name and the no-evict flag.
+    pub fn reset(&mut self) {
+        self.transaction = None;
+        self.watcher.unwatch();
+        self.stop_tracking();
+        let (subscriber, messages) = Subscriber::new(self.storage.broker());
+        self.entry.info.lock().unwrap().sender = subscriber.sender();
+        self.subscriber = subscriber;
+        self.messages = messages;
+        self.storage = self
+            .storage
+            .select(0)
+            .expect("there is always a database 0");
+        let mut info = self.entry.info.lock().unwrap();
+        info.name = None;
+        info.no_evict = false;
+    }
+
+    /// Handles the commands that drive transactions, queues the others while
+    /// one is open, and turns a command that failed to parse into its error
+    /// reply, aborting the transaction.
+    pub fn queue(&mut self, cmd: Result<Command, CommandError>, protocol: &mut Protocol) -> Queued {
+        let cmd = match cmd {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                if let Some(transaction) = &mut self.transaction {
+                    transaction.aborted = true;
+                }
+                return Queued::Reply(Err(e.into()));
+            }
+        };
+        let res = match cmd {
+            Command::Multi if self.transaction.is_some() => Err(CommandError::NestedMulti.into()),
+            Command::Multi => {
+                self.transaction = Some(Transaction::default());
+                Ok(vec![ok()])
+            }
+            Command::Discard => match self.transaction.take() {
+                Some(_) => {
+                    self.watcher.unwatch();
+                    Ok(vec![ok()])
+                }
+                None => Err(CommandError::DiscardWithoutMulti.into()),
+            },
+            Command::Exec => match self.transaction.take() {
+                Some(Transaction { aborted: true, .. }) => {
+                    self.watcher.unwatch();
+                    Err(CommandError::ExecAbort.into())
+                }
+                Some(Transaction { commands, .. }) => {
+                    self.exec(commands, protocol).map(|resp| vec![resp])
+                }
+                None => Err(CommandError::ExecWithoutMulti.into()),
+            },
+            Command::Watch(_) if self.transaction.is_some() => {
+                Err(CommandError::WatchInsideMulti.into())
+            }
+            Command::Watch(c) => {
+                self.watcher.watch(self.storage.db(), &c.keys);
+                Ok(vec![ok()])
+            }
+            Command::Unwatch if self.transaction.is_none() => {
+                self.watcher.unwatch();
+                Ok(vec![ok()])
+            }
+            cmd => match &mut self.transaction {
+                Some(transaction) => {
+                    transaction.commands.push(cmd);
+                    Ok(vec![Resp::SimpleString(SimpleString::new("QUEUED"))])
+                }
+                None => return Queued::Run(cmd),
+            },
+        };
+        Queued::Reply(res)
+    }
+
+    /// Runs the commands of a transaction as one step, unless a watched key
+    /// changed, which the null array replied then tells the client. Either
+    /// way the keys are unwatched.
+    fn exec(&mut self, commands: Vec<Command>, protocol: &mut Protocol) -> Result<Resp> {
+        let storage = self.storage.clone();
+        let scripts = commands.iter().any(Command::runs_script);
+        let replies = storage.atomically(scripts, |mut locked| {
+            if self.watcher.is_touched() {
+                return None;
+            }
+            let replies = commands
+                .into_iter()
+                .flat_map(|cmd| {
+                    let res = match cmd {
+                        // Later commands run against the newly selected
+                        // database.
+                        Command::Select(c) => locked
+                            .select(c.db)
+                            .map(|selected| {
+                                locked = selected;
+                                vec![ok()]
+                            })
+                            .map_err(Into::into),
+                        cmd => self.run(cmd, &locked, protocol),
+                    };
+                    res.unwrap_or_else(|e| vec![error_reply(e)])
+                })
+                .collect();
+            if locked.db() != self.storage.db() {
+                self.storage = storage
+                    .select(locked.db() as i64)
+                    .expect("selected inside the transaction");
+            }
+            Some(replies)
+        });
+        self.watcher.unwatch();
+        let replies = replies?;
+        let null = replies.is_none();
+        Ok(Resp::Array(Array::new(replies.unwrap_or_default(), null)))
+    }
+
+    /// Runs a command that doesn't block, whether on its own or queued in a
+    /// transaction.
+    pub fn run(
+        &mut self,
+        cmd: Command,
+        executor: &dyn CommandExecutor,
+        protocol: &mut Protocol,
+    ) -> Result<Vec<Resp>> {
+        let subscriber = &mut self.subscriber;
+        match cmd {
+            Command::Hello(hello) => hello.execute(protocol).map(|resp| vec![resp]),
+            Command::Ping(ping) if subscriber.is_subscribed() && *protocol == Protocol::Resp2 => {
+                Ok(vec![ping.subscribed_reply()])
+            }
+            Command::Client(c) => Ok(vec![self.execute(c, *protocol)?]),
+            Command::Select(c) => {
+                self.storage = self.storage.select(c.db)?;
+                Ok(vec![ok()])
+            }
+            Command::Subscribe(c) => Ok(subscriber.subscribe(&c.channels)),
+            Command::Unsubscribe(c) => Ok(subscriber.unsubscribe(&c.channels)),
+            Command::PSubscribe(c) => Ok(subscriber.psubscribe(&c.channels)),
+            Command::PUnsubscribe(c) => Ok(subscriber.punsubscribe(&c.channels)),
+            Command::SSubscribe(c) => Ok(subscriber.ssubscribe(&c.channels)),
+            Command::SUnsubscribe(c) => Ok(subscriber.sunsubscribe(&c.channels)),
+            // Queued in a transaction, whose keys `EXEC` has unwatched already.
+            Command::Unwatch => Ok(vec![ok()]),
+            cmd => {
+                self.track(&cmd);
+                cmd.execute(executor).map(|resp| vec![resp])
+            }
+        }
+    }
+
+    pub fn execute(&mut self, c: cmd::Client, protocol: Protocol) -> Result<Resp, StorageError> {
+        let clients = self.storage.clients();
+        match c {
+            cmd::Client::SetName { name } => {
+                self.entry.info.lock().unwrap().name = name;
+                Ok(ok())
+            }
+            cmd::Client::GetName => Ok(match &self.entry.info.lock().unwrap().name {
+                Some(name) => bulk_string(name.as_str()),
+                None => Resp::Null(Null),
+            }),
+            cmd::Client::Id => Ok(integer(self.id as i64)),
+            cmd::Client::Info => {
+                self.refresh(protocol);
+                Ok(bulk_string(
+                    self.entry.info.lock().unwrap().line(self.id) + "\n",
+                ))
+            }
+            cmd::Client::List(filter) => {
+                self.refresh(protocol);
+                Ok(bulk_string(clients.list(&filter)))
+            }
+            cmd::Client::Kill { filter, legacy } => {
+                let killed = clients.kill(&filter, self.id)?;
+                match legacy {
+                    true if killed == 0 => Err(StorageError::NoSuchClient),
+                    true => Ok(ok()),
+                    false => Ok(integer(killed as i64)),
+                }
+            }
+            cmd::Client::Pause { timeout, mode } => {
+                clients.pause(timeout, mode);
+                Ok(ok())
+            }
+            cmd::Client::Unpause => {
+                clients.unpause();
+                Ok(ok())
+            }
+            cmd::Client::NoEvict(on) => {
+                self.entry.info.lock().unwrap().no_evict = on;
+                Ok(ok())
+            }
+            cmd::Client::Tracking(Some(tracking)) => {
+                if tracking.redirect.is_some_and(|id| !clients.contains(id)) {
+                    return Err(StorageError::NoRedirectClient);
+                }
+                if self
+                    .tracking
+                    .as_ref()
+                    .is_some_and(|current| current.bcast != tracking.bcast)
+                {
+                    return Err(StorageError::TrackingModeSwitch);
+                }
+                self.storage.start_tracking(self.id, &tracking);
+                self.tracking = Some(tracking);
+                Ok(ok())
+            }
+            cmd::Client::Tracking(None) => {
+                self.stop_tracking();
+                Ok(ok())
+            }
+            cmd::Client::Caching(yes) => match &self.tracking {
+                Some(t) if (yes && t.optin) || (!yes && t.optout) => {
+                    self.caching = Some((yes, self.commands));
+                    Ok(ok())
+                }
+                _ if yes => Err(StorageError::CachingWithoutOptIn),
+                _ => Err(StorageError::CachingWithoutOptOut),
+            },
+            cmd::Client::GetRedir => Ok(integer(match &self.tracking {
+                Some(t) => t.redirect.unwrap_or(0) as i64,
+                None => -1,
+            })),
+        }
+    }
+
+    fn stop_tracking(&mut self) {
+        if self.tracking.take().is_some() {
+            self.storage.stop_tracking(self.id);
+        }
+        self.caching = None;
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.stop_tracking();
+        self.storage.clients().unregister(self.id);
+    }
+}
+
+/// The reply for a command that failed.
+pub fn error_reply(e: anyhow::Error) -> Resp {
+    Resp::SimpleError(crate::resp::SimpleError::new(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command, CommandExecutor};
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn client(storage: &Storage, port: u16) -> Client {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let laddr = "127.0.0.1:6379".parse().unwrap();
+        Client::new(storage, addr, laddr)
+    }
+
+    fn run(client: &mut Client, args: &[&str]) -> Result<Resp, StorageError> {
+        let Command::Client(c) = parse_command(args).unwrap() else {
+            panic!("Expected Client");
+        };
+        client.execute(c, Protocol::Resp2)
+    }
+
+    /// Runs a command other than `CLIENT` the way a connection does.
+    fn send(client: &mut Client, args: &[&str]) -> Resp {
+        let cmd = parse_command(args).unwrap();
+        client.interact(&cmd);
+        client.track(&cmd);
+        let storage = client.storage.clone();
+        cmd.execute(&storage as &dyn CommandExecutor).unwrap()
+    }
+
+    /// Sends a command the way a connection does, through `queue`.
+    fn queue(client: &mut Client, args: &[&str]) -> Resp {
+        let mut protocol = Protocol::Resp2;
+        let res = match client.queue(parse_command(args), &mut protocol) {
+            Queued::Reply(res) => res,
+            Queued::Run(cmd) => {
+                let storage = client.storage.clone();
+                client.run(cmd, &storage, &mut protocol)
+            }
+        };
+        res.map_or_else(error_reply, |mut replies| replies.remove(0))
+    }
+
+    fn error(e: CommandError) -> Resp {
+        error_reply(e.into())
+    }
+
+    fn invalidated(client: &mut Client) -> Vec<Resp> {
+        let mut messages = Vec::new();
+        while let Ok(Resp::Push(message)) = client.messages.try_recv() {
+            messages.push(Resp::Array(message));
+        }
+        messages
+    }
+
+    fn invalidation(keys: &[&str]) -> Resp {
+        let keys = keys.iter().map(|k| bulk_string(*k)).collect();
+        Resp::Array(Array::new(
+            vec![
+                bulk_string("invalidate"),
+                Resp::Array(Array::new(keys, false)),
+            ],
+            false,
+        ))
+    }
+
+    fn lines(resp: Resp) -> Vec<String> {
+        let Resp::BulkString(s) = resp else {
+            panic!("Expected a bulk string");
+        };
+        let s = s.as_str().unwrap();
+        assert!(s.ends_with('\n'));
+        s.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_client() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let b = client(&storage, 50001);
+        assert!(b.id() > a.id());
+        assert_eq!(run(&mut a, &["CLIENT", "ID"]), Ok(integer(a.id() as i64)));
+
+        assert_eq!(run(&mut a, &["CLIENT", "GETNAME"]), Ok(Resp::Null(Null)));
+        assert_eq!(run(&mut a, &["CLIENT", "SETNAME", "worker"]), Ok(ok()));
+        assert_eq!(
+            run(&mut a, &["CLIENT", "GETNAME"]),
+            Ok(bulk_string("worker"))
+        );
+
+        a.storage = storage.select(3).unwrap();
+        a.subscriber
+            .subscribe(&[Key::BulkString(BulkString::new("news", false))]);
+        a.interact(&parse_command(&["CLIENT", "INFO"]).unwrap());
+        let info = lines(run(&mut a, &["CLIENT", "INFO"]).unwrap());
+        assert_eq!(info.len(), 1);
+        assert!(info[0].starts_with(&format!("id={} addr=127.0.0.1:50000 ", a.id())));
+        for field in [
+            " name=worker ",
+            " flags=P ",
+            " db=3 ",
+            " sub=1 ",
+            " cmd=client ",
+        ] {
+            assert!(info[0].contains(field), "{field:?} missing from {info:?}");
+        }
+
+        a.transaction = Some(Transaction::default());
+        assert_eq!(run(&mut a, &["CLIENT", "NO-EVICT", "on"]), Ok(ok()));
+        a.reset();
+        assert_eq!(run(&mut a, &["CLIENT", "GETNAME"]), Ok(Resp::Null(Null)));
+        assert_eq!(a.storage.db(), 0);
+        assert!(!a.subscriber.is_subscribed());
+        assert!(a.transaction.is_none());
+        assert_eq!(
+            storage
+                .broker()
+                .numsub(&Key::BulkString(BulkString::new("news", false))),
+            0
+        );
+        let info = lines(run(&mut a, &["CLIENT", "INFO"]).unwrap());
+        assert!(info[0].contains(" flags=N "), "{info:?}");
+    }
+
+    #[tokio::test]
+    async fn test_list_and_kill() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let mut b = client(&storage, 50001);
+        let c = client(&storage, 50002);
+        b.subscriber
+            .psubscribe(&[Key::BulkString(BulkString::new("*", false))]);
+        b.sync(Protocol::Resp3, &BytesMut::new(), &BytesMut::new());
+
+        let list = lines(run(&mut a, &["CLIENT", "LIST"]).unwrap());
+        assert_eq!(list.len(), 3);
+        assert!(list[1].contains(" psub=1 ") && list[1].ends_with(" resp=3"));
+        let list = lines(run(&mut a, &["CLIENT", "LIST", "TYPE", "pubsub"]).unwrap());
+        assert_eq!(list.len(), 1);
+        assert!(list[0].starts_with(&format!("id={} ", b.id())));
+        let id = c.id().to_string();
+        let list = lines(run(&mut a, &["CLIENT", "LIST", "ID", &id, "1000"]).unwrap());
+        assert_eq!(list.len(), 1);
+
+        assert_eq!(
+            run(&mut a, &["CLIENT", "KILL", "127.0.0.1:1"]),
+            Err(StorageError::NoSuchClient)
+        );
+        assert_eq!(
+            run(&mut a, &["CLIENT", "KILL", "127.0.0.1:50002"]),
+            Ok(ok())
+        );
+        timeout(Duration::from_secs(1), c.killed()).await.unwrap();
+        drop(c);
+        assert_eq!(
+            run(&mut a, &["CLIENT", "KILL", "USER", "nobody"]),
+            Err(StorageError::NoSuchUser("nobody".to_string()))
+        );
+        // SKIPME defaults to yes, so only `b` goes.
+        assert_eq!(
+            run(&mut a, &["CLIENT", "KILL", "USER", "default"]),
+            Ok(integer(1))
+        );
+        timeout(Duration::from_secs(1), b.killed()).await.unwrap();
+        assert_eq!(
+            run(&mut a, &["CLIENT", "KILL", "MAXAGE", "100"]),
+            Ok(integer(0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let clients = storage.clients();
+        clients.wait_unpaused(true).await;
+
+        run(&mut a, &["CLIENT", "PAUSE", "60000", "WRITE"]).unwrap();
+        timeout(Duration::from_secs(1), clients.wait_unpaused(false))
+            .await
+            .unwrap();
+        let write = clients.wait_unpaused(true);
+        tokio::pin!(write);
+        assert!(timeout(Duration::from_millis(20), &mut write)
+            .await
+            .is_err());
+        run(&mut a, &["CLIENT", "UNPAUSE"]).unwrap();
+        timeout(Duration::from_secs(1), write).await.unwrap();
+
+        // A shorter pause doesn't end a longer one early, but does make it
+        // hold everything.
+        run(&mut a, &["CLIENT", "PAUSE", "60000", "WRITE"]).unwrap();
+        run(&mut a, &["CLIENT", "PAUSE", "10", "ALL"]).unwrap();
+        let read = clients.wait_unpaused(false);
+        assert!(timeout(Duration::from_millis(50), read).await.is_err());
+        run(&mut a, &["CLIENT", "UNPAUSE"]).unwrap();
+
+        run(&mut a, &["CLIENT", "PAUSE", "10"]).unwrap();
+        timeout(Duration::from_secs(1), clients.wait_unpaused(false))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tracking() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let mut b = client(&storage, 50001);
+        a.sync(Protocol::Resp3, &BytesMut::new(), &BytesMut::new());
+        assert_eq!(run(&mut a, &["CLIENT", "GETREDIR"]), Ok(integer(-1)));
+        assert_eq!(run(&mut a, &["CLIENT", "TRACKING", "ON"]), Ok(ok()));
+        assert_eq!(run(&mut a, &["CLIENT", "GETREDIR"]), Ok(integer(0)));
+
+        send(&mut a, &["GET", "k"]);
+        send(&mut a, &["TYPE", "other"]);
+        send(&mut b, &["SET", "k", "1"]);
+        assert_eq!(invalidated(&mut a), [invalidation(&["k"])]);
+        send(&mut b, &["SET", "k", "2"]);
+        assert_eq!(invalidated(&mut a), [], "k was not read again");
+
+        // NOLOOP leaves out the client's own writes.
+        run(&mut a, &["CLIENT", "TRACKING", "ON", "NOLOOP"]).unwrap();
+        send(&mut a, &["GET", "k"]);
+        send(&mut a, &["SET", "k", "3"]);
+        send(&mut a, &["SET", "other", "3"]);
+        assert_eq!(invalidated(&mut a), []);
+
+        // A RESP2 client gets them on the invalidation channel.
+        let mut c = client(&storage, 50002);
+        c.subscriber
+            .subscribe(&[Key::BulkString(BulkString::new(INVALIDATE_CHANNEL, false))]);
+        c.sync(Protocol::Resp2, &BytesMut::new(), &BytesMut::new());
+        invalidated(&mut c);
+        assert_eq!(
+            run(&mut a, &["CLIENT", "TRACKING", "ON", "REDIRECT", "999"]),
+            Err(StorageError::NoRedirectClient)
+        );
+        let id = c.id().to_string();
+        run(&mut a, &["CLIENT", "TRACKING", "ON", "REDIRECT", &id]).unwrap();
+        assert_eq!(
+            run(&mut a, &["CLIENT", "GETREDIR"]),
+            Ok(integer(c.id() as i64))
+        );
+        send(&mut a, &["HGETALL", "h"]);
+        send(&mut b, &["HSET", "h", "f", "v"]);
+        assert_eq!(invalidated(&mut a), []);
+        let message = Resp::Array(Array::new(
+            vec![
+                bulk_string("message"),
+                bulk_string(INVALIDATE_CHANNEL),
+                Resp::Array(Array::new(vec![bulk_string("h")], false)),
+            ],
+            false,
+        ));
+        assert_eq!(invalidated(&mut c), [message]);
+
+        // Once the client it redirects to is gone, it's told so instead.
+        let target = c.id();
+        drop(c);
+        send(&mut a, &["HGETALL", "h"]);
+        send(&mut b, &["HSET", "h", "f", "w"]);
+        let broken = Resp::Array(Array::new(
+            vec![bulk_string("tracking-redir-broken"), integer(target as i64)],
+            false,
+        ));
+        assert_eq!(invalidated(&mut a), [broken]);
+
+        assert_eq!(
+            run(&mut a, &["CLIENT", "TRACKING", "ON", "BCAST"]),
+            Err(StorageError::TrackingModeSwitch)
+        );
+        assert_eq!(run(&mut a, &["CLIENT", "TRACKING", "OFF"]), Ok(ok()));
+        send(&mut a, &["GET", "k"]);
+        send(&mut b, &["SET", "k", "4"]);
+        assert_eq!(invalidated(&mut a), []);
+    }
+
+    #[test]
+    fn test_tracking_restart() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let mut b = client(&storage, 50001);
+        a.sync(Protocol::Resp3, &BytesMut::new(), &BytesMut::new());
+        run(&mut a, &["CLIENT", "TRACKING", "ON"]).unwrap();
+        send(&mut a, &["GET", "x"]);
+        send(&mut a, &["GET", "y"]);
+        send(&mut b, &["SET", "x", "1"]);
+        assert_eq!(invalidated(&mut a), [invalidation(&["x"])]);
+
+        // Reads from before tracking was turned off are forgotten.
+        run(&mut a, &["CLIENT", "TRACKING", "OFF"]).unwrap();
+        run(&mut a, &["CLIENT", "TRACKING", "ON"]).unwrap();
+        send(&mut b, &["SET", "y", "1"]);
+        assert_eq!(invalidated(&mut a), []);
+    }
+
+    #[test]
+    fn test_tracking_modes() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let mut b = client(&storage, 50001);
+        a.sync(Protocol::Resp3, &BytesMut::new(), &BytesMut::new());
+
+        run(
+            &mut a,
+            &["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:"],
+        )
+        .unwrap();
+        send(&mut b, &["SET", "user:1", "x"]);
+        send(&mut b, &["SET", "post:1", "x"]);
+        send(&mut b, &["DEL", "user:2"]);
+        assert_eq!(invalidated(&mut a), [invalidation(&["user:1"])]);
+        run(&mut a, &["CLIENT", "TRACKING", "OFF"]).unwrap();
+
+        assert_eq!(
+            run(&mut a, &["CLIENT", "CACHING", "YES"]),
+            Err(StorageError::CachingWithoutOptIn)
+        );
+        run(&mut a, &["CLIENT", "TRACKING", "ON", "OPTIN"]).unwrap();
+        send(&mut a, &["GET", "x"]);
+        a.interact(&parse_command(&["CLIENT", "CACHING", "YES"]).unwrap());
+        run(&mut a, &["CLIENT", "CACHING", "YES"]).unwrap();
+        send(&mut a, &["GET", "y"]);
+        send(&mut a, &["GET", "z"]);
+        for k in ["x", "y", "z"] {
+            send(&mut b, &["SET", k, "1"]);
+        }
+        assert_eq!(
+            invalidated(&mut a),
+            [invalidation(&["y"])],
+            "CACHING YES only applies to the next command"
+        );
+
+        // Flushing invalidates everything with a null array.
+        send(&mut b, &["FLUSHALL"]);
+        assert_eq!(
+            invalidated(&mut a),
+            [Resp::Array(Array::new(
+                vec![
+                    bulk_string("invalidate"),
+                    Resp::Array(Array::new(Vec::new(), true))
+                ],
+                false
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_transaction() {
+        let storage = Storage::new();
+        let mut c = client(&storage, 50000);
+        let queued = Resp::SimpleString(SimpleString::new("QUEUED"));
+        assert_eq!(
+            queue(&mut c, &["EXEC"]),
+            error(CommandError::ExecWithoutMulti)
+        );
+        assert_eq!(queue(&mut c, &["MULTI"]), ok());
+        assert_eq!(queue(&mut c, &["MULTI"]), error(CommandError::NestedMulti));
+        assert_eq!(queue(&mut c, &["SET", "k", "0"]), queued);
+        assert_eq!(queue(&mut c, &["SELECT", "1"]), queued);
+        assert_eq!(
+            queue(&mut c, &["WATCH", "k"]),
+            error(CommandError::WatchInsideMulti)
+        );
+        assert_eq!(queue(&mut c, &["SET", "k", "1"]), queued);
+        assert_eq!(queue(&mut c, &["GET", "k"]), queued);
+        assert_eq!(c.transaction.as_ref().unwrap().commands.len(), 4);
+        assert_eq!(
+            queue(&mut c, &["EXEC"]),
+            Resp::Array(Array::new(vec![ok(), ok(), ok(), bulk_string("1")], false))
+        );
+        assert!(c.transaction.is_none());
+        assert_eq!(c.storage.db(), 1, "SELECT inside EXEC sticks");
+        assert_eq!(queue(&mut c, &["SELECT", "0"]), ok());
+        assert_eq!(queue(&mut c, &["GET", "k"]), bulk_string("0"));
+        assert_eq!(
+            queue(&mut c, &["DISCARD"]),
+            error(CommandError::DiscardWithoutMulti)
+        );
+    }
+
+    #[test]
+    fn test_exec_abort() {
+        let storage = Storage::new();
+        let mut c = client(&storage, 50000);
+        queue(&mut c, &["WATCH", "w"]);
+        queue(&mut c, &["MULTI"]);
+        queue(&mut c, &["SET", "k", "v"]);
+        assert!(matches!(queue(&mut c, &["SET", "k"]), Resp::SimpleError(_)));
+        assert!(matches!(
+            queue(&mut c, &["NOSUCHCOMMAND"]),
+            Resp::SimpleError(_)
+        ));
+        assert_eq!(queue(&mut c, &["EXEC"]), error(CommandError::ExecAbort));
+        assert!(c.transaction.is_none());
+        assert!(storage.versions[0].is_empty(), "EXECABORT unwatches");
+        assert_eq!(queue(&mut c, &["GET", "k"]), Resp::Null(Null));
+
+        // A parse error outside a transaction leaves the next one alone.
+        assert!(matches!(queue(&mut c, &["SET", "k"]), Resp::SimpleError(_)));
+        queue(&mut c, &["MULTI"]);
+        queue(&mut c, &["SET", "k", "v"]);
+        assert_eq!(
+            queue(&mut c, &["EXEC"]),
+            Resp::Array(Array::new(vec![ok()], false))
+        );
+    }
+
+    #[test]
+    fn test_exec_watched() {
+        let storage = Storage::new();
+        let mut a = client(&storage, 50000);
+        let mut b = client(&storage, 50001);
+
+        // DISCARD unwatches, so a later write doesn't fail the next EXEC.
+        assert_eq!(queue(&mut a, &["WATCH", "k"]), ok());
+        queue(&mut a, &["MULTI"]);
+        assert_eq!(queue(&mut a, &["DISCARD"]), ok());
+        assert!(storage.versions[0].is_empty());
+        queue(&mut b, &["SET", "k", "b"]);
+        queue(&mut a, &["MULTI"]);
+        queue(&mut a, &["SET", "k", "a"]);
+        assert_eq!(
+            queue(&mut a, &["EXEC"]),
+            Resp::Array(Array::new(vec![ok()], false))
+        );
+
+        // A watched key written before EXEC makes it reply with a null array.
+        queue(&mut a, &["WATCH", "k"]);
+        queue(&mut b, &["SET", "k", "b"]);
+        queue(&mut a, &["MULTI"]);
+        queue(&mut a, &["SET", "k", "a"]);
+        assert_eq!(
+            queue(&mut a, &["EXEC"]),
+            Resp::Array(Array::new(vec![], true))
+        );
+        assert_eq!(queue(&mut a, &["GET", "k"]), bulk_string("b"));
+        assert!(storage.versions[0].is_empty());
+
+        // UNWATCH queued in the transaction doesn't save it.
+        queue(&mut a, &["WATCH", "k"]);
+        queue(&mut b, &["DEL", "k"]);
+        queue(&mut a, &["MULTI"]);
+        queue(&mut a, &["UNWATCH"]);
+        assert_eq!(
+            queue(&mut a, &["EXEC"]),
+            Resp::Array(Array::new(vec![], true))
+        );
+
+        queue(&mut a, &["WATCH", "k"]);
+        assert_eq!(queue(&mut a, &["UNWATCH"]), ok());
+        assert!(storage.versions[0].is_empty());
+    }
+}
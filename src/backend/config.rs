@@ -0,0 +1,158 @@
+use super::{bulk_string, notify::Class, ok, Storage, StorageError};
+use crate::{
+    cmd::Config,
+    glob,
+    resp::{BulkString, Key, Map, Resp},
+};
+use std::sync::atomic::Ordering;
+
+/// The parameters `CONFIG` knows about.
+const PARAMETERS: [&str; 3] = [
+    "busy-reply-threshold",
+    "databases",
+    "notify-keyspace-events",
+];
+
+/// A value `CONFIG SET` checked, to apply once all of them are.
+enum Setting {
+    Events(Class),
+    BusyThreshold(u64),
+}
+
+impl Storage {
+    pub(super) fn execute_config(&self, c: Config) -> Result<Resp, StorageError> {
+        match c {
+            Config::Get { parameters } => {
+                let mut map = Map::default();
+                for name in PARAMETERS {
+                    if parameters
+                        .iter()
+                        .any(|p| glob::matches(p.as_bytes(), name.as_bytes(), false))
+                    {
+                        let value = bulk_string(self.config_get(name));
+                        map.insert(Key::BulkString(BulkString::new(name, false)), value);
+                    }
+                }
+                Ok(Resp::Map(Box::new(map)))
+            }
+            Config::Set { parameters } => {
+                // Check every value before applying any, so a failed
+                // CONFIG SET changes nothing.
+                let settings = parameters
+                    .iter()
+                    .map(|(name, value)| match name.as_str() {
+                        "notify-keyspace-events" => Class::parse(value)
+                            .map(Setting::Events)
+                            .ok_or(StorageError::InvalidConfig(name.clone(), INVALID_CLASS)),
+                        "busy-reply-threshold" => value
+                            .parse()
+                            .map(Setting::BusyThreshold)
+                            .map_err(|_| StorageError::InvalidConfig(name.clone(), NOT_AN_INTEGER)),
+                        "databases" => Err(StorageError::InvalidConfig(name.clone(), IMMUTABLE)),
+                        _ => Err(StorageError::UnknownConfig(name.clone())),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                for setting in settings {
+                    match setting {
+                        Setting::Events(class) => {
+                            self.events.store(class.bits(), Ordering::Relaxed)
+                        }
+                        Setting::BusyThreshold(ms) => {
+                            self.busy_threshold.store(ms, Ordering::Relaxed)
+                        }
+                    }
+                }
+                Ok(ok())
+            }
+        }
+    }
+
+    fn config_get(&self, name: &str) -> String {
+        match name {
+            "busy-reply-threshold" => self.busy_threshold.load(Ordering::Relaxed).to_string(),
+            "databases" => self.databases().to_string(),
+            "notify-keyspace-events" => {
+                Class::from_bits(self.events.load(Ordering::Relaxed)).to_string()
+            }
+            _ => unreachable!("unknown parameter {name}"),
+        }
+    }
+}
+
+const IMMUTABLE: &str = "can't set immutable config";
+const NOT_AN_INTEGER: &str = "argument couldn't be parsed into an integer";
+const INVALID_CLASS: &str = "Invalid event class character. Use 'Ag$lshztKEn'.";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command, CommandExecutor};
+    use anyhow::Result;
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Option<Resp>> {
+        storage.execute(parse_command(args).unwrap())
+    }
+
+    #[test]
+    fn test_config() {
+        let storage = Storage::new();
+        let get = |storage: &Storage| {
+            let Some(Resp::Map(map)) = run(storage, &["CONFIG", "GET", "notify*"]).unwrap() else {
+                panic!("Expected a map");
+            };
+            map.values().next().cloned()
+        };
+        assert_eq!(get(&storage), Some(bulk_string("")));
+        run(
+            &storage,
+            &["CONFIG", "SET", "notify-keyspace-events", "KEA"],
+        )
+        .unwrap();
+        assert_eq!(get(&storage), Some(bulk_string("AKE")));
+
+        let err = run(&storage, &["CONFIG", "SET", "notify-keyspace-events", "Q"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR CONFIG SET failed (possibly related to argument 'notify-keyspace-events') - \
+             Invalid event class character. Use 'Ag$lshztKEn'."
+        );
+        let err = run(
+            &storage,
+            &[
+                "CONFIG",
+                "SET",
+                "notify-keyspace-events",
+                "",
+                "maxmemory",
+                "1",
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::UnknownConfig("maxmemory".to_string())
+        );
+        assert_eq!(get(&storage), Some(bulk_string("AKE")));
+
+        let Some(Resp::Map(map)) = run(&storage, &["CONFIG", "GET", "databases"]).unwrap() else {
+            panic!("Expected a map");
+        };
+        assert_eq!(map.values().next(), Some(&bulk_string("16")));
+        let err = run(&storage, &["CONFIG", "SET", "databases", "4"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::InvalidConfig("databases".to_string(), IMMUTABLE)
+        );
+
+        run(&storage, &["CONFIG", "SET", "busy-reply-threshold", "100"]).unwrap();
+        let Some(Resp::Map(map)) = run(&storage, &["CONFIG", "GET", "busy*"]).unwrap() else {
+            panic!("Expected a map");
+        };
+        assert_eq!(map.values().next(), Some(&bulk_string("100")));
+        let err = run(&storage, &["CONFIG", "SET", "busy-reply-threshold", "-1"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::InvalidConfig("busy-reply-threshold".to_string(), NOT_AN_INTEGER)
+        );
+    }
+}
@@ -0,0 +1,591 @@
+//! Function libraries for `FUNCTION` and `FCALL`. A library is Lua code
+//! headed by `#!lua name=<library>` that registers named functions with
+//! `redis.register_function` when it is loaded. Libraries share one
+//! interpreter, apart from the one `EVAL` scripts use, and are kept until
+//! deleted or flushed.
+
+use super::{
+    bulk_string, ok,
+    script::{self, error_message, sha1hex, Calling},
+    Storage, StorageError,
+};
+use crate::{
+    cmd::{Eval, Function, RestorePolicy},
+    glob,
+    resp::{Array, BulkString, Key, Map, Null, Resp, Set},
+};
+use mlua::{Lua, RegistryKey, Value, Variadic};
+use std::{cell::RefCell, collections::BTreeMap};
+
+/// The flags `redis.register_function` accepts.
+const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+struct FunctionDef {
+    /// The Lua function, kept in the registry.
+    callback: RegistryKey,
+    description: Option<String>,
+    flags: Vec<&'static str>,
+}
+
+struct Library {
+    code: String,
+    functions: BTreeMap<String, FunctionDef>,
+}
+
+/// The loaded libraries and the interpreter their functions live in.
+pub(super) struct Functions {
+    lua: Lua,
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        Functions {
+            lua: script::new_lua().expect("failed to set up the Lua interpreter"),
+            libraries: BTreeMap::new(),
+        }
+    }
+}
+
+impl Functions {
+    /// Loads the library in `code`, replacing one of the same name only if
+    /// `replace` is set. Nothing changes if loading fails.
+    fn load(&mut self, code: &str, replace: bool) -> Result<String, StorageError> {
+        let (name, body) = parse_metadata(code)?;
+        if !replace && self.libraries.contains_key(&name) {
+            return Err(StorageError::LibraryExists(name));
+        }
+        let functions = self.register(body)?;
+        if functions.is_empty() {
+            return Err(StorageError::NoFunctions);
+        }
+        for (library_name, library) in &self.libraries {
+            if *library_name == name {
+                continue;
+            }
+            if let Some(f) = functions
+                .keys()
+                .find(|f| library.functions.contains_key(*f))
+            {
+                return Err(StorageError::FunctionExists(f.clone()));
+            }
+        }
+        let code = code.to_string();
+        self.libraries
+            .insert(name.clone(), Library { code, functions });
+        self.lua.expire_registry_values();
+        Ok(name)
+    }
+
+    /// Runs a library's code, collecting the functions it registers.
+    fn register(&self, body: &str) -> Result<BTreeMap<String, FunctionDef>, StorageError> {
+        // Keep line numbers in errors counting from the metadata line.
+        let chunk = self
+            .lua
+            .load(format!("\n{body}"))
+            .set_name("@user_function")
+            .into_function()
+            .map_err(|e| StorageError::FunctionCompile(error_message(&e)))?;
+        let registered = RefCell::new(BTreeMap::new());
+        self.lua
+            .scope(|scope| {
+                let register = scope.create_function(|lua, args: Variadic<Value>| {
+                    let (name, def) = registration(lua, args)?;
+                    let mut registered = registered.borrow_mut();
+                    if registered.contains_key(&name) {
+                        return Err(mlua::Error::runtime(
+                            "Function already exists in the library",
+                        ));
+                    }
+                    registered.insert(name, def);
+                    Ok(())
+                })?;
+                let redis: mlua::Table = self.lua.globals().get("redis")?;
+                redis.set("register_function", register)?;
+                let res = chunk.call::<_, ()>(());
+                redis.set("register_function", Value::Nil)?;
+                res
+            })
+            .map_err(|e| StorageError::FunctionRegister(error_message(&e)))?;
+        Ok(registered.into_inner())
+    }
+
+    fn find(&self, name: &str) -> Option<&FunctionDef> {
+        self.libraries
+            .values()
+            .find_map(|library| library.functions.get(name))
+    }
+
+    /// Serializes the libraries' code, for `FUNCTION DUMP`: each library
+    /// as its length, a newline and the code, followed by the SHA1 digest
+    /// of all that.
+    pub(super) fn dump(&self) -> String {
+        let mut payload: String = self
+            .libraries
+            .values()
+            .map(|library| format!("{}\n{}", library.code.len(), library.code))
+            .collect();
+        payload += &sha1hex(payload.as_bytes());
+        payload
+    }
+
+    /// Loads the libraries in a `dump` payload into a new set of functions
+    /// that also keeps the current libraries `policy` allows.
+    fn restore(&self, payload: &str, policy: RestorePolicy) -> Result<Self, StorageError> {
+        let codes = undump(payload).ok_or(StorageError::InvalidFunctionDump)?;
+        let mut libraries: BTreeMap<_, _> = match policy {
+            RestorePolicy::Flush => BTreeMap::new(),
+            _ => self
+                .libraries
+                .iter()
+                .map(|(name, library)| (name.clone(), library.code.clone()))
+                .collect(),
+        };
+        for code in codes {
+            let (name, _) = parse_metadata(&code)?;
+            if policy == RestorePolicy::Append && libraries.contains_key(&name) {
+                return Err(StorageError::LibraryExists(name));
+            }
+            libraries.insert(name, code);
+        }
+        let mut functions = Functions::default();
+        for code in libraries.values() {
+            functions.load(code, false)?;
+        }
+        Ok(functions)
+    }
+
+    fn list(&self, pattern: Option<&str>, with_code: bool) -> Resp {
+        let libraries = self
+            .libraries
+            .iter()
+            .filter(|(name, _)| {
+                pattern.is_none_or(|p| glob::matches(p.as_bytes(), name.as_bytes(), false))
+            })
+            .map(|(name, library)| {
+                let functions = library
+                    .functions
+                    .iter()
+                    .map(|(name, f)| {
+                        let description = match &f.description {
+                            Some(d) => bulk_string(d.as_str()),
+                            None => Resp::Null(Null),
+                        };
+                        let flags: Set = f.flags.iter().map(|flag| key(flag)).collect();
+                        let map: Map = [
+                            (key("name"), bulk_string(name.as_str())),
+                            (key("description"), description),
+                            (key("flags"), Resp::Set(flags)),
+                        ]
+                        .into_iter()
+                        .collect();
+                        Resp::Map(Box::new(map))
+                    })
+                    .collect();
+                let mut map: Map = [
+                    (key("library_name"), bulk_string(name.as_str())),
+                    (key("engine"), bulk_string("LUA")),
+                    (key("functions"), Resp::Array(Array::new(functions, false))),
+                ]
+                .into_iter()
+                .collect();
+                if with_code {
+                    map.insert(key("library_code"), bulk_string(library.code.as_str()));
+                }
+                Resp::Map(Box::new(map))
+            })
+            .collect();
+        Resp::Array(Array::new(libraries, false))
+    }
+}
+
+impl Storage {
+    /// Runs `FCALL`, or `FCALL_RO` if `read_only` is set.
+    pub(super) fn fcall(&self, c: Eval, read_only: bool) -> Result<Resp, StorageError> {
+        let functions = self.functions.lock().unwrap();
+        let f = functions
+            .find(&c.script)
+            .ok_or(StorageError::FunctionNotFound)?;
+        let no_writes = f.flags.contains(&"no-writes");
+        if read_only && !no_writes {
+            return Err(StorageError::WriteFunctionReadOnly);
+        }
+        let runtime = |msg| StorageError::ScriptRuntime(c.script.clone(), msg);
+        let lua = &functions.lua;
+        let callback = lua
+            .registry_value(&f.callback)
+            .map_err(|e| runtime(error_message(&e)))?;
+        let calling = Calling::Function {
+            read_only: no_writes,
+        };
+        script::call(lua, self, callback, &c.keys, &c.args, calling).map_err(runtime)
+    }
+
+    pub(super) fn execute_function(&self, c: Function) -> Result<Resp, StorageError> {
+        let mut functions = self.functions.lock().unwrap();
+        match c {
+            Function::Load { code, replace } => Ok(bulk_string(functions.load(&code, replace)?)),
+            Function::List { pattern, with_code } => {
+                Ok(functions.list(pattern.as_deref(), with_code))
+            }
+            Function::Delete { library } => {
+                functions
+                    .libraries
+                    .remove(&library)
+                    .ok_or(StorageError::LibraryNotFound)?;
+                functions.lua.expire_registry_values();
+                Ok(ok())
+            }
+            Function::Dump => Ok(bulk_string(functions.dump())),
+            Function::Restore { payload, policy } => {
+                *functions = functions.restore(&payload, policy)?;
+                Ok(ok())
+            }
+            Function::Flush => {
+                *functions = Functions::default();
+                Ok(ok())
+            }
+        }
+    }
+}
+
+/// Splits library code into its name, from the `#!lua name=<library>`
+/// line, and the code after that line.
+fn parse_metadata(code: &str) -> Result<(String, &str), StorageError> {
+    let (first, body) = code.split_once('\n').unwrap_or((code, ""));
+    let shebang = first
+        .strip_prefix("#!")
+        .ok_or(StorageError::MissingLibraryMetadata)?;
+    let mut parts = shebang.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(StorageError::EngineNotFound(engine.to_string()));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.split_once('=') {
+            Some(("name", value)) => name = Some(value),
+            _ => return Err(StorageError::InvalidLibraryMetadata(part.to_string())),
+        }
+    }
+    let name = name.ok_or(StorageError::MissingLibraryName)?;
+    if !is_valid_name(name) {
+        return Err(StorageError::InvalidLibraryName);
+    }
+    Ok((name.to_string(), body))
+}
+
+/// The library code in a `Functions::dump` payload, or `None` if it is
+/// malformed.
+fn undump(payload: &str) -> Option<Vec<String>> {
+    let body = payload.get(..payload.len().checked_sub(40)?)?;
+    if sha1hex(body.as_bytes()) != payload[body.len()..] {
+        return None;
+    }
+    let mut codes = Vec::new();
+    let mut rest = body;
+    while !rest.is_empty() {
+        let (len, tail) = rest.split_once('\n')?;
+        let code = tail.get(..len.parse().ok()?)?;
+        codes.push(code.to_string());
+        rest = &tail[code.len()..];
+    }
+    Some(codes)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses the arguments of `redis.register_function`, either a name and a
+/// callback or a table with `function_name`, `callback` and optionally
+/// `flags` and `description`.
+fn registration<'lua>(
+    lua: &'lua Lua,
+    args: Variadic<Value<'lua>>,
+) -> mlua::Result<(String, FunctionDef)> {
+    let error = |msg: &str| Err(mlua::Error::runtime(msg));
+    let (name, callback, flags, description) = match &args[..] {
+        [Value::Table(t)] => {
+            for pair in t.clone().pairs::<Value, Value>() {
+                let (k, _) = pair?;
+                let known = match &k {
+                    Value::String(s) => matches!(
+                        s.to_str()?,
+                        "function_name" | "callback" | "flags" | "description"
+                    ),
+                    _ => false,
+                };
+                if !known {
+                    return error("unknown argument given to redis.register_function");
+                }
+            }
+            (
+                t.get("function_name")?,
+                t.get("callback")?,
+                t.get("flags")?,
+                t.get("description")?,
+            )
+        }
+        [_] => return error("calling redis.register_function with a single argument is only applicable to Lua table (representing named arguments)."),
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return error("wrong number of arguments to redis.register_function"),
+    };
+    let Value::String(name) = name else {
+        return error("function_name argument given to redis.register_function must be a string");
+    };
+    let name = name.to_str()?.to_string();
+    if !is_valid_name(&name) {
+        return error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long");
+    }
+    let Value::Function(callback) = callback else {
+        return error("callback argument given to redis.register_function must be a function");
+    };
+    let flags = match flags {
+        Value::Nil => Vec::new(),
+        Value::Table(t) => t
+            .sequence_values::<Value>()
+            .map(|flag| match flag? {
+                Value::String(s) => FLAGS
+                    .into_iter()
+                    .find(|f| s.as_bytes() == f.as_bytes())
+                    .ok_or_else(|| mlua::Error::runtime("unknown flag given")),
+                _ => Err(mlua::Error::runtime("unknown flag given")),
+            })
+            .collect::<mlua::Result<_>>()?,
+        _ => return error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        ),
+    };
+    let description = match description {
+        Value::Nil => None,
+        Value::String(s) => Some(s.to_str()?.to_string()),
+        _ => {
+            return error("description argument given to redis.register_function must be a string")
+        }
+    };
+    Ok((
+        name,
+        FunctionDef {
+            callback: lua.create_registry_value(callback)?,
+            description,
+            flags,
+        },
+    ))
+}
+
+fn key(s: &str) -> Key {
+    Key::BulkString(BulkString::new(s, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::parse_command,
+        resp::{Integer, SimpleError},
+    };
+
+    const LIB: &str = "#!lua name=counters
+local function incr(keys, args)
+    local n = tonumber(redis.call('GET', keys[1]) or '0') + tonumber(args[1])
+    redis.call('SET', keys[1], n)
+    return n
+end
+redis.register_function('incr', incr)
+redis.register_function{
+    function_name = 'peek',
+    callback = function(keys) return redis.call('GET', keys[1]) end,
+    flags = { 'no-writes' },
+    description = 'reads a counter',
+}
+redis.register_function{
+    function_name = 'sneaky',
+    callback = function(keys) return redis.call('SET', keys[1], 0) end,
+    flags = { 'no-writes' },
+}";
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp, String> {
+        let cmd = parse_command(args).unwrap();
+        cmd.execute(storage).map_err(|e| e.to_string())
+    }
+
+    fn int(n: i64) -> Resp {
+        Resp::Integer(Integer::new(n))
+    }
+
+    #[test]
+    fn test_load_and_call() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["FUNCTION", "LOAD", LIB]),
+            Ok(bulk_string("counters"))
+        );
+        assert_eq!(run(&storage, &["FCALL", "incr", "1", "c", "5"]), Ok(int(5)));
+        assert_eq!(run(&storage, &["FCALL", "incr", "1", "c", "2"]), Ok(int(7)));
+        assert_eq!(
+            run(&storage, &["FCALL_RO", "peek", "1", "c"]),
+            Ok(bulk_string("7"))
+        );
+        assert_eq!(
+            run(&storage, &["FCALL_RO", "incr", "1", "c", "1"]),
+            Err(StorageError::WriteFunctionReadOnly.to_string())
+        );
+        assert_eq!(
+            run(&storage, &["FCALL", "sneaky", "1", "c"]),
+            Ok(Resp::SimpleError(SimpleError::new(
+                StorageError::WriteFromReadOnlyScript.to_string()
+            )))
+        );
+        assert_eq!(
+            run(&storage, &["FCALL", "nope", "0"]),
+            Err(StorageError::FunctionNotFound.to_string())
+        );
+
+        // Loading again needs REPLACE, and replacing drops old functions.
+        assert_eq!(
+            run(&storage, &["FUNCTION", "LOAD", LIB]),
+            Err(StorageError::LibraryExists("counters".into()).to_string())
+        );
+        let replacement =
+            "#!lua name=counters\nredis.register_function('peek', function() return 1 end)";
+        assert!(run(&storage, &["FUNCTION", "LOAD", "REPLACE", replacement]).is_ok());
+        assert_eq!(run(&storage, &["FCALL", "peek", "0"]), Ok(int(1)));
+        assert!(run(&storage, &["FCALL", "incr", "1", "c", "1"]).is_err());
+
+        // Function names are unique across libraries.
+        let clash = "#!lua name=other\nredis.register_function('peek', function() end)";
+        assert_eq!(
+            run(&storage, &["FUNCTION", "LOAD", clash]),
+            Err(StorageError::FunctionExists("peek".into()).to_string())
+        );
+        assert_eq!(run(&storage, &["FUNCTION", "DELETE", "counters"]), Ok(ok()));
+        assert!(run(&storage, &["FUNCTION", "LOAD", clash]).is_ok());
+        assert_eq!(
+            run(&storage, &["FUNCTION", "DELETE", "counters"]),
+            Err(StorageError::LibraryNotFound.to_string())
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let storage = Storage::new();
+        let load = |code: &str| run(&storage, &["FUNCTION", "LOAD", code]);
+        assert_eq!(
+            load("return 1"),
+            Err(StorageError::MissingLibraryMetadata.to_string())
+        );
+        assert_eq!(
+            load("#!js name=x"),
+            Err(StorageError::EngineNotFound("js".into()).to_string())
+        );
+        assert_eq!(
+            load("#!lua"),
+            Err(StorageError::MissingLibraryName.to_string())
+        );
+        assert_eq!(
+            load("#!lua name=x version=2"),
+            Err(StorageError::InvalidLibraryMetadata("version=2".into()).to_string())
+        );
+        assert_eq!(
+            load("#!lua name=a-b"),
+            Err(StorageError::InvalidLibraryName.to_string())
+        );
+        assert_eq!(
+            load("#!lua name=x\nlocal a = 1"),
+            Err(StorageError::NoFunctions.to_string())
+        );
+        assert_eq!(
+            load("#!lua name=x\nredis.register_function{function_name='f', callback=print, flags={'fast'}}"),
+            Err("ERR Error registering functions: unknown flag given".into())
+        );
+        assert_eq!(
+            load("#!lua name=x\nredis.call('SET', 'k', 'v')"),
+            Err("ERR Error registering functions: user_function:2: attempt to call field 'call' (a nil value)".into())
+        );
+        assert!(load("#!lua name=x\nredis.register_function('f'")
+            .unwrap_err()
+            .starts_with("ERR Error compiling function: user_function:2:"));
+        assert_eq!(
+            run(&storage, &["FUNCTION", "LIST"]),
+            Ok(Resp::Array(Array::new(vec![], false)))
+        );
+    }
+
+    #[test]
+    fn test_list_dump_restore() {
+        let storage = Storage::new();
+        run(&storage, &["FUNCTION", "LOAD", LIB]).unwrap();
+        let other = "#!lua name=other\nredis.register_function('one', function() return 1 end)";
+        run(&storage, &["FUNCTION", "LOAD", other]).unwrap();
+
+        let Ok(Resp::Array(libraries)) = run(
+            &storage,
+            &["FUNCTION", "LIST", "LIBRARYNAME", "c*", "WITHCODE"],
+        ) else {
+            panic!("Expected an array");
+        };
+        assert_eq!(libraries.len(), 1);
+        let Resp::Map(library) = &libraries[0] else {
+            panic!("Expected a map");
+        };
+        assert_eq!(library[&key("library_name")], bulk_string("counters"));
+        assert_eq!(library[&key("library_code")], bulk_string(LIB));
+        let Resp::Array(functions) = &library[&key("functions")] else {
+            panic!("Expected an array");
+        };
+        let Resp::Map(peek) = &functions[1] else {
+            panic!("Expected a map");
+        };
+        assert_eq!(peek[&key("description")], bulk_string("reads a counter"));
+        assert_eq!(
+            peek[&key("flags")],
+            Resp::Set([key("no-writes")].into_iter().collect())
+        );
+
+        let Ok(Resp::BulkString(dump)) = run(&storage, &["FUNCTION", "DUMP"]) else {
+            panic!("Expected a bulk string");
+        };
+        let dump = dump.as_str().unwrap().to_string();
+        assert_eq!(run(&storage, &["FUNCTION", "FLUSH"]), Ok(ok()));
+        assert!(run(&storage, &["FCALL", "one", "0"]).is_err());
+        assert_eq!(
+            run(&storage, &["FUNCTION", "RESTORE", &dump[1..]]),
+            Err(StorageError::InvalidFunctionDump.to_string())
+        );
+        assert_eq!(run(&storage, &["FUNCTION", "RESTORE", &dump]), Ok(ok()));
+        assert_eq!(run(&storage, &["FCALL", "one", "0"]), Ok(int(1)));
+        assert_eq!(
+            run(&storage, &["FUNCTION", "RESTORE", &dump]),
+            Err(StorageError::LibraryExists("counters".into()).to_string())
+        );
+        assert_eq!(
+            run(&storage, &["FUNCTION", "RESTORE", &dump, "REPLACE"]),
+            Ok(ok())
+        );
+
+        let only_other =
+            "#!lua name=other\nredis.register_function('two', function() return 2 end)";
+        run(&storage, &["FUNCTION", "LOAD", "REPLACE", only_other]).unwrap();
+        run(&storage, &["FUNCTION", "DELETE", "counters"]).unwrap();
+        let Ok(Resp::BulkString(dump)) = run(&storage, &["FUNCTION", "DUMP"]) else {
+            panic!("Expected a bulk string");
+        };
+        run(&storage, &["FUNCTION", "LOAD", LIB]).unwrap();
+        assert_eq!(
+            run(
+                &storage,
+                &["FUNCTION", "RESTORE", dump.as_str().unwrap(), "FLUSH"]
+            ),
+            Ok(ok())
+        );
+        assert_eq!(run(&storage, &["FCALL", "two", "0"]), Ok(int(2)));
+        assert!(run(&storage, &["FCALL", "incr", "1", "c", "1"]).is_err());
+    }
+}
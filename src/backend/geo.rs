@@ -0,0 +1,626 @@
+use super::{
+    array, bulk_string, integer,
+    list::null_array,
+    zset::{double, SortedSet},
+    Storage, StorageError,
+};
+use crate::{
+    cmd::{Command, GeoFrom, GeoOrder, GeoSearch, GeoShape, ZAdd, GEO_LAT_LIMIT, GEO_LON_LIMIT},
+    resp::{Key, Null, Resp},
+};
+use anyhow::Result;
+use std::f64::consts::FRAC_PI_2;
+
+/// Bits per axis of the geohash stored as a member's score.
+const STEP_MAX: u32 = 26;
+const EARTH_RADIUS: f64 = 6_372_797.560_856;
+const MERCATOR_MAX: f64 = 20_037_726.37;
+const ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// A search match: the member, its distance from the center in meters and
+/// its score.
+type Match = (Key, f64, f64);
+
+/// Spreads the bits of `x` out to the even bit positions.
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | x << 16) & 0x0000_ffff_0000_ffff;
+    x = (x | x << 8) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x << 2) & 0x3333_3333_3333_3333;
+    (x | x << 1) & 0x5555_5555_5555_5555
+}
+
+/// Gathers the even bits of `x`, undoing `spread`.
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | x >> 1) & 0x3333_3333_3333_3333;
+    x = (x | x >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x >> 4) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x >> 8) & 0x0000_ffff_0000_ffff;
+    (x | x >> 16) as u32
+}
+
+/// Interleaves a cell's latitude index into the even bits of a geohash and
+/// its longitude index into the odd ones.
+fn interleave(lat: u32, lon: u32) -> u64 {
+    spread(lat) | spread(lon) << 1
+}
+
+/// The latitude and longitude indexes of the `step`-bit grid cell holding a
+/// point, with latitudes spanning `-lat_limit..lat_limit`.
+fn cell(lon: f64, lat: f64, lat_limit: f64, step: u32) -> (u32, u32) {
+    let cells = (1u64 << step) as f64;
+    let lat_offset = (lat + lat_limit) / (2.0 * lat_limit);
+    let lon_offset = (lon + GEO_LON_LIMIT) / (2.0 * GEO_LON_LIMIT);
+    ((lat_offset * cells) as u32, (lon_offset * cells) as u32)
+}
+
+/// The score a point is stored with: its 52 bit geohash.
+fn encode(lon: f64, lat: f64) -> f64 {
+    let (lat, lon) = cell(lon, lat, GEO_LAT_LIMIT, STEP_MAX);
+    interleave(lat, lon) as f64
+}
+
+/// The center of the cell a score encodes, as `(longitude, latitude)`.
+fn decode(score: f64) -> (f64, f64) {
+    let bits = score as u64;
+    let cells = (1u64 << STEP_MAX) as f64;
+    let center = |index: u32, limit: f64| {
+        let min = -limit + index as f64 / cells * 2.0 * limit;
+        let max = -limit + (index as f64 + 1.0) / cells * 2.0 * limit;
+        ((min + max) / 2.0).clamp(-limit, limit)
+    };
+    (
+        center(squash(bits >> 1), GEO_LON_LIMIT),
+        center(squash(bits), GEO_LAT_LIMIT),
+    )
+}
+
+/// The standard 11 character geohash of a stored score. Unlike scores, it
+/// uses the full `-90..90` latitude range.
+fn geohash(score: f64) -> String {
+    let (lon, lat) = decode(score);
+    let (lat, lon) = cell(lon, lat, 90.0, STEP_MAX);
+    let bits = interleave(lat, lon);
+    (0..11)
+        .map(|i| match i {
+            10 => ALPHABET[0],
+            _ => ALPHABET[(bits >> (52 - (i + 1) * 5)) as usize & 0x1f],
+        } as char)
+        .collect()
+}
+
+/// Great-circle distance in meters.
+fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// The distance from `center` to `point` if the point lies within `shape`.
+fn within(shape: GeoShape, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+    match shape {
+        GeoShape::Radius(radius) => Some(distance(center, point)).filter(|&d| d <= radius),
+        GeoShape::Box { width, height } => {
+            let lat_distance = EARTH_RADIUS * (point.1 - center.1).to_radians().abs();
+            let lon_distance = distance((center.0, point.1), point);
+            (lat_distance <= height / 2.0 && lon_distance <= width / 2.0)
+                .then(|| distance(center, point))
+        }
+    }
+}
+
+/// The coarsest grid step whose cells are still larger than `range`.
+fn estimate_step(mut range: f64, lat: f64) -> u32 {
+    if range == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step = 1i32;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    if lat.abs() > 66.0 {
+        step -= 1;
+        if lat.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+/// The score ranges of the cell holding `center` and its eight neighbors,
+/// at a step coarse enough for them to cover all of `shape`.
+fn search_ranges(center: (f64, f64), shape: GeoShape) -> Vec<(f64, f64)> {
+    let (lon, lat) = center;
+    let (width, height) = match shape {
+        GeoShape::Radius(radius) => (radius, radius),
+        GeoShape::Box { width, height } => (width / 2.0, height / 2.0),
+    };
+    let lat_delta = (height / EARTH_RADIUS).to_degrees();
+    // How far in longitude a match can be: for a circle, its widest point;
+    // for a box, a great circle of half its width at its poleward edge.
+    let lon_ratio = match shape {
+        _ if width / EARTH_RADIUS >= FRAC_PI_2 => 1.0,
+        GeoShape::Radius(_) if lat.abs() + lat_delta >= 90.0 => 1.0,
+        GeoShape::Radius(_) => (width / EARTH_RADIUS).sin() / lat.to_radians().cos(),
+        GeoShape::Box { .. } => {
+            let poleward = (lat.abs() + lat_delta).min(GEO_LAT_LIMIT).to_radians();
+            (width / EARTH_RADIUS / 2.0).sin() / poleward.cos()
+        }
+    };
+    let lon_delta = match shape {
+        _ if lon_ratio >= 1.0 => GEO_LON_LIMIT,
+        GeoShape::Radius(_) => lon_ratio.asin().to_degrees(),
+        GeoShape::Box { .. } => 2.0 * lon_ratio.asin().to_degrees(),
+    };
+
+    let mut step = estimate_step(width.hypot(height), lat);
+    let (lat_index, lon_index) = loop {
+        let (lat_index, lon_index) = cell(lon, lat, GEO_LAT_LIMIT, step);
+        let cells = (1u64 << step) as f64;
+        let (cell_height, cell_width) = (2.0 * GEO_LAT_LIMIT / cells, 360.0 / cells);
+        let lat_min = -GEO_LAT_LIMIT + lat_index as f64 * cell_height;
+        let lon_min = -GEO_LON_LIMIT + lon_index as f64 * cell_width;
+        let lat_covered = (lat - lat_delta).max(-GEO_LAT_LIMIT) >= lat_min - cell_height
+            && (lat + lat_delta).min(GEO_LAT_LIMIT) <= lat_min + 2.0 * cell_height;
+        let lon_covered = 3.0 * cell_width >= 360.0
+            || (lon - lon_delta >= lon_min - cell_width
+                && lon + lon_delta <= lon_min + 2.0 * cell_width);
+        if step == 1 || (lat_covered && lon_covered) {
+            break (lat_index as i64, lon_index as i64);
+        }
+        step -= 1;
+    };
+
+    let cells = 1i64 << step;
+    let shift = 2 * (STEP_MAX - step);
+    let mut ranges = Vec::with_capacity(9);
+    for dlat in -1..=1 {
+        let lat = lat_index + dlat;
+        if !(0..cells).contains(&lat) {
+            continue;
+        }
+        for dlon in -1..=1 {
+            let lon = (lon_index + dlon).rem_euclid(cells);
+            let bits = interleave(lat as u32, lon as u32);
+            let range = ((bits << shift) as f64, ((bits + 1) << shift) as f64);
+            if !ranges.contains(&range) {
+                ranges.push(range);
+            }
+        }
+    }
+    ranges
+}
+
+/// The members of `zset` within the search area, nearest first if sorted.
+fn search(zset: &SortedSet, c: &GeoSearch) -> Result<Vec<Match>, StorageError> {
+    let center = match &c.from {
+        GeoFrom::Member(member) => {
+            decode(zset.score(member).ok_or(StorageError::GeoMemberNotFound)?)
+        }
+        GeoFrom::LonLat(lon, lat) => (*lon, *lat),
+    };
+    let limit = c.count.filter(|_| c.any).unwrap_or(usize::MAX);
+    let mut matches = Vec::new();
+    'ranges: for (min, max) in search_ranges(center, c.shape) {
+        for (member, score) in zset.score_range(min, max) {
+            if let Some(dist) = within(c.shape, center, decode(score)) {
+                matches.push((member.clone(), dist, score));
+                if matches.len() == limit {
+                    break 'ranges;
+                }
+            }
+        }
+    }
+    let order = match c.order {
+        None if c.count.is_some() && !c.any => Some(GeoOrder::Asc),
+        order => order,
+    };
+    match order {
+        Some(GeoOrder::Asc) => matches.sort_by(|a, b| a.1.total_cmp(&b.1)),
+        Some(GeoOrder::Desc) => matches.sort_by(|a, b| b.1.total_cmp(&a.1)),
+        None => {}
+    }
+    matches.truncate(c.count.unwrap_or(usize::MAX));
+    Ok(matches)
+}
+
+/// A distance in the requested unit, rounded to 4 decimals like Redis.
+fn reply_distance(meters: f64, unit: f64) -> Resp {
+    double(((meters / unit) * 10_000.0).round() / 10_000.0)
+}
+
+fn position((lon, lat): (f64, f64)) -> Resp {
+    array([double(lon), double(lat)])
+}
+
+impl Storage {
+    pub(super) fn execute_geo(&self, cmd: Command) -> Result<Option<Resp>> {
+        let res = match cmd {
+            Command::GeoAdd(c) => {
+                let zadd = ZAdd {
+                    key: c.key,
+                    condition: c.condition,
+                    comparison: None,
+                    ch: c.ch,
+                    incr: false,
+                    pairs: c
+                        .points
+                        .into_iter()
+                        .map(|(lon, lat, member)| (encode(lon, lat), member))
+                        .collect(),
+                };
+                let res = self.zadd(&zadd)?;
+                self.signal_ready(&zadd.key);
+                res
+            }
+            Command::GeoDist(c) => self
+                .read(&c.key, |z: &SortedSet| {
+                    Some(distance(
+                        decode(z.score(&c.member1)?),
+                        decode(z.score(&c.member2)?),
+                    ))
+                })?
+                .flatten()
+                .map_or(Resp::Null(Null), |d| reply_distance(d, c.unit)),
+            Command::GeoPos(c) => {
+                let scores = self
+                    .read(&c.key, |z: &SortedSet| {
+                        c.members.iter().map(|m| z.score(m)).collect()
+                    })?
+                    .unwrap_or_else(|| vec![None; c.members.len()]);
+                array(scores.into_iter().map(|score| match score {
+                    Some(score) => position(decode(score)),
+                    None => null_array(),
+                }))
+            }
+            Command::GeoHash(c) => {
+                let scores = self
+                    .read(&c.key, |z: &SortedSet| {
+                        c.members.iter().map(|m| z.score(m)).collect()
+                    })?
+                    .unwrap_or_else(|| vec![None; c.members.len()]);
+                array(scores.into_iter().map(|score| match score {
+                    Some(score) => bulk_string(geohash(score)),
+                    None => Resp::Null(Null),
+                }))
+            }
+            Command::GeoSearch(c) => {
+                let matches = self
+                    .read(&c.key, |z: &SortedSet| search(z, &c))?
+                    .transpose()?
+                    .unwrap_or_default();
+                array(matches.into_iter().map(|(member, dist, score)| {
+                    if !(c.with_dist || c.with_hash || c.with_coord) {
+                        return member.into();
+                    }
+                    let mut item = vec![member.into()];
+                    if c.with_dist {
+                        item.push(reply_distance(dist, c.unit));
+                    }
+                    if c.with_hash {
+                        item.push(integer(score as i64));
+                    }
+                    if c.with_coord {
+                        item.push(position(decode(score)));
+                    }
+                    array(item)
+                }))
+            }
+            Command::GeoSearchStore(c) => {
+                let matches = self
+                    .read(&c.key, |z: &SortedSet| search(z, &c))?
+                    .transpose()?
+                    .unwrap_or_default();
+                let zset: SortedSet = matches
+                    .into_iter()
+                    .map(|(member, dist, score)| match c.store_dist {
+                        true => (member, dist / c.unit),
+                        false => (member, score),
+                    })
+                    .collect();
+                let destination = c.destination.as_ref().expect("GEOSEARCHSTORE destination");
+                integer(self.store_zset(destination, zset, "geosearchstore") as i64)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn bulk(s: &str) -> Resp {
+        Resp::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args)?;
+        Ok(storage.execute_geo(cmd)?.unwrap())
+    }
+
+    fn sicily() -> Storage {
+        let storage = Storage::new();
+        let args = [
+            "GEOADD",
+            "Sicily",
+            "13.361389",
+            "38.115556",
+            "Palermo",
+            "15.087269",
+            "37.502669",
+            "Catania",
+        ];
+        assert_eq!(run(&storage, &args).unwrap(), integer(2));
+        let args = [
+            "GEOADD",
+            "Sicily",
+            "12.758489",
+            "38.788135",
+            "edge1",
+            "17.241510",
+            "38.788135",
+            "edge2",
+        ];
+        assert_eq!(run(&storage, &args).unwrap(), integer(2));
+        storage
+    }
+
+    fn float(resp: &Resp) -> f64 {
+        match resp {
+            Resp::Double(d) => d.value(),
+            _ => panic!("Expected Double"),
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(encode(13.361389, 38.115556), 3479099956230698.0);
+        assert_eq!(encode(15.087269, 37.502669), 3479447370796909.0);
+        let (lon, lat) = decode(3479099956230698.0);
+        assert!((lon - 13.361389338970184).abs() < 1e-12);
+        assert!((lat - 38.1155563954963).abs() < 1e-12);
+        assert_eq!(geohash(3479099956230698.0), "sqc8b49rny0");
+        assert_eq!(geohash(3479447370796909.0), "sqdtr74hyu0");
+        for x in [0, 1, 0x1234_5678, u32::MAX] {
+            assert_eq!(squash(spread(x)), x);
+        }
+    }
+
+    #[test]
+    fn test_geodist_geopos_geohash() {
+        let storage = sicily();
+        let dist = |unit: &str| {
+            float(&run(&storage, &["GEODIST", "Sicily", "Palermo", "Catania", unit]).unwrap())
+        };
+        assert_eq!(dist("m"), 166274.1516);
+        assert_eq!(dist("km"), 166.2742);
+        assert_eq!(dist("mi"), 103.3182);
+        let res = run(&storage, &["GEODIST", "Sicily", "Palermo", "Nowhere"]).unwrap();
+        assert_eq!(res, Resp::Null(Null));
+
+        let Resp::Array(pos) = run(&storage, &["GEOPOS", "Sicily", "Palermo", "Nowhere"]).unwrap()
+        else {
+            panic!("Expected Array");
+        };
+        let Resp::Array(palermo) = &pos[0] else {
+            panic!("Expected Array");
+        };
+        assert!((float(&palermo[0]) - 13.361389).abs() < 1e-5);
+        assert!((float(&palermo[1]) - 38.115556).abs() < 1e-5);
+        assert_eq!(pos[1], null_array());
+
+        let res = run(&storage, &["GEOHASH", "Sicily", "Palermo", "Catania", "x"]).unwrap();
+        assert_eq!(
+            res,
+            array([bulk("sqc8b49rny0"), bulk("sqdtr74hyu0"), Resp::Null(Null)])
+        );
+    }
+
+    #[test]
+    fn test_geosearch() {
+        let storage = sicily();
+        let args = [
+            "GEOSEARCH",
+            "Sicily",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "BYRADIUS",
+            "200",
+            "km",
+            "ASC",
+        ];
+        let res = run(&storage, &args).unwrap();
+        assert_eq!(res, array([bulk("Catania"), bulk("Palermo")]));
+
+        let args = [
+            "GEOSEARCH",
+            "Sicily",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "BYBOX",
+            "400",
+            "400",
+            "km",
+            "ASC",
+            "WITHDIST",
+        ];
+        let res = run(&storage, &args).unwrap();
+        assert_eq!(
+            res,
+            array([
+                array([bulk("Catania"), double(56.4413)]),
+                array([bulk("Palermo"), double(190.4424)]),
+                array([bulk("edge2"), double(279.7403)]),
+                array([bulk("edge1"), double(279.7405)]),
+            ])
+        );
+        let args = [
+            "GEOSEARCH",
+            "Sicily",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "BYRADIUS",
+            "200",
+            "km",
+            "WITHHASH",
+        ];
+        let Resp::Array(res) = run(&storage, &args).unwrap() else {
+            panic!("Expected Array");
+        };
+        assert!(res.contains(&array([bulk("Palermo"), integer(3479099956230698)])));
+
+        let args = [
+            "GEOSEARCH",
+            "Sicily",
+            "FROMMEMBER",
+            "Palermo",
+            "BYRADIUS",
+            "500",
+            "km",
+            "DESC",
+            "COUNT",
+            "2",
+        ];
+        let res = run(&storage, &args).unwrap();
+        assert_eq!(res, array([bulk("edge2"), bulk("Catania")]));
+        let args = [
+            "GEOSEARCH",
+            "Sicily",
+            "FROMMEMBER",
+            "Palermo",
+            "BYRADIUS",
+            "500",
+            "km",
+            "COUNT",
+            "1",
+            "ANY",
+            "WITHCOORD",
+        ];
+        let Resp::Array(res) = run(&storage, &args).unwrap() else {
+            panic!("Expected Array");
+        };
+        assert_eq!(res.len(), 1);
+
+        let args = [
+            "GEOSEARCH",
+            "Sicily",
+            "FROMMEMBER",
+            "Rome",
+            "BYRADIUS",
+            "1",
+            "km",
+        ];
+        let err = run(&storage, &args).unwrap_err();
+        assert_eq!(err.to_string(), StorageError::GeoMemberNotFound.to_string());
+        let args = [
+            "GEOSEARCH",
+            "Nowhere",
+            "FROMMEMBER",
+            "Rome",
+            "BYRADIUS",
+            "1",
+            "km",
+        ];
+        assert_eq!(run(&storage, &args).unwrap(), array([]));
+    }
+
+    #[test]
+    fn test_geosearchstore() {
+        let storage = sicily();
+        let args = [
+            "GEOSEARCHSTORE",
+            "dst",
+            "Sicily",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "BYRADIUS",
+            "200",
+            "km",
+            "STOREDIST",
+        ];
+        assert_eq!(run(&storage, &args).unwrap(), integer(2));
+        let dist = storage
+            .read(
+                &Key::BulkString(BulkString::new("dst", false)),
+                |z: &SortedSet| z.score(&Key::BulkString(BulkString::new("Catania", false))),
+            )
+            .unwrap()
+            .flatten()
+            .unwrap();
+        assert!((dist - 56.4413).abs() < 1e-4);
+
+        let args = [
+            "GEOSEARCHSTORE",
+            "dst",
+            "Sicily",
+            "FROMLONLAT",
+            "0",
+            "0",
+            "BYRADIUS",
+            "1",
+            "km",
+        ];
+        assert_eq!(run(&storage, &args).unwrap(), integer(0));
+        let dst = Key::BulkString(BulkString::new("dst", false));
+        assert!(!storage.keyspace().contains_key(&dst));
+    }
+
+    #[test]
+    fn test_search_matches_full_scan() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let zset: SortedSet = (0..2_000)
+            .map(|i| {
+                let lon = rng.gen_range(-GEO_LON_LIMIT..GEO_LON_LIMIT);
+                let lat = rng.gen_range(-GEO_LAT_LIMIT..GEO_LAT_LIMIT);
+                let member = Key::BulkString(BulkString::new(i.to_string(), false));
+                (member, encode(lon, lat))
+            })
+            .collect();
+        for _ in 0..200 {
+            let center = (
+                rng.gen_range(-GEO_LON_LIMIT..GEO_LON_LIMIT),
+                rng.gen_range(-GEO_LAT_LIMIT..GEO_LAT_LIMIT),
+            );
+            let shape = match rng.gen_bool(0.5) {
+                true => GeoShape::Radius(rng.gen_range(0.0..5_000_000.0)),
+                false => GeoShape::Box {
+                    width: rng.gen_range(0.0..5_000_000.0),
+                    height: rng.gen_range(0.0..5_000_000.0),
+                },
+            };
+            let c = GeoSearch {
+                key: Key::BulkString(BulkString::new("g", false)),
+                destination: None,
+                from: GeoFrom::LonLat(center.0, center.1),
+                shape,
+                unit: 1.0,
+                order: Some(GeoOrder::Asc),
+                count: None,
+                any: false,
+                with_coord: false,
+                with_dist: false,
+                with_hash: false,
+                store_dist: false,
+            };
+            let found = search(&zset, &c).unwrap().len();
+            let expected = zset
+                .iter()
+                .filter(|&(_, score)| within(shape, center, decode(score)).is_some())
+                .count();
+            assert_eq!(found, expected, "{shape:?} around {center:?}");
+        }
+    }
+}
@@ -0,0 +1,964 @@
+//! Stream consumer groups. A group remembers the last entry it handed out
+//! and keeps a pending entries list (PEL) of deliveries awaiting an `XACK`,
+//! indexed both by ID and by consumer so either side can be walked in order.
+
+use super::{
+    array,
+    blocking::Serve,
+    bulk_string, integer,
+    list::null_array,
+    notify::Class,
+    ok,
+    stream::{entries_reply, entry_reply, now_ms, Stream},
+    Storage, StorageError,
+};
+use crate::{
+    cmd::{StreamId, XAck, XAutoClaim, XClaim, XGroup, XInfo, XPending, XReadGroup, XReadId},
+    resp::{BulkString, Key, Map, Null, Resp},
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+/// `XAUTOCLAIM` looks at up to this many pending entries per entry it may
+/// claim, as in Redis.
+const AUTOCLAIM_ATTEMPTS_FACTOR: usize = 10;
+
+type Entries = BTreeMap<StreamId, Vec<Resp>>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct ConsumerGroup {
+    /// Every entry up to this ID has been delivered to some consumer.
+    last_delivered: StreamId,
+    /// How many entries the group has read, when known.
+    entries_read: Option<u64>,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<Key, Consumer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PendingEntry {
+    consumer: Key,
+    /// When the entry was last delivered, in Unix milliseconds.
+    delivered_at: u64,
+    deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Consumer {
+    pending: BTreeSet<StreamId>,
+    /// When the consumer last tried to read or claim.
+    seen_at: u64,
+    /// When the consumer last actually got something.
+    active_at: Option<u64>,
+}
+
+impl ConsumerGroup {
+    /// Points the group at `id`, or at the end of the stream for `$`.
+    /// Without an explicit count, the number of entries read is only known
+    /// at either end of the stream.
+    fn set_id(&mut self, stream: &Stream, id: XReadId, entries_read: Option<u64>) {
+        self.last_delivered = match id {
+            XReadId::After(id) => id,
+            XReadId::New | XReadId::Undelivered => stream.last_id(),
+        };
+        self.entries_read = entries_read.or(match self.last_delivered {
+            id if id == stream.last_id() => Some(stream.entries_added),
+            StreamId::MIN => Some(0),
+            _ => None,
+        });
+    }
+
+    /// Looks up a consumer, creating it if needed, and marks it as seen.
+    fn consumer(&mut self, name: &Key, now: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.clone()).or_default();
+        consumer.seen_at = now;
+        consumer
+    }
+
+    /// Records a delivery of `id` to `consumer`, taking it from whichever
+    /// consumer had it before.
+    fn deliver(&mut self, id: StreamId, consumer: &Key, delivered_at: u64, deliveries: u64) {
+        let entry = PendingEntry {
+            consumer: consumer.clone(),
+            delivered_at,
+            deliveries,
+        };
+        if let Some(old) = self.pending.insert(id, entry) {
+            if let Some(old) = self.consumers.get_mut(&old.consumer) {
+                old.pending.remove(&id);
+            }
+        }
+        self.consumers
+            .entry(consumer.clone())
+            .or_default()
+            .pending
+            .insert(id);
+    }
+
+    /// Drops `id` from the pending entries list, returning whether it was
+    /// there.
+    fn ack(&mut self, id: &StreamId) -> bool {
+        let Some(entry) = self.pending.remove(id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+        true
+    }
+
+    /// Hands `consumer` the entries no one in the group has seen yet,
+    /// adding them to the PEL unless `noack`. `None` if there are none.
+    fn read_new(
+        &mut self,
+        entries: &Entries,
+        consumer: &Key,
+        count: Option<usize>,
+        noack: bool,
+        now: u64,
+    ) -> Option<Resp> {
+        self.consumer(consumer, now);
+        let new: Vec<_> = entries
+            .range((Bound::Excluded(self.last_delivered), Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect();
+        let (last, _) = new.last()?;
+        self.last_delivered = *last;
+        if let Some(read) = &mut self.entries_read {
+            *read += new.len() as u64;
+        }
+        self.consumer(consumer, now).active_at = Some(now);
+        if !noack {
+            for (id, _) in &new {
+                self.deliver(*id, consumer, now, 1);
+            }
+        }
+        Some(entries_reply(new))
+    }
+
+    /// Replays the entries pending for `consumer` after `after`. Entries
+    /// deleted from the stream since show up with no fields.
+    fn read_history(
+        &mut self,
+        entries: &Entries,
+        consumer: &Key,
+        after: StreamId,
+        count: Option<usize>,
+        now: u64,
+    ) -> Resp {
+        let consumer = self.consumer(consumer, now);
+        let ids = consumer
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX));
+        array(ids.map(|id| match entries.get(id) {
+            Some(fields) => entry_reply(*id, fields.clone()),
+            None => array([bulk_string(id.to_string()), null_array()]),
+        }))
+    }
+
+    /// Entries after the last delivered one.
+    fn lag(&self, entries: &Entries) -> usize {
+        entries
+            .range((Bound::Excluded(self.last_delivered), Bound::Unbounded))
+            .count()
+    }
+}
+
+fn no_group(key: &Key, group: &Key) -> StorageError {
+    StorageError::NoGroup(key.to_string(), group.to_string())
+}
+
+impl Storage {
+    /// Runs `f` on consumer group `group` of the stream at `key`, along with
+    /// the stream's entries. `None` if there is no such key.
+    fn write_group<T>(
+        &self,
+        key: &Key,
+        group: &Key,
+        f: impl FnOnce(&mut ConsumerGroup, &Entries) -> Result<T, StorageError>,
+    ) -> Result<Option<T>, StorageError> {
+        self.write(key, false, |s: &mut Stream| {
+            let g = s
+                .groups
+                .get_mut(group)
+                .ok_or_else(|| no_group(key, group))?;
+            f(g, &s.entries)
+        })
+    }
+
+    /// Like `write_group`, for commands acting on behalf of `consumer`:
+    /// raises `xgroup-createconsumer` if `f` had to create it.
+    fn write_consumer<T>(
+        &self,
+        key: &Key,
+        group: &Key,
+        consumer: &Key,
+        f: impl FnOnce(&mut ConsumerGroup, &Entries) -> Result<T, StorageError>,
+    ) -> Result<Option<T>, StorageError> {
+        let res = self.write_group(key, group, |g, entries| {
+            let existed = g.consumers.contains_key(consumer);
+            let res = f(g, entries)?;
+            Ok((res, !existed && g.consumers.contains_key(consumer)))
+        })?;
+        Ok(res.map(|(res, created)| {
+            if created {
+                self.notify(Class::STREAM, "xgroup-createconsumer", key);
+            }
+            res
+        }))
+    }
+
+    pub(super) fn xreadgroup(&self, c: &XReadGroup) -> Result<Option<Resp>, StorageError> {
+        let now = now_ms();
+        let mut streams = Vec::new();
+        for (key, id) in c.read.keys.iter().zip(&c.read.ids) {
+            let read = self
+                .write_consumer(key, &c.group, &c.consumer, |g, entries| {
+                    Ok(match *id {
+                        XReadId::Undelivered => {
+                            g.read_new(entries, &c.consumer, c.read.count, c.noack, now)
+                        }
+                        XReadId::After(after) => {
+                            Some(g.read_history(entries, &c.consumer, after, c.read.count, now))
+                        }
+                        // `$` is rejected when parsing XREADGROUP.
+                        XReadId::New => None,
+                    })
+                })?
+                .ok_or_else(|| no_group(key, &c.group))?;
+            if let Some(entries) = read {
+                streams.push(array([key.clone().into(), entries]));
+            }
+        }
+        Ok((!streams.is_empty()).then(|| array(streams)))
+    }
+
+    pub(super) fn xgroup(&self, c: &XGroup) -> Result<Resp, StorageError> {
+        let now = now_ms();
+        let (key, event) = match c {
+            XGroup::Create { key, .. } => (key, "xgroup-create"),
+            XGroup::SetId { key, .. } => (key, "xgroup-setid"),
+            XGroup::Destroy { key, .. } => (key, "xgroup-destroy"),
+            XGroup::CreateConsumer { key, .. } => (key, "xgroup-createconsumer"),
+            XGroup::DelConsumer { key, .. } => (key, "xgroup-delconsumer"),
+        };
+        // Whether the subcommand changed anything worth an event.
+        let mut changed = true;
+        let res = match c {
+            XGroup::Create {
+                key,
+                group,
+                id,
+                mkstream,
+                entries_read,
+            } => self.write(key, *mkstream, |s: &mut Stream| {
+                if s.groups.contains_key(group) {
+                    return Err(StorageError::BusyGroup);
+                }
+                let mut g = ConsumerGroup::default();
+                g.set_id(s, *id, *entries_read);
+                s.groups.insert(group.clone(), g);
+                Ok(ok())
+            })?,
+            XGroup::SetId {
+                key,
+                group,
+                id,
+                entries_read,
+            } => self.write(key, false, |s: &mut Stream| {
+                let mut g = s.groups.remove(group).ok_or_else(|| no_group(key, group))?;
+                g.set_id(s, *id, *entries_read);
+                s.groups.insert(group.clone(), g);
+                Ok(ok())
+            })?,
+            XGroup::Destroy { key, group } => {
+                let destroyed = self.write(key, false, |s: &mut Stream| {
+                    Ok(s.groups.remove(group).is_some())
+                })?;
+                changed = destroyed == Some(true);
+                if changed {
+                    // Clients blocked reading the group get an error.
+                    self.signal_ready(key);
+                }
+                destroyed.map(|d| integer(d as i64))
+            }
+            XGroup::CreateConsumer {
+                key,
+                group,
+                consumer,
+            } => self.write_group(key, group, |g, _| {
+                changed = !g.consumers.contains_key(consumer);
+                g.consumer(consumer, now);
+                Ok(integer(changed as i64))
+            })?,
+            XGroup::DelConsumer {
+                key,
+                group,
+                consumer,
+            } => self.write_group(key, group, |g, _| {
+                let pending = match g.consumers.remove(consumer) {
+                    Some(consumer) => {
+                        for id in &consumer.pending {
+                            g.pending.remove(id);
+                        }
+                        consumer.pending.len()
+                    }
+                    None => {
+                        changed = false;
+                        0
+                    }
+                };
+                Ok(integer(pending as i64))
+            })?,
+        };
+        let res = res.ok_or(StorageError::XGroupNoKey)?;
+        if changed {
+            self.notify(Class::STREAM, event, key);
+        }
+        Ok(res)
+    }
+
+    /// Acknowledging against a missing key or group acknowledges nothing.
+    pub(super) fn xack(&self, c: &XAck) -> Result<Resp, StorageError> {
+        let acked = self
+            .write(&c.key, false, |s: &mut Stream| {
+                Ok(s.groups
+                    .get_mut(&c.group)
+                    .map_or(0, |g| c.ids.iter().filter(|id| g.ack(id)).count()))
+            })?
+            .unwrap_or(0);
+        Ok(integer(acked as i64))
+    }
+
+    pub(super) fn xpending(&self, c: &XPending) -> Result<Resp, StorageError> {
+        let now = now_ms();
+        self.read(&c.key, |s: &Stream| {
+            let g = s.groups.get(&c.group)?;
+            let Some(range) = &c.range else {
+                let (Some((first, _)), Some((last, _))) =
+                    (g.pending.first_key_value(), g.pending.last_key_value())
+                else {
+                    return Some(array([
+                        integer(0),
+                        Resp::Null(Null),
+                        Resp::Null(Null),
+                        null_array(),
+                    ]));
+                };
+                let consumers = g
+                    .consumers
+                    .iter()
+                    .filter(|(_, consumer)| !consumer.pending.is_empty())
+                    .map(|(name, consumer)| {
+                        array([
+                            name.clone().into(),
+                            bulk_string(consumer.pending.len().to_string()),
+                        ])
+                    });
+                return Some(array([
+                    integer(g.pending.len() as i64),
+                    bulk_string(first.to_string()),
+                    bulk_string(last.to_string()),
+                    array(consumers),
+                ]));
+            };
+            if range.start > range.end {
+                return Some(array([]));
+            }
+            let pending = g
+                .pending
+                .range(range.start..=range.end)
+                .filter(|(_, p)| range.consumer.as_ref().is_none_or(|c| *c == p.consumer))
+                .filter(|(_, p)| now.saturating_sub(p.delivered_at) >= range.min_idle)
+                .take(range.count)
+                .map(|(id, p)| {
+                    array([
+                        bulk_string(id.to_string()),
+                        p.consumer.clone().into(),
+                        integer(now.saturating_sub(p.delivered_at) as i64),
+                        integer(p.deliveries as i64),
+                    ])
+                });
+            Some(array(pending))
+        })?
+        .flatten()
+        .ok_or_else(|| no_group(&c.key, &c.group))
+    }
+
+    pub(super) fn xclaim(&self, c: &XClaim) -> Result<Resp, StorageError> {
+        let now = now_ms();
+        let delivered_at = match (c.time, c.idle) {
+            (Some(time), _) => time,
+            (None, Some(idle)) => now.saturating_sub(idle),
+            (None, None) => now,
+        };
+        self.write_consumer(&c.key, &c.group, &c.consumer, |g, entries| {
+            g.consumer(&c.consumer, now);
+            let mut claimed = Vec::new();
+            for id in &c.ids {
+                let pending = g.pending.get(id).map(|p| (p.delivered_at, p.deliveries));
+                let deliveries = match pending {
+                    // Entries deleted from the stream can't be claimed any
+                    // more, so they are dropped from the PEL as well.
+                    Some(_) if !entries.contains_key(id) => {
+                        g.ack(id);
+                        continue;
+                    }
+                    Some((at, _)) if now.saturating_sub(at) < c.min_idle => continue,
+                    Some((_, deliveries)) => deliveries,
+                    None if c.force && entries.contains_key(id) => 0,
+                    None => continue,
+                };
+                let deliveries = match c.retry_count {
+                    Some(n) => n,
+                    None if c.justid => deliveries,
+                    None => deliveries + 1,
+                };
+                g.deliver(*id, &c.consumer, delivered_at, deliveries);
+                claimed.push(*id);
+            }
+            if let Some(last_id) = c.last_id {
+                g.last_delivered = g.last_delivered.max(last_id);
+            }
+            if !claimed.is_empty() {
+                g.consumer(&c.consumer, now).active_at = Some(now);
+            }
+            Ok(claimed_reply(claimed, entries, c.justid))
+        })?
+        .ok_or_else(|| no_group(&c.key, &c.group))
+    }
+
+    /// Claims idle pending entries from `start` on, replying with the ID to
+    /// continue from (0-0 once the PEL is exhausted), the claimed entries and
+    /// the IDs found deleted from the stream.
+    pub(super) fn xautoclaim(&self, c: &XAutoClaim) -> Result<Resp, StorageError> {
+        let now = now_ms();
+        self.write_consumer(&c.key, &c.group, &c.consumer, |g, entries| {
+            g.consumer(&c.consumer, now);
+            let attempts = c.count.saturating_mul(AUTOCLAIM_ATTEMPTS_FACTOR);
+            let candidates: Vec<StreamId> = g
+                .pending
+                .range(c.start..)
+                .map(|(id, _)| *id)
+                .take(attempts.saturating_add(1))
+                .collect();
+            let mut cursor = StreamId::MIN;
+            let mut claimed = Vec::new();
+            let mut deleted = Vec::new();
+            for (i, id) in candidates.iter().enumerate() {
+                if i == attempts || claimed.len() == c.count {
+                    cursor = *id;
+                    break;
+                }
+                if !entries.contains_key(id) {
+                    g.ack(id);
+                    deleted.push(bulk_string(id.to_string()));
+                    continue;
+                }
+                let p = &g.pending[id];
+                if now.saturating_sub(p.delivered_at) < c.min_idle {
+                    continue;
+                }
+                let deliveries = if c.justid {
+                    p.deliveries
+                } else {
+                    p.deliveries + 1
+                };
+                g.deliver(*id, &c.consumer, now, deliveries);
+                claimed.push(*id);
+            }
+            if !claimed.is_empty() {
+                g.consumer(&c.consumer, now).active_at = Some(now);
+            }
+            Ok(array([
+                bulk_string(cursor.to_string()),
+                claimed_reply(claimed, entries, c.justid),
+                array(deleted),
+            ]))
+        })?
+        .ok_or_else(|| no_group(&c.key, &c.group))
+    }
+
+    pub(super) fn xinfo(&self, c: &XInfo) -> Result<Resp, StorageError> {
+        let now = now_ms();
+        match c {
+            XInfo::Stream { key } => self
+                .read(key, |s: &Stream| {
+                    let first = s.entries.first_key_value();
+                    let last = s.entries.last_key_value();
+                    let entry = |e: Option<(&StreamId, &Vec<Resp>)>| {
+                        e.map_or(Resp::Null(Null), |(id, f)| entry_reply(*id, f.clone()))
+                    };
+                    info_map([
+                        ("length", integer(s.len() as i64)),
+                        ("last-generated-id", bulk_string(s.last_id().to_string())),
+                        (
+                            "max-deleted-entry-id",
+                            bulk_string(s.max_deleted_id.to_string()),
+                        ),
+                        ("entries-added", integer(s.entries_added as i64)),
+                        (
+                            "recorded-first-entry-id",
+                            bulk_string(first.map_or(StreamId::MIN, |(id, _)| *id).to_string()),
+                        ),
+                        ("groups", integer(s.groups.len() as i64)),
+                        ("first-entry", entry(first)),
+                        ("last-entry", entry(last)),
+                    ])
+                })?
+                .ok_or(StorageError::NoSuchKey),
+            XInfo::Groups { key } => self
+                .read(key, |s: &Stream| {
+                    array(s.groups.iter().map(|(name, g)| {
+                        info_map([
+                            ("name", name.clone().into()),
+                            ("consumers", integer(g.consumers.len() as i64)),
+                            ("pending", integer(g.pending.len() as i64)),
+                            (
+                                "last-delivered-id",
+                                bulk_string(g.last_delivered.to_string()),
+                            ),
+                            (
+                                "entries-read",
+                                g.entries_read
+                                    .map_or(Resp::Null(Null), |n| integer(n as i64)),
+                            ),
+                            ("lag", integer(g.lag(&s.entries) as i64)),
+                        ])
+                    }))
+                })?
+                .ok_or(StorageError::NoSuchKey),
+            XInfo::Consumers { key, group } => self
+                .read(key, |s: &Stream| {
+                    let g = s.groups.get(group)?;
+                    Some(array(g.consumers.iter().map(|(name, consumer)| {
+                        let inactive = consumer
+                            .active_at
+                            .map_or(-1, |at| now.saturating_sub(at) as i64);
+                        info_map([
+                            ("name", name.clone().into()),
+                            ("pending", integer(consumer.pending.len() as i64)),
+                            ("idle", integer(now.saturating_sub(consumer.seen_at) as i64)),
+                            ("inactive", integer(inactive)),
+                        ])
+                    })))
+                })?
+                .flatten()
+                .ok_or_else(|| no_group(key, group)),
+        }
+    }
+}
+
+/// Serves `XREADGROUP` like it ran the moment a stream became ready. New
+/// entries go to one consumer only, so the next waiter in line finds none.
+pub(super) fn xreadgroup_serve(c: XReadGroup) -> Box<Serve> {
+    Box::new(move |storage, _key| storage.xreadgroup(&c))
+}
+
+/// Claimed entries, or just their IDs for `JUSTID`.
+fn claimed_reply(claimed: Vec<StreamId>, entries: &Entries, justid: bool) -> Resp {
+    array(claimed.into_iter().map(|id| match justid {
+        true => bulk_string(id.to_string()),
+        false => entry_reply(id, entries[&id].clone()),
+    }))
+}
+
+/// A RESP3 map of named fields. RESP2 clients see it flattened into an
+/// array.
+fn info_map<const N: usize>(fields: [(&str, Resp); N]) -> Resp {
+    let map: Map = fields
+        .into_iter()
+        .map(|(name, value)| (Key::BulkString(BulkString::new(name, false)), value))
+        .collect();
+    Resp::Map(Box::new(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::CommandExecutor;
+    use anyhow::Result;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn bulk(s: &str) -> Resp {
+        Resp::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args).unwrap();
+        Ok(storage.execute(cmd)?.unwrap())
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> Resp {
+        array([bulk(id), array(fields.iter().map(|f| bulk(f)))])
+    }
+
+    fn storage_err(res: Result<Resp>) -> StorageError {
+        res.unwrap_err().downcast().unwrap()
+    }
+
+    /// A stream `s` with entries 1-0 to 3-0 and a group `g` reading from the
+    /// start.
+    fn setup() -> Storage {
+        let storage = Storage::new();
+        for id in ["1-0", "2-0", "3-0"] {
+            run(&storage, &["XADD", "s", id, "id", id]).unwrap();
+        }
+        run(&storage, &["XGROUP", "CREATE", "s", "g", "0"]).unwrap();
+        storage
+    }
+
+    fn pending(storage: &Storage, consumer: &str) -> Vec<Resp> {
+        let res = run(storage, &["XPENDING", "s", "g", "-", "+", "10", consumer]).unwrap();
+        let Resp::Array(a) = res else {
+            panic!("Expected an array");
+        };
+        a.to_vec()
+    }
+
+    #[test]
+    fn test_xgroup() {
+        let storage = setup();
+        assert_eq!(
+            storage_err(run(&storage, &["XGROUP", "CREATE", "s", "g", "$"])),
+            StorageError::BusyGroup
+        );
+        assert_eq!(
+            storage_err(run(&storage, &["XGROUP", "CREATE", "none", "g", "$"])),
+            StorageError::XGroupNoKey
+        );
+        assert_eq!(
+            run(&storage, &["XGROUP", "CREATE", "new", "g", "$", "MKSTREAM"]).unwrap(),
+            ok()
+        );
+        assert_eq!(run(&storage, &["XLEN", "new"]).unwrap(), integer(0));
+        assert_eq!(
+            run(&storage, &["XGROUP", "CREATECONSUMER", "s", "g", "c"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["XGROUP", "CREATECONSUMER", "s", "g", "c"]).unwrap(),
+            integer(0)
+        );
+        assert_eq!(
+            storage_err(run(&storage, &["XGROUP", "CREATECONSUMER", "s", "x", "c"])),
+            StorageError::NoGroup("s".to_string(), "x".to_string())
+        );
+        assert_eq!(
+            run(&storage, &["XGROUP", "DESTROY", "s", "g"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["XGROUP", "DESTROY", "s", "g"]).unwrap(),
+            integer(0)
+        );
+    }
+
+    #[test]
+    fn test_xreadgroup() {
+        let storage = setup();
+        assert_eq!(
+            run(
+                &storage,
+                &[
+                    "XREADGROUP",
+                    "GROUP",
+                    "g",
+                    "a",
+                    "COUNT",
+                    "2",
+                    "STREAMS",
+                    "s",
+                    ">"
+                ]
+            )
+            .unwrap(),
+            array([array([
+                bulk("s"),
+                array([entry("1-0", &["id", "1-0"]), entry("2-0", &["id", "2-0"])])
+            ])])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["XREADGROUP", "GROUP", "g", "b", "STREAMS", "s", ">"]
+            )
+            .unwrap(),
+            array([array([bulk("s"), array([entry("3-0", &["id", "3-0"])])])])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["XREADGROUP", "GROUP", "g", "b", "STREAMS", "s", ">"]
+            )
+            .unwrap(),
+            null_array()
+        );
+
+        // History replays only the consumer's own pending entries, deleted
+        // ones without fields.
+        run(&storage, &["XDEL", "s", "1-0"]).unwrap();
+        assert_eq!(
+            run(
+                &storage,
+                &["XREADGROUP", "GROUP", "g", "a", "STREAMS", "s", "0"]
+            )
+            .unwrap(),
+            array([array([
+                bulk("s"),
+                array([
+                    array([bulk("1-0"), null_array()]),
+                    entry("2-0", &["id", "2-0"])
+                ])
+            ])])
+        );
+        assert_eq!(
+            run(&storage, &["XACK", "s", "g", "1-0", "2-0", "9-0"]).unwrap(),
+            integer(2)
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["XREADGROUP", "GROUP", "g", "a", "STREAMS", "s", "0"]
+            )
+            .unwrap(),
+            array([array([bulk("s"), array([])])])
+        );
+        assert_eq!(
+            run(&storage, &["XACK", "s", "x", "3-0"]).unwrap(),
+            integer(0)
+        );
+
+        // NOACK delivers without tracking.
+        run(&storage, &["XADD", "s", "4-0", "id", "4-0"]).unwrap();
+        run(
+            &storage,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "NOACK",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        )
+        .unwrap();
+        assert!(pending(&storage, "a").is_empty());
+
+        assert_eq!(
+            storage_err(run(
+                &storage,
+                &["XREADGROUP", "GROUP", "x", "a", "STREAMS", "s", ">"]
+            )),
+            StorageError::NoGroup("s".to_string(), "x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_xpending() {
+        let storage = setup();
+        assert_eq!(
+            run(&storage, &["XPENDING", "s", "g"]).unwrap(),
+            array([integer(0), Resp::Null(Null), Resp::Null(Null), null_array()])
+        );
+        run(
+            &storage,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "COUNT",
+                "2",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        )
+        .unwrap();
+        run(
+            &storage,
+            &["XREADGROUP", "GROUP", "g", "b", "STREAMS", "s", ">"],
+        )
+        .unwrap();
+        assert_eq!(
+            run(&storage, &["XPENDING", "s", "g"]).unwrap(),
+            array([
+                integer(3),
+                bulk("1-0"),
+                bulk("3-0"),
+                array([array([bulk("a"), bulk("2")]), array([bulk("b"), bulk("1")])])
+            ])
+        );
+        let b = pending(&storage, "b");
+        assert_eq!(b.len(), 1);
+        let Resp::Array(entry) = &b[0] else {
+            panic!("Expected an array");
+        };
+        assert_eq!(entry[0], bulk("3-0"));
+        assert_eq!(entry[1], bulk("b"));
+        assert_eq!(entry[3], integer(1));
+        assert_eq!(
+            run(
+                &storage,
+                &["XPENDING", "s", "g", "IDLE", "60000", "-", "+", "10"]
+            )
+            .unwrap(),
+            array([])
+        );
+        assert_eq!(
+            storage_err(run(&storage, &["XPENDING", "s", "x"])),
+            StorageError::NoGroup("s".to_string(), "x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_xclaim() {
+        let storage = setup();
+        run(
+            &storage,
+            &["XREADGROUP", "GROUP", "g", "a", "STREAMS", "s", ">"],
+        )
+        .unwrap();
+        // Not idle long enough.
+        assert_eq!(
+            run(&storage, &["XCLAIM", "s", "g", "b", "60000", "1-0"]).unwrap(),
+            array([])
+        );
+        assert_eq!(
+            run(&storage, &["XCLAIM", "s", "g", "b", "0", "1-0", "2-0"]).unwrap(),
+            array([entry("1-0", &["id", "1-0"]), entry("2-0", &["id", "2-0"])])
+        );
+        assert_eq!(pending(&storage, "a").len(), 1);
+        let b = pending(&storage, "b");
+        let Resp::Array(entry) = &b[0] else {
+            panic!("Expected an array");
+        };
+        assert_eq!(entry[3], integer(2));
+
+        // Deleted entries leave the PEL instead of being claimed.
+        run(&storage, &["XDEL", "s", "3-0"]).unwrap();
+        assert_eq!(
+            run(&storage, &["XCLAIM", "s", "g", "b", "0", "3-0", "JUSTID"]).unwrap(),
+            array([])
+        );
+        assert!(pending(&storage, "a").is_empty());
+
+        // FORCE claims entries nobody has been delivered.
+        run(&storage, &["XADD", "s", "4-0", "id", "4-0"]).unwrap();
+        assert_eq!(
+            run(
+                &storage,
+                &["XCLAIM", "s", "g", "c", "0", "4-0", "FORCE", "JUSTID", "LASTID", "4-0"]
+            )
+            .unwrap(),
+            array([bulk("4-0")])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["XREADGROUP", "GROUP", "g", "a", "STREAMS", "s", ">"]
+            )
+            .unwrap(),
+            null_array()
+        );
+    }
+
+    #[test]
+    fn test_xautoclaim() {
+        let storage = setup();
+        run(
+            &storage,
+            &["XREADGROUP", "GROUP", "g", "a", "STREAMS", "s", ">"],
+        )
+        .unwrap();
+        run(&storage, &["XDEL", "s", "2-0"]).unwrap();
+        assert_eq!(
+            run(
+                &storage,
+                &["XAUTOCLAIM", "s", "g", "b", "0", "-", "COUNT", "1"]
+            )
+            .unwrap(),
+            array([
+                bulk("2-0"),
+                array([entry("1-0", &["id", "1-0"])]),
+                array([])
+            ])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["XAUTOCLAIM", "s", "g", "b", "0", "2-0", "JUSTID"]
+            )
+            .unwrap(),
+            array([bulk("0-0"), array([bulk("3-0")]), array([bulk("2-0")])])
+        );
+        assert!(pending(&storage, "a").is_empty());
+        assert_eq!(pending(&storage, "b").len(), 2);
+    }
+
+    #[test]
+    fn test_xinfo() {
+        let storage = setup();
+        run(
+            &storage,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "COUNT",
+                "1",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        )
+        .unwrap();
+        run(&storage, &["XDEL", "s", "3-0"]).unwrap();
+
+        let Resp::Map(info) = run(&storage, &["XINFO", "STREAM", "s"]).unwrap() else {
+            panic!("Expected a map");
+        };
+        assert_eq!(info[&key("length")], integer(2));
+        assert_eq!(info[&key("entries-added")], integer(3));
+        assert_eq!(info[&key("max-deleted-entry-id")], bulk("3-0"));
+        assert_eq!(info[&key("last-generated-id")], bulk("3-0"));
+        assert_eq!(info[&key("first-entry")], entry("1-0", &["id", "1-0"]));
+        assert_eq!(info[&key("groups")], integer(1));
+
+        let Resp::Array(groups) = run(&storage, &["XINFO", "GROUPS", "s"]).unwrap() else {
+            panic!("Expected an array");
+        };
+        let Resp::Map(group) = &groups[0] else {
+            panic!("Expected a map");
+        };
+        assert_eq!(group[&key("name")], bulk("g"));
+        assert_eq!(group[&key("pending")], integer(1));
+        assert_eq!(group[&key("last-delivered-id")], bulk("1-0"));
+        assert_eq!(group[&key("entries-read")], integer(1));
+        assert_eq!(group[&key("lag")], integer(1));
+
+        let Resp::Array(consumers) = run(&storage, &["XINFO", "CONSUMERS", "s", "g"]).unwrap()
+        else {
+            panic!("Expected an array");
+        };
+        let Resp::Map(consumer) = &consumers[0] else {
+            panic!("Expected a map");
+        };
+        assert_eq!(consumer[&key("name")], bulk("a"));
+        assert_eq!(consumer[&key("pending")], integer(1));
+
+        assert_eq!(
+            storage_err(run(&storage, &["XINFO", "STREAM", "none"])),
+            StorageError::NoSuchKey
+        );
+    }
+}
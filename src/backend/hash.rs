@@ -0,0 +1,387 @@
+use super::{
+    array, bulk_string, integer, notify::Class, parse_float, parse_integer, scan::ScanOrder,
+    scan_reply, Container, Storage, StorageError, Value,
+};
+use crate::{
+    cmd::{Command, HRandField, HScan},
+    glob,
+    resp::{Key, Map, Null, Resp},
+};
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use std::collections::{hash_map, HashMap};
+
+/// A hash's fields, plus their order for `HSCAN`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Hash {
+    fields: HashMap<Key, Resp>,
+    order: ScanOrder<Key>,
+}
+
+impl Hash {
+    fn get(&self, field: &Key) -> Option<&Resp> {
+        self.fields.get(field)
+    }
+
+    fn contains_key(&self, field: &Key) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    fn iter(&self) -> hash_map::Iter<'_, Key, Resp> {
+        self.fields.iter()
+    }
+
+    fn insert(&mut self, field: Key, value: Resp) -> Option<Resp> {
+        let old = self.fields.insert(field.clone(), value);
+        if old.is_none() {
+            self.order.insert(field);
+        }
+        old
+    }
+
+    fn remove(&mut self, field: &Key) -> Option<Resp> {
+        let old = self.fields.remove(field)?;
+        self.order.remove(field);
+        Some(old)
+    }
+}
+
+impl Container for Hash {
+    fn downcast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Hash(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    fn downcast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Hash(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Hash(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl Storage {
+    pub(super) fn execute_hash(&self, cmd: Command) -> Result<Option<Resp>> {
+        let res = match cmd {
+            Command::HSet(c) => integer(self.hset(&c.key, c.pairs)?),
+            Command::HGet(c) => self
+                .read(&c.key, |h: &Hash| h.get(&c.field).cloned())?
+                .flatten()
+                .unwrap_or(Resp::Null(Null)),
+            Command::HMGet(c) => {
+                let values = self
+                    .read(&c.key, |h: &Hash| {
+                        c.fields.iter().map(|f| h.get(f).cloned()).collect()
+                    })?
+                    .unwrap_or_else(|| vec![None; c.fields.len()]);
+                array(values.into_iter().map(|v| v.unwrap_or(Resp::Null(Null))))
+            }
+            Command::HGetAll(c) => {
+                let map: Map = self
+                    .read(&c.key, |h: &Hash| {
+                        h.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+                    })?
+                    .unwrap_or_default();
+                Resp::Map(Box::new(map))
+            }
+            Command::HDel(c) => {
+                let deleted = self
+                    .write(&c.key, false, |h: &mut Hash| {
+                        Ok(c.fields.iter().filter(|f| h.remove(f).is_some()).count() as i64)
+                    })?
+                    .unwrap_or(0);
+                if deleted > 0 {
+                    self.notify_write(Class::HASH, "hdel", &c.key);
+                }
+                integer(deleted)
+            }
+            Command::HIncrBy(c) => integer(self.hincrby(&c.key, c.field, c.increment)?),
+            Command::HIncrByFloat(c) => {
+                bulk_string(self.hincrbyfloat(&c.key, c.field, c.increment)?.to_string())
+            }
+            Command::HExists(c) => integer(
+                self.read(&c.key, |h: &Hash| h.contains_key(&c.field))?
+                    .unwrap_or(false) as i64,
+            ),
+            Command::HLen(c) => integer(self.read(&c.key, |h: &Hash| h.len())?.unwrap_or(0) as i64),
+            Command::HKeys(c) => array(
+                self.read(&c.key, |h: &Hash| {
+                    h.fields.keys().cloned().map(Resp::from).collect()
+                })?
+                .unwrap_or_else(Vec::new),
+            ),
+            Command::HVals(c) => array(
+                self.read(&c.key, |h: &Hash| h.fields.values().cloned().collect())?
+                    .unwrap_or_else(Vec::new),
+            ),
+            Command::HRandField(c) => self.hrandfield(&c)?,
+            Command::HScan(c) => self.hscan(&c)?,
+            _ => return Ok(None),
+        };
+        Ok(Some(res))
+    }
+
+    fn hset(&self, key: &Key, pairs: Vec<(Key, Resp)>) -> Result<i64, StorageError> {
+        let added = self.write(key, true, |h: &mut Hash| {
+            Ok(pairs
+                .into_iter()
+                .filter(|(f, v)| h.insert(f.clone(), v.clone()).is_none())
+                .count() as i64)
+        })?;
+        self.notify(Class::HASH, "hset", key);
+        Ok(added.unwrap_or(0))
+    }
+
+    fn hincrby(&self, key: &Key, field: Key, increment: i64) -> Result<i64, StorageError> {
+        let res = self.write(key, true, |h: &mut Hash| {
+            let current = match h.get(&field) {
+                Some(v) => parse_integer(v).ok_or(StorageError::NotAnInteger("hash value"))?,
+                None => 0,
+            };
+            let new = current
+                .checked_add(increment)
+                .ok_or(StorageError::Overflow)?;
+            h.insert(field, bulk_string(new.to_string()));
+            Ok(new)
+        })?;
+        self.notify(Class::HASH, "hincrby", key);
+        Ok(res.unwrap_or_default())
+    }
+
+    fn hincrbyfloat(&self, key: &Key, field: Key, increment: f64) -> Result<f64, StorageError> {
+        let res = self.write(key, true, |h: &mut Hash| {
+            let current = match h.get(&field) {
+                Some(v) => parse_float(v).ok_or(StorageError::NotAFloat("hash value"))?,
+                None => 0.0,
+            };
+            let new = current + increment;
+            if !new.is_finite() {
+                return Err(StorageError::NanOrInfinity);
+            }
+            h.insert(field, bulk_string(new.to_string()));
+            Ok(new)
+        })?;
+        self.notify(Class::HASH, "hincrbyfloat", key);
+        Ok(res.unwrap_or_default())
+    }
+
+    /// With a positive count the fields are distinct, with a negative one the
+    /// same field may be returned several times.
+    fn hrandfield(&self, c: &HRandField) -> Result<Resp, StorageError> {
+        let pairs = self.read(&c.key, |h: &Hash| {
+            let mut rng = rand::thread_rng();
+            let pairs: Vec<_> = h.iter().collect();
+            let picked: Vec<_> = match c.count {
+                None => pairs.choose(&mut rng).into_iter().collect(),
+                Some(n) if n >= 0 => pairs.choose_multiple(&mut rng, n as usize).collect(),
+                Some(n) => (0..n.unsigned_abs())
+                    .filter_map(|_| pairs.choose(&mut rng))
+                    .collect(),
+            };
+            picked
+                .into_iter()
+                .map(|(f, v)| ((*f).clone(), (*v).clone()))
+                .collect::<Vec<_>>()
+        })?;
+        let pairs = pairs.unwrap_or_default();
+        if c.count.is_none() {
+            return Ok(match pairs.into_iter().next() {
+                Some((f, _)) => f.into(),
+                None => Resp::Null(Null),
+            });
+        }
+        Ok(array(pairs.into_iter().flat_map(|(f, v)| {
+            let value = c.with_values.then_some(v);
+            std::iter::once(Resp::from(f)).chain(value)
+        })))
+    }
+
+    fn hscan(&self, c: &HScan) -> Result<Resp, StorageError> {
+        let (cursor, pairs) = self
+            .read(&c.key, |h: &Hash| {
+                let (cursor, fields) = h.order.scan(c.cursor, c.options.count);
+                let pairs: Vec<_> = fields
+                    .into_iter()
+                    .map(|f| (f.clone(), h.fields[f].clone()))
+                    .collect();
+                (cursor, pairs)
+            })?
+            .unwrap_or_default();
+        let items = pairs
+            .into_iter()
+            .filter(|(f, _)| match &c.options.pattern {
+                Some(p) => glob::matches(p.as_bytes(), f.to_string().as_bytes(), false),
+                None => true,
+            })
+            .flat_map(|(f, v)| {
+                let value = (!c.no_values).then_some(v);
+                std::iter::once(Resp::from(f)).chain(value)
+            });
+        Ok(scan_reply(cursor, items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args)?;
+        Ok(storage.execute_hash(cmd)?.unwrap())
+    }
+
+    #[test]
+    fn test_hset_hget_hdel() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["HSET", "h", "a", "1", "b", "2"]).unwrap(),
+            integer(2)
+        );
+        assert_eq!(
+            run(&storage, &["HSET", "h", "a", "3", "c", "4"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["HGET", "h", "a"]).unwrap(),
+            bulk_string("3")
+        );
+        assert_eq!(
+            run(&storage, &["HGET", "h", "x"]).unwrap(),
+            Resp::Null(Null)
+        );
+        assert_eq!(run(&storage, &["HLEN", "h"]).unwrap(), integer(3));
+        assert_eq!(
+            run(&storage, &["HMGET", "h", "a", "x"]).unwrap(),
+            array([bulk_string("3"), Resp::Null(Null)])
+        );
+        assert_eq!(
+            run(&storage, &["HDEL", "h", "a", "b", "c", "x"]).unwrap(),
+            integer(3)
+        );
+        assert_eq!(storage.key_type(&key("h")), "none");
+    }
+
+    #[test]
+    fn test_hgetall() {
+        let storage = Storage::new();
+        run(&storage, &["HSET", "h", "a", "1", "b", "2"]).unwrap();
+        let mut map = Map::default();
+        map.insert(key("a"), bulk_string("1"));
+        map.insert(key("b"), bulk_string("2"));
+        assert_eq!(
+            run(&storage, &["HGETALL", "h"]).unwrap(),
+            Resp::Map(Box::new(map))
+        );
+        assert_eq!(
+            run(&storage, &["HGETALL", "none"]).unwrap(),
+            Resp::Map(Box::default())
+        );
+    }
+
+    #[test]
+    fn test_hincrby() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["HINCRBY", "h", "n", "5"]).unwrap(),
+            integer(5)
+        );
+        assert_eq!(
+            run(&storage, &["HINCRBY", "h", "n", "-7"]).unwrap(),
+            integer(-2)
+        );
+        assert_eq!(
+            run(&storage, &["HINCRBYFLOAT", "h", "n", "0.5"]).unwrap(),
+            bulk_string("-1.5")
+        );
+        run(
+            &storage,
+            &["HSET", "h", "s", "abc", "max", &i64::MAX.to_string()],
+        )
+        .unwrap();
+        let err = run(&storage, &["HINCRBY", "h", "s", "1"]).unwrap_err();
+        assert_eq!(err.to_string(), "ERR hash value is not an integer");
+        let err = run(&storage, &["HINCRBY", "h", "max", "1"]).unwrap_err();
+        assert_eq!(err.to_string(), "ERR increment or decrement would overflow");
+        let err = run(&storage, &["HINCRBYFLOAT", "h", "s", "1"]).unwrap_err();
+        assert_eq!(err.to_string(), "ERR hash value is not a float");
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let storage = Storage::new();
+        storage.set(key("s"), bulk_string("v"));
+        let err = run(&storage, &["HSET", "s", "a", "1"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::WrongType
+        );
+        run(&storage, &["HSET", "h", "a", "1"]).unwrap();
+        assert_eq!(storage.get(&key("h")), Err(StorageError::WrongType));
+        assert_eq!(storage.key_type(&key("h")), "hash");
+    }
+
+    #[test]
+    fn test_hrandfield() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["HRANDFIELD", "h"]).unwrap(),
+            Resp::Null(Null)
+        );
+        assert_eq!(run(&storage, &["HRANDFIELD", "h", "3"]).unwrap(), array([]));
+        run(&storage, &["HSET", "h", "a", "1", "b", "2"]).unwrap();
+        let Resp::Array(a) = run(&storage, &["HRANDFIELD", "h", "5"]).unwrap() else {
+            panic!("Expected Array");
+        };
+        assert_eq!(a.len(), 2);
+        let Resp::Array(a) = run(&storage, &["HRANDFIELD", "h", "-5", "WITHVALUES"]).unwrap()
+        else {
+            panic!("Expected Array");
+        };
+        assert_eq!(a.len(), 10);
+    }
+
+    #[test]
+    fn test_hscan() {
+        let storage = Storage::new();
+        for i in 0..30 {
+            run(&storage, &["HSET", "h", &format!("f{}", i), "v"]).unwrap();
+        }
+        let mut cursor = "0".to_string();
+        let mut fields = 0;
+        loop {
+            let Resp::Array(reply) =
+                run(&storage, &["HSCAN", "h", &cursor, "COUNT", "4", "NOVALUES"]).unwrap()
+            else {
+                panic!("Expected Array");
+            };
+            let (Resp::BulkString(next), Resp::Array(items)) = (&reply[0], &reply[1]) else {
+                panic!("Expected cursor and items");
+            };
+            fields += items.len();
+            if next.value == b"0" {
+                break;
+            }
+            cursor = next.as_str().unwrap().to_string();
+        }
+        assert_eq!(fields, 30);
+    }
+}
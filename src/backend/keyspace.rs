@@ -0,0 +1,432 @@
+use super::{notify::Class, Storage, StorageError, Value};
+use crate::{
+    cmd::ScanOptions,
+    glob,
+    resp::{Key, Null, Resp},
+};
+use dashmap::mapref::entry::Entry;
+use rand::Rng;
+use std::sync::atomic::Ordering;
+use tokio::runtime::Handle;
+
+/// Values made of more elements than this are dropped on a background task
+/// by `UNLINK` instead of on the connection task.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+impl Storage {
+    pub(super) fn del(&self, keys: &[Key]) -> i64 {
+        let mut deleted = 0;
+        for key in keys {
+            if self.keyspace().remove(key).is_some() {
+                self.notify(Class::GENERIC, "del", key);
+                deleted += 1;
+            }
+        }
+        deleted
+    }
+
+    pub(super) fn unlink(&self, keys: &[Key]) -> i64 {
+        let mut deleted = 0;
+        let mut garbage = Vec::new();
+        for key in keys {
+            if let Some((_, value)) = self.keyspace().remove(key) {
+                self.notify(Class::GENERIC, "del", key);
+                deleted += 1;
+                if free_effort(&value) > LAZYFREE_THRESHOLD {
+                    garbage.push(value);
+                }
+            }
+        }
+        free_in_background(garbage);
+        deleted
+    }
+
+    pub(super) fn exists(&self, keys: &[Key]) -> i64 {
+        keys.iter()
+            .filter(|key| self.keyspace().contains_key(*key))
+            .count() as i64
+    }
+
+    pub(super) fn key_type(&self, key: &Key) -> &'static str {
+        match self.keyspace().get(key) {
+            Some(value) => value.type_name(),
+            None => "none",
+        }
+    }
+
+    /// Moves the value at `key` to `new_key`. This is a remove and an
+    /// insert, so `RENAME` runs exclusively for no one to see neither key.
+    pub(super) fn rename(&self, key: &Key, new_key: Key) -> Result<(), StorageError> {
+        if *key == new_key {
+            return match self.keyspace().contains_key(key) {
+                true => Ok(()),
+                false => Err(StorageError::NoSuchKey),
+            };
+        }
+        let (_, value) = self.keyspace().remove(key).ok_or(StorageError::NoSuchKey)?;
+        self.insert(new_key.clone(), value);
+        self.notify(Class::GENERIC, "rename_from", key);
+        self.notify(Class::GENERIC, "rename_to", &new_key);
+        Ok(())
+    }
+
+    /// Like `rename`, unless `new_key` exists. `RENAMENX` runs exclusively
+    /// too, so that no key appears between the check and the rename.
+    pub(super) fn rename_nx(&self, key: &Key, new_key: Key) -> Result<bool, StorageError> {
+        if !self.keyspace().contains_key(key) {
+            return Err(StorageError::NoSuchKey);
+        }
+        if self.keyspace().contains_key(&new_key) {
+            return Ok(false);
+        }
+        self.rename(key, new_key)?;
+        Ok(true)
+    }
+
+    /// Copies `source` to `destination` in `target`, which may be this
+    /// database or another one.
+    pub(super) fn copy(
+        &self,
+        source: &Key,
+        destination: Key,
+        target: &Storage,
+        replace: bool,
+    ) -> Result<bool, StorageError> {
+        if target.db == self.db && *source == destination {
+            return Err(StorageError::SameObject);
+        }
+        let Some(value) = self.keyspace().get(source).map(|v| v.clone()) else {
+            return Ok(false);
+        };
+        match target.keyspace().entry(destination.clone()) {
+            Entry::Occupied(mut e) => {
+                if !replace {
+                    return Ok(false);
+                }
+                e.insert(value);
+            }
+            Entry::Vacant(e) => {
+                e.insert(value);
+                target.notify(Class::NEW, "new", &destination);
+            }
+        }
+        target.notify(Class::GENERIC, "copy_to", &destination);
+        Ok(true)
+    }
+
+    /// Moves `key` to `target` unless `target` already has it.
+    pub(super) fn move_key(&self, key: &Key, target: &Storage) -> Result<bool, StorageError> {
+        if target.db == self.db {
+            return Err(StorageError::SameObject);
+        }
+        if target.keyspace().contains_key(key) {
+            return Ok(false);
+        }
+        let Some((key, value)) = self.keyspace().remove(key) else {
+            return Ok(false);
+        };
+        self.notify(Class::GENERIC, "move_from", &key);
+        target.insert(key.clone(), value);
+        target.notify(Class::GENERIC, "move_to", &key);
+        Ok(true)
+    }
+
+    /// Swaps the contents of two databases. Clients connected to either one
+    /// see the other's keys from then on.
+    pub(super) fn swap_db(&self, first: &Storage, second: &Storage) {
+        if first.db == second.db {
+            return;
+        }
+        self.touch_databases(&[first.db, second.db]);
+        let a = self.dbs[first.db].load(Ordering::Relaxed);
+        let b = self.dbs[second.db].swap(a, Ordering::Relaxed);
+        self.dbs[first.db].store(b, Ordering::Relaxed);
+        first.signal_all();
+        second.signal_all();
+    }
+
+    /// Removes every key of this database. With `lazy`, the values are
+    /// dropped on a background task.
+    pub(super) fn flush(&self, lazy: bool) {
+        self.touch_databases(&[self.db]);
+        self.key_order().clear();
+        if !lazy {
+            self.keyspace().clear();
+            return;
+        }
+        let mut garbage = Vec::new();
+        self.keyspace().retain(|_, value| {
+            garbage.push(std::mem::replace(value, Value::String(Resp::Null(Null))));
+            false
+        });
+        free_in_background(garbage);
+    }
+
+    /// Picks a key uniformly at random. DashMap has no random access, so this
+    /// walks the map up to the chosen position.
+    pub(super) fn random_key(&self) -> Option<Key> {
+        let len = self.keyspace().len();
+        if len == 0 {
+            return None;
+        }
+        let n = rand::thread_rng().gen_range(0..len);
+        self.keyspace().iter().nth(n).map(|e| e.key().clone())
+    }
+
+    pub(super) fn keys(&self, pattern: &str) -> Vec<Key> {
+        self.keyspace()
+            .iter()
+            .map(|e| e.key().clone())
+            .filter(|key| glob::matches(pattern.as_bytes(), key.to_string().as_bytes(), false))
+            .collect()
+    }
+
+    /// One `SCAN` step. `MATCH` and `TYPE` filter the batch after it has been
+    /// picked, so a step may return fewer keys than `COUNT`, or none at all.
+    pub(super) fn scan_keys(&self, cursor: u64, options: &ScanOptions) -> (u64, Vec<Key>) {
+        let (cursor, keys) = self.key_order().scan(cursor, options.count, |key| {
+            self.keyspace().contains_key(key)
+        });
+        let keys = keys
+            .into_iter()
+            .filter(|key| match &options.pattern {
+                Some(p) => glob::matches(p.as_bytes(), key.to_string().as_bytes(), false),
+                None => true,
+            })
+            .filter(|key| match &options.type_filter {
+                Some(t) => self.key_type(key).eq_ignore_ascii_case(t),
+                None => true,
+            })
+            .collect();
+        (cursor, keys)
+    }
+}
+
+/// Drops `garbage` on a blocking task, off the connection task, when
+/// running inside a runtime.
+fn free_in_background(garbage: Vec<Value>) {
+    if garbage.is_empty() {
+        return;
+    }
+    match Handle::try_current() {
+        Ok(handle) => drop(handle.spawn_blocking(move || drop(garbage))),
+        Err(_) => drop(garbage),
+    }
+}
+
+/// Roughly how much work freeing `value` takes, in number of elements.
+fn free_effort(value: &Value) -> usize {
+    match value {
+        Value::String(Resp::Array(a)) => a.len(),
+        Value::String(Resp::Map(m)) => m.len(),
+        Value::String(Resp::Set(s)) => s.len(),
+        Value::String(_) => 1,
+        Value::Hash(h) => h.len(),
+        Value::List(l) => l.len(),
+        Value::Set(s) => s.len(),
+        Value::ZSet(z) => z.len(),
+        Value::Stream(s) => s.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::{Array, BulkString, Integer};
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn value(n: i64) -> Resp {
+        Resp::Integer(Integer::new(n))
+    }
+
+    #[test]
+    fn test_del_and_exists() {
+        let storage = Storage::new();
+        storage.set(key("a"), value(1));
+        storage.set(key("b"), value(2));
+        assert_eq!(storage.exists(&[key("a"), key("a"), key("c")]), 2);
+        assert_eq!(storage.del(&[key("a"), key("c")]), 1);
+        assert_eq!(storage.exists(&[key("a"), key("b")]), 1);
+        assert_eq!(storage.key_type(&key("a")), "none");
+        assert_eq!(storage.key_type(&key("b")), "string");
+    }
+
+    #[tokio::test]
+    async fn test_unlink_large_value() {
+        let storage = Storage::new();
+        let big = Array::new((0..1000).map(value).collect(), false);
+        storage.set(key("big"), Resp::Array(big));
+        storage.set(key("small"), value(1));
+        assert_eq!(storage.unlink(&[key("big"), key("small"), key("none")]), 2);
+        assert_eq!(storage.keyspace().len(), 0);
+    }
+
+    #[test]
+    fn test_rename() {
+        let storage = Storage::new();
+        storage.set(key("a"), value(1));
+        storage.set(key("b"), value(2));
+        assert_eq!(
+            storage.rename(&key("c"), key("d")),
+            Err(StorageError::NoSuchKey)
+        );
+        assert_eq!(storage.rename_nx(&key("a"), key("b")), Ok(false));
+        assert_eq!(storage.rename(&key("a"), key("b")), Ok(()));
+        assert_eq!(storage.get(&key("b")), Ok(Some(value(1))));
+        assert_eq!(storage.rename_nx(&key("b"), key("c")), Ok(true));
+        assert_eq!(storage.exists(&[key("a"), key("b"), key("c")]), 1);
+    }
+
+    #[test]
+    fn test_rename_is_atomic() {
+        use crate::cmd::{parse_command, CommandExecutor};
+        let storage = Storage::new();
+        storage.set(key("a"), value(1));
+        let renamer = {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for _ in 0..2000 {
+                    for args in [["RENAMENX", "a", "b"], ["RENAME", "b", "a"]] {
+                        let cmd = parse_command(&args).unwrap();
+                        cmd.execute(&storage as &dyn CommandExecutor).unwrap();
+                    }
+                }
+            })
+        };
+        while !renamer.is_finished() {
+            let cmd = parse_command(&["EXISTS", "a", "b"]).unwrap();
+            let exists = cmd.execute(&storage as &dyn CommandExecutor).unwrap();
+            assert_eq!(exists, value(1), "the key is always under one name");
+        }
+        renamer.join().unwrap();
+    }
+
+    #[test]
+    fn test_copy() {
+        let storage = Storage::new();
+        storage.set(key("a"), value(1));
+        storage.set(key("b"), value(2));
+        assert_eq!(
+            storage.copy(&key("none"), key("c"), &storage, false),
+            Ok(false)
+        );
+        assert_eq!(
+            storage.copy(&key("a"), key("b"), &storage, false),
+            Ok(false)
+        );
+        assert_eq!(storage.copy(&key("a"), key("b"), &storage, true), Ok(true));
+        assert_eq!(storage.copy(&key("a"), key("c"), &storage, false), Ok(true));
+        assert_eq!(storage.get(&key("b")), Ok(Some(value(1))));
+        assert_eq!(storage.get(&key("c")), Ok(Some(value(1))));
+        assert_eq!(
+            storage.copy(&key("a"), key("a"), &storage, true),
+            Err(StorageError::SameObject)
+        );
+        let other = storage.select(1).unwrap();
+        assert_eq!(storage.copy(&key("a"), key("a"), &other, false), Ok(true));
+        assert_eq!(other.get(&key("a")), Ok(Some(value(1))));
+    }
+
+    #[test]
+    fn test_move_and_swap() {
+        let storage = Storage::new();
+        let other = storage.select(1).unwrap();
+        storage.set(key("a"), value(1));
+        storage.set(key("b"), value(2));
+        other.set(key("b"), value(3));
+        assert_eq!(
+            storage.move_key(&key("a"), &storage),
+            Err(StorageError::SameObject)
+        );
+        assert_eq!(storage.move_key(&key("b"), &other), Ok(false));
+        assert_eq!(storage.move_key(&key("none"), &other), Ok(false));
+        assert_eq!(storage.move_key(&key("a"), &other), Ok(true));
+        assert_eq!(storage.exists(&[key("a"), key("b")]), 1);
+        assert_eq!(other.get(&key("a")), Ok(Some(value(1))));
+
+        storage.swap_db(&storage, &other);
+        assert_eq!(storage.get(&key("b")), Ok(Some(value(3))));
+        assert_eq!(other.get(&key("b")), Ok(Some(value(2))));
+        assert_eq!(storage.keyspace().len(), 2);
+        assert_eq!(other.keyspace().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let storage = Storage::new();
+        let other = storage.select(1).unwrap();
+        storage.set(key("a"), value(1));
+        other.set(key("a"), value(1));
+        storage.flush(true);
+        assert_eq!(storage.keyspace().len(), 0);
+        assert_eq!(other.keyspace().len(), 1);
+        other.flush(false);
+        assert_eq!(other.keyspace().len(), 0);
+    }
+
+    #[test]
+    fn test_keys() {
+        let storage = Storage::new();
+        for k in ["user:1", "user:2", "order:1"] {
+            storage.set(key(k), value(1));
+        }
+        let mut keys = storage.keys("user:*");
+        keys.sort();
+        assert_eq!(keys, vec![key("user:1"), key("user:2")]);
+        assert_eq!(storage.keys("*").len(), 3);
+        assert!(storage.keys("x*").is_empty());
+    }
+
+    #[test]
+    fn test_scan_keys() {
+        let storage = Storage::new();
+        for i in 0..100 {
+            storage.set(key(&format!("key:{}", i)), value(i));
+        }
+        let options = ScanOptions {
+            pattern: Some("key:1*".to_string()),
+            ..Default::default()
+        };
+        let mut cursor = 0;
+        let mut found = Vec::new();
+        loop {
+            let (next, keys) = storage.scan_keys(cursor, &options);
+            found.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            // Keys added and removed mid-scan don't disturb the others.
+            storage.set(key(&format!("new:{}", cursor)), value(0));
+            storage.del(&[key("key:99")]);
+        }
+        found.sort();
+        found.dedup();
+        assert_eq!(found.len(), 11);
+
+        // A key deleted and created again is still scanned.
+        storage.del(&[key("key:1")]);
+        storage.set(key("key:1"), value(1));
+        let options = ScanOptions {
+            count: 1000,
+            ..Default::default()
+        };
+        let (next, keys) = storage.scan_keys(0, &options);
+        assert_eq!(next, 0);
+        assert_eq!(keys.len(), storage.keyspace().len());
+        assert!(keys.contains(&key("key:1")) && !keys.contains(&key("key:99")));
+        storage.flush(false);
+        assert_eq!(storage.scan_keys(0, &options), (0, Vec::new()));
+    }
+
+    #[test]
+    fn test_random_key() {
+        let storage = Storage::new();
+        assert_eq!(storage.random_key(), None);
+        storage.set(key("a"), value(1));
+        assert_eq!(storage.random_key(), Some(key("a")));
+    }
+}
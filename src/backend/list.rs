@@ -0,0 +1,672 @@
+use super::{
+    array, blocking::Serve, integer, notify::Class, ok, quicklist::QuickList, Container, Storage,
+    StorageError, Value,
+};
+use crate::{
+    cmd::{Command, End, LMove, LPos},
+    resp::{Array, Key, Null, Resp},
+};
+use anyhow::Result;
+use dashmap::SharedValue;
+
+impl Container for QuickList {
+    fn downcast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    fn downcast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::List(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        QuickList::is_empty(self)
+    }
+}
+
+impl Storage {
+    pub(super) fn execute_list(&self, cmd: Command) -> Result<Option<Resp>> {
+        let res = match cmd {
+            Command::LPush(c) => {
+                let len = self.push(&c.key, c.elements, End::Left)?;
+                self.signal_ready(&c.key);
+                integer(len as i64)
+            }
+            Command::RPush(c) => {
+                let len = self.push(&c.key, c.elements, End::Right)?;
+                self.signal_ready(&c.key);
+                integer(len as i64)
+            }
+            Command::LPop(c) => self.pop_reply(&c.key, End::Left, c.count)?,
+            Command::RPop(c) => self.pop_reply(&c.key, End::Right, c.count)?,
+            Command::LRange(c) => array(
+                self.read(&c.key, |l: &QuickList| {
+                    match range(c.start, c.stop, l.len()) {
+                        Some((start, stop)) => l
+                            .iter()
+                            .skip(start)
+                            .take(stop - start + 1)
+                            .cloned()
+                            .collect(),
+                        None => Vec::new(),
+                    }
+                })?
+                .unwrap_or_default(),
+            ),
+            Command::LIndex(c) => self
+                .read(&c.key, |l: &QuickList| {
+                    index(c.index, l.len()).and_then(|i| l.get(i).cloned())
+                })?
+                .flatten()
+                .unwrap_or(Resp::Null(Null)),
+            Command::LSet(c) => {
+                self.write(&c.key, false, |l: &mut QuickList| {
+                    let i = index(c.index, l.len()).ok_or(StorageError::IndexOutOfRange)?;
+                    *l.get_mut(i).ok_or(StorageError::IndexOutOfRange)? = c.element;
+                    Ok(())
+                })?
+                .ok_or(StorageError::NoSuchKey)?;
+                self.notify(Class::LIST, "lset", &c.key);
+                ok()
+            }
+            Command::LInsert(c) => {
+                let len = self
+                    .write(&c.key, false, |l: &mut QuickList| {
+                        let Some(pos) = l.iter().position(|v| *v == c.pivot) else {
+                            return Ok(-1);
+                        };
+                        l.insert(if c.before { pos } else { pos + 1 }, c.element);
+                        Ok(l.len() as i64)
+                    })?
+                    .unwrap_or(0);
+                if len > 0 {
+                    self.notify(Class::LIST, "linsert", &c.key);
+                }
+                integer(len)
+            }
+            Command::LRem(c) => {
+                let removed = self
+                    .write(&c.key, false, |l: &mut QuickList| {
+                        Ok(lrem(l, c.count, &c.element))
+                    })?
+                    .unwrap_or(0);
+                if removed > 0 {
+                    self.notify_write(Class::LIST, "lrem", &c.key);
+                }
+                integer(removed)
+            }
+            Command::LTrim(c) => {
+                let trimmed = self.write(&c.key, false, |l: &mut QuickList| {
+                    ltrim(l, c.start, c.stop);
+                    Ok(())
+                })?;
+                if trimmed.is_some() {
+                    self.notify_write(Class::LIST, "ltrim", &c.key);
+                }
+                ok()
+            }
+            Command::LLen(c) => {
+                integer(self.read(&c.key, |l: &QuickList| l.len())?.unwrap_or(0) as i64)
+            }
+            Command::LPos(c) => self.lpos(&c)?,
+            Command::LMove(c) => self.lmove_reply(&c)?,
+            // Outside of a connection's blocking path these behave as if
+            // the timeout expired straight away.
+            Command::BLPop(c) => self
+                .try_serve(&c.keys, &*bpop_serve(End::Left))?
+                .unwrap_or_else(null_array),
+            Command::BRPop(c) => self
+                .try_serve(&c.keys, &*bpop_serve(End::Right))?
+                .unwrap_or_else(null_array),
+            Command::BLMPop(c) => self
+                .try_serve(&c.keys, &*blmpop_serve(c.end, c.count))?
+                .unwrap_or_else(null_array),
+            Command::BLMove(c) => self.lmove_reply(&c.lmove)?,
+            _ => return Ok(None),
+        };
+        Ok(Some(res))
+    }
+
+    /// Pushes `elements` one by one onto the `end` of the list, creating it if
+    /// needed, and returns the new length.
+    pub(super) fn push(
+        &self,
+        key: &Key,
+        elements: Vec<Resp>,
+        end: End,
+    ) -> Result<usize, StorageError> {
+        let len = self.write(key, true, |l: &mut QuickList| {
+            for element in elements {
+                match end {
+                    End::Left => l.push_front(element),
+                    End::Right => l.push_back(element),
+                }
+            }
+            Ok(l.len())
+        })?;
+        self.notify(Class::LIST, push_event(end), key);
+        Ok(len.unwrap_or_default())
+    }
+
+    /// Pops up to `count` elements from the `end` of the list. Returns `None`
+    /// if the key doesn't exist.
+    pub(super) fn pop(
+        &self,
+        key: &Key,
+        end: End,
+        count: usize,
+    ) -> Result<Option<Vec<Resp>>, StorageError> {
+        let popped = self.write(key, false, |l: &mut QuickList| {
+            Ok((0..count)
+                .map_while(|_| match end {
+                    End::Left => l.pop_front(),
+                    End::Right => l.pop_back(),
+                })
+                .collect::<Vec<_>>())
+        })?;
+        if popped.as_ref().is_some_and(|v| !v.is_empty()) {
+            self.notify_write(Class::LIST, pop_event(end), key);
+        }
+        Ok(popped)
+    }
+
+    fn lmove_reply(&self, c: &LMove) -> Result<Resp, StorageError> {
+        let value = self.lmove(&c.source, &c.destination, c.from, c.to)?;
+        if value.is_some() {
+            self.signal_ready(&c.destination);
+        }
+        Ok(value.unwrap_or(Resp::Null(Null)))
+    }
+
+    fn pop_reply(&self, key: &Key, end: End, count: Option<usize>) -> Result<Resp, StorageError> {
+        let popped = self.pop(key, end, count.unwrap_or(1))?;
+        Ok(match (popped, count) {
+            (Some(mut values), None) => values.pop().unwrap_or(Resp::Null(Null)),
+            (Some(values), Some(_)) => array(values),
+            (None, None) => Resp::Null(Null),
+            (None, Some(_)) => Resp::Array(Array::new(vec![], true)),
+        })
+    }
+
+    fn lpos(&self, c: &LPos) -> Result<Resp, StorageError> {
+        let positions = self
+            .read(&c.key, |l: &QuickList| {
+                let limit = match c.max_len {
+                    0 => l.len(),
+                    n => n.min(l.len()),
+                };
+                let wanted = match c.count {
+                    None => 1,
+                    Some(0) => usize::MAX,
+                    Some(n) => n,
+                };
+                let skip = (c.rank.unsigned_abs() - 1) as usize;
+                let matches = |(_, v): &(usize, &Resp)| **v == c.element;
+                let positions: Vec<usize> = if c.rank > 0 {
+                    l.iter()
+                        .enumerate()
+                        .take(limit)
+                        .filter(matches)
+                        .skip(skip)
+                        .take(wanted)
+                        .map(|(i, _)| i)
+                        .collect()
+                } else {
+                    l.iter()
+                        .rev()
+                        .enumerate()
+                        .take(limit)
+                        .filter(matches)
+                        .skip(skip)
+                        .take(wanted)
+                        .map(|(i, _)| l.len() - 1 - i)
+                        .collect()
+                };
+                positions
+            })?
+            .unwrap_or_default();
+        Ok(match c.count {
+            Some(_) => array(positions.into_iter().map(|i| integer(i as i64))),
+            None => match positions.first() {
+                Some(i) => integer(*i as i64),
+                None => Resp::Null(Null),
+            },
+        })
+    }
+
+    /// Pops from `source` and pushes onto `destination`. When both are the
+    /// same key this rotates the list under a single lock.
+    pub(super) fn lmove(
+        &self,
+        source: &Key,
+        destination: &Key,
+        from: End,
+        to: End,
+    ) -> Result<Option<Resp>, StorageError> {
+        if source == destination {
+            let value = self
+                .write(source, false, |l: &mut QuickList| {
+                    let value = match from {
+                        End::Left => l.pop_front(),
+                        End::Right => l.pop_back(),
+                    };
+                    if let Some(value) = &value {
+                        match to {
+                            End::Left => l.push_front(value.clone()),
+                            End::Right => l.push_back(value.clone()),
+                        }
+                    }
+                    Ok(value)
+                })?
+                .flatten();
+            if value.is_some() {
+                self.notify(Class::LIST, pop_event(from), source);
+                self.notify(Class::LIST, push_event(to), source);
+            }
+            return Ok(value);
+        }
+        let keyspace = self.keyspace();
+        let (s, d) = (
+            keyspace.determine_map(source),
+            keyspace.determine_map(destination),
+        );
+        // Both shards stay locked for the whole move, so nothing can retype
+        // the destination between the pop and the push. They're locked in
+        // index order so that two moves can't deadlock.
+        let shards = keyspace.shards();
+        let mut low = shards[s.min(d)].write();
+        let mut high = (s != d).then(|| shards[s.max(d)].write());
+
+        // Check the destination first so a type error doesn't lose the element.
+        let dst = shard(&mut low, &mut high, d > s);
+        if let Some(v) = dst.get(destination) {
+            QuickList::downcast(v.get()).ok_or(StorageError::WrongType)?;
+        }
+        let src = shard(&mut low, &mut high, s > d);
+        let Some(v) = src.get_mut(source) else {
+            return Ok(None);
+        };
+        let l = QuickList::downcast_mut(v.get_mut()).ok_or(StorageError::WrongType)?;
+        let Some(value) = (match from {
+            End::Left => l.pop_front(),
+            End::Right => l.pop_back(),
+        }) else {
+            return Ok(None);
+        };
+        if l.is_empty() {
+            src.remove(source);
+        }
+        let dst = shard(&mut low, &mut high, d > s);
+        let created = !dst.contains_key(destination);
+        if created {
+            let list = SharedValue::new(QuickList::default().into_value());
+            dst.insert(destination.clone(), list);
+        }
+        let l = QuickList::downcast_mut(dst.get_mut(destination).unwrap().get_mut()).unwrap();
+        match to {
+            End::Left => l.push_front(value.clone()),
+            End::Right => l.push_back(value.clone()),
+        }
+        drop((low, high));
+
+        self.notify_write(Class::LIST, pop_event(from), source);
+        if created {
+            self.notify(Class::NEW, "new", destination);
+        }
+        self.notify(Class::LIST, push_event(to), destination);
+        Ok(Some(value))
+    }
+}
+
+/// One of the two shards `lmove` holds locked: the higher one if `high` is
+/// set, otherwise the lower one, which is the only one when both keys share it.
+fn shard<'a, S>(low: &'a mut S, high: &'a mut Option<S>, upper: bool) -> &'a mut S {
+    match high {
+        Some(high) if upper => high,
+        _ => low,
+    }
+}
+
+/// Serves `BLPOP` and `BRPOP` with a `[key, element]` pair.
+pub(super) fn bpop_serve(end: End) -> Box<Serve> {
+    Box::new(move |storage, key| {
+        let popped = storage.pop(key, end, 1)?.and_then(|mut v| v.pop());
+        Ok(popped.map(|value| array([key.clone().into(), value])))
+    })
+}
+
+/// Serves `BLMPOP` with a `[key, [element ...]]` pair.
+pub(super) fn blmpop_serve(end: End, count: usize) -> Box<Serve> {
+    Box::new(move |storage, key| {
+        let popped = storage.pop(key, end, count)?;
+        Ok(popped.map(|values| array([key.clone().into(), array(values)])))
+    })
+}
+
+/// Serves `BLMOVE` with the moved element. Waking clients blocked on the
+/// destination is left to the caller.
+pub(super) fn blmove_serve(c: &LMove) -> Box<Serve> {
+    let LMove {
+        destination,
+        from,
+        to,
+        ..
+    } = c.clone();
+    Box::new(move |storage, key| storage.lmove(key, &destination, from, to))
+}
+
+fn push_event(end: End) -> &'static str {
+    match end {
+        End::Left => "lpush",
+        End::Right => "rpush",
+    }
+}
+
+fn pop_event(end: End) -> &'static str {
+    match end {
+        End::Left => "lpop",
+        End::Right => "rpop",
+    }
+}
+
+pub(super) fn null_array() -> Resp {
+    Resp::Array(Array::new(vec![], true))
+}
+
+/// Resolves a possibly negative index against a list of `len` elements.
+fn index(index: i64, len: usize) -> Option<usize> {
+    let i = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&i).then_some(i as usize)
+}
+
+/// Resolves an inclusive `start..=stop` range with Redis's negative-index and
+/// clamping rules. Returns `None` when the range is empty.
+pub(super) fn range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// Removes up to `count` occurrences of `element`: from the head when `count`
+/// is positive, from the tail when negative, and all of them when zero.
+fn lrem(list: &mut QuickList, count: i64, element: &Resp) -> i64 {
+    let matches: Vec<usize> = list
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| *v == element)
+        .map(|(i, _)| i)
+        .collect();
+    let limit = match count {
+        0 => usize::MAX,
+        n => n.unsigned_abs() as usize,
+    };
+    let mut remove: Vec<usize> = if count >= 0 {
+        matches.into_iter().take(limit).collect()
+    } else {
+        matches.into_iter().rev().take(limit).collect()
+    };
+    // Remove back to front so earlier indexes stay valid.
+    remove.sort_unstable_by(|a, b| b.cmp(a));
+    for &i in &remove {
+        list.remove(i);
+    }
+    remove.len() as i64
+}
+
+fn ltrim(list: &mut QuickList, start: i64, stop: i64) {
+    let Some((start, stop)) = range(start, stop, list.len()) else {
+        while list.pop_back().is_some() {}
+        return;
+    };
+    let tail = list.len() - stop - 1;
+    for _ in 0..start {
+        list.pop_front();
+    }
+    for _ in 0..tail {
+        list.pop_back();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn bulk(s: &str) -> Resp {
+        Resp::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args)?;
+        Ok(storage.execute_list(cmd)?.unwrap())
+    }
+
+    fn list(items: &[&str]) -> Resp {
+        array(items.iter().map(|s| bulk(s)))
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["RPUSH", "l", "a", "b"]).unwrap(),
+            integer(2)
+        );
+        assert_eq!(
+            run(&storage, &["LPUSH", "l", "x", "y"]).unwrap(),
+            integer(4)
+        );
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "0", "-1"]).unwrap(),
+            list(&["y", "x", "a", "b"])
+        );
+        assert_eq!(run(&storage, &["LPOP", "l"]).unwrap(), bulk("y"));
+        assert_eq!(
+            run(&storage, &["RPOP", "l", "2"]).unwrap(),
+            list(&["b", "a"])
+        );
+        assert_eq!(run(&storage, &["RPOP", "l", "5"]).unwrap(), list(&["x"]));
+        assert_eq!(
+            storage.key_type(&Key::BulkString(BulkString::new("l", false))),
+            "none"
+        );
+        assert_eq!(run(&storage, &["LPOP", "l"]).unwrap(), Resp::Null(Null));
+        assert_eq!(
+            run(&storage, &["LPOP", "l", "2"]).unwrap(),
+            Resp::Array(Array::new(vec![], true))
+        );
+    }
+
+    #[test]
+    fn test_negative_indexes() {
+        let storage = Storage::new();
+        run(&storage, &["RPUSH", "l", "a", "b", "c", "d"]).unwrap();
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "-3", "-2"]).unwrap(),
+            list(&["b", "c"])
+        );
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "-100", "100"]).unwrap(),
+            list(&["a", "b", "c", "d"])
+        );
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "3", "1"]).unwrap(),
+            list(&[])
+        );
+        assert_eq!(run(&storage, &["LINDEX", "l", "-1"]).unwrap(), bulk("d"));
+        assert_eq!(
+            run(&storage, &["LINDEX", "l", "4"]).unwrap(),
+            Resp::Null(Null)
+        );
+        run(&storage, &["LSET", "l", "-2", "C"]).unwrap();
+        assert_eq!(run(&storage, &["LINDEX", "l", "2"]).unwrap(), bulk("C"));
+        let err = run(&storage, &["LSET", "l", "10", "x"]).unwrap_err();
+        assert_eq!(err.to_string(), "ERR index out of range");
+        let err = run(&storage, &["LSET", "none", "0", "x"]).unwrap_err();
+        assert_eq!(err.to_string(), "ERR no such key");
+        run(&storage, &["LTRIM", "l", "1", "-2"]).unwrap();
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "0", "-1"]).unwrap(),
+            list(&["b", "C"])
+        );
+        run(&storage, &["LTRIM", "l", "5", "10"]).unwrap();
+        assert_eq!(run(&storage, &["LLEN", "l"]).unwrap(), integer(0));
+    }
+
+    #[test]
+    fn test_linsert_lrem() {
+        let storage = Storage::new();
+        run(&storage, &["RPUSH", "l", "a", "x", "b", "x", "c", "x"]).unwrap();
+        assert_eq!(
+            run(&storage, &["LINSERT", "l", "BEFORE", "b", "y"]).unwrap(),
+            integer(7)
+        );
+        assert_eq!(
+            run(&storage, &["LINSERT", "l", "AFTER", "c", "z"]).unwrap(),
+            integer(8)
+        );
+        assert_eq!(
+            run(&storage, &["LINSERT", "l", "AFTER", "q", "z"]).unwrap(),
+            integer(-1)
+        );
+        assert_eq!(
+            run(&storage, &["LINSERT", "none", "AFTER", "q", "z"]).unwrap(),
+            integer(0)
+        );
+        assert_eq!(
+            run(&storage, &["LREM", "l", "-1", "x"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "0", "-1"]).unwrap(),
+            list(&["a", "x", "y", "b", "x", "c", "z"])
+        );
+        assert_eq!(run(&storage, &["LREM", "l", "1", "x"]).unwrap(), integer(1));
+        assert_eq!(run(&storage, &["LREM", "l", "0", "x"]).unwrap(), integer(1));
+        assert_eq!(
+            run(&storage, &["LRANGE", "l", "0", "-1"]).unwrap(),
+            list(&["a", "y", "b", "c", "z"])
+        );
+    }
+
+    #[test]
+    fn test_lpos() {
+        let storage = Storage::new();
+        run(
+            &storage,
+            &["RPUSH", "l", "a", "b", "c", "1", "2", "3", "c", "c"],
+        )
+        .unwrap();
+        assert_eq!(run(&storage, &["LPOS", "l", "c"]).unwrap(), integer(2));
+        assert_eq!(
+            run(&storage, &["LPOS", "l", "c", "RANK", "2"]).unwrap(),
+            integer(6)
+        );
+        assert_eq!(
+            run(&storage, &["LPOS", "l", "c", "RANK", "-1"]).unwrap(),
+            integer(7)
+        );
+        assert_eq!(
+            run(&storage, &["LPOS", "l", "c", "COUNT", "0"]).unwrap(),
+            array([integer(2), integer(6), integer(7)])
+        );
+        assert_eq!(
+            run(&storage, &["LPOS", "l", "c", "RANK", "-1", "COUNT", "2"]).unwrap(),
+            array([integer(7), integer(6)])
+        );
+        assert_eq!(
+            run(&storage, &["LPOS", "l", "c", "MAXLEN", "2"]).unwrap(),
+            Resp::Null(Null)
+        );
+        assert_eq!(
+            run(&storage, &["LPOS", "l", "x"]).unwrap(),
+            Resp::Null(Null)
+        );
+    }
+
+    #[test]
+    fn test_lmove() {
+        let storage = Storage::new();
+        run(&storage, &["RPUSH", "src", "a", "b", "c"]).unwrap();
+        assert_eq!(
+            run(&storage, &["LMOVE", "src", "dst", "LEFT", "RIGHT"]).unwrap(),
+            bulk("a")
+        );
+        assert_eq!(
+            run(&storage, &["LMOVE", "src", "dst", "RIGHT", "LEFT"]).unwrap(),
+            bulk("c")
+        );
+        assert_eq!(
+            run(&storage, &["LRANGE", "dst", "0", "-1"]).unwrap(),
+            list(&["c", "a"])
+        );
+        assert_eq!(
+            run(&storage, &["LMOVE", "dst", "dst", "LEFT", "RIGHT"]).unwrap(),
+            bulk("c")
+        );
+        assert_eq!(
+            run(&storage, &["LRANGE", "dst", "0", "-1"]).unwrap(),
+            list(&["a", "c"])
+        );
+        assert_eq!(
+            run(&storage, &["LMOVE", "none", "dst", "LEFT", "RIGHT"]).unwrap(),
+            Resp::Null(Null)
+        );
+
+        storage.set(Key::BulkString(BulkString::new("s", false)), bulk("v"));
+        assert!(run(&storage, &["LMOVE", "src", "s", "LEFT", "RIGHT"]).is_err());
+        assert_eq!(run(&storage, &["LLEN", "src"]).unwrap(), integer(1));
+    }
+
+    #[test]
+    fn test_lmove_is_atomic() {
+        let storage = Storage::new();
+        run(&storage, &["RPUSH", "a", "1", "2", "3"]).unwrap();
+        let b = Key::BulkString(BulkString::new("b", false));
+        let mover = {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20_000 {
+                    // Fails while "b" is a string, which must not cost an element.
+                    let _ = run(&storage, &["LMOVE", "a", "b", "LEFT", "RIGHT"]);
+                    let _ = run(&storage, &["LMOVE", "b", "a", "LEFT", "RIGHT"]);
+                }
+            })
+        };
+        // Keeps turning a missing "b" into a string and back, without ever
+        // touching it while it's a list.
+        while !mover.is_finished() {
+            storage
+                .keyspace()
+                .entry(b.clone())
+                .or_insert_with(|| Value::String(bulk("x")));
+            storage
+                .keyspace()
+                .remove_if(&b, |_, v| matches!(v, Value::String(_)));
+        }
+        mover.join().unwrap();
+        let len = |key| match run(&storage, &["LLEN", key]) {
+            Ok(Resp::Integer(n)) => n.value(),
+            _ => 0,
+        };
+        assert_eq!(len("a") + len("b"), 3, "no element was lost");
+    }
+}
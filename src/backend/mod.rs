@@ -1,45 +1,730 @@
+mod blocking;
+mod client;
+mod config;
+mod function;
+mod geo;
+mod group;
+mod hash;
+mod hyperloglog;
+mod keyspace;
+mod list;
+mod notify;
+mod pubsub;
+mod quicklist;
+mod scan;
+mod script;
+mod set;
+mod skiplist;
+mod stream;
+mod tracking;
+mod watch;
+mod zset;
+
 use crate::{
-    cmd::{Command, CommandExecutor},
-    resp::{Key, Resp},
+    cmd::{Command, CommandExecutor, Script},
+    resp::{Array, BulkString, Integer, Key, Resp, SimpleString},
+};
+use anyhow::Result;
+use blocking::WaitQueues;
+pub use client::{error_reply, Client, Clients, Queued, Transaction};
+use dashmap::{mapref::entry::Entry, DashMap};
+use function::Functions;
+use hash::Hash;
+use notify::Class;
+pub use pubsub::{Broker, Subscriber};
+use quicklist::QuickList;
+use scan::KeyOrder;
+use script::{Running, Scripts, BUSY_THRESHOLD};
+use set::Set;
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
-use anyhow::{Ok, Result};
-use dashmap::DashMap;
-use std::sync::Arc;
+use stream::Stream;
+use thiserror::Error;
+use tracking::Tracking;
+use watch::Versions;
+pub use watch::Watcher;
+use zset::SortedSet;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum StorageError {
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("ERR DB index is out of range")]
+    DbIndexOutOfRange,
+    #[error("ERR No such client")]
+    NoSuchClient,
+    #[error("ERR No such user '{0}'")]
+    NoSuchUser(String),
+    #[error("ERR The client ID you want redirect to does not exist")]
+    NoRedirectClient,
+    #[error("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.")]
+    TrackingModeSwitch,
+    #[error("ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.")]
+    CachingWithoutOptIn,
+    #[error("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.")]
+    CachingWithoutOptOut,
+    #[error("ERR source and destination objects are the same")]
+    SameObject,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR {0} is not an integer")]
+    NotAnInteger(&'static str),
+    #[error("ERR {0} is not a float")]
+    NotAFloat(&'static str),
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("ERR increment would produce NaN or Infinity")]
+    NanOrInfinity,
+    #[error("ERR index out of range")]
+    IndexOutOfRange,
+    #[error("ERR resulting score is not a number (NaN)")]
+    ScoreNan,
+    #[error("ERR The ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+    #[error("ERR The stream has exhausted the last possible ID, unable to add more items")]
+    StreamExhausted,
+    #[error("NOGROUP No such key '{0}' or consumer group '{1}'")]
+    NoGroup(String, String),
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+    #[error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")]
+    XGroupNoKey,
+    #[error("ERR could not decode requested zset member")]
+    GeoMemberNotFound,
+    #[error("WRONGTYPE Key is not a valid HyperLogLog string value.")]
+    InvalidHll,
+    #[error("INVALIDOBJ Corrupted HLL object detected")]
+    CorruptedHll,
+    #[error("ERR Unknown option or number of arguments for CONFIG SET - '{0}'")]
+    UnknownConfig(String),
+    #[error("ERR CONFIG SET failed (possibly related to argument '{0}') - {1}")]
+    InvalidConfig(String, &'static str),
+    #[error("NOSCRIPT No matching script. Please use EVAL.")]
+    NoScript,
+    #[error("ERR Error compiling script (new function): {0}")]
+    ScriptCompile(String),
+    #[error("ERR Error running script (call to {0}): {1}")]
+    ScriptRuntime(String, String),
+    #[error("ERR Please specify at least one argument for this redis lib call")]
+    ScriptNoArgs,
+    #[error("ERR Lua redis lib command arguments must be strings or integers")]
+    ScriptArgType,
+    #[error("ERR Unknown Redis command called from script")]
+    UnknownScriptCommand,
+    #[error("ERR This Redis command is not allowed from script")]
+    NotAllowedFromScript,
+    #[error("ERR Write commands are not allowed from read-only scripts.")]
+    WriteFromReadOnlyScript,
+    #[error(
+        "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE."
+    )]
+    Busy,
+    #[error("NOTBUSY No scripts in execution right now.")]
+    NotBusy,
+    #[error("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.")]
+    Unkillable,
+    #[error("ERR Function not found")]
+    FunctionNotFound,
+    #[error("ERR Can not execute a script with write flag using *_ro command.")]
+    WriteFunctionReadOnly,
+    #[error("ERR Missing library metadata")]
+    MissingLibraryMetadata,
+    #[error("ERR Engine '{0}' not found")]
+    EngineNotFound(String),
+    #[error("ERR Library name was not given")]
+    MissingLibraryName,
+    #[error("ERR Invalid metadata value given: {0}")]
+    InvalidLibraryMetadata(String),
+    #[error("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long")]
+    InvalidLibraryName,
+    #[error("ERR Library '{0}' already exists")]
+    LibraryExists(String),
+    #[error("ERR Library not found")]
+    LibraryNotFound,
+    #[error("ERR Function {0} already exists")]
+    FunctionExists(String),
+    #[error("ERR No functions registered")]
+    NoFunctions,
+    #[error("ERR Error compiling function: {0}")]
+    FunctionCompile(String),
+    #[error("ERR Error registering functions: {0}")]
+    FunctionRegister(String),
+    #[error("ERR payload version or checksum are wrong")]
+    InvalidFunctionDump,
+}
 
-#[derive(Clone, Default)]
+/// How many databases there are unless configured otherwise.
+pub const DATABASES: usize = 16;
+
+/// A value stored under a key.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(Resp),
+    Hash(Hash),
+    List(QuickList),
+    Set(Set),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+/// A collection type that can live in a `Value`. Collections are created on
+/// first write and the key is removed once they become empty.
+trait Container: Default {
+    fn downcast(value: &Value) -> Option<&Self>;
+    fn downcast_mut(value: &mut Value) -> Option<&mut Self>;
+    fn into_value(self) -> Value;
+    fn is_empty(&self) -> bool;
+}
+
+/// A handle on one of the databases. Clones share the same data, and
+/// `select` gives a handle on another database.
+#[derive(Clone)]
 pub struct Storage {
-    storage: Arc<DashMap<Key, Resp>>,
+    /// The database commands run against.
+    db: usize,
+    /// The ID of the client running commands through this handle, or 0.
+    client: u64,
+    /// The keyspaces, as many as there are databases, reached through
+    /// `keyspace_of` so that `SWAPDB` only has to swap two indices.
+    keyspaces: Arc<Vec<DashMap<Key, Value>>>,
+    /// The keys of each keyspace in `SCAN` order.
+    key_orders: Arc<Vec<KeyOrder>>,
+    /// For each database, the index of its keyspace.
+    dbs: Arc<Vec<AtomicUsize>>,
+    /// The clients blocked in each database.
+    blocked: Arc<Vec<WaitQueues>>,
+    broker: Broker,
+    /// The `notify-keyspace-events` classes, as `notify::Class` bits.
+    events: Arc<AtomicU16>,
+    /// The `WATCH` versions of each database.
+    versions: Arc<Vec<Versions>>,
+    scripts: Arc<Mutex<Scripts>>,
+    /// The script in flight, if any.
+    running: Arc<Running>,
+    /// The `busy-reply-threshold`, in milliseconds.
+    busy_threshold: Arc<AtomicU64>,
+    functions: Arc<Mutex<Functions>>,
+    clients: Arc<Clients>,
+    tracking: Arc<Tracking>,
+    /// Held shared by every command and exclusively by those that must see
+    /// and update several keys as one step.
+    lock: Arc<RwLock<()>>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage::new()
+    }
 }
 
 impl CommandExecutor for Storage {
+    /// Runs `cmd` under the storage lock. It's a std lock because commands
+    /// never hold it across an `.await`: a client waiting on it stalls its
+    /// runtime thread only while the commands ahead of it run, though an
+    /// exclusive one stalls every other client for that long. Scripts,
+    /// which may hold it for long, are waited for beforehand with
+    /// `wait_for_script` instead.
     fn execute(&self, cmd: Command) -> Result<Option<Resp>> {
+        // The script it stops holds the lock.
+        if let Command::Script(Script::Kill) = cmd {
+            return self.dispatch(cmd);
+        }
+        let _script = cmd.runs_script().then(|| self.running.enter());
+        let _exclusive;
+        let _shared;
+        if is_exclusive(&cmd) {
+            _exclusive = self.write_lock(_script.is_some())?;
+        } else {
+            _shared = self.read_lock()?;
+        }
+        self.dispatch(cmd)
+    }
+}
+
+impl Storage {
+    /// Runs `f` with no other command in flight. The handle it is given
+    /// runs commands without taking the lock again, so that `EXEC` can run
+    /// a whole transaction as one step. `scripts` tells whether `f` runs
+    /// any.
+    pub fn atomically<T>(
+        &self,
+        scripts: bool,
+        f: impl FnOnce(Locked<'_>) -> T,
+    ) -> Result<T, StorageError> {
+        let _script = scripts.then(|| self.running.enter());
+        let _exclusive = self.write_lock(scripts)?;
+        Ok(f(Locked::new(self)))
+    }
+
+    fn read_lock(&self) -> Result<RwLockReadGuard<'_, ()>, StorageError> {
+        self.lock_unless_busy(false, || self.lock.try_read(), || self.lock.read())
+    }
+
+    /// Takes the lock exclusively, for a script if `script` is set.
+    fn write_lock(&self, script: bool) -> Result<RwLockWriteGuard<'_, ()>, StorageError> {
+        self.lock_unless_busy(script, || self.lock.try_write(), || self.lock.write())
+    }
+}
+
+/// A handle on a database whose storage lock is already held exclusively,
+/// valid for as long as `Storage::atomically` holds it.
+pub struct Locked<'a> {
+    storage: Cow<'a, Storage>,
+}
+
+impl<'a> Locked<'a> {
+    fn new(storage: &'a Storage) -> Self {
+        Locked {
+            storage: Cow::Borrowed(storage),
+        }
+    }
+
+    /// A handle on database `db` under the same lock, for `SELECT` inside
+    /// a transaction.
+    pub fn select(&self, db: i64) -> Result<Locked<'a>, StorageError> {
+        Ok(Locked {
+            storage: Cow::Owned(self.storage.select(db)?),
+        })
+    }
+
+    pub fn db(&self) -> usize {
+        self.storage.db
+    }
+}
+
+impl CommandExecutor for Locked<'_> {
+    fn execute(&self, cmd: Command) -> Result<Option<Resp>> {
+        self.storage.dispatch(cmd)
+    }
+}
+
+/// Whether `cmd` has to run with no other command in flight.
+fn is_exclusive(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::SInterStore(_)
+            | Command::SUnionStore(_)
+            | Command::SDiffStore(_)
+            | Command::ZUnionStore(_)
+            | Command::ZInterStore(_)
+            | Command::ZDiffStore(_)
+            | Command::ZUnion(_)
+            | Command::ZInter(_)
+            | Command::ZDiff(_)
+            | Command::PfMerge(_)
+            | Command::GeoSearchStore(_)
+            | Command::Eval(_)
+            | Command::EvalSha(_)
+            | Command::FCall(_)
+            | Command::FCallRo(_)
+            | Command::Rename(_)
+            | Command::RenameNx(_)
+            | Command::Move(_)
+            | Command::SwapDb(_)
+            | Command::FlushDb(_)
+            | Command::FlushAll(_)
+    ) || matches!(cmd, Command::PfCount(c) if c.keys.len() > 1)
+        || matches!(cmd, Command::Copy(c) if c.db.is_some())
+}
+
+impl Storage {
+    fn dispatch(&self, cmd: Command) -> Result<Option<Resp>> {
         match cmd {
-            Command::Get(get) => Ok(self.get(&get.key)),
+            Command::Get(get) => Ok(self.get(&get.key)?),
             Command::Set(set) => {
                 self.set(set.key, set.value);
                 Ok(None)
             }
+            Command::Del(del) => Ok(Some(integer(self.del(&del.keys)))),
+            Command::Unlink(unlink) => Ok(Some(integer(self.unlink(&unlink.keys)))),
+            Command::Exists(exists) => Ok(Some(integer(self.exists(&exists.keys)))),
+            Command::Touch(touch) => Ok(Some(integer(self.exists(&touch.keys)))),
+            Command::Type(t) => Ok(Some(Resp::SimpleString(SimpleString::new(
+                self.key_type(&t.key),
+            )))),
+            Command::Rename(rename) => {
+                self.rename(&rename.key, rename.new_key.clone())?;
+                self.signal_ready(&rename.new_key);
+                Ok(Some(ok()))
+            }
+            Command::RenameNx(rename) => {
+                let renamed = self.rename_nx(&rename.key, rename.new_key.clone())?;
+                if renamed {
+                    self.signal_ready(&rename.new_key);
+                }
+                Ok(Some(integer(renamed as i64)))
+            }
+            Command::Copy(copy) => {
+                let target = match copy.db {
+                    Some(db) => Cow::Owned(self.select(db)?),
+                    None => Cow::Borrowed(self),
+                };
+                let copied = self.copy(
+                    &copy.source,
+                    copy.destination.clone(),
+                    &target,
+                    copy.replace,
+                )?;
+                if copied {
+                    target.signal_ready(&copy.destination);
+                }
+                Ok(Some(integer(copied as i64)))
+            }
+            Command::Move(c) => {
+                let target = self.select(c.db)?;
+                let moved = self.move_key(&c.key, &target)?;
+                if moved {
+                    target.signal_ready(&c.key);
+                }
+                Ok(Some(integer(moved as i64)))
+            }
+            Command::SwapDb(c) => {
+                self.swap_db(&self.select(c.first)?, &self.select(c.second)?);
+                Ok(Some(ok()))
+            }
+            Command::FlushDb(c) => {
+                self.flush(c.lazy);
+                self.invalidate_all();
+                Ok(Some(ok()))
+            }
+            Command::FlushAll(c) => {
+                for db in 0..self.databases() {
+                    self.select(db as i64)?.flush(c.lazy);
+                }
+                self.invalidate_all();
+                Ok(Some(ok()))
+            }
+            Command::RandomKey => Ok(self.random_key().map(Resp::from)),
+            Command::DbSize => Ok(Some(integer(self.keyspace().len() as i64))),
+            Command::Keys(keys) => Ok(Some(array(
+                self.keys(&keys.pattern).into_iter().map(Resp::from),
+            ))),
+            Command::Scan(scan) => {
+                let (cursor, keys) = self.scan_keys(scan.cursor, &scan.options);
+                Ok(Some(scan_reply(cursor, keys.into_iter().map(Resp::from))))
+            }
+            cmd @ (Command::HSet(_)
+            | Command::HGet(_)
+            | Command::HMGet(_)
+            | Command::HGetAll(_)
+            | Command::HDel(_)
+            | Command::HIncrBy(_)
+            | Command::HIncrByFloat(_)
+            | Command::HExists(_)
+            | Command::HLen(_)
+            | Command::HKeys(_)
+            | Command::HVals(_)
+            | Command::HRandField(_)
+            | Command::HScan(_)) => self.execute_hash(cmd),
+            cmd @ (Command::LPush(_)
+            | Command::RPush(_)
+            | Command::LPop(_)
+            | Command::RPop(_)
+            | Command::LRange(_)
+            | Command::LIndex(_)
+            | Command::LSet(_)
+            | Command::LInsert(_)
+            | Command::LRem(_)
+            | Command::LTrim(_)
+            | Command::LLen(_)
+            | Command::LPos(_)
+            | Command::LMove(_)
+            | Command::BLPop(_)
+            | Command::BRPop(_)
+            | Command::BLMove(_)
+            | Command::BLMPop(_)) => self.execute_list(cmd),
+            cmd @ (Command::SAdd(_)
+            | Command::SRem(_)
+            | Command::SMembers(_)
+            | Command::SIsMember(_)
+            | Command::SMIsMember(_)
+            | Command::SCard(_)
+            | Command::SPop(_)
+            | Command::SRandMember(_)
+            | Command::SMove(_)
+            | Command::SScan(_)
+            | Command::SInter(_)
+            | Command::SUnion(_)
+            | Command::SDiff(_)
+            | Command::SInterStore(_)
+            | Command::SUnionStore(_)
+            | Command::SDiffStore(_)
+            | Command::SInterCard(_)) => self.execute_set(cmd),
+            cmd @ (Command::ZAdd(_)
+            | Command::ZRange(_)
+            | Command::ZRank(_)
+            | Command::ZRevRank(_)
+            | Command::ZScore(_)
+            | Command::ZIncrBy(_)
+            | Command::ZRem(_)
+            | Command::ZCount(_)
+            | Command::ZCard(_)
+            | Command::ZPopMin(_)
+            | Command::ZPopMax(_)
+            | Command::BZPopMin(_)
+            | Command::BZPopMax(_)
+            | Command::BZMPop(_)
+            | Command::ZScan(_)
+            | Command::ZUnionStore(_)
+            | Command::ZInterStore(_)
+            | Command::ZDiffStore(_)
+            | Command::ZUnion(_)
+            | Command::ZInter(_)
+            | Command::ZDiff(_)) => self.execute_zset(cmd),
+            cmd @ (Command::XAdd(_)
+            | Command::XRange(_)
+            | Command::XRevRange(_)
+            | Command::XLen(_)
+            | Command::XTrim(_)
+            | Command::XDel(_)
+            | Command::XRead(_)
+            | Command::XReadGroup(_)
+            | Command::XGroup(_)
+            | Command::XAck(_)
+            | Command::XPending(_)
+            | Command::XClaim(_)
+            | Command::XAutoClaim(_)
+            | Command::XInfo(_)) => self.execute_stream(cmd),
+            cmd @ (Command::GeoAdd(_)
+            | Command::GeoDist(_)
+            | Command::GeoPos(_)
+            | Command::GeoHash(_)
+            | Command::GeoSearch(_)
+            | Command::GeoSearchStore(_)) => self.execute_geo(cmd),
+            Command::Publish(c) => Ok(Some(integer(
+                self.broker.publish(&c.channel, &c.message) as i64
+            ))),
+            Command::SPublish(c) => Ok(Some(integer(
+                self.broker.spublish(&c.channel, &c.message) as i64
+            ))),
+            Command::PubSub(c) => Ok(Some(self.broker.pubsub(&c))),
+            Command::Config(c) => Ok(Some(self.execute_config(c)?)),
+            Command::Eval(c) => Ok(Some(self.eval(c, false)?)),
+            Command::EvalSha(c) => Ok(Some(self.eval(c, true)?)),
+            Command::Script(c) => Ok(Some(self.execute_script(c)?)),
+            Command::FCall(c) => Ok(Some(self.fcall(c, false)?)),
+            Command::FCallRo(c) => Ok(Some(self.fcall(c, true)?)),
+            Command::Function(c) => Ok(Some(self.execute_function(c)?)),
+            cmd @ (Command::PfAdd(_) | Command::PfCount(_) | Command::PfMerge(_)) => {
+                self.execute_hyperloglog(cmd)
+            }
             _ => Ok(None),
         }
     }
-}
 
-impl Storage {
     pub fn new() -> Self {
+        Storage::with_databases(DATABASES)
+    }
+
+    /// A handle on database 0 of a storage with `databases` databases.
+    pub fn with_databases(databases: usize) -> Self {
         Self {
-            storage: DashMap::new().into(),
+            db: 0,
+            client: 0,
+            keyspaces: (0..databases)
+                .map(|_| DashMap::new())
+                .collect::<Vec<_>>()
+                .into(),
+            key_orders: (0..databases)
+                .map(|_| KeyOrder::default())
+                .collect::<Vec<_>>()
+                .into(),
+            dbs: (0..databases)
+                .map(AtomicUsize::new)
+                .collect::<Vec<_>>()
+                .into(),
+            blocked: (0..databases)
+                .map(|_| WaitQueues::default())
+                .collect::<Vec<_>>()
+                .into(),
+            broker: Broker::default(),
+            events: Arc::default(),
+            versions: (0..databases)
+                .map(|_| Versions::default())
+                .collect::<Vec<_>>()
+                .into(),
+            scripts: Arc::default(),
+            running: Arc::default(),
+            busy_threshold: Arc::new(AtomicU64::new(BUSY_THRESHOLD)),
+            functions: Arc::default(),
+            clients: Arc::default(),
+            tracking: Arc::default(),
+            lock: Arc::default(),
         }
     }
 
-    fn get(&self, key: &Key) -> Option<Resp> {
-        self.storage.get(key).map(|v| v.value().clone())
+    /// A handle on database `db`, sharing everything else with this one.
+    pub fn select(&self, db: i64) -> Result<Storage, StorageError> {
+        let db = usize::try_from(db)
+            .ok()
+            .filter(|db| *db < self.databases())
+            .ok_or(StorageError::DbIndexOutOfRange)?;
+        Ok(Storage { db, ..self.clone() })
+    }
+
+    /// The index of the database this handle runs commands against.
+    pub fn db(&self) -> usize {
+        self.db
+    }
+
+    pub fn databases(&self) -> usize {
+        self.keyspaces.len()
+    }
+
+    fn keyspace(&self) -> &DashMap<Key, Value> {
+        self.keyspace_of(self.db)
+    }
+
+    fn keyspace_of(&self, db: usize) -> &DashMap<Key, Value> {
+        &self.keyspaces[self.dbs[db].load(Ordering::Relaxed)]
+    }
+
+    fn key_order(&self) -> &KeyOrder {
+        &self.key_orders[self.dbs[self.db].load(Ordering::Relaxed)]
+    }
+
+    fn blocked(&self) -> &WaitQueues {
+        &self.blocked[self.db]
+    }
+
+    /// The connected clients.
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    /// The pub/sub broker connections subscribe through.
+    pub fn broker(&self) -> &Broker {
+        &self.broker
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Resp>, StorageError> {
+        match self.keyspace().get(key).as_deref() {
+            Some(Value::String(v)) => Ok(Some(v.clone())),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
     }
 
     fn set(&self, key: Key, value: Resp) {
-        self.storage.insert(key, value);
+        self.insert(key.clone(), Value::String(value));
+        self.notify(Class::STRING, "set", &key);
+    }
+
+    /// Stores `value` at `key`, replacing whatever was there.
+    fn insert(&self, key: Key, value: Value) {
+        if self.keyspace().insert(key.clone(), value).is_none() {
+            self.notify(Class::NEW, "new", &key);
+        }
+    }
+
+    /// Runs `f` on the collection at `key`, or returns `None` if there is none.
+    fn read<C: Container, T>(
+        &self,
+        key: &Key,
+        f: impl FnOnce(&C) -> T,
+    ) -> Result<Option<T>, StorageError> {
+        match self.keyspace().get(key) {
+            Some(value) => {
+                let c = C::downcast(&value).ok_or(StorageError::WrongType)?;
+                Ok(Some(f(c)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Runs `f` on the collection at `key` while holding its shard lock. A
+    /// missing collection is created first if `create` is set, otherwise
+    /// `None` is returned. Collections left empty by `f` are removed.
+    fn write<C: Container, T>(
+        &self,
+        key: &Key,
+        create: bool,
+        f: impl FnOnce(&mut C) -> Result<T, StorageError>,
+    ) -> Result<Option<T>, StorageError> {
+        match self.keyspace().entry(key.clone()) {
+            Entry::Occupied(mut e) => {
+                let c = C::downcast_mut(e.get_mut()).ok_or(StorageError::WrongType)?;
+                let res = f(c);
+                if c.is_empty() {
+                    e.remove();
+                }
+                res.map(Some)
+            }
+            Entry::Vacant(e) => {
+                if !create {
+                    return Ok(None);
+                }
+                let mut c = C::default();
+                let res = f(&mut c)?;
+                if !c.is_empty() {
+                    e.insert(c.into_value());
+                    self.notify(Class::NEW, "new", key);
+                }
+                Ok(Some(res))
+            }
+        }
     }
 }
 
+fn ok() -> Resp {
+    Resp::SimpleString(SimpleString::new("OK"))
+}
+
+fn integer(n: i64) -> Resp {
+    Resp::Integer(Integer::new(n))
+}
+
+fn array(items: impl IntoIterator<Item = Resp>) -> Resp {
+    Resp::Array(Array::new(items.into_iter().collect(), false))
+}
+
+fn bulk_string(s: impl Into<Vec<u8>>) -> Resp {
+    Resp::BulkString(BulkString::new(s, false))
+}
+
+/// Reads a stored string as an integer, the way `INCR`-style commands do.
+fn parse_integer(value: &Resp) -> Option<i64> {
+    match value {
+        Resp::Integer(i) => Some(i.value()),
+        Resp::BulkString(s) if !s.is_null => s.as_str()?.parse().ok(),
+        Resp::SimpleString(s) => s.as_str().parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse_float(value: &Resp) -> Option<f64> {
+    match value {
+        Resp::Integer(i) => Some(i.value() as f64),
+        Resp::Double(d) => Some(d.value()),
+        Resp::BulkString(s) if !s.is_null => s.as_str()?.parse().ok(),
+        Resp::SimpleString(s) => s.as_str().parse().ok(),
+        _ => None,
+    }
+    .filter(|f: &f64| !f.is_nan())
+}
+
+fn scan_reply(cursor: u64, items: impl IntoIterator<Item = Resp>) -> Resp {
+    array([bulk_string(cursor.to_string()), array(items)])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,4 +747,53 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), Some(value));
     }
+
+    #[test]
+    fn test_atomically() {
+        let storage = Storage::new();
+        let run = |executor: &dyn CommandExecutor, args: &[&str]| {
+            crate::cmd::parse_command(args)
+                .unwrap()
+                .execute(executor)
+                .unwrap()
+        };
+        let replies = storage
+            .atomically(false, |executor| {
+                // A plain command would wait for the lock held here forever.
+                assert!(storage.lock.try_read().is_err());
+                [
+                    run(&executor, &["RPUSH", "l", "a"]),
+                    run(&executor, &["ECHO", "hi"]),
+                    run(&executor, &["LPOP", "l"]),
+                ]
+            })
+            .unwrap();
+        assert_eq!(
+            replies,
+            [
+                Resp::Integer(Integer::new(1)),
+                bulk_string("hi"),
+                bulk_string("a")
+            ]
+        );
+        assert!(storage.lock.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_write_removes_empty_collections() {
+        let storage = Storage::new();
+        let key = Key::BulkString(BulkString::new("key", false));
+        let res = storage.write(&key, false, |_: &mut Hash| Ok(()));
+        assert_eq!(res, Ok(None));
+        assert!(storage.keyspace().is_empty());
+
+        storage.set(key.clone(), Resp::Integer(Integer::new(1)));
+        let res = storage.write(&key, true, |_: &mut Hash| Ok(()));
+        assert_eq!(res, Err(StorageError::WrongType));
+        storage.del(std::slice::from_ref(&key));
+
+        let res = storage.write(&key, true, |_: &mut Hash| Ok(()));
+        assert_eq!(res, Ok(Some(())));
+        assert!(storage.keyspace().is_empty());
+    }
 }
@@ -0,0 +1,248 @@
+//! Keyspace notifications. Writes publish the event they performed on
+//! `__keyspace@<db>__:<key>` and the key they touched on
+//! `__keyevent@<db>__:<event>`, for the event classes enabled through the
+//! `notify-keyspace-events` setting.
+
+use super::{bulk_string, Storage};
+use crate::resp::{BulkString, Key};
+use std::sync::atomic::Ordering;
+
+/// A set of event classes, one bit per `notify-keyspace-events` character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(super) struct Class(u16);
+
+impl Class {
+    pub(super) const KEYSPACE: Class = Class(1 << 0);
+    pub(super) const KEYEVENT: Class = Class(1 << 1);
+    pub(super) const GENERIC: Class = Class(1 << 2);
+    pub(super) const STRING: Class = Class(1 << 3);
+    pub(super) const LIST: Class = Class(1 << 4);
+    pub(super) const SET: Class = Class(1 << 5);
+    pub(super) const HASH: Class = Class(1 << 6);
+    pub(super) const ZSET: Class = Class(1 << 7);
+    pub(super) const STREAM: Class = Class(1 << 10);
+    pub(super) const NEW: Class = Class(1 << 11);
+
+    /// The classes `A` stands for. `n` is left out, as in Redis. Keys never
+    /// expire or get evicted here, so there are no `x` and `e` classes.
+    const ALL: Class = Class(0b100_1111_1100);
+
+    /// The classes in the order `Class::to_string` lists them.
+    const CHARS: [(char, Class); 7] = [
+        ('g', Class::GENERIC),
+        ('$', Class::STRING),
+        ('l', Class::LIST),
+        ('s', Class::SET),
+        ('h', Class::HASH),
+        ('z', Class::ZSET),
+        ('t', Class::STREAM),
+    ];
+
+    pub(super) fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub(super) fn from_bits(bits: u16) -> Self {
+        Class(bits)
+    }
+
+    fn contains(self, other: Class) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Parses a `notify-keyspace-events` value, returning `None` if it has
+    /// a character that isn't a class, including Redis's `x` and `e`.
+    pub(super) fn parse(s: &str) -> Option<Self> {
+        s.chars().try_fold(Class::default(), |acc, c| {
+            let class = match c {
+                'A' => Class::ALL,
+                'K' => Class::KEYSPACE,
+                'E' => Class::KEYEVENT,
+                'n' => Class::NEW,
+                c => Class::CHARS.iter().find(|(ch, _)| *ch == c)?.1,
+            };
+            Some(Class(acc.0 | class.0))
+        })
+    }
+}
+
+impl std::fmt::Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.contains(Class::ALL) {
+            write!(f, "A")?;
+        } else {
+            for (c, class) in Class::CHARS {
+                if self.contains(class) {
+                    write!(f, "{c}")?;
+                }
+            }
+        }
+        for (c, class) in [
+            ('K', Class::KEYSPACE),
+            ('E', Class::KEYEVENT),
+            ('n', Class::NEW),
+        ] {
+            if self.contains(class) {
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage {
+    /// Publishes `event` on `key` if its class is enabled. Every write
+    /// raises an event, so this is also where watched keys are touched and
+    /// tracking clients told about the change, once per write: `new` always
+    /// comes with the event of the write that created the key. `new` is
+    /// also where a key joins the `SCAN` order.
+    pub(super) fn notify(&self, class: Class, event: &str, key: &Key) {
+        self.touch(key);
+        if class == Class::NEW {
+            self.key_order()
+                .insert(key, |k| self.keyspace().contains_key(k));
+        } else {
+            self.invalidate(key);
+        }
+        let enabled = Class::from_bits(self.events.load(Ordering::Relaxed));
+        if !enabled.contains(class) {
+            return;
+        }
+        if enabled.contains(Class::KEYSPACE) {
+            let channel = channel(format!("__keyspace@{}__:{key}", self.db));
+            self.broker.publish(&channel, &bulk_string(event));
+        }
+        if enabled.contains(Class::KEYEVENT) {
+            let channel = channel(format!("__keyevent@{}__:{event}", self.db));
+            self.broker.publish(&channel, &key.clone().into());
+        }
+    }
+
+    /// Publishes `event` for a write to the collection at `key`, followed by
+    /// `del` if the write left it empty and so removed it.
+    pub(super) fn notify_write(&self, class: Class, event: &str, key: &Key) {
+        self.notify(class, event, key);
+        if !self.keyspace().contains_key(key) {
+            self.notify(Class::GENERIC, "del", key);
+        }
+    }
+}
+
+fn channel(name: String) -> Key {
+    Key::BulkString(BulkString::new(name, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::Subscriber,
+        cmd::{parse_command, CommandExecutor},
+        resp::Resp,
+    };
+
+    fn run(storage: &Storage, args: &[&str]) -> Resp {
+        let cmd = parse_command(args).unwrap();
+        storage
+            .execute(cmd)
+            .unwrap()
+            .unwrap_or(Resp::Null(crate::resp::Null))
+    }
+
+    fn key(s: &str) -> Key {
+        channel(s.to_string())
+    }
+
+    /// The channels and payloads of the messages received so far.
+    fn received(rx: &mut tokio::sync::mpsc::UnboundedReceiver<Resp>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|push| match push {
+                Resp::Push(items) => {
+                    let [.., channel, payload] = &items[..] else {
+                        panic!("Expected a pmessage, got {items:?}");
+                    };
+                    let channel = Key::try_from(channel.clone()).unwrap();
+                    let payload = Key::try_from(payload.clone()).unwrap();
+                    format!("{channel} {payload}")
+                }
+                push => panic!("Expected a push, got {push:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_classes() {
+        assert_eq!(Class::parse(""), Some(Class::default()));
+        assert_eq!(Class::parse("gK").unwrap().to_string(), "gK");
+        assert_eq!(Class::parse("EKg$lshzt").unwrap().to_string(), "AKE");
+        assert_eq!(Class::parse("Agn").unwrap().to_string(), "An");
+        assert_eq!(Class::parse("Ex"), None);
+        assert_eq!(Class::parse("Ke"), None);
+        assert_eq!(Class::parse("Kq"), None);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let storage = Storage::new();
+        let (mut sub, mut rx) = Subscriber::new(storage.broker());
+        sub.psubscribe(&[key("__key*__:*")]);
+
+        run(&storage, &["SET", "s", "1"]);
+        assert!(received(&mut rx).is_empty());
+
+        run(
+            &storage,
+            &["CONFIG", "SET", "notify-keyspace-events", "Elgn"],
+        );
+        run(&storage, &["RPUSH", "l", "a", "b"]);
+        run(&storage, &["LPOP", "l", "2"]);
+        run(&storage, &["SADD", "set", "a"]);
+        assert_eq!(
+            received(&mut rx),
+            [
+                "__keyevent@0__:new l",
+                "__keyevent@0__:rpush l",
+                "__keyevent@0__:lpop l",
+                "__keyevent@0__:del l",
+                "__keyevent@0__:new set",
+            ]
+        );
+
+        run(
+            &storage,
+            &["CONFIG", "SET", "notify-keyspace-events", "Kg$"],
+        );
+        run(&storage, &["SET", "s", "2"]);
+        run(&storage, &["RENAME", "s", "t"]);
+        run(&storage, &["DEL", "t", "missing"]);
+        assert_eq!(
+            received(&mut rx),
+            [
+                "__keyspace@0__:s set",
+                "__keyspace@0__:s rename_from",
+                "__keyspace@0__:t rename_to",
+                "__keyspace@0__:t del",
+            ]
+        );
+
+        run(&storage, &["CONFIG", "SET", "notify-keyspace-events", "Et"]);
+        run(&storage, &["XADD", "st", "1-1", "f", "v"]);
+        run(&storage, &["XGROUP", "CREATE", "st", "g", "0"]);
+        run(
+            &storage,
+            &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "st", ">"],
+        );
+        run(
+            &storage,
+            &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "st", ">"],
+        );
+        assert_eq!(
+            received(&mut rx),
+            [
+                "__keyevent@0__:xadd st",
+                "__keyevent@0__:xgroup-create st",
+                "__keyevent@0__:xgroup-createconsumer st",
+            ]
+        );
+    }
+}
@@ -0,0 +1,477 @@
+use super::{array, bulk_string, integer};
+use crate::{
+    cmd::PubSub,
+    glob,
+    resp::{Array, Key, Null, Resp},
+    slot::key_slot,
+};
+use dashmap::DashMap;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// The subscribers of a channel or pattern, by subscriber id.
+type Subscribers = HashMap<u64, UnboundedSender<Resp>>;
+
+/// What a subscription is to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Channel,
+    Pattern,
+    /// A shard channel, which lives in the hash slot of its name.
+    Shard,
+}
+
+impl Kind {
+    /// The names of the subscribe and unsubscribe confirmations.
+    fn replies(self) -> (&'static str, &'static str) {
+        match self {
+            Kind::Channel => ("subscribe", "unsubscribe"),
+            Kind::Pattern => ("psubscribe", "punsubscribe"),
+            Kind::Shard => ("ssubscribe", "sunsubscribe"),
+        }
+    }
+}
+
+/// Routes published messages to the connections subscribed to them.
+#[derive(Clone, Default)]
+pub struct Broker {
+    channels: Arc<DashMap<Key, Subscribers>>,
+    patterns: Arc<DashMap<Key, Subscribers>>,
+    /// Shard channels grouped by hash slot, so that a slot's channels can
+    /// be found and served by whichever shard owns it.
+    shards: Arc<DashMap<u16, HashMap<Key, Subscribers>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Broker {
+    /// Sends `message` to the subscribers of `channel` and of every pattern
+    /// matching it, returning how many receivers there were.
+    pub fn publish(&self, channel: &Key, message: &Resp) -> usize {
+        let mut receivers = 0;
+        if let Some(subscribers) = self.channels.get(channel) {
+            let push = push([
+                bulk_string("message"),
+                channel.clone().into(),
+                message.clone(),
+            ]);
+            receivers += send(&subscribers, &push);
+        }
+        let name = channel.to_string();
+        for entry in self.patterns.iter() {
+            let pattern = entry.key();
+            if !glob::matches(pattern.to_string().as_bytes(), name.as_bytes(), false) {
+                continue;
+            }
+            let push = push([
+                bulk_string("pmessage"),
+                pattern.clone().into(),
+                channel.clone().into(),
+                message.clone(),
+            ]);
+            receivers += send(entry.value(), &push);
+        }
+        receivers
+    }
+
+    /// Sends `message` to the subscribers of the shard channel `channel`.
+    /// Patterns never match shard channels.
+    pub fn spublish(&self, channel: &Key, message: &Resp) -> usize {
+        let Some(slot) = self.shards.get(&slot(channel)) else {
+            return 0;
+        };
+        let Some(subscribers) = slot.get(channel) else {
+            return 0;
+        };
+        let push = push([
+            bulk_string("smessage"),
+            channel.clone().into(),
+            message.clone(),
+        ]);
+        send(subscribers, &push)
+    }
+
+    /// The channels with at least one subscriber, optionally only those
+    /// matching `pattern`.
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<Key> {
+        let channels = self.channels.iter().map(|entry| entry.key().clone());
+        matching(channels, pattern)
+    }
+
+    pub fn numsub(&self, channel: &Key) -> usize {
+        self.channels.get(channel).map_or(0, |s| s.len())
+    }
+
+    /// The number of distinct patterns subscribed to.
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// The shard channels with at least one subscriber, optionally only
+    /// those matching `pattern`.
+    pub fn shard_channels(&self, pattern: Option<&str>) -> Vec<Key> {
+        let channels = self
+            .shards
+            .iter()
+            .flat_map(|slot| slot.keys().cloned().collect::<Vec<_>>());
+        matching(channels, pattern)
+    }
+
+    pub fn shard_numsub(&self, channel: &Key) -> usize {
+        self.shards
+            .get(&slot(channel))
+            .and_then(|slot| slot.get(channel).map(|s| s.len()))
+            .unwrap_or(0)
+    }
+
+    pub(super) fn pubsub(&self, c: &PubSub) -> Resp {
+        let numsub =
+            |channels: &[Key], numsub: &dyn Fn(&Key) -> usize| {
+                array(channels.iter().flat_map(|channel| {
+                    [Resp::from(channel.clone()), integer(numsub(channel) as i64)]
+                }))
+            };
+        match c {
+            PubSub::Channels { pattern } => array(
+                self.channels(pattern.as_deref())
+                    .into_iter()
+                    .map(Resp::from),
+            ),
+            PubSub::NumSub { channels } => numsub(channels, &|c| self.numsub(c)),
+            PubSub::NumPat => integer(self.numpat() as i64),
+            PubSub::ShardChannels { pattern } => array(
+                self.shard_channels(pattern.as_deref())
+                    .into_iter()
+                    .map(Resp::from),
+            ),
+            PubSub::ShardNumSub { channels } => numsub(channels, &|c| self.shard_numsub(c)),
+        }
+    }
+
+    fn register(&self, kind: Kind, name: &Key, id: u64, tx: &UnboundedSender<Resp>) {
+        match kind {
+            Kind::Channel => self.channels.entry(name.clone()).or_default(),
+            Kind::Pattern => self.patterns.entry(name.clone()).or_default(),
+            Kind::Shard => {
+                let mut slot = self.shards.entry(slot(name)).or_default();
+                slot.entry(name.clone()).or_default().insert(id, tx.clone());
+                return;
+            }
+        }
+        .insert(id, tx.clone());
+    }
+
+    fn unregister(&self, kind: Kind, name: &Key, id: u64) {
+        let remove = |_: &Key, subscribers: &mut Subscribers| {
+            subscribers.remove(&id);
+            subscribers.is_empty()
+        };
+        match kind {
+            Kind::Channel => {
+                self.channels.remove_if_mut(name, remove);
+            }
+            Kind::Pattern => {
+                self.patterns.remove_if_mut(name, remove);
+            }
+            Kind::Shard => {
+                self.shards.remove_if_mut(&slot(name), |_, slot| {
+                    if slot.get_mut(name).is_some_and(|s| remove(name, s)) {
+                        slot.remove(name);
+                    }
+                    slot.is_empty()
+                });
+            }
+        }
+    }
+}
+
+fn slot(channel: &Key) -> u16 {
+    key_slot(channel.to_string().as_bytes())
+}
+
+fn send(subscribers: &Subscribers, push: &Resp) -> usize {
+    subscribers
+        .values()
+        .filter(|tx| tx.send(push.clone()).is_ok())
+        .count()
+}
+
+fn matching(channels: impl Iterator<Item = Key>, pattern: Option<&str>) -> Vec<Key> {
+    channels
+        .filter(|channel| match pattern {
+            Some(p) => glob::matches(p.as_bytes(), channel.to_string().as_bytes(), false),
+            None => true,
+        })
+        .collect()
+}
+
+fn push<const N: usize>(items: [Resp; N]) -> Resp {
+    Resp::Push(Array::new(items.into(), false))
+}
+
+/// A connection's subscriptions. Messages for them arrive on the receiver
+/// handed out by `Subscriber::new`, and dropping the subscriber cancels
+/// them all.
+pub struct Subscriber {
+    id: u64,
+    broker: Broker,
+    tx: UnboundedSender<Resp>,
+    channels: BTreeSet<Key>,
+    patterns: BTreeSet<Key>,
+    shard_channels: BTreeSet<Key>,
+}
+
+impl Subscriber {
+    pub fn new(broker: &Broker) -> (Self, UnboundedReceiver<Resp>) {
+        let (tx, rx) = unbounded_channel();
+        let subscriber = Subscriber {
+            id: broker.next_id.fetch_add(1, Ordering::Relaxed),
+            broker: broker.clone(),
+            tx,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+        };
+        (subscriber, rx)
+    }
+
+    /// Whether the connection is in subscribed mode.
+    pub fn is_subscribed(&self) -> bool {
+        self.count(Kind::Channel) + self.count(Kind::Shard) > 0
+    }
+
+    /// A sender for the connection's messages, for anything other than the
+    /// broker that pushes to it.
+    pub(super) fn sender(&self) -> UnboundedSender<Resp> {
+        self.tx.clone()
+    }
+
+    pub(super) fn has_channel(&self, channel: &Key) -> bool {
+        self.channels.contains(channel)
+    }
+
+    /// How many channels, patterns and shard channels the connection is
+    /// subscribed to.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.channels.len(),
+            self.patterns.len(),
+            self.shard_channels.len(),
+        )
+    }
+
+    /// The count reported in confirmations: shard channels are counted
+    /// apart from channels and patterns.
+    fn count(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+            Kind::Shard => self.shard_channels.len(),
+        }
+    }
+
+    fn subscribed(&mut self, kind: Kind) -> &mut BTreeSet<Key> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Subscribes to `channels`, replying with a confirmation for each.
+    pub fn subscribe(&mut self, channels: &[Key]) -> Vec<Resp> {
+        self.add(Kind::Channel, channels)
+    }
+
+    /// Unsubscribes from `channels`, or from all channels if none are given.
+    pub fn unsubscribe(&mut self, channels: &[Key]) -> Vec<Resp> {
+        self.remove(Kind::Channel, channels)
+    }
+
+    pub fn psubscribe(&mut self, patterns: &[Key]) -> Vec<Resp> {
+        self.add(Kind::Pattern, patterns)
+    }
+
+    pub fn punsubscribe(&mut self, patterns: &[Key]) -> Vec<Resp> {
+        self.remove(Kind::Pattern, patterns)
+    }
+
+    pub fn ssubscribe(&mut self, channels: &[Key]) -> Vec<Resp> {
+        self.add(Kind::Shard, channels)
+    }
+
+    pub fn sunsubscribe(&mut self, channels: &[Key]) -> Vec<Resp> {
+        self.remove(Kind::Shard, channels)
+    }
+
+    fn add(&mut self, kind: Kind, names: &[Key]) -> Vec<Resp> {
+        let (reply, _) = kind.replies();
+        let mut replies = Vec::with_capacity(names.len());
+        for name in names {
+            if self.subscribed(kind).insert(name.clone()) {
+                self.broker.register(kind, name, self.id, &self.tx);
+            }
+            let count = integer(self.count(kind) as i64);
+            replies.push(push([bulk_string(reply), name.clone().into(), count]));
+        }
+        replies
+    }
+
+    fn remove(&mut self, kind: Kind, names: &[Key]) -> Vec<Resp> {
+        let (_, reply) = kind.replies();
+        let names = match names {
+            [] => self.subscribed(kind).iter().cloned().collect(),
+            names => names.to_vec(),
+        };
+        if names.is_empty() {
+            let count = integer(self.count(kind) as i64);
+            return vec![push([bulk_string(reply), Resp::Null(Null), count])];
+        }
+        let mut replies = Vec::with_capacity(names.len());
+        for name in names {
+            if self.subscribed(kind).remove(&name) {
+                self.broker.unregister(kind, &name, self.id);
+            }
+            let count = integer(self.count(kind) as i64);
+            replies.push(push([bulk_string(reply), name.into(), count]));
+        }
+        replies
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        for kind in [Kind::Channel, Kind::Pattern, Kind::Shard] {
+            for name in std::mem::take(self.subscribed(kind)) {
+                self.broker.unregister(kind, &name, self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    #[test]
+    fn test_publish() {
+        let broker = Broker::default();
+        let (mut a, mut a_rx) = Subscriber::new(&broker);
+        let (mut b, mut b_rx) = Subscriber::new(&broker);
+        assert_eq!(
+            a.subscribe(&[key("news"), key("news")]),
+            vec![
+                push([bulk_string("subscribe"), bulk_string("news"), integer(1)]),
+                push([bulk_string("subscribe"), bulk_string("news"), integer(1)]),
+            ]
+        );
+        b.psubscribe(&[key("n*")]);
+        assert!(a.is_subscribed() && b.is_subscribed());
+        assert_eq!(broker.numsub(&key("news")), 1);
+        assert_eq!(broker.numpat(), 1);
+
+        assert_eq!(broker.publish(&key("news"), &bulk_string("hi")), 2);
+        assert_eq!(
+            a_rx.try_recv().unwrap(),
+            push([
+                bulk_string("message"),
+                bulk_string("news"),
+                bulk_string("hi")
+            ])
+        );
+        assert_eq!(
+            b_rx.try_recv().unwrap(),
+            push([
+                bulk_string("pmessage"),
+                bulk_string("n*"),
+                bulk_string("news"),
+                bulk_string("hi")
+            ])
+        );
+        assert_eq!(broker.publish(&key("other"), &bulk_string("hi")), 0);
+        assert!(a_rx.try_recv().is_err() && b_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let broker = Broker::default();
+        let (mut sub, _rx) = Subscriber::new(&broker);
+        sub.subscribe(&[key("a"), key("b")]);
+        sub.psubscribe(&[key("c*")]);
+        assert_eq!(broker.channels(Some("a*")), vec![key("a")]);
+
+        let replies = sub.unsubscribe(&[]);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            replies[1],
+            push([bulk_string("unsubscribe"), bulk_string("b"), integer(1)])
+        );
+        assert_eq!(
+            sub.unsubscribe(&[]),
+            vec![push([
+                bulk_string("unsubscribe"),
+                Resp::Null(Null),
+                integer(1)
+            ])]
+        );
+        assert!(broker.channels(None).is_empty());
+        assert!(sub.is_subscribed());
+
+        drop(sub);
+        assert_eq!(broker.numpat(), 0);
+    }
+
+    #[test]
+    fn test_shard_channels() {
+        let broker = Broker::default();
+        let (mut a, mut a_rx) = Subscriber::new(&broker);
+        let (mut b, mut b_rx) = Subscriber::new(&broker);
+        a.subscribe(&[key("{orders}.new")]);
+        assert_eq!(
+            a.ssubscribe(&[key("{orders}.new"), key("{orders}.done")]),
+            vec![
+                push([
+                    bulk_string("ssubscribe"),
+                    bulk_string("{orders}.new"),
+                    integer(1)
+                ]),
+                push([
+                    bulk_string("ssubscribe"),
+                    bulk_string("{orders}.done"),
+                    integer(2)
+                ]),
+            ]
+        );
+        b.psubscribe(&[key("*")]);
+        assert_eq!(broker.shard_numsub(&key("{orders}.new")), 1);
+        assert_eq!(broker.shards.len(), 1);
+        let mut channels = broker.shard_channels(Some("*.done"));
+        channels.sort();
+        assert_eq!(channels, vec![key("{orders}.done")]);
+
+        assert_eq!(broker.spublish(&key("{orders}.new"), &bulk_string("1")), 1);
+        assert_eq!(
+            a_rx.try_recv().unwrap(),
+            push([
+                bulk_string("smessage"),
+                bulk_string("{orders}.new"),
+                bulk_string("1")
+            ])
+        );
+        assert!(a_rx.try_recv().is_err() && b_rx.try_recv().is_err());
+
+        assert_eq!(a.sunsubscribe(&[]).len(), 2);
+        assert!(a.is_subscribed());
+        assert!(broker.shards.is_empty());
+        assert_eq!(broker.spublish(&key("{orders}.new"), &bulk_string("2")), 0);
+    }
+}
@@ -0,0 +1,198 @@
+//! A chunked deque in the spirit of Redis's quicklist: elements live in
+//! small nodes so pushes and pops at either end are O(1) and never move the
+//! whole list, while inserts and removals in the middle only shift elements
+//! within one node.
+
+use crate::resp::Resp;
+use std::collections::VecDeque;
+
+/// Maximum number of elements per node.
+const NODE_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct QuickList {
+    nodes: VecDeque<VecDeque<Resp>>,
+    len: usize,
+}
+
+impl QuickList {
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(super) fn push_front(&mut self, value: Resp) {
+        match self.nodes.front_mut() {
+            Some(node) if node.len() < NODE_SIZE => node.push_front(value),
+            _ => self.nodes.push_front(VecDeque::from([value])),
+        }
+        self.len += 1;
+    }
+
+    pub(super) fn push_back(&mut self, value: Resp) {
+        match self.nodes.back_mut() {
+            Some(node) if node.len() < NODE_SIZE => node.push_back(value),
+            _ => self.nodes.push_back(VecDeque::from([value])),
+        }
+        self.len += 1;
+    }
+
+    pub(super) fn pop_front(&mut self) -> Option<Resp> {
+        let node = self.nodes.front_mut()?;
+        let value = node.pop_front();
+        if node.is_empty() {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        value
+    }
+
+    pub(super) fn pop_back(&mut self) -> Option<Resp> {
+        let node = self.nodes.back_mut()?;
+        let value = node.pop_back();
+        if node.is_empty() {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        value
+    }
+
+    pub(super) fn get(&self, index: usize) -> Option<&Resp> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].get(offset)
+    }
+
+    pub(super) fn get_mut(&mut self, index: usize) -> Option<&mut Resp> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].get_mut(offset)
+    }
+
+    /// Inserts `value` so that it ends up at `index`. Full nodes are split in
+    /// half first.
+    pub(super) fn insert(&mut self, index: usize, value: Resp) {
+        if index == self.len {
+            return self.push_back(value);
+        }
+        let Some((mut node, mut offset)) = self.locate(index) else {
+            return;
+        };
+        if self.nodes[node].len() >= NODE_SIZE {
+            let tail = self.nodes[node].split_off(NODE_SIZE / 2);
+            self.nodes.insert(node + 1, tail);
+            if offset >= NODE_SIZE / 2 {
+                node += 1;
+                offset -= NODE_SIZE / 2;
+            }
+        }
+        self.nodes[node].insert(offset, value);
+        self.len += 1;
+    }
+
+    pub(super) fn remove(&mut self, index: usize) -> Option<Resp> {
+        let (node, offset) = self.locate(index)?;
+        let value = self.nodes[node].remove(offset);
+        if self.nodes[node].is_empty() {
+            self.nodes.remove(node);
+        }
+        self.len -= 1;
+        value
+    }
+
+    pub(super) fn iter(&self) -> impl DoubleEndedIterator<Item = &Resp> {
+        self.nodes.iter().flatten()
+    }
+
+    /// Finds the node holding `index` and the offset within it, walking from
+    /// whichever end is closer.
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+        if index < self.len / 2 {
+            let mut offset = index;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if offset < node.len() {
+                    return Some((i, offset));
+                }
+                offset -= node.len();
+            }
+        } else {
+            let mut remaining = self.len - index;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if remaining <= node.len() {
+                    return Some((i, node.len() - remaining));
+                }
+                remaining -= node.len();
+            }
+        }
+        None
+    }
+}
+
+impl FromIterator<Resp> for QuickList {
+    fn from_iter<T: IntoIterator<Item = Resp>>(iter: T) -> Self {
+        let mut list = QuickList::default();
+        for value in iter {
+            list.push_back(value);
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::Integer;
+
+    fn value(n: i64) -> Resp {
+        Resp::Integer(Integer::new(n))
+    }
+
+    fn values(list: &QuickList) -> Vec<i64> {
+        list.iter()
+            .map(|v| match v {
+                Resp::Integer(i) => i.value(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut list = QuickList::default();
+        for i in 0..1000 {
+            list.push_back(value(i));
+            list.push_front(value(-i - 1));
+        }
+        assert_eq!(list.len(), 2000);
+        assert_eq!(list.get(0), Some(&value(-1000)));
+        assert_eq!(list.get(1999), Some(&value(999)));
+        assert_eq!(list.get(1000), Some(&value(0)));
+        for i in 0..1000 {
+            assert_eq!(list.pop_front(), Some(value(-1000 + i)));
+            assert_eq!(list.pop_back(), Some(value(999 - i)));
+        }
+        assert!(list.is_empty());
+        assert_eq!(list.pop_front(), None);
+        assert_eq!(list.pop_back(), None);
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut list: QuickList = (0..300).map(value).collect();
+        list.insert(100, value(-1));
+        list.insert(0, value(-2));
+        list.insert(302, value(-3));
+        assert_eq!(list.len(), 303);
+        assert_eq!(list.get(0), Some(&value(-2)));
+        assert_eq!(list.get(101), Some(&value(-1)));
+        assert_eq!(list.get(302), Some(&value(-3)));
+        assert_eq!(list.remove(101), Some(value(-1)));
+        assert_eq!(list.remove(0), Some(value(-2)));
+        assert_eq!(list.remove(300), Some(value(-3)));
+        assert_eq!(values(&list), (0..300).collect::<Vec<_>>());
+    }
+}
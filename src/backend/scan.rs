@@ -0,0 +1,320 @@
+//! Cursor machinery shared by `SCAN`, `HSCAN`, `SSCAN` and `ZSCAN`.
+//!
+//! DashMap doesn't expose its buckets, so instead of Redis's reverse-binary
+//! bucket walk the cursor is a position in a fixed order: elements are
+//! visited by increasing hash, with a hasher that doesn't depend on the
+//! collection's state. Inserts, deletes and resizes can't move an element
+//! across the cursor, so every element present for the whole scan is
+//! returned exactly once.
+//!
+//! So that a step can resume from the cursor without walking everything,
+//! the elements are also kept sorted by hash: by each collection in a
+//! `ScanOrder`, and for the keyspaces in a `KeyOrder`. A step then costs
+//! O(log n + COUNT).
+
+use crate::resp::Key;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+};
+
+/// Position of `item` in the scan order.
+pub(super) fn scan_hash<T: Hash + ?Sized>(item: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A collection's elements sorted by `scan_hash`, kept next to the
+/// collection and updated along with it.
+#[derive(Debug, Clone)]
+pub(super) struct ScanOrder<K> {
+    hashes: BTreeMap<u64, Vec<K>>,
+    len: usize,
+}
+
+impl<K> Default for ScanOrder<K> {
+    fn default() -> Self {
+        ScanOrder {
+            hashes: BTreeMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<K: Eq> PartialEq for ScanOrder<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self.hashes.iter().all(|(hash, elements)| {
+                other.hashes.get(hash).is_some_and(|others| {
+                    others.len() == elements.len() && elements.iter().all(|e| others.contains(e))
+                })
+            })
+    }
+}
+
+impl<K: Hash + Eq> ScanOrder<K> {
+    pub(super) fn insert(&mut self, element: K) {
+        let elements = self.hashes.entry(scan_hash(&element)).or_default();
+        if !elements.contains(&element) {
+            elements.push(element);
+            self.len += 1;
+        }
+    }
+
+    pub(super) fn remove(&mut self, element: &K) {
+        let hash = scan_hash(element);
+        let Some(elements) = self.hashes.get_mut(&hash) else {
+            return;
+        };
+        let before = elements.len();
+        elements.retain(|e| e != element);
+        self.len -= before - elements.len();
+        if elements.is_empty() {
+            self.hashes.remove(&hash);
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the elements from `cursor` on, up to `count` of them,
+    /// together with the cursor to resume from (`0` when the scan is
+    /// complete).
+    ///
+    /// Elements sharing a hash are always returned in the same batch, so a
+    /// batch may hold a few more than `count` elements.
+    pub(super) fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&K>) {
+        let count = count.max(1);
+        let mut batch = Vec::new();
+        for (&hash, elements) in self.hashes.range(cursor..) {
+            // Past the first batch, so never `0`.
+            if batch.len() >= count {
+                return (hash, batch);
+            }
+            batch.extend(elements);
+        }
+        (0, batch)
+    }
+
+    /// Like `scan`, but drops the elements `live` rejects instead of
+    /// returning them.
+    fn scan_live(
+        &mut self,
+        cursor: u64,
+        count: usize,
+        live: impl Fn(&K) -> bool,
+    ) -> (Option<u64>, Vec<K>)
+    where
+        K: Clone,
+    {
+        let mut batch = Vec::new();
+        let mut dead = Vec::new();
+        let mut next = None;
+        for (&hash, elements) in self.hashes.range(cursor..) {
+            if batch.len() >= count {
+                next = Some(hash);
+                break;
+            }
+            for element in elements {
+                match live(element) {
+                    true => batch.push(element.clone()),
+                    false => dead.push(element.clone()),
+                }
+            }
+        }
+        for element in &dead {
+            self.remove(element);
+        }
+        (next, batch)
+    }
+
+    fn retain(&mut self, live: impl Fn(&K) -> bool) {
+        self.hashes.retain(|_, elements| {
+            elements.retain(&live);
+            !elements.is_empty()
+        });
+        self.len = self.hashes.values().map(Vec::len).sum();
+    }
+}
+
+/// The fewest keys a shard is swept at, so that small shards aren't swept
+/// over and over.
+const MIN_SWEEP: usize = 64;
+
+/// The keys of a keyspace in scan order. Each shard holds one range of
+/// hashes, so a scan goes through them in turn and creating a key only
+/// locks the shard it falls in.
+///
+/// Keys are added when they are created, but deleting one leaves it in:
+/// scans drop the keys they find gone, and a shard that has doubled in size
+/// since it was last swept drops all of them.
+pub(super) struct KeyOrder {
+    shards: Vec<Mutex<KeyShard>>,
+}
+
+#[derive(Default)]
+struct KeyShard {
+    keys: ScanOrder<Key>,
+    /// How many keys the shard is swept at.
+    sweep_at: usize,
+}
+
+impl Default for KeyOrder {
+    fn default() -> Self {
+        KeyOrder {
+            shards: (0..64).map(|_| Mutex::default()).collect(),
+        }
+    }
+}
+
+impl KeyOrder {
+    /// The shard holding `hash`, and the first hash of the shard after it.
+    fn shard(&self, hash: u64) -> (usize, Option<u64>) {
+        let bits = self.shards.len().trailing_zeros();
+        let shard = (hash >> (64 - bits)) as usize;
+        let next = ((shard + 1) as u64).checked_shl(64 - bits);
+        (shard, next.filter(|_| shard + 1 < self.shards.len()))
+    }
+
+    /// Records that `key` was created. `live` tells whether a key still
+    /// exists, for sweeping.
+    pub(super) fn insert(&self, key: &Key, live: impl Fn(&Key) -> bool) {
+        let (shard, _) = self.shard(scan_hash(key));
+        let mut shard = self.shards[shard].lock().unwrap();
+        shard.keys.insert(key.clone());
+        if shard.keys.len() >= shard.sweep_at {
+            shard.keys.retain(live);
+            shard.sweep_at = (2 * shard.keys.len()).max(MIN_SWEEP);
+        }
+    }
+
+    pub(super) fn clear(&self) {
+        for shard in &self.shards {
+            *shard.lock().unwrap() = KeyShard::default();
+        }
+    }
+
+    /// One `SCAN` step over the keys `live` accepts; see `ScanOrder::scan`.
+    /// Each shard stays locked while `live` checks its keys, so a key
+    /// created meanwhile can't be dropped as gone.
+    pub(super) fn scan(
+        &self,
+        mut cursor: u64,
+        count: usize,
+        live: impl Fn(&Key) -> bool,
+    ) -> (u64, Vec<Key>) {
+        let count = count.max(1);
+        let mut batch = Vec::new();
+        loop {
+            let (shard, next_shard) = self.shard(cursor);
+            let (next, keys) = self.shards[shard].lock().unwrap().keys.scan_live(
+                cursor,
+                count - batch.len().min(count),
+                &live,
+            );
+            batch.extend(keys);
+            if let Some(next) = next {
+                return (next, batch);
+            }
+            match next_shard {
+                Some(next_shard) => cursor = next_shard,
+                None => return (0, batch),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+    use std::collections::HashSet;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    #[test]
+    fn test_scan_all() {
+        let mut order = ScanOrder::<i32>::default();
+        for i in 0..100 {
+            order.insert(i);
+        }
+        order.insert(7);
+        assert_eq!(order.len(), 100);
+        let mut cursor = 0;
+        let mut seen = HashSet::new();
+        loop {
+            let (next, batch) = order.scan(cursor, 7);
+            assert!(batch.len() >= 7 || next == 0);
+            seen.extend(batch.into_iter().copied());
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[test]
+    fn test_scan_under_modification() {
+        let mut order = ScanOrder::default();
+        for i in 0..1000 {
+            order.insert(i);
+        }
+        let mut cursor = 0;
+        let mut seen = HashSet::new();
+        let mut round = 0;
+        loop {
+            let (next, batch) = order.scan(cursor, 10);
+            assert!(batch.len() <= 11);
+            seen.extend(batch.into_iter().copied());
+            // Churn elements outside the stable range [0, 500).
+            round += 1;
+            order.remove(&(500 + round));
+            order.insert(10_000 + round);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert!((0..500).all(|i| seen.contains(&i)));
+    }
+
+    #[test]
+    fn test_key_order() {
+        let order = KeyOrder::default();
+        let live: HashSet<Key> = (0..1000).map(|i| key(&format!("k{i}"))).collect();
+        for i in 0..10_000 {
+            order.insert(&key(&format!("k{i}")), |k| live.contains(k));
+        }
+        let total = |order: &KeyOrder| {
+            order
+                .shards
+                .iter()
+                .map(|s| s.lock().unwrap().keys.len())
+                .sum::<usize>()
+        };
+        assert!(total(&order) < 5000, "shards were swept as they grew");
+
+        let mut cursor = 0;
+        let mut seen = Vec::new();
+        loop {
+            let (next, batch) = order.scan(cursor, 10, |k| live.contains(k));
+            assert!(batch.len() <= 11);
+            seen.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 1000);
+        assert_eq!(seen.iter().collect::<HashSet<_>>(), live.iter().collect());
+        assert_eq!(total(&order), 1000, "deleted keys were dropped on the way");
+        order.clear();
+        assert_eq!(order.scan(0, 10, |_| true), (0, Vec::new()));
+    }
+}
@@ -0,0 +1,760 @@
+//! Lua scripting for `EVAL`, `EVALSHA` and `SCRIPT`. Scripts are compiled
+//! once into functions kept in the Lua registry under `f_<sha1>`, and run
+//! with the storage lock held exclusively, so a script is atomic. They
+//! reach the keyspace through `redis.call` and `redis.pcall`, which convert
+//! between Lua values and replies the way Redis does.
+//!
+//! Commands never wait on the lock behind a script: a connection waits for
+//! the script to end before running its command, without holding a runtime
+//! thread, and a command that finds one in the way anyway fails with
+//! `BUSY`. So does every command once the script has run for
+//! `busy-reply-threshold`, until it ends or, if it hasn't written anything,
+//! `SCRIPT KILL` stops it.
+
+use super::{bulk_string, integer, Locked, Storage, StorageError};
+use crate::{
+    cmd::{Command, CommandError, Eval, Script},
+    resp::{Array, BulkString, Integer, Key, Null, Resp, SimpleError, SimpleString},
+};
+use mlua::{Function, HookTriggers, Lua, LuaOptions, StdLib, Table, Value, Variadic};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, LockResult, Mutex, TryLockError, TryLockResult,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// The default `busy-reply-threshold`, in milliseconds.
+pub(super) const BUSY_THRESHOLD: u64 = 5000;
+
+/// How many instructions a script runs between checks for `SCRIPT KILL`.
+const HOOK_INSTRUCTIONS: u32 = 100_000;
+
+/// Sets up the `redis` library and returns a function that wraps
+/// `redis.pcall` into `redis.call`, which raises error replies instead of
+/// returning them.
+const PRELUDE: &str = r#"
+redis = {
+    LOG_DEBUG = 0,
+    LOG_VERBOSE = 1,
+    LOG_NOTICE = 2,
+    LOG_WARNING = 3,
+    log = function() end,
+    error_reply = function(msg) return { err = msg } end,
+    status_reply = function(msg) return { ok = msg } end,
+}
+loadfile = nil
+dofile = nil
+setmetatable(_G, {
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __metatable = false,
+})
+return function(pcall)
+    return function(...)
+        local reply = pcall(...)
+        if type(reply) == 'table' and reply.err then
+            error(reply)
+        end
+        return reply
+    end
+end
+"#;
+
+/// Creates an interpreter with the `redis` library, short of `call` and
+/// `pcall`, which only exist while a script runs. Scripts can't create
+/// globals, so that one can't leave state behind for the next.
+pub(super) fn new_lua() -> mlua::Result<Lua> {
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH;
+    let lua = Lua::new_with(libs, LuaOptions::default())?;
+    {
+        let make_call: Function = lua.load(PRELUDE).set_name("@prelude").call(())?;
+        lua.set_named_registry_value("make_call", make_call)?;
+        // Kept aside so scripts redefining `pcall` can't break `EVAL`.
+        let pcall: Function = lua.globals().get("pcall")?;
+        lua.set_named_registry_value("pcall", pcall)?;
+        let redis: Table = lua.globals().get("redis")?;
+        let sha1hex = lua.create_function(|_, s: mlua::String| Ok(sha1hex(s.as_bytes())))?;
+        redis.set("sha1hex", sha1hex)?;
+    }
+    Ok(lua)
+}
+
+/// How a script is handed its keys and arguments.
+pub(super) enum Calling {
+    /// `EVAL` scripts find them in the `KEYS` and `ARGV` globals.
+    Script,
+    /// Functions are called with them, and may be barred from writing.
+    Function { read_only: bool },
+}
+
+/// Calls `f` against `storage`, whose lock the caller holds exclusively.
+/// Error replies the script raises become its reply; other errors are
+/// returned as their message.
+pub(super) fn call<'lua>(
+    lua: &'lua Lua,
+    storage: &Storage,
+    f: Function<'lua>,
+    keys: &[Key],
+    args: &[Vec<u8>],
+    calling: Calling,
+) -> Result<Resp, String> {
+    let read_only = matches!(calling, Calling::Function { read_only: true });
+    // `SELECT` in a script switches databases for the rest of the script.
+    let view = RefCell::new(storage.clone());
+    let kill = storage.running.start();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        move |_, _| match kill.load(Ordering::Relaxed) {
+            true => Err(mlua::Error::runtime(
+                "Script killed by user with SCRIPT KILL...",
+            )),
+            false => Ok(()),
+        },
+    );
+    let res = lua.scope(|scope| {
+        let pcall = scope.create_function(|lua, args: Variadic<Value>| {
+            let reply = match script_call(&view, &args, read_only) {
+                Ok(resp) => to_lua(lua, resp.into_resp2())?,
+                Err(e) => error_table(lua, e.to_string())?,
+            };
+            Ok(reply)
+        })?;
+        let make_call: Function = lua.named_registry_value("make_call")?;
+        let redis: Table = lua.globals().get("redis")?;
+        redis.set("call", make_call.call::<_, Function>(pcall.clone())?)?;
+        redis.set("pcall", pcall)?;
+        let keys = keys
+            .iter()
+            .map(|key| lua.create_string(key_bytes(key)))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let keys = lua.create_sequence_from(keys)?;
+        let args = args
+            .iter()
+            .map(|arg| lua.create_string(arg))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let args = lua.create_sequence_from(args)?;
+
+        let pcall: Function = lua.named_registry_value("pcall")?;
+        let (ok, value): (bool, Value) = match calling {
+            Calling::Script => {
+                lua.globals().raw_set("KEYS", keys)?;
+                lua.globals().raw_set("ARGV", args)?;
+                pcall.call(f)?
+            }
+            Calling::Function { .. } => pcall.call((f, keys, args))?,
+        };
+        let reply = match value {
+            _ if ok => Ok(from_lua(value)?),
+            // An error reply raised by `redis.call` or `error()` is
+            // passed on as is.
+            Value::Table(t) => match t.raw_get("err")? {
+                Value::String(e) => Ok(Resp::SimpleError(SimpleError::new(e.to_string_lossy()))),
+                _ => Err("Unknown error".to_string()),
+            },
+            Value::String(e) => Err(e.to_string_lossy().into_owned()),
+            Value::Error(e) => Err(error_message(&e)),
+            _ => Err("Unknown error".to_string()),
+        };
+        redis.set("call", Value::Nil)?;
+        redis.set("pcall", Value::Nil)?;
+        Ok(reply)
+    });
+    lua.remove_hook();
+    storage.running.stop();
+    res.unwrap_or_else(|e| Err(error_message(&e)))
+}
+
+/// The script in flight, which other connections look at without waiting
+/// for the storage lock the script holds.
+#[derive(Default)]
+pub(super) struct Running {
+    /// How many scripts run or wait for the storage lock.
+    scripts: AtomicUsize,
+    /// Woken when a script ends.
+    ended: Notify,
+    /// When the script started, while one runs.
+    started: Mutex<Option<Instant>>,
+    /// Set by `SCRIPT KILL`, for the script's hook to raise an error.
+    kill: Arc<AtomicBool>,
+    /// Set once the script has run a write command, after which stopping it
+    /// would leave the dataset half updated.
+    wrote: AtomicBool,
+}
+
+impl Running {
+    /// Records that a script started, returning the flag that tells it to
+    /// stop.
+    fn start(&self) -> Arc<AtomicBool> {
+        let mut started = self.started.lock().unwrap();
+        *started = Some(Instant::now());
+        self.kill.store(false, Ordering::Relaxed);
+        self.wrote.store(false, Ordering::Relaxed);
+        self.kill.clone()
+    }
+
+    fn stop(&self) {
+        *self.started.lock().unwrap() = None;
+    }
+
+    /// Counts a script in from before it asks for the storage lock, so that
+    /// no command starts waiting on the lock behind it, until the returned
+    /// guard is dropped.
+    pub(super) fn enter(&self) -> Entered<'_> {
+        self.scripts.fetch_add(1, Ordering::SeqCst);
+        Entered(self)
+    }
+}
+
+/// A script counted in by `Running::enter`.
+pub(super) struct Entered<'a>(&'a Running);
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        self.0.scripts.fetch_sub(1, Ordering::SeqCst);
+        self.0.ended.notify_waiters();
+    }
+}
+
+/// The message of a Lua error, without the tracebacks of the Rust
+/// callbacks it went through or of the error itself.
+pub(super) fn error_message(e: &mlua::Error) -> String {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        mlua::Error::RuntimeError(msg) => match msg.split_once("\nstack traceback:") {
+            Some((msg, _)) => msg.to_string(),
+            None => msg.clone(),
+        },
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+/// The Lua interpreter and the digests of the scripts compiled into it.
+pub(super) struct Scripts {
+    lua: Lua,
+    shas: HashSet<String>,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts {
+            lua: new_lua().expect("failed to set up the Lua interpreter"),
+            shas: HashSet::new(),
+        }
+    }
+}
+
+impl Scripts {
+    /// Compiles `script` unless it is cached already, returning its digest.
+    fn load(&mut self, script: &str) -> Result<String, StorageError> {
+        let sha = sha1hex(script.as_bytes());
+        if !self.shas.contains(&sha) {
+            let compile = |e| StorageError::ScriptCompile(error_message(&e));
+            let f = self
+                .lua
+                .load(script)
+                .set_name("@user_script")
+                .into_function()
+                .map_err(compile)?;
+            self.lua
+                .set_named_registry_value(&format!("f_{sha}"), f)
+                .map_err(compile)?;
+            self.shas.insert(sha.clone());
+        }
+        Ok(sha)
+    }
+
+    /// Runs the cached script `sha` against `storage`, whose lock the
+    /// caller holds exclusively.
+    fn run(&self, storage: &Storage, sha: &str, c: &Eval) -> Result<Resp, StorageError> {
+        let name = format!("f_{sha}");
+        let f = self
+            .lua
+            .named_registry_value(&name)
+            .map_err(|e| StorageError::ScriptRuntime(name.clone(), error_message(&e)))?;
+        call(&self.lua, storage, f, &c.keys, &c.args, Calling::Script)
+            .map_err(|msg| StorageError::ScriptRuntime(name, msg))
+    }
+}
+
+impl Storage {
+    /// Runs `EVAL`, or `EVALSHA` if `by_sha` is set.
+    pub(super) fn eval(&self, c: Eval, by_sha: bool) -> Result<Resp, StorageError> {
+        let mut scripts = self.scripts.lock().unwrap();
+        let sha = if by_sha {
+            let sha = c.script.to_lowercase();
+            if !scripts.shas.contains(&sha) {
+                return Err(StorageError::NoScript);
+            }
+            sha
+        } else {
+            scripts.load(&c.script)?
+        };
+        scripts.run(self, &sha, &c)
+    }
+
+    pub(super) fn execute_script(&self, c: Script) -> Result<Resp, StorageError> {
+        // The script running holds `scripts`.
+        if let Script::Kill = c {
+            return self.kill_script();
+        }
+        let mut scripts = self.scripts.lock().unwrap();
+        match c {
+            Script::Load { script } => Ok(bulk_string(scripts.load(&script)?)),
+            Script::Exists { shas } => Ok(Resp::Array(Array::new(
+                shas.iter()
+                    .map(|sha| integer(scripts.shas.contains(&sha.to_lowercase()) as i64))
+                    .collect(),
+                false,
+            ))),
+            Script::Flush => {
+                *scripts = Scripts::default();
+                Ok(super::ok())
+            }
+            Script::Kill => unreachable!("handled above"),
+        }
+    }
+
+    /// Stops the script or function running, unless it has written.
+    fn kill_script(&self) -> Result<Resp, StorageError> {
+        let started = self.running.started.lock().unwrap();
+        if started.is_none() {
+            return Err(StorageError::NotBusy);
+        }
+        if self.running.wrote.load(Ordering::Relaxed) {
+            return Err(StorageError::Unkillable);
+        }
+        self.running.kill.store(true, Ordering::Relaxed);
+        Ok(super::ok())
+    }
+
+    /// Waits for the scripts running or about to, failing with `BUSY` once
+    /// the one running has run for `busy-reply-threshold`.
+    pub async fn wait_for_script(&self) -> Result<(), StorageError> {
+        loop {
+            let ended = self.running.ended.notified();
+            tokio::pin!(ended);
+            // Registered before the check, so an end right after it isn't
+            // missed.
+            ended.as_mut().enable();
+            if self.running.scripts.load(Ordering::SeqCst) == 0 {
+                return Ok(());
+            }
+            let threshold = Duration::from_millis(self.busy_threshold.load(Ordering::Relaxed));
+            let elapsed = self
+                .running
+                .started
+                .lock()
+                .unwrap()
+                .map_or(Duration::ZERO, |started| started.elapsed());
+            if elapsed >= threshold {
+                return Err(StorageError::Busy);
+            }
+            let _ = tokio::time::timeout(threshold - elapsed, ended).await;
+        }
+    }
+
+    /// Takes the storage lock with `try_lock`, or else waits for it with
+    /// `lock`, unless a script other than the caller's own (if `script`) is
+    /// running or about to: that could hold the lock for long, so this
+    /// fails with `BUSY` instead.
+    pub(super) fn lock_unless_busy<G>(
+        &self,
+        script: bool,
+        try_lock: impl FnOnce() -> TryLockResult<G>,
+        lock: impl FnOnce() -> LockResult<G>,
+    ) -> Result<G, StorageError> {
+        match try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(e)) => panic!("{e}"),
+            Err(TryLockError::WouldBlock) => {}
+        }
+        if self.running.scripts.load(Ordering::SeqCst) > script as usize {
+            return Err(StorageError::Busy);
+        }
+        Ok(lock().unwrap())
+    }
+}
+
+/// Runs a command for `redis.call` or `redis.pcall` against the
+/// database `view` is on, refusing writes if `read_only` is set.
+fn script_call(view: &RefCell<Storage>, args: &[Value], read_only: bool) -> anyhow::Result<Resp> {
+    if args.is_empty() {
+        return Err(StorageError::ScriptNoArgs.into());
+    }
+    let args = args
+        .iter()
+        .map(|arg| {
+            let s = match arg {
+                Value::String(s) => s.as_bytes().to_vec(),
+                Value::Integer(i) => i.to_string().into_bytes(),
+                Value::Number(n) if n.fract() == 0.0 => (*n as i64).to_string().into_bytes(),
+                Value::Number(n) => n.to_string().into_bytes(),
+                _ => return Err(StorageError::ScriptArgType),
+            };
+            Ok(Resp::BulkString(BulkString::new(s, false)))
+        })
+        .collect::<Result<_, _>>()?;
+    let cmd = match Command::try_from(Resp::Array(Array::new(args, false))) {
+        Err(CommandError::UnsupportedCommand(_)) => {
+            return Err(StorageError::UnknownScriptCommand.into())
+        }
+        cmd => cmd?,
+    };
+    if !is_allowed_from_script(&cmd) {
+        return Err(StorageError::NotAllowedFromScript.into());
+    }
+    if read_only && cmd.is_write() {
+        return Err(StorageError::WriteFromReadOnlyScript.into());
+    }
+    if cmd.is_write() {
+        view.borrow().running.wrote.store(true, Ordering::Relaxed);
+    }
+    if let Command::Select(c) = &cmd {
+        let db = view.borrow().select(c.db)?;
+        *view.borrow_mut() = db;
+        return Ok(super::ok());
+    }
+    let storage = view.borrow();
+    cmd.execute(&Locked::new(&storage))
+}
+
+/// Commands that only make sense on a connection, or would run scripts
+/// from scripts.
+fn is_allowed_from_script(cmd: &Command) -> bool {
+    !matches!(
+        cmd,
+        Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch(_)
+            | Command::Unwatch
+            | Command::Hello(_)
+            | Command::Quit
+            | Command::Reset
+            | Command::Client(_)
+            | Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::PSubscribe(_)
+            | Command::PUnsubscribe(_)
+            | Command::SSubscribe(_)
+            | Command::SUnsubscribe(_)
+            | Command::Eval(_)
+            | Command::EvalSha(_)
+            | Command::Script(_)
+            | Command::FCall(_)
+            | Command::FCallRo(_)
+            | Command::Function(_)
+    )
+}
+
+pub(super) fn sha1hex(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+/// The bytes of a key, as the client sent them.
+fn key_bytes(key: &Key) -> Vec<u8> {
+    match key {
+        Key::BulkString(s) => s.value.clone(),
+        key => key.to_string().into_bytes(),
+    }
+}
+
+fn error_table(lua: &Lua, msg: String) -> mlua::Result<Value<'_>> {
+    let t = lua.create_table()?;
+    t.raw_set("err", msg)?;
+    Ok(Value::Table(t))
+}
+
+/// Converts a RESP2 reply to a Lua value: integers become numbers, bulk
+/// strings strings, arrays tables, status and error replies tables with an
+/// `ok` or `err` field, and nulls `false`.
+fn to_lua(lua: &Lua, resp: Resp) -> mlua::Result<Value<'_>> {
+    Ok(match resp {
+        Resp::Integer(i) => Value::Number(i.value() as f64),
+        Resp::BulkString(s) if !s.is_null => Value::String(lua.create_string(&s.value)?),
+        Resp::Array(a) if !a.is_null() => {
+            let t = lua.create_table_with_capacity(a.len(), 0)?;
+            for (i, resp) in a.iter().enumerate() {
+                t.raw_set(i + 1, to_lua(lua, resp.clone())?)?;
+            }
+            Value::Table(t)
+        }
+        Resp::SimpleString(s) => {
+            let t = lua.create_table()?;
+            t.raw_set("ok", s.as_str())?;
+            Value::Table(t)
+        }
+        Resp::SimpleError(e) => error_table(lua, e.as_str().to_string())?,
+        _ => Value::Boolean(false),
+    })
+}
+
+/// Converts a script's return value to a reply: numbers are truncated to
+/// integers, tables with an `err` or `ok` field become error or status
+/// replies and other tables arrays up to their first nil, `true` becomes 1,
+/// and `false` and nil become null.
+fn from_lua(value: Value) -> mlua::Result<Resp> {
+    Ok(match value {
+        Value::Boolean(true) => Resp::Integer(Integer::new(1)),
+        Value::Integer(i) => Resp::Integer(Integer::new(i)),
+        Value::Number(n) => Resp::Integer(Integer::new(n as i64)),
+        Value::String(s) => Resp::BulkString(BulkString::new(s.as_bytes(), false)),
+        Value::Table(t) => {
+            if let Value::String(e) = t.raw_get("err")? {
+                Resp::SimpleError(SimpleError::new(e.to_string_lossy()))
+            } else if let Value::String(s) = t.raw_get("ok")? {
+                Resp::SimpleString(SimpleString::new(s.to_string_lossy()))
+            } else {
+                let items = t
+                    .sequence_values::<Value>()
+                    .map(|v| from_lua(v?))
+                    .collect::<mlua::Result<_>>()?;
+                Resp::Array(Array::new(items, false))
+            }
+        }
+        _ => Resp::Null(Null),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command, CommandExecutor};
+    use std::thread;
+
+    fn run(storage: &Storage, args: &[&str]) -> Resp {
+        let cmd = parse_command(args).unwrap();
+        cmd.execute(storage).unwrap()
+    }
+
+    fn run_err(storage: &Storage, args: &[&str]) -> String {
+        let cmd = parse_command(args).unwrap();
+        cmd.execute(storage).unwrap_err().to_string()
+    }
+
+    fn bulk(s: &str) -> Resp {
+        Resp::BulkString(BulkString::new(s, false))
+    }
+
+    #[test]
+    fn test_conversions() {
+        let storage = Storage::new();
+        let eval = |script: &str| run(&storage, &["EVAL", script, "0"]);
+        assert_eq!(eval("return 3.7"), integer(3));
+        assert_eq!(eval("return 'x'"), bulk("x"));
+        assert_eq!(eval("return true"), integer(1));
+        assert_eq!(eval("return false"), Resp::Null(Null));
+        assert_eq!(eval("return nil"), Resp::Null(Null));
+        assert_eq!(
+            eval("return {1, 'a', {2}, nil, 3}"),
+            Resp::Array(Array::new(
+                vec![
+                    integer(1),
+                    bulk("a"),
+                    Resp::Array(Array::new(vec![integer(2)], false))
+                ],
+                false
+            ))
+        );
+        assert_eq!(
+            eval("return redis.status_reply('FINE')"),
+            Resp::SimpleString(SimpleString::new("FINE"))
+        );
+        assert_eq!(
+            eval("return {err = 'ERR mine'}"),
+            Resp::SimpleError(SimpleError::new("ERR mine"))
+        );
+
+        // Replies going the other way.
+        assert_eq!(
+            eval("return type(redis.call('GET', 'missing'))"),
+            bulk("boolean")
+        );
+        assert_eq!(eval("return redis.call('SET', 'k', 5).ok"), bulk("OK"));
+        assert_eq!(eval("return redis.call('GET', 'k') + 1"), integer(6));
+        run(&storage, &["RPUSH", "l", "a", "b"]);
+        assert_eq!(eval("return #redis.call('LRANGE', 'l', 0, -1)"), integer(2));
+        assert_eq!(
+            eval("return redis.sha1hex('')"),
+            bulk("da39a3ee5e6b4b0d3255bfef95601890afd80709")
+        );
+    }
+
+    #[test]
+    fn test_call_and_pcall() {
+        let storage = Storage::new();
+        run(&storage, &["RPUSH", "l", "a"]);
+        let script = "return redis.call('GET', KEYS[1])";
+        assert_eq!(
+            run(&storage, &["EVAL", script, "1", "l"]),
+            Resp::SimpleError(SimpleError::new(StorageError::WrongType.to_string()))
+        );
+        let script = "local r = redis.pcall('GET', KEYS[1]); return {type(r), r.err}";
+        assert_eq!(
+            run(&storage, &["EVAL", script, "1", "l"]),
+            Resp::Array(Array::new(
+                vec![bulk("table"), bulk(&StorageError::WrongType.to_string())],
+                false
+            ))
+        );
+        let script = "return redis.pcall('MULTI')";
+        assert_eq!(
+            run(&storage, &["EVAL", script, "0"]),
+            Resp::SimpleError(SimpleError::new(
+                StorageError::NotAllowedFromScript.to_string()
+            ))
+        );
+        assert_eq!(
+            run_err(&storage, &["EVAL", "return nosuch()", "0"]),
+            format!(
+                "ERR Error running script (call to f_{}): user_script:1: attempt to call global 'nosuch' (a nil value)",
+                sha1hex(b"return nosuch()")
+            )
+        );
+        assert!(run_err(&storage, &["EVAL", "return (", "0"])
+            .starts_with("ERR Error compiling script (new function): user_script:1:"));
+        assert!(run_err(&storage, &["EVAL", "return os.time()", "0"])
+            .contains("attempt to index global 'os'"));
+    }
+
+    #[test]
+    fn test_binary_values() {
+        let storage = Storage::new();
+        let raw = |bytes: &[u8]| Resp::BulkString(BulkString::new(bytes, false));
+        let (key, value) = (b"k\xff".as_slice(), b"\x00\xfe\xff".as_slice());
+        let script = "redis.call('SET', KEYS[1], ARGV[1]); return redis.call('GET', KEYS[1])";
+        let eval = Command::try_from(Resp::Array(Array::new(
+            vec![
+                raw(b"EVAL"),
+                raw(script.as_bytes()),
+                raw(b"1"),
+                raw(key),
+                raw(value),
+            ],
+            false,
+        )))
+        .unwrap();
+        assert_eq!(eval.execute(&storage).unwrap(), raw(value));
+        let get = Command::try_from(Resp::Array(Array::new(vec![raw(b"GET"), raw(key)], false)));
+        assert_eq!(get.unwrap().execute(&storage).unwrap(), raw(value));
+
+        run(
+            &storage,
+            &["EVAL", "redis.call('SET', 'k', '\\128\\0')", "0"],
+        );
+        assert_eq!(run(&storage, &["GET", "k"]), raw(b"\x80\x00"));
+    }
+
+    #[test]
+    fn test_script_cache() {
+        let storage = Storage::new();
+        let script = "return redis.call('SET', KEYS[1], ARGV[1])";
+        let sha = sha1hex(script.as_bytes());
+        assert_eq!(
+            run_err(&storage, &["EVALSHA", &sha, "1", "k", "v"]),
+            StorageError::NoScript.to_string()
+        );
+        assert_eq!(run(&storage, &["SCRIPT", "LOAD", script]), bulk(&sha));
+        run(&storage, &["EVALSHA", &sha.to_uppercase(), "1", "k", "v"]);
+        assert_eq!(run(&storage, &["GET", "k"]), bulk("v"));
+        assert_eq!(
+            run(&storage, &["SCRIPT", "EXISTS", &sha, "nope"]),
+            Resp::Array(Array::new(vec![integer(1), integer(0)], false))
+        );
+
+        // EVAL caches the scripts it runs too.
+        run(&storage, &["EVAL", "return 1", "0"]);
+        let other = sha1hex(b"return 1");
+        assert_eq!(run(&storage, &["EVALSHA", &other, "0"]), integer(1));
+
+        run(&storage, &["SCRIPT", "FLUSH"]);
+        assert_eq!(
+            run(&storage, &["SCRIPT", "EXISTS", &sha, &other]),
+            Resp::Array(Array::new(vec![integer(0), integer(0)], false))
+        );
+        assert!(storage
+            .execute(parse_command(&["EVALSHA", &sha, "0"]).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_globals() {
+        let storage = Storage::new();
+        assert!(run_err(&storage, &["EVAL", "x = 1", "0"])
+            .contains("Script attempted to create global variable 'x'"));
+        assert!(run_err(&storage, &["EVAL", "setmetatable(_G, nil)", "0"])
+            .contains("cannot change a protected metatable"));
+        assert_eq!(
+            run(
+                &storage,
+                &["EVAL", "local x = 1; return x + #KEYS", "1", "k"]
+            ),
+            integer(2)
+        );
+        assert_eq!(run(&storage, &["EVAL", "return x", "0"]), Resp::Null(Null));
+    }
+
+    /// Runs `script` on a thread of its own, and waits for it to start.
+    fn spawn_eval(storage: &Storage, script: &str) -> thread::JoinHandle<anyhow::Result<Resp>> {
+        let cmd = parse_command(&["EVAL", script, "0"]).unwrap();
+        let running = storage.clone();
+        let handle = thread::spawn(move || cmd.execute(&running));
+        while storage.running.started.lock().unwrap().is_none() && !handle.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn test_script_kill() {
+        let storage = Storage::new();
+        run(&storage, &["CONFIG", "SET", "busy-reply-threshold", "20"]);
+        assert_eq!(
+            run_err(&storage, &["SCRIPT", "KILL"]),
+            StorageError::NotBusy.to_string()
+        );
+
+        let script = spawn_eval(&storage, "while true do end");
+        // Commands don't wait on the lock behind it.
+        assert_eq!(
+            run_err(&storage, &["GET", "k"]),
+            StorageError::Busy.to_string()
+        );
+        // Connections wait for it, then give up with BUSY.
+        assert_eq!(storage.wait_for_script().await, Err(StorageError::Busy));
+        let ok = Resp::SimpleString(SimpleString::new("OK"));
+        assert_eq!(run(&storage, &["SCRIPT", "KILL"]), ok);
+        let err = script.join().unwrap().unwrap_err().to_string();
+        assert!(err.ends_with("Script killed by user with SCRIPT KILL..."));
+        assert_eq!(storage.wait_for_script().await, Ok(()));
+        assert_eq!(run(&storage, &["GET", "k"]), Resp::Null(Null));
+
+        // A script that wrote can't be stopped halfway.
+        let script = spawn_eval(
+            &storage,
+            "redis.call('SET', 'k', 'v'); for i = 1, 2e7 do end; return redis.call('GET', 'k')",
+        );
+        assert_eq!(storage.wait_for_script().await, Err(StorageError::Busy));
+        assert_eq!(
+            run_err(&storage, &["SCRIPT", "KILL"]),
+            StorageError::Unkillable.to_string()
+        );
+        assert_eq!(script.join().unwrap().unwrap(), bulk("v"));
+
+        // One that ends before the threshold is waited for.
+        run(
+            &storage,
+            &["CONFIG", "SET", "busy-reply-threshold", "60000"],
+        );
+        let script = spawn_eval(&storage, "for i = 1, 1e7 do end; return 1");
+        assert_eq!(storage.wait_for_script().await, Ok(()));
+        assert_eq!(script.join().unwrap().unwrap(), integer(1));
+    }
+}
@@ -0,0 +1,711 @@
+use super::{
+    array, integer, notify::Class, scan::ScanOrder, scan_reply, Container, Storage, StorageError,
+    Value,
+};
+use crate::{
+    cmd::{Command, SMove, SPop, SRandMember, SScan},
+    glob,
+    resp::{BulkString, Key, Null, Resp},
+};
+use anyhow::Result;
+use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+use std::collections::HashSet;
+
+/// Sets of integers up to this size are kept as a sorted array, like Redis's
+/// `set-max-intset-entries`.
+const MAX_INTSET_ENTRIES: usize = 512;
+
+/// A set of members. Small sets holding only integers are stored compactly
+/// as a sorted array and converted to a hash set once that stops fitting,
+/// along with the members' order for `SSCAN`.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Set {
+    Ints(Vec<i64>),
+    Members(HashSet<Key>, ScanOrder<Key>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::Ints(Vec::new())
+    }
+}
+
+impl Set {
+    pub(super) fn len(&self) -> usize {
+        match self {
+            Set::Ints(ints) => ints.len(),
+            Set::Members(members, _) => members.len(),
+        }
+    }
+
+    pub(super) fn contains(&self, member: &Key) -> bool {
+        match self {
+            Set::Ints(ints) => as_int(member).is_some_and(|n| ints.binary_search(&n).is_ok()),
+            Set::Members(members, _) => members.contains(member),
+        }
+    }
+
+    /// Adds `member`, returning whether it was new.
+    pub(super) fn insert(&mut self, member: Key) -> bool {
+        if let Set::Ints(ints) = self {
+            match as_int(&member) {
+                Some(n) => match ints.binary_search(&n) {
+                    Ok(_) => return false,
+                    Err(pos) if ints.len() < MAX_INTSET_ENTRIES => {
+                        ints.insert(pos, n);
+                        return true;
+                    }
+                    Err(_) => self.convert(),
+                },
+                None => self.convert(),
+            }
+        }
+        match self {
+            Set::Members(members, order) => {
+                let added = members.insert(member.clone());
+                if added {
+                    order.insert(member);
+                }
+                added
+            }
+            Set::Ints(_) => unreachable!("set was converted above"),
+        }
+    }
+
+    /// Removes `member`, returning whether it was present.
+    pub(super) fn remove(&mut self, member: &Key) -> bool {
+        match self {
+            Set::Ints(ints) => match as_int(member).map(|n| ints.binary_search(&n)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Set::Members(members, order) => {
+                let removed = members.remove(member);
+                if removed {
+                    order.remove(member);
+                }
+                removed
+            }
+        }
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = Key> + '_ {
+        let (ints, members) = match self {
+            Set::Ints(ints) => (Some(ints.iter().map(|n| int_key(*n))), None),
+            Set::Members(members, _) => (None, Some(members.iter().cloned())),
+        };
+        ints.into_iter()
+            .flatten()
+            .chain(members.into_iter().flatten())
+    }
+
+    /// Picks up to `count` distinct members at random.
+    pub(super) fn random(&self, count: usize) -> Vec<Key> {
+        let mut rng = rand::thread_rng();
+        match self {
+            Set::Ints(ints) => ints
+                .choose_multiple(&mut rng, count)
+                .map(|n| int_key(*n))
+                .collect(),
+            Set::Members(members, _) => members.iter().cloned().choose_multiple(&mut rng, count),
+        }
+    }
+
+    /// One `SSCAN` step. Like Redis, a set still in the compact encoding is
+    /// returned whole.
+    fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Key>) {
+        match self {
+            Set::Ints(_) => (0, self.iter().collect()),
+            Set::Members(_, order) => {
+                let (cursor, members) = order.scan(cursor, count);
+                (cursor, members.into_iter().cloned().collect())
+            }
+        }
+    }
+
+    fn convert(&mut self) {
+        if let Set::Ints(ints) = self {
+            let members: Vec<_> = ints.iter().map(|n| int_key(*n)).collect();
+            let mut order = ScanOrder::default();
+            for member in &members {
+                order.insert(member.clone());
+            }
+            *self = Set::Members(members.into_iter().collect(), order);
+        }
+    }
+}
+
+impl FromIterator<Key> for Set {
+    fn from_iter<T: IntoIterator<Item = Key>>(iter: T) -> Self {
+        let mut set = Set::default();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+/// The member as an integer, if it is one written in canonical form, so that
+/// it comes back out of the compact encoding byte for byte.
+fn as_int(member: &Key) -> Option<i64> {
+    match member {
+        Key::BulkString(s) if !s.is_null => s
+            .as_str()?
+            .parse()
+            .ok()
+            .filter(|n: &i64| n.to_string().as_bytes() == s.value),
+        _ => None,
+    }
+}
+
+fn int_key(n: i64) -> Key {
+    Key::BulkString(BulkString::new(n.to_string(), false))
+}
+
+impl Container for Set {
+    fn downcast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Set(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn downcast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Set(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Set(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Storage {
+    pub(super) fn execute_set(&self, cmd: Command) -> Result<Option<Resp>> {
+        let res = match cmd {
+            Command::SAdd(c) => {
+                let added = self
+                    .write(&c.key, true, |s: &mut Set| {
+                        Ok(c.members
+                            .into_iter()
+                            .filter(|m| s.insert(m.clone()))
+                            .count() as i64)
+                    })?
+                    .unwrap_or(0);
+                if added > 0 {
+                    self.notify(Class::SET, "sadd", &c.key);
+                }
+                integer(added)
+            }
+            Command::SRem(c) => {
+                let removed = self
+                    .write(&c.key, false, |s: &mut Set| {
+                        Ok(c.members.iter().filter(|m| s.remove(m)).count() as i64)
+                    })?
+                    .unwrap_or(0);
+                if removed > 0 {
+                    self.notify_write(Class::SET, "srem", &c.key);
+                }
+                integer(removed)
+            }
+            Command::SMembers(c) => Resp::Set(
+                self.read(&c.key, |s: &Set| s.iter().collect())?
+                    .unwrap_or_default(),
+            ),
+            Command::SIsMember(c) => integer(
+                self.read(&c.key, |s: &Set| s.contains(&c.member))?
+                    .unwrap_or(false) as i64,
+            ),
+            Command::SMIsMember(c) => {
+                let found = self
+                    .read(&c.key, |s: &Set| {
+                        c.members.iter().map(|m| s.contains(m)).collect()
+                    })?
+                    .unwrap_or_else(|| vec![false; c.members.len()]);
+                array(found.into_iter().map(|f| integer(f as i64)))
+            }
+            Command::SCard(c) => integer(self.read(&c.key, |s: &Set| s.len())?.unwrap_or(0) as i64),
+            Command::SPop(c) => self.spop(&c)?,
+            Command::SRandMember(c) => self.srandmember(&c)?,
+            Command::SMove(c) => integer(self.smove(&c)? as i64),
+            Command::SScan(c) => self.sscan(&c)?,
+            Command::SInter(c) => members_reply(self.sinter(&c.keys, usize::MAX)?),
+            Command::SUnion(c) => members_reply(self.sunion(&c.keys)?),
+            Command::SDiff(c) => members_reply(self.sdiff(&c.keys)?),
+            Command::SInterStore(c) => {
+                let set = self.sinter(&c.keys, usize::MAX)?;
+                integer(self.store_set(c.destination, set, "sinterstore") as i64)
+            }
+            Command::SUnionStore(c) => {
+                let set = self.sunion(&c.keys)?;
+                integer(self.store_set(c.destination, set, "sunionstore") as i64)
+            }
+            Command::SDiffStore(c) => {
+                let set = self.sdiff(&c.keys)?;
+                integer(self.store_set(c.destination, set, "sdiffstore") as i64)
+            }
+            Command::SInterCard(c) => {
+                let limit = match c.limit {
+                    0 => usize::MAX,
+                    n => n,
+                };
+                integer(self.sinter(&c.keys, limit)?.len() as i64)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(res))
+    }
+
+    /// Reads the sizes of the sets at `keys`, failing if any holds another
+    /// type. Missing keys count as empty sets.
+    fn set_sizes<'a>(&self, keys: &'a [Key]) -> Result<Vec<(usize, &'a Key)>, StorageError> {
+        keys.iter()
+            .map(|key| Ok((self.read(key, |s: &Set| s.len())?.unwrap_or(0), key)))
+            .collect()
+    }
+
+    /// Intersects the sets at `keys`, stopping once `limit` members are
+    /// found. Starts from the smallest set so each pass can only shrink the
+    /// candidates.
+    fn sinter(&self, keys: &[Key], limit: usize) -> Result<Set, StorageError> {
+        let mut sizes = self.set_sizes(keys)?;
+        sizes.sort_by_key(|(len, _)| *len);
+        let Some(&(len, smallest)) = sizes.first() else {
+            return Ok(Set::default());
+        };
+        if len == 0 {
+            return Ok(Set::default());
+        }
+        let mut candidates: Vec<Key> = self
+            .read(smallest, |s: &Set| s.iter().collect())?
+            .unwrap_or_default();
+        let others = &sizes[1..];
+        for (i, (_, key)) in others.iter().enumerate() {
+            let take = if i + 1 == others.len() {
+                limit
+            } else {
+                usize::MAX
+            };
+            candidates = self
+                .read(key, |s: &Set| {
+                    candidates
+                        .into_iter()
+                        .filter(|m| s.contains(m))
+                        .take(take)
+                        .collect()
+                })?
+                .unwrap_or_default();
+            if candidates.is_empty() {
+                break;
+            }
+        }
+        candidates.truncate(limit);
+        Ok(candidates.into_iter().collect())
+    }
+
+    fn sunion(&self, keys: &[Key]) -> Result<Set, StorageError> {
+        self.set_sizes(keys)?;
+        let mut union = Set::default();
+        for key in keys {
+            self.read(key, |s: &Set| {
+                for member in s.iter() {
+                    union.insert(member);
+                }
+            })?;
+        }
+        Ok(union)
+    }
+
+    /// Removes the members of the other sets from the first one.
+    fn sdiff(&self, keys: &[Key]) -> Result<Set, StorageError> {
+        self.set_sizes(keys)?;
+        let Some((first, others)) = keys.split_first() else {
+            return Ok(Set::default());
+        };
+        let mut diff = self.read(first, |s: &Set| s.clone())?.unwrap_or_default();
+        for key in others {
+            if diff.is_empty() {
+                break;
+            }
+            self.read(key, |s: &Set| {
+                for member in s.iter() {
+                    diff.remove(&member);
+                }
+            })?;
+        }
+        Ok(diff)
+    }
+
+    /// Replaces whatever is at `key` with `set`, deleting the key if the set
+    /// is empty, and raises `event`. Returns the size of the set.
+    fn store_set(&self, key: Key, set: Set, event: &str) -> usize {
+        let len = set.len();
+        if len == 0 {
+            if self.keyspace().remove(&key).is_some() {
+                self.notify(Class::GENERIC, "del", &key);
+            }
+        } else {
+            self.insert(key.clone(), Value::Set(set));
+            self.notify(Class::SET, event, &key);
+        }
+        len
+    }
+
+    fn spop(&self, c: &SPop) -> Result<Resp, StorageError> {
+        let popped = self.write(&c.key, false, |s: &mut Set| {
+            let popped = s.random(c.count.unwrap_or(1));
+            for member in &popped {
+                s.remove(member);
+            }
+            Ok(popped)
+        })?;
+        let popped = popped.unwrap_or_default();
+        if !popped.is_empty() {
+            self.notify_write(Class::SET, "spop", &c.key);
+        }
+        Ok(match c.count {
+            Some(_) => array(popped.into_iter().map(Resp::from)),
+            None => popped
+                .into_iter()
+                .next()
+                .map_or(Resp::Null(Null), Resp::from),
+        })
+    }
+
+    /// With a positive count the members are distinct, with a negative one
+    /// the same member may be returned several times.
+    fn srandmember(&self, c: &SRandMember) -> Result<Resp, StorageError> {
+        let members = self
+            .read(&c.key, |s: &Set| match c.count {
+                None => s.random(1),
+                Some(n) if n >= 0 => s.random(n as usize),
+                Some(n) => {
+                    let mut rng = rand::thread_rng();
+                    let all: Vec<Key> = s.iter().collect();
+                    (0..n.unsigned_abs())
+                        .map(|_| all[rng.gen_range(0..all.len())].clone())
+                        .collect()
+                }
+            })?
+            .unwrap_or_default();
+        Ok(match c.count {
+            Some(_) => array(members.into_iter().map(Resp::from)),
+            None => members
+                .into_iter()
+                .next()
+                .map_or(Resp::Null(Null), Resp::from),
+        })
+    }
+
+    fn smove(&self, c: &SMove) -> Result<bool, StorageError> {
+        if c.source == c.destination {
+            return Ok(self
+                .read(&c.source, |s: &Set| s.contains(&c.member))?
+                .unwrap_or(false));
+        }
+        // Check the destination first so a type error doesn't lose the member.
+        self.read(&c.destination, |_: &Set| ())?;
+        let removed = self
+            .write(&c.source, false, |s: &mut Set| Ok(s.remove(&c.member)))?
+            .unwrap_or(false);
+        if removed {
+            self.notify_write(Class::SET, "srem", &c.source);
+            self.write(&c.destination, true, |s: &mut Set| {
+                Ok(s.insert(c.member.clone()))
+            })?;
+            self.notify(Class::SET, "sadd", &c.destination);
+        }
+        Ok(removed)
+    }
+
+    fn sscan(&self, c: &SScan) -> Result<Resp, StorageError> {
+        let (cursor, members) = self
+            .read(&c.key, |s: &Set| s.scan(c.cursor, c.options.count))?
+            .unwrap_or_default();
+        let items = members
+            .into_iter()
+            .filter(|m| match &c.options.pattern {
+                Some(p) => glob::matches(p.as_bytes(), m.to_string().as_bytes(), false),
+                None => true,
+            })
+            .map(Resp::from);
+        Ok(scan_reply(cursor, items))
+    }
+}
+
+fn members_reply(set: Set) -> Resp {
+    Resp::Set(set.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::Array;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args)?;
+        Ok(storage.execute_set(cmd)?.unwrap())
+    }
+
+    fn members(items: &[&str]) -> Resp {
+        Resp::Set(items.iter().map(|s| key(s)).collect())
+    }
+
+    fn len(resp: Resp) -> usize {
+        match resp {
+            Resp::Array(a) => a.len(),
+            _ => panic!("Expected Array"),
+        }
+    }
+
+    #[test]
+    fn test_intset_encoding() {
+        let mut set: Set = ["3", "1", "2", "1"].iter().map(|s| key(s)).collect();
+        assert_eq!(set, Set::Ints(vec![1, 2, 3]));
+        assert!(set.contains(&key("2")));
+        assert!(!set.contains(&key("02")));
+        set.insert(key("02"));
+        assert!(matches!(set, Set::Members(..)));
+        assert_eq!(set.len(), 4);
+        assert!(set.contains(&key("2")));
+
+        let mut set: Set = (0..MAX_INTSET_ENTRIES as i64)
+            .map(|n| key(&n.to_string()))
+            .collect();
+        assert!(matches!(set, Set::Ints(_)));
+        set.insert(key("-1"));
+        assert!(matches!(set, Set::Members(..)));
+        assert_eq!(set.len(), MAX_INTSET_ENTRIES + 1);
+    }
+
+    #[test]
+    fn test_sadd_srem() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["SADD", "s", "1", "2", "a", "2"]).unwrap(),
+            integer(3)
+        );
+        assert_eq!(run(&storage, &["SCARD", "s"]).unwrap(), integer(3));
+        assert_eq!(
+            run(&storage, &["SMEMBERS", "s"]).unwrap(),
+            members(&["1", "2", "a"])
+        );
+        assert_eq!(
+            run(&storage, &["SMISMEMBER", "s", "a", "b", "1"]).unwrap(),
+            array([integer(1), integer(0), integer(1)])
+        );
+        assert_eq!(
+            run(&storage, &["SREM", "s", "1", "2", "a", "x"]).unwrap(),
+            integer(3)
+        );
+        assert_eq!(storage.key_type(&key("s")), "none");
+        assert_eq!(run(&storage, &["SMEMBERS", "s"]).unwrap(), members(&[]));
+        assert_eq!(run(&storage, &["SISMEMBER", "s", "a"]).unwrap(), integer(0));
+    }
+
+    #[test]
+    fn test_spop_srandmember() {
+        let storage = Storage::new();
+        assert_eq!(run(&storage, &["SPOP", "s"]).unwrap(), Resp::Null(Null));
+        assert_eq!(run(&storage, &["SPOP", "s", "2"]).unwrap(), array([]));
+        run(&storage, &["SADD", "s", "a", "b", "c"]).unwrap();
+        assert_eq!(len(run(&storage, &["SRANDMEMBER", "s", "5"]).unwrap()), 3);
+        assert_eq!(len(run(&storage, &["SRANDMEMBER", "s", "-5"]).unwrap()), 5);
+        assert_eq!(len(run(&storage, &["SPOP", "s", "2"]).unwrap()), 2);
+        assert_eq!(run(&storage, &["SCARD", "s"]).unwrap(), integer(1));
+        assert!(matches!(
+            run(&storage, &["SPOP", "s"]).unwrap(),
+            Resp::BulkString(_)
+        ));
+        assert_eq!(storage.key_type(&key("s")), "none");
+        assert_eq!(
+            run(&storage, &["SRANDMEMBER", "s"]).unwrap(),
+            Resp::Null(Null)
+        );
+    }
+
+    #[test]
+    fn test_smove() {
+        let storage = Storage::new();
+        run(&storage, &["SADD", "a", "x", "y"]).unwrap();
+        assert_eq!(
+            run(&storage, &["SMOVE", "a", "b", "x"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["SMOVE", "a", "b", "x"]).unwrap(),
+            integer(0)
+        );
+        assert_eq!(
+            run(&storage, &["SMOVE", "a", "a", "y"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(run(&storage, &["SMEMBERS", "b"]).unwrap(), members(&["x"]));
+
+        storage.set(key("str"), Resp::from(key("v")));
+        let err = run(&storage, &["SMOVE", "a", "str", "y"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::WrongType
+        );
+        assert_eq!(run(&storage, &["SMEMBERS", "a"]).unwrap(), members(&["y"]));
+    }
+
+    #[test]
+    fn test_sscan() {
+        let storage = Storage::new();
+        for i in 0..30 {
+            run(&storage, &["SADD", "s", &i.to_string(), &format!("m{}", i)]).unwrap();
+        }
+        let mut cursor = "0".to_string();
+        let mut seen = 0;
+        loop {
+            let Resp::Array(reply) = run(
+                &storage,
+                &["SSCAN", "s", &cursor, "MATCH", "m*", "COUNT", "7"],
+            )
+            .unwrap() else {
+                panic!("Expected Array");
+            };
+            let (Resp::BulkString(next), Resp::Array(items)) = (&reply[0], &reply[1]) else {
+                panic!("Expected cursor and items");
+            };
+            seen += items.len();
+            if next.value == b"0" {
+                break;
+            }
+            cursor = next.as_str().unwrap().to_string();
+        }
+        assert_eq!(seen, 30);
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let storage = Storage::new();
+        run(&storage, &["SADD", "a", "1", "2", "3", "4"]).unwrap();
+        run(&storage, &["SADD", "b", "2", "3", "x"]).unwrap();
+        run(&storage, &["SADD", "c", "3", "x", "y"]).unwrap();
+        assert_eq!(
+            run(&storage, &["SINTER", "a", "b", "c"]).unwrap(),
+            members(&["3"])
+        );
+        assert_eq!(
+            run(&storage, &["SINTER", "a", "missing"]).unwrap(),
+            members(&[])
+        );
+        assert_eq!(
+            run(&storage, &["SUNION", "b", "c", "missing"]).unwrap(),
+            members(&["2", "3", "x", "y"])
+        );
+        assert_eq!(
+            run(&storage, &["SDIFF", "a", "b", "c"]).unwrap(),
+            members(&["1", "4"])
+        );
+        assert_eq!(
+            run(&storage, &["SINTERCARD", "2", "a", "b"]).unwrap(),
+            integer(2)
+        );
+        assert_eq!(
+            run(&storage, &["SINTERCARD", "2", "a", "b", "LIMIT", "1"]).unwrap(),
+            integer(1)
+        );
+
+        storage.set(key("str"), Resp::from(key("v")));
+        let err = run(&storage, &["SUNION", "a", "str"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::WrongType
+        );
+        let err = run(&storage, &["SINTER", "missing", "str"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::WrongType
+        );
+    }
+
+    #[test]
+    fn test_store_variants() {
+        let storage = Storage::new();
+        run(&storage, &["SADD", "a", "1", "2", "3"]).unwrap();
+        run(&storage, &["SADD", "b", "3", "4"]).unwrap();
+        storage.set(key("dest"), Resp::from(key("v")));
+        assert_eq!(
+            run(&storage, &["SUNIONSTORE", "dest", "a", "b"]).unwrap(),
+            integer(4)
+        );
+        assert_eq!(storage.key_type(&key("dest")), "set");
+        assert_eq!(
+            run(&storage, &["SINTERSTORE", "a", "a", "b"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(run(&storage, &["SMEMBERS", "a"]).unwrap(), members(&["3"]));
+        assert_eq!(
+            run(&storage, &["SDIFFSTORE", "dest", "a", "b"]).unwrap(),
+            integer(0)
+        );
+        assert_eq!(storage.key_type(&key("dest")), "none");
+    }
+
+    /// Members keep moving between two sets while their union is stored.
+    /// Since the union runs exclusively it never sees a member in flight.
+    #[test]
+    fn test_store_is_atomic() {
+        use crate::cmd::CommandExecutor;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let storage = Storage::new();
+        for i in 0..1000 {
+            run(&storage, &["SADD", "a", &format!("m{}", i)]).unwrap();
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let mover = {
+            let storage = storage.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let m = format!("m{}", i % 1000);
+                    for (from, to) in [("a", "b"), ("b", "a")] {
+                        let cmd = crate::cmd::parse_command(&["SMOVE", from, to, &m]).unwrap();
+                        storage.execute(cmd).unwrap();
+                    }
+                    i += 1;
+                }
+            })
+        };
+        for _ in 0..200 {
+            let cmd = crate::cmd::parse_command(&["SUNIONSTORE", "u", "a", "b"]).unwrap();
+            assert_eq!(storage.execute(cmd).unwrap(), Some(integer(1000)));
+        }
+        done.store(true, Ordering::Relaxed);
+        mover.join().unwrap();
+    }
+
+    #[test]
+    fn test_smembers_resp2() {
+        let storage = Storage::new();
+        run(&storage, &["SADD", "s", "b", "a"]).unwrap();
+        let reply = run(&storage, &["SMEMBERS", "s"]).unwrap();
+        assert_eq!(
+            reply.into_resp2(),
+            Resp::Array(Array::new(
+                vec![Resp::from(key("a")), Resp::from(key("b"))],
+                false
+            ))
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! The ordered half of a sorted set: Redis's skiplist, keyed by
+//! `(score, member)`, where every link also records how many elements it
+//! skips. Summing those spans along a search path gives an element's rank,
+//! so rank lookups and rank-based seeks are O(log N) like score lookups.
+//!
+//! Nodes live in an arena and link to each other by index; the head node is
+//! always at index 0 and holds no element.
+
+use crate::resp::{Key, Null};
+use rand::Rng;
+
+const MAX_LEVEL: usize = 32;
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Level {
+    forward: Option<usize>,
+    /// Number of elements between this node and `forward`, counting the
+    /// latter.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Key,
+    score: f64,
+    levels: Vec<Level>,
+    backward: Option<usize>,
+}
+
+impl Node {
+    /// Whether this node sorts before `(score, member)`.
+    fn before(&self, score: f64, member: &Key) -> bool {
+        self.score < score || (self.score == score && self.member < *member)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        SkipList {
+            nodes: vec![Node {
+                member: Key::Null(Null),
+                score: 0.0,
+                levels: vec![Level::default(); MAX_LEVEL],
+                backward: None,
+            }],
+            free: Vec::new(),
+            tail: None,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+impl SkipList {
+    /// Inserts an element. The caller makes sure `member` isn't already in
+    /// the list.
+    pub(super) fn insert(&mut self, score: f64, member: Key) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if !self.nodes[next].before(score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let new = self.alloc(Node {
+            member,
+            score,
+            levels: vec![Level::default(); level],
+            backward: (update[0] != HEAD).then_some(update[0]),
+        });
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            self.nodes[new].levels[i] = Level {
+                forward: prev.forward,
+                span: prev.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Level {
+                forward: Some(new),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        for (i, u) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*u].levels[i].span += 1;
+        }
+        match self.nodes[new].levels[0].forward {
+            Some(next) => self.nodes[next].backward = Some(new),
+            None => self.tail = Some(new),
+        }
+        self.len += 1;
+    }
+
+    /// Removes the element, returning whether it was there.
+    pub(super) fn remove(&mut self, score: f64, member: &Key) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if !self.nodes[next].before(score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let Some(x) = self.nodes[x].levels[0].forward else {
+            return false;
+        };
+        if self.nodes[x].score != score || self.nodes[x].member != *member {
+            return false;
+        }
+        let removed = std::mem::take(&mut self.nodes[x].levels);
+        for (i, u) in update.iter().enumerate().take(self.level) {
+            let level = &mut self.nodes[*u].levels[i];
+            if level.forward == Some(x) {
+                level.span = level.span + removed[i].span - 1;
+                level.forward = removed[i].forward;
+            } else {
+                level.span -= 1;
+            }
+        }
+        let backward = self.nodes[x].backward;
+        match removed[0].forward {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.nodes[x].member = Key::Null(Null);
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    /// 0-based rank of the element, if present.
+    pub(super) fn rank(&self, score: f64, member: &Key) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                let node = &self.nodes[next];
+                if !(node.before(score, member) || node.member == *member) {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].score == score && self.nodes[x].member == *member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Counts the elements for which `pred` holds. `pred` must hold for a
+    /// prefix of the list, e.g. "score below some bound".
+    pub(super) fn count_while(&self, pred: impl Fn(f64, &Key) -> bool) -> usize {
+        let mut count = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                let node = &self.nodes[next];
+                if !pred(node.score, &node.member) {
+                    break;
+                }
+                count += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        count
+    }
+
+    /// Iterates forward from the element at `rank`.
+    pub(super) fn iter_from(&self, rank: usize) -> Iter<'_> {
+        Iter {
+            list: self,
+            next: self.node_at(rank),
+            rev: false,
+        }
+    }
+
+    /// Iterates backward from the element at `rank`.
+    pub(super) fn rev_iter_from(&self, rank: usize) -> Iter<'_> {
+        Iter {
+            list: self,
+            next: self.node_at(rank),
+            rev: true,
+        }
+    }
+
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        if rank + 1 == self.len {
+            return self.tail;
+        }
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if traversed + self.nodes[x].levels[i].span > target {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+}
+
+/// Each level holds a quarter of the nodes of the one below, as in Redis.
+fn random_level() -> usize {
+    let mut rng = rand::thread_rng();
+    let mut level = 1;
+    while level < MAX_LEVEL && rng.gen_ratio(1, 4) {
+        level += 1;
+    }
+    level
+}
+
+pub(super) struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Key, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.next?];
+        self.next = match self.rev {
+            false => node.levels[0].forward,
+            true => node.backward,
+        };
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn members(iter: Iter<'_>) -> Vec<String> {
+        iter.map(|(m, _)| m.to_string()).collect()
+    }
+
+    #[test]
+    fn test_order_and_rank() {
+        let mut list = SkipList::default();
+        for i in (0..1000).rev() {
+            list.insert((i / 2) as f64, key(&format!("m{:04}", i)));
+        }
+        assert_eq!(list.len, 1000);
+        for i in [0, 1, 500, 999] {
+            let member = key(&format!("m{:04}", i));
+            assert_eq!(list.rank((i / 2) as f64, &member), Some(i));
+            let (m, score) = list.iter_from(i).next().unwrap();
+            assert_eq!((m, score), (&member, (i / 2) as f64));
+        }
+        assert_eq!(list.rank(1.0, &key("m0000")), None);
+        assert_eq!(
+            members(list.rev_iter_from(2)),
+            vec!["m0002", "m0001", "m0000"]
+        );
+        assert_eq!(list.count_while(|score, _| score < 10.0), 20);
+        assert_eq!(list.iter_from(1000).next(), None);
+    }
+
+    #[test]
+    fn test_rank_after_update() {
+        let mut list = SkipList::default();
+        for i in 0..100 {
+            list.insert(i as f64, key(&format!("m{:02}", i)));
+        }
+        // Updating a score is a remove and an insert at the new score.
+        assert!(list.remove(10.0, &key("m10")));
+        list.insert(200.0, key("m10"));
+        assert_eq!(list.rank(200.0, &key("m10")), Some(99));
+        assert_eq!(list.rank(150.0, &key("m10")), None);
+        assert_eq!(list.rank(10.0, &key("m10")), None);
+        assert_eq!(list.rank(11.0, &key("m11")), Some(10));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut list = SkipList::default();
+        for i in 0..200 {
+            list.insert(i as f64, key(&i.to_string()));
+        }
+        for i in (0..200).step_by(2) {
+            assert!(list.remove(i as f64, &key(&i.to_string())));
+        }
+        assert!(!list.remove(0.0, &key("0")));
+        assert!(!list.remove(3.0, &key("4")));
+        assert_eq!(list.len, 100);
+        for i in 0..100 {
+            let member = (2 * i + 1).to_string();
+            assert_eq!(list.rank((2 * i + 1) as f64, &key(&member)), Some(i));
+        }
+        assert_eq!(list.rev_iter_from(99).next().unwrap().1, 199.0);
+        for i in (1..200).step_by(2) {
+            assert!(list.remove(i as f64, &key(&i.to_string())));
+        }
+        assert_eq!(list.len, 0);
+        assert_eq!(list.iter_from(0).next(), None);
+
+        list.insert(1.0, key("a"));
+        assert_eq!(members(list.iter_from(0)), vec!["a"]);
+        assert!(list.nodes.len() <= 201);
+    }
+
+    #[test]
+    fn test_matches_sorted_vec() {
+        let mut rng = rand::thread_rng();
+        let mut list = SkipList::default();
+        let mut model: Vec<(i64, String)> = Vec::new();
+        for _ in 0..3000 {
+            let score = rng.gen_range(0..50);
+            let member = format!("m{}", rng.gen_range(0..300));
+            match model.iter().position(|(_, m)| *m == member) {
+                Some(i) => {
+                    let (old, _) = model.remove(i);
+                    assert!(list.remove(old as f64, &key(&member)));
+                }
+                None => {
+                    list.insert(score as f64, key(&member));
+                    model.push((score, member));
+                    model.sort();
+                }
+            }
+        }
+        assert_eq!(list.len, model.len());
+        let expected: Vec<String> = model.iter().map(|(_, m)| m.clone()).collect();
+        assert_eq!(members(list.iter_from(0)), expected);
+        for (rank, (score, member)) in model.iter().enumerate() {
+            assert_eq!(list.rank(*score as f64, &key(member)), Some(rank));
+            assert_eq!(list.iter_from(rank).next().unwrap().0, &key(member));
+        }
+        if let Some(last) = model.len().checked_sub(1) {
+            let reversed: Vec<String> = expected.iter().rev().cloned().collect();
+            assert_eq!(members(list.rev_iter_from(last)), reversed);
+        }
+    }
+}
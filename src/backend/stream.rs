@@ -0,0 +1,494 @@
+//! Streams: append-only logs of field-value entries ordered by ID.
+//!
+//! Entries live in a B-tree keyed by ID. Redis packs entries into radix tree
+//! nodes of up to 100 entries and approximate trimming only ever evicts
+//! whole nodes; we mimic that by evicting in multiples of `NODE_ENTRIES`.
+//! Consumer groups hang off the stream and are handled in `group`.
+
+use super::{
+    array, blocking::Serve, bulk_string, group::ConsumerGroup, integer, list::null_array,
+    notify::Class, Container, Storage, StorageError, Value,
+};
+use crate::{
+    cmd::{Command, StreamId, Trim, TrimStrategy, XAdd, XAddId, XRange, XRead, XReadId},
+    resp::{Key, Null, Resp},
+};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries per node, as in Redis's `stream-node-max-entries`.
+const NODE_ENTRIES: usize = 100;
+/// How many entries an approximate trim evicts at most without a `LIMIT`.
+const DEFAULT_TRIM_LIMIT: usize = 100 * NODE_ENTRIES;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Stream {
+    /// Fields and values of each entry, interleaved.
+    pub(super) entries: BTreeMap<StreamId, Vec<Resp>>,
+    /// The greatest ID ever added, which new IDs must exceed even if that
+    /// entry has since been deleted.
+    last_id: StreamId,
+    /// Entries ever added, deleted ones included.
+    pub(super) entries_added: u64,
+    /// The greatest ID removed by `XDEL`.
+    pub(super) max_deleted_id: StreamId,
+    pub(super) groups: BTreeMap<Key, ConsumerGroup>,
+}
+
+impl Container for Stream {
+    fn downcast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Stream(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn downcast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Stream(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Stream(self)
+    }
+
+    /// Streams keep their key once emptied, so that the last ID survives.
+    fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl Stream {
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(super) fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Picks the ID for a new entry, which must be greater than any before.
+    fn next_id(&self, id: XAddId) -> Result<StreamId, StorageError> {
+        let last = self.last_id;
+        let id = match id {
+            XAddId::Auto => {
+                let now = now_ms();
+                if now > last.ms {
+                    StreamId::new(now, 0)
+                } else {
+                    last.next().ok_or(StorageError::StreamExhausted)?
+                }
+            }
+            XAddId::AutoSeq(ms) if ms == last.ms => match last.seq.checked_add(1) {
+                Some(seq) => StreamId::new(ms, seq),
+                None => return Err(StorageError::StreamIdTooSmall),
+            },
+            XAddId::AutoSeq(ms) => StreamId::new(ms, 0),
+            XAddId::Explicit(id) => id,
+        };
+        if id <= last {
+            return Err(StorageError::StreamIdTooSmall);
+        }
+        Ok(id)
+    }
+
+    fn add(&mut self, id: StreamId, fields: Vec<Resp>) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Evicts the oldest entries as `trim` asks, returning how many went.
+    fn trim(&mut self, trim: &Trim) -> usize {
+        let mut evict = match trim.strategy {
+            TrimStrategy::MaxLen(n) => self.len().saturating_sub(n),
+            TrimStrategy::MinId(id) => self.entries.range(..id).count(),
+        };
+        if trim.approximate {
+            let limit = match trim.limit {
+                None => DEFAULT_TRIM_LIMIT,
+                Some(0) => usize::MAX,
+                Some(limit) => limit,
+            };
+            evict = evict.min(limit);
+            evict -= evict % NODE_ENTRIES;
+        }
+        for _ in 0..evict {
+            self.entries.pop_first();
+        }
+        evict
+    }
+
+    /// Entries from `start` to `end` inclusive, newest first if `rev`.
+    pub(super) fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Vec<(StreamId, Vec<Resp>)> {
+        if start > end {
+            return Vec::new();
+        }
+        let range = self.entries.range(start..=end);
+        let count = count.unwrap_or(usize::MAX);
+        let entries: Vec<_> = match rev {
+            false => range.take(count).map(|(id, f)| (*id, f.clone())).collect(),
+            true => range
+                .rev()
+                .take(count)
+                .map(|(id, f)| (*id, f.clone()))
+                .collect(),
+        };
+        entries
+    }
+
+    /// Entries with IDs greater than `id`, oldest first.
+    pub(super) fn after(&self, id: StreamId, count: Option<usize>) -> Vec<(StreamId, Vec<Resp>)> {
+        match id.next() {
+            Some(start) => self.range(start, StreamId::MAX, count, false),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Storage {
+    pub(super) fn execute_stream(&self, cmd: Command) -> Result<Option<Resp>> {
+        let res = match cmd {
+            Command::XAdd(c) => {
+                let id = self.xadd(&c)?;
+                if id.is_some() {
+                    self.signal_ready(&c.key);
+                }
+                id.map_or(Resp::Null(Null), |id| bulk_string(id.to_string()))
+            }
+            Command::XRange(c) => self.xrange(&c, false)?,
+            Command::XRevRange(c) => self.xrange(&c, true)?,
+            Command::XLen(c) => {
+                integer(self.read(&c.key, |s: &Stream| s.len())?.unwrap_or(0) as i64)
+            }
+            Command::XTrim(c) => {
+                let trimmed = self
+                    .write(&c.key, false, |s: &mut Stream| Ok(s.trim(&c.trim)))?
+                    .unwrap_or(0);
+                if trimmed > 0 {
+                    self.notify(Class::STREAM, "xtrim", &c.key);
+                }
+                integer(trimmed as i64)
+            }
+            Command::XDel(c) => {
+                let deleted = self
+                    .write(&c.key, false, |s: &mut Stream| {
+                        let mut deleted = 0;
+                        for id in &c.ids {
+                            if s.entries.remove(id).is_some() {
+                                s.max_deleted_id = s.max_deleted_id.max(*id);
+                                deleted += 1;
+                            }
+                        }
+                        Ok(deleted)
+                    })?
+                    .unwrap_or(0);
+                if deleted > 0 {
+                    self.notify(Class::STREAM, "xdel", &c.key);
+                }
+                integer(deleted as i64)
+            }
+            // With BLOCK but outside of a connection's blocking path, this
+            // behaves as if the timeout expired straight away.
+            Command::XRead(c) => {
+                let after = self.xread_ids(&c)?;
+                self.xread(&after, c.count)?.unwrap_or_else(null_array)
+            }
+            Command::XReadGroup(c) => self.xreadgroup(&c)?.unwrap_or_else(null_array),
+            Command::XGroup(c) => self.xgroup(&c)?,
+            Command::XAck(c) => self.xack(&c)?,
+            Command::XPending(c) => self.xpending(&c)?,
+            Command::XClaim(c) => self.xclaim(&c)?,
+            Command::XAutoClaim(c) => self.xautoclaim(&c)?,
+            Command::XInfo(c) => self.xinfo(&c)?,
+            _ => return Ok(None),
+        };
+        Ok(Some(res))
+    }
+
+    fn xadd(&self, c: &XAdd) -> Result<Option<StreamId>, StorageError> {
+        let added = self.write(&c.key, !c.nomkstream, |s: &mut Stream| {
+            let id = s.next_id(c.id)?;
+            s.add(id, c.fields.clone());
+            let trimmed = c.trim.as_ref().map_or(0, |trim| s.trim(trim));
+            Ok((id, trimmed))
+        })?;
+        let Some((id, trimmed)) = added else {
+            return Ok(None);
+        };
+        self.notify(Class::STREAM, "xadd", &c.key);
+        if trimmed > 0 {
+            self.notify(Class::STREAM, "xtrim", &c.key);
+        }
+        Ok(Some(id))
+    }
+
+    fn xrange(&self, c: &XRange, rev: bool) -> Result<Resp, StorageError> {
+        let entries = self
+            .read(&c.key, |s: &Stream| s.range(c.start, c.end, c.count, rev))?
+            .unwrap_or_default();
+        Ok(entries_reply(entries))
+    }
+
+    /// Resolves `$` to each stream's current last ID, so that only entries
+    /// added from now on are read.
+    pub(super) fn xread_ids(&self, c: &XRead) -> Result<Vec<(Key, StreamId)>, StorageError> {
+        c.keys
+            .iter()
+            .zip(&c.ids)
+            .map(|(key, id)| {
+                let id = match id {
+                    XReadId::After(id) => *id,
+                    // `>` is only parsed for XREADGROUP.
+                    XReadId::New | XReadId::Undelivered => self
+                        .read(key, |s: &Stream| s.last_id())?
+                        .unwrap_or_default(),
+                };
+                Ok((key.clone(), id))
+            })
+            .collect()
+    }
+
+    /// Reads the entries after the given ID of each stream, replying with a
+    /// `[key, entries]` pair for every stream that has some.
+    fn xread(
+        &self,
+        after: &[(Key, StreamId)],
+        count: Option<usize>,
+    ) -> Result<Option<Resp>, StorageError> {
+        let mut streams = Vec::new();
+        for (key, id) in after {
+            let entries = self
+                .read(key, |s: &Stream| s.after(*id, count))?
+                .unwrap_or_default();
+            if !entries.is_empty() {
+                streams.push(array([key.clone().into(), entries_reply(entries)]));
+            }
+        }
+        Ok((!streams.is_empty()).then(|| array(streams)))
+    }
+}
+
+/// Serves `XREAD` with every stream that has entries past its ID. Reading
+/// takes nothing away, so every client waiting on a stream gets served.
+pub(super) fn xread_serve(after: Vec<(Key, StreamId)>, count: Option<usize>) -> Box<Serve> {
+    Box::new(move |storage, _key| storage.xread(&after, count))
+}
+
+pub(super) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// An entry as an `[id, [field, value ...]]` pair.
+pub(super) fn entry_reply(id: StreamId, fields: Vec<Resp>) -> Resp {
+    array([bulk_string(id.to_string()), array(fields)])
+}
+
+pub(super) fn entries_reply(entries: Vec<(StreamId, Vec<Resp>)>) -> Resp {
+    array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| entry_reply(id, fields)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd::CommandExecutor, resp::BulkString};
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn bulk(s: &str) -> Resp {
+        Resp::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args).unwrap();
+        Ok(storage.execute(cmd)?.unwrap())
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> Resp {
+        array([bulk(id), array(fields.iter().map(|f| bulk(f)))])
+    }
+
+    fn storage_err(res: Result<Resp>) -> StorageError {
+        res.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn test_xadd_ids() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["XADD", "s", "5-1", "a", "1"]).unwrap(),
+            bulk("5-1")
+        );
+        assert_eq!(
+            run(&storage, &["XADD", "s", "5-*", "b", "2"]).unwrap(),
+            bulk("5-2")
+        );
+        assert_eq!(
+            run(&storage, &["XADD", "s", "7", "c", "3"]).unwrap(),
+            bulk("7-0")
+        );
+        assert_eq!(
+            storage_err(run(&storage, &["XADD", "s", "7-0", "d", "4"])),
+            StorageError::StreamIdTooSmall
+        );
+        assert_eq!(
+            storage_err(run(&storage, &["XADD", "s", "6-*", "d", "4"])),
+            StorageError::StreamIdTooSmall
+        );
+        let Resp::BulkString(auto) = run(&storage, &["XADD", "s", "*", "e", "5"]).unwrap() else {
+            panic!("Expected an ID");
+        };
+        let (ms, _) = auto.as_str().unwrap().split_once('-').unwrap();
+        assert!(ms.parse::<u64>().unwrap() > 1_600_000_000_000);
+        assert_eq!(run(&storage, &["XLEN", "s"]).unwrap(), integer(4));
+
+        assert_eq!(
+            run(&storage, &["XADD", "new", "0-*", "f", "v"]).unwrap(),
+            bulk("0-1")
+        );
+        assert_eq!(
+            run(&storage, &["XADD", "none", "NOMKSTREAM", "*", "f", "v"]).unwrap(),
+            Resp::Null(Null)
+        );
+        assert_eq!(storage.key_type(&key("none")), "none");
+    }
+
+    #[test]
+    fn test_xrange() {
+        let storage = Storage::new();
+        for id in ["1-0", "1-1", "2-0", "3-5"] {
+            run(&storage, &["XADD", "s", id, "id", id]).unwrap();
+        }
+        assert_eq!(
+            run(&storage, &["XRANGE", "s", "1", "2"]).unwrap(),
+            array([
+                entry("1-0", &["id", "1-0"]),
+                entry("1-1", &["id", "1-1"]),
+                entry("2-0", &["id", "2-0"]),
+            ])
+        );
+        assert_eq!(
+            run(&storage, &["XRANGE", "s", "(1-0", "+", "COUNT", "2"]).unwrap(),
+            array([entry("1-1", &["id", "1-1"]), entry("2-0", &["id", "2-0"])])
+        );
+        assert_eq!(
+            run(&storage, &["XREVRANGE", "s", "+", "-", "COUNT", "2"]).unwrap(),
+            array([entry("3-5", &["id", "3-5"]), entry("2-0", &["id", "2-0"])])
+        );
+        assert_eq!(
+            run(&storage, &["XRANGE", "s", "3", "1"]).unwrap(),
+            array([])
+        );
+        assert_eq!(
+            run(&storage, &["XRANGE", "missing", "-", "+"]).unwrap(),
+            array([])
+        );
+    }
+
+    #[test]
+    fn test_xdel_keeps_last_id() {
+        let storage = Storage::new();
+        run(&storage, &["XADD", "s", "1-1", "f", "v"]).unwrap();
+        run(&storage, &["XADD", "s", "2-1", "f", "v"]).unwrap();
+        assert_eq!(
+            run(&storage, &["XDEL", "s", "2-1", "2-1", "9-9"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            storage_err(run(&storage, &["XADD", "s", "2-1", "f", "v"])),
+            StorageError::StreamIdTooSmall
+        );
+        run(&storage, &["XDEL", "s", "1-1"]).unwrap();
+        assert_eq!(run(&storage, &["XLEN", "s"]).unwrap(), integer(0));
+        assert_eq!(storage.key_type(&key("s")), "stream");
+        assert_eq!(
+            run(&storage, &["XADD", "s", "2-*", "f", "v"]).unwrap(),
+            bulk("2-2")
+        );
+    }
+
+    #[test]
+    fn test_trim() {
+        let storage = Storage::new();
+        for i in 1..=250 {
+            run(&storage, &["XADD", "s", &format!("{}-0", i), "f", "v"]).unwrap();
+        }
+        assert_eq!(
+            run(&storage, &["XTRIM", "s", "MAXLEN", "~", "120"]).unwrap(),
+            integer(100)
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["XTRIM", "s", "MAXLEN", "~", "100", "LIMIT", "10"]
+            )
+            .unwrap(),
+            integer(0)
+        );
+        assert_eq!(
+            run(&storage, &["XTRIM", "s", "MAXLEN", "=", "120"]).unwrap(),
+            integer(30)
+        );
+        assert_eq!(
+            run(&storage, &["XTRIM", "s", "MINID", "200"]).unwrap(),
+            integer(69)
+        );
+        assert_eq!(run(&storage, &["XLEN", "s"]).unwrap(), integer(51));
+        run(&storage, &["XADD", "s", "MAXLEN", "2", "*", "f", "v"]).unwrap();
+        assert_eq!(run(&storage, &["XLEN", "s"]).unwrap(), integer(2));
+        assert_eq!(
+            run(&storage, &["XRANGE", "s", "-", "250"]).unwrap(),
+            array([entry("250-0", &["f", "v"])])
+        );
+    }
+
+    #[test]
+    fn test_xread() {
+        let storage = Storage::new();
+        run(&storage, &["XADD", "a", "1-0", "f", "1"]).unwrap();
+        run(&storage, &["XADD", "a", "2-0", "f", "2"]).unwrap();
+        run(&storage, &["XADD", "b", "1-0", "f", "3"]).unwrap();
+        assert_eq!(
+            run(
+                &storage,
+                &["XREAD", "COUNT", "1", "STREAMS", "a", "b", "c", "0", "1", "0"]
+            )
+            .unwrap(),
+            array([array([bulk("a"), array([entry("1-0", &["f", "1"])])])])
+        );
+        assert_eq!(
+            run(&storage, &["XREAD", "STREAMS", "a", "b", "1", "0"]).unwrap(),
+            array([
+                array([bulk("a"), array([entry("2-0", &["f", "2"])])]),
+                array([bulk("b"), array([entry("1-0", &["f", "3"])])]),
+            ])
+        );
+        assert_eq!(
+            run(&storage, &["XREAD", "BLOCK", "10", "STREAMS", "a", "$"]).unwrap(),
+            null_array()
+        );
+
+        storage.set(key("str"), bulk("v"));
+        assert_eq!(
+            storage_err(run(&storage, &["XREAD", "STREAMS", "str", "0"])),
+            StorageError::WrongType
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Server-assisted client-side caching. A client that turns `CLIENT
+//! TRACKING` on is sent an `invalidate` message when a key it may have
+//! cached changes: a key it read, or in BCAST mode any key with one of its
+//! prefixes. As in Redis, keys are tracked by name whatever their
+//! database, and a key read is forgotten once invalidated or once the
+//! client that read it stops tracking.
+
+use super::Storage;
+use crate::{cmd, resp::Key};
+use dashmap::DashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+};
+
+/// Which invalidations a tracking client wants, and where they go.
+struct Tracker {
+    redirect: Option<u64>,
+    bcast: bool,
+    prefixes: Vec<String>,
+    noloop: bool,
+}
+
+impl Tracker {
+    fn broadcasts(&self, key: &str) -> bool {
+        self.bcast && (self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p)))
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Tracking {
+    trackers: RwLock<HashMap<u64, Tracker>>,
+    /// How many clients track keys, so that writes skip the tables when
+    /// none do.
+    active: AtomicUsize,
+    /// The clients that read each key since it last changed.
+    readers: DashMap<Key, HashSet<u64>>,
+    /// The keys each client read, to take it out of `readers` when it
+    /// stops tracking. This may still hold keys that changed since.
+    reads: DashMap<u64, HashSet<Key>>,
+}
+
+impl Storage {
+    /// Turns tracking on for `client`, or replaces its options.
+    pub(super) fn start_tracking(&self, client: u64, options: &cmd::Tracking) {
+        let tracker = Tracker {
+            redirect: options.redirect,
+            bcast: options.bcast,
+            prefixes: options.prefixes.clone(),
+            noloop: options.noloop,
+        };
+        let mut trackers = self.tracking.trackers.write().unwrap();
+        trackers.insert(client, tracker);
+        self.tracking
+            .active
+            .store(trackers.len(), Ordering::Relaxed);
+    }
+
+    /// Turns tracking off for `client` and forgets the keys it read.
+    pub(super) fn stop_tracking(&self, client: u64) {
+        let mut trackers = self.tracking.trackers.write().unwrap();
+        if trackers.remove(&client).is_some() {
+            self.tracking
+                .active
+                .store(trackers.len(), Ordering::Relaxed);
+        }
+        drop(trackers);
+        let Some((_, keys)) = self.tracking.reads.remove(&client) else {
+            return;
+        };
+        for key in keys {
+            if let Some(mut readers) = self.tracking.readers.get_mut(&key) {
+                readers.remove(&client);
+            }
+            self.tracking
+                .readers
+                .remove_if(&key, |_, readers| readers.is_empty());
+        }
+    }
+
+    /// Remembers that `client` read `keys`. Each goes in `readers` before
+    /// `reads`, so that a racing `invalidate` can leave a key in `reads`
+    /// only, which is harmless, and never in `readers` only.
+    pub(super) fn track(&self, client: u64, keys: Vec<Key>) {
+        for key in &keys {
+            self.tracking
+                .readers
+                .entry(key.clone())
+                .or_default()
+                .insert(client);
+        }
+        self.tracking.reads.entry(client).or_default().extend(keys);
+    }
+
+    /// Tells the clients tracking `key` that it changed. The client making
+    /// the change is left out if it asked for `NOLOOP`.
+    pub(super) fn invalidate(&self, key: &Key) {
+        // With no client tracking, `readers` is empty too.
+        if self.tracking.active.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let readers = self
+            .tracking
+            .readers
+            .remove(key)
+            .map(|(_, readers)| readers)
+            .unwrap_or_default();
+        for id in &readers {
+            if let Some(mut keys) = self.tracking.reads.get_mut(id) {
+                keys.remove(key);
+            }
+        }
+        let trackers = self.tracking.trackers.read().unwrap();
+        let name = key.to_string();
+        let broadcast = trackers
+            .iter()
+            .filter(|(_, tracker)| tracker.broadcasts(&name))
+            .map(|(id, _)| *id);
+        for id in readers.into_iter().chain(broadcast) {
+            let Some(tracker) = trackers.get(&id) else {
+                continue;
+            };
+            if tracker.noloop && id == self.client {
+                continue;
+            }
+            self.send_invalidation(id, tracker, Some(key));
+        }
+    }
+
+    /// Tells every tracking client to drop its whole cache, after a flush.
+    pub(super) fn invalidate_all(&self) {
+        if self.tracking.active.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        // `reads` first, for the same reason `track` fills it last.
+        self.tracking.reads.clear();
+        self.tracking.readers.clear();
+        let trackers = self.tracking.trackers.read().unwrap();
+        for (id, tracker) in trackers.iter() {
+            self.send_invalidation(*id, tracker, None);
+        }
+    }
+
+    /// Sends client `id` an invalidation, or to the client it redirects
+    /// them to. If that one is gone, `id` is told its redirection broke.
+    fn send_invalidation(&self, id: u64, tracker: &Tracker, key: Option<&Key>) {
+        let target = tracker.redirect.unwrap_or(id);
+        if !self.clients.invalidate(target, key) && target != id {
+            self.clients.redirect_broken(id, target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    #[test]
+    fn test_stop_tracking() {
+        let storage = Storage::new();
+        let tracking = &storage.tracking;
+        storage.start_tracking(1, &cmd::Tracking::default());
+        storage.start_tracking(2, &cmd::Tracking::default());
+        storage.track(1, vec![key("x"), key("y")]);
+        storage.track(2, vec![key("x"), key("z")]);
+        storage.invalidate(&key("y"));
+        assert_eq!(tracking.readers.len(), 2);
+        assert!(!tracking.reads.get(&1).unwrap().contains(&key("y")));
+
+        storage.stop_tracking(1);
+        assert!(!tracking.reads.contains_key(&1));
+        assert_eq!(
+            tracking.readers.get(&key("x")).as_deref(),
+            Some(&HashSet::from([2]))
+        );
+        assert_eq!(tracking.readers.len(), 2);
+        storage.stop_tracking(2);
+        assert!(tracking.readers.is_empty());
+        assert!(tracking.reads.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Key versions for `WATCH`. Only keys some connection watches have a
+//! version, bumped by every write to them; `EXEC` compares the versions
+//! seen at `WATCH` time with the current ones. Each database has its own.
+
+use super::Storage;
+use crate::resp::Key;
+use dashmap::DashMap;
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Default)]
+pub(super) struct Version {
+    /// How many connections watch the key.
+    watchers: usize,
+    version: u64,
+}
+
+pub(super) type Versions = DashMap<Key, Version>;
+
+impl Storage {
+    /// Records a write to `key`.
+    pub(super) fn touch(&self, key: &Key) {
+        if let Some(mut v) = self.versions[self.db].get_mut(key) {
+            v.version += 1;
+        }
+    }
+
+    /// Records a write to every watched key in `dbs` that exists in one of
+    /// them, for commands that replace whole databases.
+    pub(super) fn touch_databases(&self, dbs: &[usize]) {
+        for &db in dbs {
+            for mut v in self.versions[db].iter_mut() {
+                if dbs
+                    .iter()
+                    .any(|&d| self.keyspace_of(d).contains_key(v.key()))
+                {
+                    v.version += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The keys a connection watches, by database, and the versions it saw.
+/// Dropping it unwatches them.
+pub struct Watcher {
+    versions: Arc<Vec<Versions>>,
+    keys: HashMap<(usize, Key), u64>,
+}
+
+impl Watcher {
+    pub fn new(storage: &Storage) -> Self {
+        Watcher {
+            versions: storage.versions.clone(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Watches `keys` in database `db`.
+    pub fn watch(&mut self, db: usize, keys: &[Key]) {
+        for key in keys {
+            let id = (db, key.clone());
+            if self.keys.contains_key(&id) {
+                continue;
+            }
+            let mut v = self.versions[db].entry(key.clone()).or_default();
+            v.watchers += 1;
+            self.keys.insert(id, v.version);
+        }
+    }
+
+    pub fn unwatch(&mut self) {
+        for ((db, key), _) in self.keys.drain() {
+            self.versions[db].remove_if_mut(&key, |_, v| {
+                v.watchers -= 1;
+                v.watchers == 0
+            });
+        }
+    }
+
+    /// How many keys are watched.
+    pub(super) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether any watched key was written since it was watched.
+    pub fn is_touched(&self) -> bool {
+        self.keys.iter().any(|((db, key), seen)| {
+            self.versions[*db]
+                .get(key)
+                .is_some_and(|v| v.version != *seen)
+        })
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.unwatch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{Client, Queued},
+        cmd::{parse_command, CommandExecutor},
+        resp::{BulkString, Protocol, Resp},
+    };
+    use std::thread;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn run(executor: &dyn CommandExecutor, args: &[&str]) -> Resp {
+        parse_command(args).unwrap().execute(executor).unwrap()
+    }
+
+    fn get(storage: &Storage, k: &str) -> String {
+        Key::try_from(run(storage, &["GET", k]))
+            .unwrap()
+            .to_string()
+    }
+
+    fn client(storage: &Storage) -> Client {
+        let addr = "127.0.0.1:50000".parse().unwrap();
+        let laddr = "127.0.0.1:6379".parse().unwrap();
+        Client::new(storage, addr, laddr)
+    }
+
+    /// Sends `args` through the client's transaction state, as a connection
+    /// does.
+    fn send(client: &mut Client, args: &[&str]) -> Resp {
+        let mut protocol = Protocol::Resp2;
+        let Queued::Reply(Ok(mut replies)) = client.queue(parse_command(args), &mut protocol)
+        else {
+            panic!("Expected {args:?} to be handled by the transaction");
+        };
+        replies.remove(0)
+    }
+
+    /// Runs `commands` in a `MULTI`/`EXEC` transaction, or returns `None` if
+    /// a watched key changed.
+    fn exec(client: &mut Client, commands: &[&[&str]]) -> Option<Vec<Resp>> {
+        send(client, &["MULTI"]);
+        for args in commands {
+            send(client, args);
+        }
+        match send(client, &["EXEC"]) {
+            Resp::Array(replies) if replies.is_null() => None,
+            Resp::Array(replies) => Some(replies.to_vec()),
+            other => panic!("Unexpected EXEC reply {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_watch() {
+        let storage = Storage::new();
+        let mut a = client(&storage);
+        let mut b = client(&storage);
+        send(&mut a, &["WATCH", "k", "k"]);
+        send(&mut b, &["WATCH", "k"]);
+        assert_eq!(storage.versions[0].get(&key("k")).unwrap().watchers, 2);
+
+        run(&storage, &["SET", "other", "1"]);
+        run(&storage, &["GET", "k"]);
+        assert!(!a.watcher.is_touched());
+        run(&storage, &["DEL", "k"]);
+        assert!(
+            !a.watcher.is_touched(),
+            "deleting a missing key changes nothing"
+        );
+
+        // The other connection's transaction goes through and writes `k`.
+        assert!(exec(&mut b, &[&["SET", "k", "b"]]).is_some());
+        assert_eq!(exec(&mut a, &[&["SET", "k", "a"]]), None);
+        assert_eq!(get(&storage, "k"), "b");
+        assert!(storage.versions[0].is_empty());
+
+        send(&mut a, &["WATCH", "k"]);
+        run(&storage, &["DEL", "k"]);
+        assert!(a.watcher.is_touched());
+        drop(a);
+        assert!(storage.versions[0].is_empty());
+    }
+
+    #[test]
+    fn test_check_and_set_race() {
+        let storage = Storage::new();
+        run(&storage, &["SET", "counter", "0"]);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let storage = storage.clone();
+                thread::spawn(move || {
+                    let mut client = client(&storage);
+                    for _ in 0..200 {
+                        loop {
+                            send(&mut client, &["WATCH", "counter"]);
+                            let n: i64 = get(&storage, "counter").parse().unwrap();
+                            let next = (n + 1).to_string();
+                            let set: &[&str] = &["SET", "counter", &next];
+                            if exec(&mut client, &[set]).is_some() {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(get(&storage, "counter"), "800");
+        assert!(storage.versions[0].is_empty());
+    }
+
+    #[test]
+    fn test_watch_databases() {
+        let storage = Storage::new();
+        let other = storage.select(1).unwrap();
+        run(&other, &["SET", "k", "1"]);
+        let mut watcher = Watcher::new(&storage);
+        watcher.watch(1, &[key("k"), key("missing")]);
+        run(&storage, &["SET", "k", "0"]);
+        run(&storage, &["FLUSHDB"]);
+        assert!(!watcher.is_touched(), "database 0 has its own keys");
+        run(&storage, &["SWAPDB", "0", "2"]);
+        assert!(!watcher.is_touched());
+        run(&storage, &["FLUSHALL", "ASYNC"]);
+        assert!(watcher.is_touched());
+    }
+
+    #[test]
+    fn test_watch_pfcount_cache() {
+        let storage = Storage::new();
+        run(&storage, &["PFADD", "hll", "a", "b"]);
+        let mut watcher = Watcher::new(&storage);
+        watcher.watch(0, &[key("hll")]);
+        run(&storage, &["PFCOUNT", "hll"]);
+        assert!(watcher.is_touched(), "caching the count rewrites the value");
+
+        let mut watcher = Watcher::new(&storage);
+        watcher.watch(0, &[key("hll")]);
+        run(&storage, &["PFCOUNT", "hll"]);
+        assert!(!watcher.is_touched(), "the cached count was fresh");
+    }
+}
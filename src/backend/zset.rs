@@ -0,0 +1,961 @@
+use super::{
+    array,
+    blocking::Serve,
+    bulk_string, integer,
+    list::{null_array, range},
+    notify::Class,
+    scan::ScanOrder,
+    scan_reply,
+    set::Set,
+    skiplist::SkipList,
+    Container, Storage, StorageError, Value,
+};
+use crate::{
+    cmd::{
+        Aggregate, Command, ZAdd, ZAddComparison, ZAddCondition, ZPop, ZRange, ZRangeBy, ZRank,
+        ZScan, ZSetOp,
+    },
+    glob,
+    resp::{Double, Key, Null, Resp},
+};
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// A sorted set: a skiplist ordered by `(score, member)` for range and rank
+/// queries, plus a hash index from member to score.
+#[derive(Debug, Clone, Default)]
+pub(super) struct SortedSet {
+    scores: HashMap<Key, f64>,
+    list: SkipList,
+    /// The members' order for `ZSCAN`.
+    order: ScanOrder<Key>,
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl SortedSet {
+    pub(super) fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub(super) fn score(&self, member: &Key) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`, returning its previous score.
+    pub(super) fn insert(&mut self, member: Key, score: f64) -> Option<f64> {
+        let old = self.scores.insert(member.clone(), score);
+        match old {
+            Some(old) if old == score => {}
+            Some(old) => {
+                self.list.remove(old, &member);
+                self.list.insert(score, member);
+            }
+            None => {
+                self.order.insert(member.clone());
+                self.list.insert(score, member);
+            }
+        }
+        old
+    }
+
+    pub(super) fn remove(&mut self, member: &Key) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(score, member);
+        self.order.remove(member);
+        Some(score)
+    }
+
+    /// 0-based rank of `member` in ascending order.
+    pub(super) fn rank(&self, member: &Key) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(score, member)
+    }
+
+    /// Up to `count` elements starting at `rank`, walking towards higher
+    /// scores, or towards lower ones if `rev` is set.
+    pub(super) fn range(&self, rank: usize, count: usize, rev: bool) -> Vec<(Key, f64)> {
+        let iter = match rev {
+            false => self.list.iter_from(rank),
+            true => self.list.rev_iter_from(rank),
+        };
+        iter.take(count).map(|(m, s)| (m.clone(), s)).collect()
+    }
+
+    /// Removes and returns up to `count` elements with the lowest scores, or
+    /// the highest if `max` is set.
+    pub(super) fn pop(&mut self, count: usize, max: bool) -> Vec<(Key, f64)> {
+        let popped = match max {
+            false => self.range(0, count, false),
+            true => self.range(self.len().saturating_sub(1), count, true),
+        };
+        for (member, _) in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+
+    /// Elements with scores in `[min, max)`, in ascending order.
+    pub(super) fn score_range(&self, min: f64, max: f64) -> impl Iterator<Item = (&Key, f64)> {
+        let rank = self.list.count_while(|s, _| s < min);
+        self.list.iter_from(rank).take_while(move |&(_, s)| s < max)
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = (&Key, f64)> {
+        self.scores.iter().map(|(m, s)| (m, *s))
+    }
+
+    /// The ranks selected by `by`, as a half-open ascending range.
+    fn ranks(&self, by: &ZRangeBy, rev: bool) -> (usize, usize) {
+        let len = self.len();
+        let (lo, hi) = match by {
+            ZRangeBy::Rank(start, stop) => match range(*start, *stop, len) {
+                Some((start, stop)) if rev => (len - 1 - stop, len - start),
+                Some((start, stop)) => (start, stop + 1),
+                None => (0, 0),
+            },
+            ZRangeBy::Score(min, max) => (
+                self.list.count_while(|s, _| min.below_min(s)),
+                self.list.count_while(|s, _| max.within_max(s)),
+            ),
+            ZRangeBy::Lex(min, max) => (
+                self.list.count_while(|_, m| min.below_min(m)),
+                self.list.count_while(|_, m| max.within_max(m)),
+            ),
+        };
+        (lo, hi.max(lo))
+    }
+}
+
+impl FromIterator<(Key, f64)> for SortedSet {
+    fn from_iter<T: IntoIterator<Item = (Key, f64)>>(iter: T) -> Self {
+        let mut zset = SortedSet::default();
+        for (member, score) in iter {
+            zset.insert(member, score);
+        }
+        zset
+    }
+}
+
+/// An input of `ZUNION` and friends. Plain sets are accepted too, with every
+/// member scoring 1.
+enum Input<'a> {
+    ZSet(&'a SortedSet),
+    Set(&'a Set),
+}
+
+impl Input<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Input::ZSet(z) => z.len(),
+            Input::Set(s) => s.len(),
+        }
+    }
+
+    fn score(&self, member: &Key) -> Option<f64> {
+        match self {
+            Input::ZSet(z) => z.score(member),
+            Input::Set(s) => s.contains(member).then_some(1.0),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Key, f64)> + '_> {
+        match self {
+            Input::ZSet(z) => Box::new(z.iter().map(|(m, s)| (m.clone(), s))),
+            Input::Set(s) => Box::new(s.iter().map(|m| (m, 1.0))),
+        }
+    }
+}
+
+/// Scales a score by its input's weight. Like Redis, `0 * inf` counts as 0.
+fn weighted(score: f64, weight: f64) -> f64 {
+    let score = score * weight;
+    if score.is_nan() {
+        0.0
+    } else {
+        score
+    }
+}
+
+fn aggregate(aggregate: Aggregate, acc: f64, score: f64) -> f64 {
+    match aggregate {
+        Aggregate::Sum => {
+            let sum = acc + score;
+            if sum.is_nan() {
+                0.0
+            } else {
+                sum
+            }
+        }
+        Aggregate::Min => acc.min(score),
+        Aggregate::Max => acc.max(score),
+    }
+}
+
+impl Container for SortedSet {
+    fn downcast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::ZSet(z) => Some(z),
+            _ => None,
+        }
+    }
+
+    fn downcast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::ZSet(z) => Some(z),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::ZSet(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Storage {
+    pub(super) fn execute_zset(&self, cmd: Command) -> Result<Option<Resp>> {
+        let res = match cmd {
+            Command::ZAdd(c) => {
+                let res = self.zadd(&c)?;
+                self.signal_ready(&c.key);
+                res
+            }
+            Command::ZRange(c) => self.zrange(&c)?,
+            Command::ZRank(c) => self.zrank(&c, false)?,
+            Command::ZRevRank(c) => self.zrank(&c, true)?,
+            Command::ZScore(c) => self
+                .read(&c.key, |z: &SortedSet| z.score(&c.member))?
+                .flatten()
+                .map_or(Resp::Null(Null), double),
+            Command::ZIncrBy(c) => {
+                let score = self
+                    .write(&c.key, true, |z: &mut SortedSet| {
+                        let score = z.score(&c.member).unwrap_or(0.0) + c.increment;
+                        if score.is_nan() {
+                            return Err(StorageError::ScoreNan);
+                        }
+                        z.insert(c.member.clone(), score);
+                        Ok(score)
+                    })?
+                    .unwrap_or_default();
+                self.notify(Class::ZSET, "zincr", &c.key);
+                self.signal_ready(&c.key);
+                double(score)
+            }
+            Command::ZRem(c) => {
+                let removed = self
+                    .write(&c.key, false, |z: &mut SortedSet| {
+                        Ok(c.members.iter().filter(|m| z.remove(m).is_some()).count() as i64)
+                    })?
+                    .unwrap_or(0);
+                if removed > 0 {
+                    self.notify_write(Class::ZSET, "zrem", &c.key);
+                }
+                integer(removed)
+            }
+            Command::ZCount(c) => integer(
+                self.read(&c.key, |z: &SortedSet| {
+                    let (lo, hi) = z.ranks(&ZRangeBy::Score(c.min, c.max), false);
+                    hi - lo
+                })?
+                .unwrap_or(0) as i64,
+            ),
+            Command::ZCard(c) => {
+                integer(self.read(&c.key, |z: &SortedSet| z.len())?.unwrap_or(0) as i64)
+            }
+            Command::ZPopMin(c) => self.zpop(&c, false)?,
+            Command::ZPopMax(c) => self.zpop(&c, true)?,
+            // Outside of a connection's blocking path these behave as if
+            // the timeout expired straight away.
+            Command::BZPopMin(c) => self
+                .try_serve(&c.keys, &*bzpop_serve(false))?
+                .unwrap_or_else(null_array),
+            Command::BZPopMax(c) => self
+                .try_serve(&c.keys, &*bzpop_serve(true))?
+                .unwrap_or_else(null_array),
+            Command::BZMPop(c) => self
+                .try_serve(&c.keys, &*bzmpop_serve(c.max, c.count))?
+                .unwrap_or_else(null_array),
+            Command::ZScan(c) => self.zscan(&c)?,
+            Command::ZUnionStore(c) | Command::ZUnion(c) => {
+                let zset = self.zunion(&c)?;
+                self.zsetop_reply(&c, zset, "zunionstore")
+            }
+            Command::ZInterStore(c) | Command::ZInter(c) => {
+                let zset = self.zinter(&c)?;
+                self.zsetop_reply(&c, zset, "zinterstore")
+            }
+            Command::ZDiffStore(c) | Command::ZDiff(c) => {
+                let zset = self.zdiff(&c)?;
+                self.zsetop_reply(&c, zset, "zdiffstore")
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(res))
+    }
+
+    pub(super) fn zadd(&self, c: &ZAdd) -> Result<Resp, StorageError> {
+        let create = c.condition != Some(ZAddCondition::Xx);
+        let res = self.write(&c.key, create, |z: &mut SortedSet| {
+            let (mut added, mut changed) = (0, 0);
+            let mut last = None;
+            for (score, member) in &c.pairs {
+                let new = match z.score(member) {
+                    Some(_) if c.condition == Some(ZAddCondition::Nx) => continue,
+                    None if c.condition == Some(ZAddCondition::Xx) => continue,
+                    Some(old) => {
+                        let new = if c.incr { old + score } else { *score };
+                        if new.is_nan() {
+                            return Err(StorageError::ScoreNan);
+                        }
+                        match c.comparison {
+                            Some(ZAddComparison::Gt) if new <= old => continue,
+                            Some(ZAddComparison::Lt) if new >= old => continue,
+                            _ => {}
+                        }
+                        if new != old {
+                            changed += 1;
+                        }
+                        new
+                    }
+                    None => {
+                        added += 1;
+                        *score
+                    }
+                };
+                z.insert(member.clone(), new);
+                last = Some(new);
+            }
+            Ok((added, changed, last))
+        })?;
+        let (added, changed, last) = res.unwrap_or_default();
+        if added + changed > 0 {
+            let event = if c.incr { "zincr" } else { "zadd" };
+            self.notify(Class::ZSET, event, &c.key);
+        }
+        Ok(match (c.incr, c.ch) {
+            (true, _) => last.map_or(Resp::Null(Null), double),
+            (false, true) => integer(added + changed),
+            (false, false) => integer(added),
+        })
+    }
+
+    fn zrange(&self, c: &ZRange) -> Result<Resp, StorageError> {
+        let (offset, count) = match c.limit {
+            Some((offset, _)) if offset < 0 => return Ok(array([])),
+            Some((offset, count)) if count >= 0 => (offset as usize, count as usize),
+            Some((offset, _)) => (offset as usize, usize::MAX),
+            None => (0, usize::MAX),
+        };
+        let items = self
+            .read(&c.key, |z: &SortedSet| {
+                let (lo, hi) = z.ranks(&c.by, c.rev);
+                let count = (hi - lo).saturating_sub(offset).min(count);
+                if count == 0 {
+                    return Vec::new();
+                }
+                match c.rev {
+                    false => z.range(lo + offset, count, false),
+                    true => z.range(hi - 1 - offset, count, true),
+                }
+            })?
+            .unwrap_or_default();
+        Ok(scored_reply(items, c.with_scores))
+    }
+
+    fn zrank(&self, c: &ZRank, rev: bool) -> Result<Resp, StorageError> {
+        let found = self
+            .read(&c.key, |z: &SortedSet| {
+                let rank = z.rank(&c.member)?;
+                let score = z.score(&c.member)?;
+                Some(match rev {
+                    false => (rank, score),
+                    true => (z.len() - 1 - rank, score),
+                })
+            })?
+            .flatten();
+        Ok(match found {
+            None => Resp::Null(Null),
+            Some((rank, score)) if c.with_score => array([integer(rank as i64), double(score)]),
+            Some((rank, _)) => integer(rank as i64),
+        })
+    }
+
+    fn zpop(&self, c: &ZPop, max: bool) -> Result<Resp, StorageError> {
+        let popped = self
+            .pop_scored(&c.key, c.count.unwrap_or(1), max)?
+            .unwrap_or_default();
+        Ok(scored_reply(popped, true))
+    }
+
+    /// Pops up to `count` of the lowest or highest scored members. Returns
+    /// `None` if the key doesn't exist.
+    fn pop_scored(
+        &self,
+        key: &Key,
+        count: usize,
+        max: bool,
+    ) -> Result<Option<Vec<(Key, f64)>>, StorageError> {
+        let popped = self.write(key, false, |z: &mut SortedSet| Ok(z.pop(count, max)))?;
+        if popped.as_ref().is_some_and(|p| !p.is_empty()) {
+            let event = if max { "zpopmax" } else { "zpopmin" };
+            self.notify_write(Class::ZSET, event, key);
+        }
+        Ok(popped)
+    }
+
+    /// Runs `f` on the sorted set or set at `key`.
+    fn read_input<T>(
+        &self,
+        key: &Key,
+        f: impl FnOnce(Input) -> T,
+    ) -> Result<Option<T>, StorageError> {
+        match self.keyspace().get(key).as_deref() {
+            Some(Value::ZSet(z)) => Ok(Some(f(Input::ZSet(z)))),
+            Some(Value::Set(s)) => Ok(Some(f(Input::Set(s)))),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn zunion(&self, c: &ZSetOp) -> Result<SortedSet, StorageError> {
+        let mut union: HashMap<Key, f64> = HashMap::new();
+        for (key, weight) in c.keys.iter().zip(&c.weights) {
+            self.read_input(key, |input| {
+                for (member, score) in input.iter() {
+                    let score = weighted(score, *weight);
+                    union
+                        .entry(member)
+                        .and_modify(|acc| *acc = aggregate(c.aggregate, *acc, score))
+                        .or_insert(score);
+                }
+            })?;
+        }
+        Ok(union.into_iter().collect())
+    }
+
+    /// Starts from the smallest input, so each pass can only shrink the
+    /// candidates.
+    fn zinter(&self, c: &ZSetOp) -> Result<SortedSet, StorageError> {
+        let mut inputs = Vec::with_capacity(c.keys.len());
+        for (key, weight) in c.keys.iter().zip(&c.weights) {
+            match self.read_input(key, |input| input.len())? {
+                Some(len) => inputs.push((len, key, *weight)),
+                None => inputs.push((0, key, *weight)),
+            }
+        }
+        inputs.sort_by_key(|(len, _, _)| *len);
+        let Some(&(len, smallest, weight)) = inputs.first() else {
+            return Ok(SortedSet::default());
+        };
+        if len == 0 {
+            return Ok(SortedSet::default());
+        }
+        let mut candidates: Vec<(Key, f64)> = self
+            .read_input(smallest, |input| {
+                input
+                    .iter()
+                    .map(|(m, s)| (m, weighted(s, weight)))
+                    .collect()
+            })?
+            .unwrap_or_default();
+        for (_, key, weight) in &inputs[1..] {
+            candidates = self
+                .read_input(key, |input| {
+                    candidates
+                        .into_iter()
+                        .filter_map(|(m, acc)| {
+                            let score = weighted(input.score(&m)?, *weight);
+                            Some((m, aggregate(c.aggregate, acc, score)))
+                        })
+                        .collect()
+                })?
+                .unwrap_or_default();
+            if candidates.is_empty() {
+                break;
+            }
+        }
+        Ok(candidates.into_iter().collect())
+    }
+
+    /// The members of the first input missing from all the others, with
+    /// their scores in the first.
+    fn zdiff(&self, c: &ZSetOp) -> Result<SortedSet, StorageError> {
+        for key in &c.keys {
+            self.read_input(key, |_| ())?;
+        }
+        let Some((first, others)) = c.keys.split_first() else {
+            return Ok(SortedSet::default());
+        };
+        let mut diff: Vec<(Key, f64)> = self
+            .read_input(first, |input| input.iter().collect())?
+            .unwrap_or_default();
+        for key in others {
+            if diff.is_empty() {
+                break;
+            }
+            self.read_input(key, |input| diff.retain(|(m, _)| input.score(m).is_none()))?;
+        }
+        Ok(diff.into_iter().collect())
+    }
+
+    /// Stores `zset` at the destination, raising `event`, and replies with
+    /// its size, or replies with its members if there is no destination.
+    fn zsetop_reply(&self, c: &ZSetOp, zset: SortedSet, event: &str) -> Resp {
+        let Some(destination) = &c.destination else {
+            return scored_reply(zset.range(0, zset.len(), false), c.with_scores);
+        };
+        integer(self.store_zset(destination, zset, event) as i64)
+    }
+
+    /// Replaces whatever is at `key` with `zset`, deleting the key if the
+    /// sorted set is empty, and raises `event`. Returns its size.
+    pub(super) fn store_zset(&self, key: &Key, zset: SortedSet, event: &str) -> usize {
+        let len = zset.len();
+        if len == 0 {
+            if self.keyspace().remove(key).is_some() {
+                self.notify(Class::GENERIC, "del", key);
+            }
+        } else {
+            self.insert(key.clone(), Value::ZSet(zset));
+            self.notify(Class::ZSET, event, key);
+            self.signal_ready(key);
+        }
+        len
+    }
+
+    fn zscan(&self, c: &ZScan) -> Result<Resp, StorageError> {
+        let (cursor, pairs) = self
+            .read(&c.key, |z: &SortedSet| {
+                let (cursor, members) = z.order.scan(c.cursor, c.options.count);
+                let pairs: Vec<_> = members
+                    .into_iter()
+                    .map(|m| (m.clone(), z.scores[m]))
+                    .collect();
+                (cursor, pairs)
+            })?
+            .unwrap_or_default();
+        let items = pairs
+            .into_iter()
+            .filter(|(m, _)| match &c.options.pattern {
+                Some(p) => glob::matches(p.as_bytes(), m.to_string().as_bytes(), false),
+                None => true,
+            })
+            .flat_map(|(m, s)| [Resp::from(m), bulk_string(s.to_string())]);
+        Ok(scan_reply(cursor, items))
+    }
+}
+
+/// Serves `BZPOPMIN` and `BZPOPMAX` with a `[key, member, score]` triple.
+pub(super) fn bzpop_serve(max: bool) -> Box<Serve> {
+    Box::new(move |storage, key| {
+        let popped = storage
+            .pop_scored(key, 1, max)?
+            .and_then(|mut popped| popped.pop());
+        Ok(popped.map(|(member, score)| array([key.clone().into(), member.into(), double(score)])))
+    })
+}
+
+/// Serves `BZMPOP` with a `[key, [[member, score] ...]]` pair.
+pub(super) fn bzmpop_serve(max: bool, count: usize) -> Box<Serve> {
+    Box::new(move |storage, key| {
+        let popped = storage.pop_scored(key, count, max)?;
+        Ok(popped.map(|popped| {
+            let pairs = popped
+                .into_iter()
+                .map(|(member, score)| array([member.into(), double(score)]));
+            array([key.clone().into(), array(pairs)])
+        }))
+    })
+}
+
+pub(super) fn double(score: f64) -> Resp {
+    Resp::Double(Double::new(score))
+}
+
+/// Members in order, each followed by its score if `with_scores` is set.
+fn scored_reply(items: Vec<(Key, f64)>, with_scores: bool) -> Resp {
+    array(items.into_iter().flat_map(|(member, score)| {
+        let score = with_scores.then(|| double(score));
+        std::iter::once(Resp::from(member)).chain(score)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::BulkString;
+
+    fn key(s: &str) -> Key {
+        Key::BulkString(BulkString::new(s, false))
+    }
+
+    fn bulk(s: &str) -> Resp {
+        Resp::from(key(s))
+    }
+
+    fn run(storage: &Storage, args: &[&str]) -> Result<Resp> {
+        let cmd = crate::cmd::parse_command(args)?;
+        Ok(storage.execute_zset(cmd)?.unwrap())
+    }
+
+    fn members(items: &[&str]) -> Resp {
+        array(items.iter().map(|s| bulk(s)))
+    }
+
+    fn leaderboard() -> Storage {
+        let storage = Storage::new();
+        run(
+            &storage,
+            &[
+                "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+            ],
+        )
+        .unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_zadd_flags() {
+        let storage = Storage::new();
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "1", "a", "2", "b"]).unwrap(),
+            integer(2)
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "NX", "5", "a", "3", "c"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "XX", "CH", "5", "a", "9", "x"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "GT", "CH", "4", "a", "6", "b"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "LT", "CH", "1", "a", "7", "b"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "0", "-1", "WITHSCORES"]).unwrap(),
+            array([
+                bulk("a"),
+                double(1.0),
+                bulk("c"),
+                double(3.0),
+                bulk("b"),
+                double(6.0)
+            ])
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "INCR", "2.5", "a"]).unwrap(),
+            double(3.5)
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "z", "GT", "INCR", "-1", "a"]).unwrap(),
+            Resp::Null(Null)
+        );
+        assert_eq!(
+            run(&storage, &["ZADD", "none", "XX", "1", "a"]).unwrap(),
+            integer(0)
+        );
+        assert_eq!(storage.key_type(&key("none")), "none");
+        assert_eq!(storage.key_type(&key("z")), "zset");
+    }
+
+    #[test]
+    fn test_zincrby_nan() {
+        let storage = Storage::new();
+        run(&storage, &["ZADD", "z", "inf", "a"]).unwrap();
+        let err = run(&storage, &["ZINCRBY", "z", "-inf", "a"]).unwrap_err();
+        assert_eq!(err.to_string(), "ERR resulting score is not a number (NaN)");
+        assert_eq!(
+            run(&storage, &["ZINCRBY", "z", "2", "b"]).unwrap(),
+            double(2.0)
+        );
+        assert_eq!(
+            run(&storage, &["ZSCORE", "z", "a"]).unwrap(),
+            double(f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn test_zrange_by_rank() {
+        let storage = leaderboard();
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "1", "-2"]).unwrap(),
+            members(&["b", "c", "d"])
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "0", "1", "REV"]).unwrap(),
+            members(&["e", "d"])
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "3", "1"]).unwrap(),
+            members(&[])
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "missing", "0", "-1"]).unwrap(),
+            members(&[])
+        );
+    }
+
+    #[test]
+    fn test_zrange_by_score() {
+        let storage = leaderboard();
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "(1", "4", "BYSCORE"]).unwrap(),
+            members(&["b", "c", "d"])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["ZRANGE", "z", "+inf", "(2", "BYSCORE", "REV", "LIMIT", "1", "2"]
+            )
+            .unwrap(),
+            members(&["d", "c"])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["ZRANGE", "z", "-inf", "+inf", "BYSCORE", "LIMIT", "3", "-1"]
+            )
+            .unwrap(),
+            members(&["d", "e"])
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "4", "2", "BYSCORE"]).unwrap(),
+            members(&[])
+        );
+        assert_eq!(
+            run(&storage, &["ZCOUNT", "z", "2", "(4"]).unwrap(),
+            integer(2)
+        );
+    }
+
+    #[test]
+    fn test_zrange_by_lex() {
+        let storage = Storage::new();
+        run(
+            &storage,
+            &["ZADD", "z", "0", "a", "0", "b", "0", "c", "0", "d"],
+        )
+        .unwrap();
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "[b", "(d", "BYLEX"]).unwrap(),
+            members(&["b", "c"])
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "+", "(b", "BYLEX", "REV"]).unwrap(),
+            members(&["d", "c"])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["ZRANGE", "z", "-", "+", "BYLEX", "LIMIT", "1", "1"]
+            )
+            .unwrap(),
+            members(&["b"])
+        );
+    }
+
+    #[test]
+    fn test_zrank_zrem() {
+        let storage = leaderboard();
+        assert_eq!(run(&storage, &["ZRANK", "z", "c"]).unwrap(), integer(2));
+        assert_eq!(run(&storage, &["ZREVRANK", "z", "a"]).unwrap(), integer(4));
+        assert_eq!(
+            run(&storage, &["ZRANK", "z", "e", "WITHSCORE"]).unwrap(),
+            array([integer(4), double(5.0)])
+        );
+        assert_eq!(
+            run(&storage, &["ZRANK", "z", "x"]).unwrap(),
+            Resp::Null(Null)
+        );
+        assert_eq!(
+            run(&storage, &["ZREM", "z", "a", "c", "x"]).unwrap(),
+            integer(2)
+        );
+        assert_eq!(run(&storage, &["ZRANK", "z", "d"]).unwrap(), integer(1));
+        assert_eq!(run(&storage, &["ZCARD", "z"]).unwrap(), integer(3));
+    }
+
+    #[test]
+    fn test_zpop() {
+        let storage = leaderboard();
+        assert_eq!(
+            run(&storage, &["ZPOPMIN", "z"]).unwrap(),
+            array([bulk("a"), double(1.0)])
+        );
+        assert_eq!(
+            run(&storage, &["ZPOPMAX", "z", "2"]).unwrap(),
+            array([bulk("e"), double(5.0), bulk("d"), double(4.0)])
+        );
+        assert_eq!(
+            run(&storage, &["ZPOPMIN", "z", "10"]).unwrap(),
+            array([bulk("b"), double(2.0), bulk("c"), double(3.0)])
+        );
+        assert_eq!(storage.key_type(&key("z")), "none");
+        assert_eq!(run(&storage, &["ZPOPMIN", "z"]).unwrap(), array([]));
+    }
+
+    #[test]
+    fn test_rank_lookups_at_scale() {
+        let storage = Storage::new();
+        for i in 0..2000 {
+            let score = ((i * 7919) % 2000).to_string();
+            run(&storage, &["ZADD", "z", &score, &format!("m{}", i)]).unwrap();
+        }
+        for i in [0, 1, 999, 1999] {
+            let member = format!("m{}", i);
+            let rank = (i * 7919) % 2000;
+            assert_eq!(
+                run(&storage, &["ZRANK", "z", &member]).unwrap(),
+                integer(rank)
+            );
+        }
+        assert_eq!(
+            run(&storage, &["ZRANGE", "z", "1000", "1000", "BYSCORE"]).unwrap(),
+            members(&["m1000"])
+        );
+    }
+
+    #[test]
+    fn test_zunion_zinter() {
+        let storage = Storage::new();
+        run(&storage, &["ZADD", "a", "1", "x", "2", "y", "3", "z"]).unwrap();
+        run(&storage, &["ZADD", "b", "10", "y", "20", "z", "30", "w"]).unwrap();
+        assert_eq!(
+            run(&storage, &["ZUNION", "2", "a", "b", "WITHSCORES"]).unwrap(),
+            array([
+                bulk("x"),
+                double(1.0),
+                bulk("y"),
+                double(12.0),
+                bulk("z"),
+                double(23.0),
+                bulk("w"),
+                double(30.0)
+            ])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &[
+                    "ZINTER",
+                    "2",
+                    "a",
+                    "b",
+                    "WEIGHTS",
+                    "2",
+                    "0.5",
+                    "AGGREGATE",
+                    "MAX",
+                    "WITHSCORES"
+                ]
+            )
+            .unwrap(),
+            array([bulk("y"), double(5.0), bulk("z"), double(10.0)])
+        );
+        assert_eq!(
+            run(&storage, &["ZINTER", "2", "a", "missing"]).unwrap(),
+            array([])
+        );
+        assert_eq!(
+            run(&storage, &["ZDIFF", "2", "b", "a", "WITHSCORES"]).unwrap(),
+            array([bulk("w"), double(30.0)])
+        );
+    }
+
+    #[test]
+    fn test_store_variants() {
+        let storage = Storage::new();
+        run(&storage, &["ZADD", "a", "1", "x", "2", "y"]).unwrap();
+        storage
+            .write(&key("s"), true, |s: &mut Set| {
+                s.insert(key("y"));
+                s.insert(key("q"));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            run(
+                &storage,
+                &["ZUNIONSTORE", "dest", "2", "a", "s", "AGGREGATE", "MIN"]
+            )
+            .unwrap(),
+            integer(3)
+        );
+        assert_eq!(
+            run(&storage, &["ZRANGE", "dest", "0", "-1", "WITHSCORES"]).unwrap(),
+            array([
+                bulk("q"),
+                double(1.0),
+                bulk("x"),
+                double(1.0),
+                bulk("y"),
+                double(1.0)
+            ])
+        );
+        assert_eq!(
+            run(&storage, &["ZINTERSTORE", "a", "2", "a", "s"]).unwrap(),
+            integer(1)
+        );
+        assert_eq!(run(&storage, &["ZSCORE", "a", "y"]).unwrap(), double(3.0));
+        assert_eq!(
+            run(&storage, &["ZDIFFSTORE", "dest", "2", "a", "s"]).unwrap(),
+            integer(0)
+        );
+        assert_eq!(storage.key_type(&key("dest")), "none");
+
+        storage.set(key("str"), bulk("v"));
+        let err = run(&storage, &["ZUNIONSTORE", "dest", "2", "a", "str"]).unwrap_err();
+        assert_eq!(
+            err.downcast::<StorageError>().unwrap(),
+            StorageError::WrongType
+        );
+    }
+
+    #[test]
+    fn test_aggregate_infinities() {
+        let storage = Storage::new();
+        run(&storage, &["ZADD", "a", "inf", "x"]).unwrap();
+        run(&storage, &["ZADD", "b", "-inf", "x"]).unwrap();
+        assert_eq!(
+            run(&storage, &["ZUNION", "2", "a", "b", "WITHSCORES"]).unwrap(),
+            array([bulk("x"), double(0.0)])
+        );
+        assert_eq!(
+            run(
+                &storage,
+                &["ZUNION", "1", "a", "WEIGHTS", "0", "WITHSCORES"]
+            )
+            .unwrap(),
+            array([bulk("x"), double(0.0)])
+        );
+    }
+
+    #[test]
+    fn test_zscan() {
+        let storage = leaderboard();
+        let Resp::Array(reply) = run(&storage, &["ZSCAN", "z", "0", "MATCH", "[ab]"]).unwrap()
+        else {
+            panic!("Expected Array");
+        };
+        let Resp::Array(items) = &reply[1] else {
+            panic!("Expected items");
+        };
+        assert_eq!(items.len(), 4);
+        assert!(items.contains(&bulk("1")));
+    }
+}
@@ -0,0 +1,79 @@
+use super::{expect_min_args, extract_string, CommandError};
+use crate::resp::Resp;
+
+/// `CONFIG GET parameter [parameter ...]` and
+/// `CONFIG SET parameter value [parameter value ...]`. Parameter names are
+/// lowercased while parsing.
+#[derive(Debug, Clone)]
+pub enum Config {
+    Get { parameters: Vec<String> },
+    Set { parameters: Vec<(String, String)> },
+}
+
+impl TryFrom<&[Resp]> for Config {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let rest = &args[1..];
+        match subcommand.as_str() {
+            "GET" => {
+                expect_min_args(rest, 1)?;
+                let parameters = rest
+                    .iter()
+                    .map(|p| Ok(extract_string(p)?.to_lowercase()))
+                    .collect::<Result<_, CommandError>>()?;
+                Ok(Config::Get { parameters })
+            }
+            "SET" => {
+                expect_min_args(rest, 2)?;
+                if !rest.len().is_multiple_of(2) {
+                    return Err(CommandError::SyntaxError);
+                }
+                let parameters = rest
+                    .chunks(2)
+                    .map(|pair| {
+                        Ok((
+                            extract_string(&pair[0])?.to_lowercase(),
+                            extract_string(&pair[1])?,
+                        ))
+                    })
+                    .collect::<Result<_, CommandError>>()?;
+                Ok(Config::Set { parameters })
+            }
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_config() {
+        match command(&["CONFIG", "get", "Notify-*", "maxmemory"]).unwrap() {
+            Command::Config(Config::Get { parameters }) => {
+                assert_eq!(parameters, ["notify-*", "maxmemory"])
+            }
+            _ => panic!("Expected Config"),
+        }
+        match command(&["CONFIG", "SET", "notify-keyspace-events", "KEA"]).unwrap() {
+            Command::Config(Config::Set { parameters }) => assert_eq!(
+                parameters,
+                [("notify-keyspace-events".to_string(), "KEA".to_string())]
+            ),
+            _ => panic!("Expected Config"),
+        }
+        assert_eq!(
+            command(&["CONFIG", "SET", "a", "b", "c"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert!(command(&["CONFIG", "GET"]).is_err());
+        assert_eq!(
+            command(&["CONFIG", "REWRITE"]).unwrap_err(),
+            CommandError::UnknownSubcommand("REWRITE".to_string())
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use super::{expect_args, expect_min_args, extract_integer, extract_string, CommandError};
+use crate::resp::{
+    Array, BulkString, Integer, Key, Map, Protocol, Resp, SimpleError, SimpleString,
+};
+use anyhow::Result;
+use std::time::Duration;
+
+/// `HELLO [protover]`
+#[derive(Debug, Clone)]
+pub struct Hello {
+    pub protover: Option<i64>,
+}
+
+impl TryFrom<&[Resp]> for Hello {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        if args.len() > 1 {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(Hello {
+            protover: args.first().map(extract_integer).transpose()?,
+        })
+    }
+}
+
+/// `SELECT index`
+#[derive(Debug, Clone)]
+pub struct Select {
+    pub db: i64,
+}
+
+impl TryFrom<&[Resp]> for Select {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(Select {
+            db: extract_integer(&args[0])?,
+        })
+    }
+}
+
+/// `PING [message]`
+#[derive(Debug, Clone)]
+pub struct Ping {
+    pub message: Option<Resp>,
+}
+
+impl TryFrom<&[Resp]> for Ping {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        if args.len() > 1 {
+            return Err(CommandError::WrongNumberOfArguments(1, args.len()));
+        }
+        Ok(Ping {
+            message: args.first().cloned(),
+        })
+    }
+}
+
+impl Ping {
+    pub(super) fn execute(&self) -> Result<Resp> {
+        Ok(match &self.message {
+            Some(message) => message.clone(),
+            None => Resp::SimpleString(SimpleString::new("PONG")),
+        })
+    }
+
+    /// The reply on a RESP2 connection in subscribed mode, where a plain
+    /// `PONG` could be mistaken for a message.
+    pub fn subscribed_reply(&self) -> Resp {
+        let message = match &self.message {
+            Some(message) => message.clone(),
+            None => Resp::BulkString(BulkString::new("", false)),
+        };
+        Resp::Array(Array::new(
+            vec![Resp::BulkString(BulkString::new("pong", false)), message],
+            false,
+        ))
+    }
+}
+
+/// `CLIENT SETNAME name`, `CLIENT GETNAME`, `CLIENT ID`, `CLIENT INFO`,
+/// `CLIENT LIST`, `CLIENT KILL`, `CLIENT PAUSE timeout [WRITE | ALL]`,
+/// `CLIENT UNPAUSE`, `CLIENT NO-EVICT ON | OFF`, `CLIENT TRACKING`,
+/// `CLIENT CACHING YES | NO` and `CLIENT GETREDIR`.
+#[derive(Debug, Clone)]
+pub enum Client {
+    /// An empty name clears the current one.
+    SetName {
+        name: Option<String>,
+    },
+    GetName,
+    Id,
+    Info,
+    /// `CLIENT LIST [TYPE type] [ID id [id ...]]`
+    List(ClientFilter),
+    /// `CLIENT KILL addr:port`, the legacy form, or `CLIENT KILL` followed
+    /// by filters.
+    Kill {
+        filter: ClientFilter,
+        legacy: bool,
+    },
+    Pause {
+        timeout: Duration,
+        mode: PauseMode,
+    },
+    Unpause,
+    NoEvict(bool),
+    /// `CLIENT TRACKING ON | OFF`, with the options for `ON`.
+    Tracking(Option<Tracking>),
+    Caching(bool),
+    GetRedir,
+}
+
+/// How a client tracks keys, as given to `CLIENT TRACKING ON [REDIRECT id]
+/// [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tracking {
+    /// The client that receives the invalidation messages instead.
+    pub redirect: Option<u64>,
+    /// Invalidate every key with one of these prefixes, or any key if
+    /// there are none, instead of the keys the client read.
+    pub bcast: bool,
+    pub prefixes: Vec<String>,
+    /// Track only reads right after `CLIENT CACHING YES`.
+    pub optin: bool,
+    /// Track every read except right after `CLIENT CACHING NO`.
+    pub optout: bool,
+    /// Don't invalidate keys the client changed itself.
+    pub noloop: bool,
+}
+
+/// The clients `CLIENT LIST` and `CLIENT KILL` apply to: those matching
+/// every filter given.
+#[derive(Debug, Clone, Default)]
+pub struct ClientFilter {
+    /// Any of these IDs, if not empty.
+    pub ids: Vec<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    pub user: Option<String>,
+    pub client_type: Option<ClientType>,
+    /// Leave out the client running the command.
+    pub skip_me: bool,
+    /// Only clients connected for longer than this many seconds.
+    pub max_age: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientType {
+    Normal,
+    Master,
+    Replica,
+    PubSub,
+}
+
+/// Which commands `CLIENT PAUSE` holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum PauseMode {
+    /// Commands that may write.
+    Write,
+    #[default]
+    All,
+}
+
+impl TryFrom<&[Resp]> for Client {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let rest = &args[1..];
+        match subcommand.as_str() {
+            "SETNAME" if rest.len() == 1 => {
+                let name = extract_string(&rest[0])?;
+                // Names show up in CLIENT LIST, which separates fields with
+                // spaces and clients with newlines.
+                if !name.bytes().all(|b| b.is_ascii_graphic()) {
+                    return Err(CommandError::InvalidClientName);
+                }
+                Ok(Client::SetName {
+                    name: Some(name).filter(|name| !name.is_empty()),
+                })
+            }
+            "GETNAME" if rest.is_empty() => Ok(Client::GetName),
+            "ID" if rest.is_empty() => Ok(Client::Id),
+            "INFO" if rest.is_empty() => Ok(Client::Info),
+            "LIST" => {
+                let mut filter = ClientFilter::default();
+                let mut rest = rest.iter();
+                while let Some(arg) = rest.next() {
+                    match extract_string(arg)?.to_uppercase().as_str() {
+                        "TYPE" => {
+                            let arg = rest.next().ok_or(CommandError::SyntaxError)?;
+                            filter.client_type = Some(extract_client_type(arg)?);
+                        }
+                        "ID" => {
+                            // The IDs run to the end of the command.
+                            filter.ids = rest
+                                .by_ref()
+                                .map(extract_client_id)
+                                .collect::<Result<_, _>>()?;
+                            if filter.ids.is_empty() {
+                                return Err(CommandError::SyntaxError);
+                            }
+                        }
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                }
+                Ok(Client::List(filter))
+            }
+            "KILL" if rest.len() == 1 => Ok(Client::Kill {
+                filter: ClientFilter {
+                    addr: Some(extract_string(&rest[0])?),
+                    ..Default::default()
+                },
+                legacy: true,
+            }),
+            "KILL" if !rest.is_empty() && rest.len().is_multiple_of(2) => {
+                let mut filter = ClientFilter {
+                    skip_me: true,
+                    ..Default::default()
+                };
+                for pair in rest.chunks(2) {
+                    let value = &pair[1];
+                    match extract_string(&pair[0])?.to_uppercase().as_str() {
+                        "ID" => filter.ids = vec![extract_client_id(value)?],
+                        "ADDR" => filter.addr = Some(extract_string(value)?),
+                        "LADDR" => filter.laddr = Some(extract_string(value)?),
+                        "USER" => filter.user = Some(extract_string(value)?),
+                        "TYPE" => filter.client_type = Some(extract_client_type(value)?),
+                        "SKIPME" => {
+                            filter.skip_me = match extract_string(value)?.to_uppercase().as_str() {
+                                "YES" => true,
+                                "NO" => false,
+                                _ => return Err(CommandError::SyntaxError),
+                            }
+                        }
+                        "MAXAGE" => {
+                            let age = extract_integer(value)?;
+                            filter.max_age =
+                                Some(u64::try_from(age).map_err(|_| CommandError::NotAnInteger)?);
+                        }
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                }
+                Ok(Client::Kill {
+                    filter,
+                    legacy: false,
+                })
+            }
+            "PAUSE" if !rest.is_empty() && rest.len() <= 2 => {
+                let timeout = extract_integer(&rest[0])
+                    .ok()
+                    .and_then(|ms| u64::try_from(ms).ok())
+                    .ok_or(CommandError::InvalidPauseTimeout)?;
+                let mode = match rest.get(1).map(extract_string).transpose()? {
+                    None => PauseMode::default(),
+                    Some(mode) => match mode.to_uppercase().as_str() {
+                        "WRITE" => PauseMode::Write,
+                        "ALL" => PauseMode::All,
+                        _ => return Err(CommandError::SyntaxError),
+                    },
+                };
+                Ok(Client::Pause {
+                    timeout: Duration::from_millis(timeout),
+                    mode,
+                })
+            }
+            "UNPAUSE" if rest.is_empty() => Ok(Client::Unpause),
+            "TRACKING" if !rest.is_empty() => {
+                let on = match extract_string(&rest[0])?.to_uppercase().as_str() {
+                    "ON" => true,
+                    "OFF" => false,
+                    _ => return Err(CommandError::SyntaxError),
+                };
+                let mut tracking = Tracking::default();
+                let mut options = rest[1..].iter();
+                while let Some(arg) = options.next() {
+                    match extract_string(arg)?.to_uppercase().as_str() {
+                        "REDIRECT" => {
+                            let id = options.next().ok_or(CommandError::SyntaxError)?;
+                            tracking.redirect = Some(extract_client_id(id)?);
+                        }
+                        "PREFIX" => {
+                            let prefix = options.next().ok_or(CommandError::SyntaxError)?;
+                            tracking.prefixes.push(extract_string(prefix)?);
+                        }
+                        "BCAST" => tracking.bcast = true,
+                        "OPTIN" => tracking.optin = true,
+                        "OPTOUT" => tracking.optout = true,
+                        "NOLOOP" => tracking.noloop = true,
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                }
+                if !tracking.bcast && !tracking.prefixes.is_empty() {
+                    return Err(CommandError::PrefixWithoutBcast);
+                }
+                if tracking.optin && tracking.optout {
+                    return Err(CommandError::OptInWithOptOut);
+                }
+                if tracking.bcast && (tracking.optin || tracking.optout) {
+                    return Err(CommandError::OptInWithBcast);
+                }
+                Ok(Client::Tracking(on.then_some(tracking)))
+            }
+            "CACHING" if rest.len() == 1 => {
+                match extract_string(&rest[0])?.to_uppercase().as_str() {
+                    "YES" => Ok(Client::Caching(true)),
+                    "NO" => Ok(Client::Caching(false)),
+                    _ => Err(CommandError::SyntaxError),
+                }
+            }
+            "GETREDIR" if rest.is_empty() => Ok(Client::GetRedir),
+            "NO-EVICT" if rest.len() == 1 => {
+                match extract_string(&rest[0])?.to_uppercase().as_str() {
+                    "ON" => Ok(Client::NoEvict(true)),
+                    "OFF" => Ok(Client::NoEvict(false)),
+                    _ => Err(CommandError::SyntaxError),
+                }
+            }
+            "SETNAME" | "GETNAME" | "ID" | "INFO" | "KILL" | "PAUSE" | "UNPAUSE" | "NO-EVICT"
+            | "TRACKING" | "CACHING" | "GETREDIR" => Err(CommandError::SyntaxError),
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+fn extract_client_id(arg: &Resp) -> Result<u64, CommandError> {
+    extract_integer(arg)
+        .ok()
+        .and_then(|id| u64::try_from(id).ok())
+        .filter(|id| *id > 0)
+        .ok_or(CommandError::InvalidClientId)
+}
+
+fn extract_client_type(arg: &Resp) -> Result<ClientType, CommandError> {
+    let name = extract_string(arg)?;
+    match name.to_lowercase().as_str() {
+        "normal" => Ok(ClientType::Normal),
+        "master" => Ok(ClientType::Master),
+        "replica" | "slave" => Ok(ClientType::Replica),
+        "pubsub" => Ok(ClientType::PubSub),
+        _ => Err(CommandError::UnknownClientType(name)),
+    }
+}
+
+impl Hello {
+    /// Switches the connection to the requested protocol and describes the
+    /// server. The reply is already encoded with the new protocol.
+    pub fn execute(&self, protocol: &mut Protocol) -> Result<Resp> {
+        match self.protover {
+            Some(2) => *protocol = Protocol::Resp2,
+            Some(3) => *protocol = Protocol::Resp3,
+            Some(_) => {
+                return Ok(Resp::SimpleError(SimpleError::new(
+                    "NOPROTO unsupported protocol version",
+                )))
+            }
+            None => {}
+        }
+        let proto = match protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+        let field = |s: &str| Key::BulkString(BulkString::new(s, false));
+        let bulk = |s: &str| Resp::BulkString(BulkString::new(s, false));
+        let mut map = Map::default();
+        map.insert(field("server"), bulk("redis"));
+        map.insert(field("version"), bulk(env!("CARGO_PKG_VERSION")));
+        map.insert(field("proto"), Resp::Integer(Integer::new(proto)));
+        map.insert(field("mode"), bulk("standalone"));
+        map.insert(field("role"), bulk("master"));
+        map.insert(field("modules"), Resp::Array(Array::default()));
+        Ok(Resp::Map(Box::new(map)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_hello() {
+        let mut protocol = Protocol::default();
+        let Command::Hello(hello) = command(&["HELLO", "3"]).unwrap() else {
+            panic!("Expected Hello");
+        };
+        let Resp::Map(map) = hello.execute(&mut protocol).unwrap() else {
+            panic!("Expected Map");
+        };
+        assert_eq!(protocol, Protocol::Resp3);
+        assert_eq!(
+            map.get(&Key::BulkString(BulkString::new("proto", false))),
+            Some(&Resp::Integer(Integer::new(3)))
+        );
+
+        let Command::Hello(hello) = command(&["HELLO", "4"]).unwrap() else {
+            panic!("Expected Hello");
+        };
+        assert!(matches!(
+            hello.execute(&mut protocol).unwrap(),
+            Resp::SimpleError(_)
+        ));
+        assert_eq!(protocol, Protocol::Resp3);
+    }
+
+    #[test]
+    fn test_parse_connection() {
+        let Command::Ping(ping) = command(&["PING"]).unwrap() else {
+            panic!("Expected Ping");
+        };
+        assert_eq!(
+            ping.execute().unwrap(),
+            Resp::SimpleString(SimpleString::new("PONG"))
+        );
+        let Command::Ping(ping) = command(&["ping", "hi"]).unwrap() else {
+            panic!("Expected Ping");
+        };
+        assert_eq!(
+            ping.execute().unwrap(),
+            Resp::BulkString(BulkString::new("hi", false))
+        );
+        assert_eq!(
+            command(&["PING", "a", "b"]).unwrap_err(),
+            CommandError::WrongNumberOfArguments(1, 2)
+        );
+        assert!(matches!(command(&["QUIT"]).unwrap(), Command::Quit));
+        assert!(matches!(command(&["reset"]).unwrap(), Command::Reset));
+
+        assert!(matches!(
+            command(&["CLIENT", "SETNAME", "worker-1"]).unwrap(),
+            Command::Client(Client::SetName { name: Some(name) }) if name == "worker-1"
+        ));
+        assert!(matches!(
+            command(&["CLIENT", "SETNAME", ""]).unwrap(),
+            Command::Client(Client::SetName { name: None })
+        ));
+        assert_eq!(
+            command(&["CLIENT", "SETNAME", "a b"]).unwrap_err(),
+            CommandError::InvalidClientName
+        );
+        assert!(matches!(
+            command(&["client", "id"]).unwrap(),
+            Command::Client(Client::Id)
+        ));
+        assert_eq!(
+            command(&["CLIENT", "GETNAME", "x"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["CLIENT", "NOPE"]).unwrap_err(),
+            CommandError::UnknownSubcommand("NOPE".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_client_admin() {
+        match command(&["CLIENT", "LIST", "TYPE", "pubsub", "ID", "3", "5"]).unwrap() {
+            Command::Client(Client::List(filter)) => {
+                assert_eq!(filter.client_type, Some(ClientType::PubSub));
+                assert_eq!(filter.ids, vec![3, 5]);
+            }
+            _ => panic!("Expected Client"),
+        }
+        assert_eq!(
+            command(&["CLIENT", "LIST", "TYPE", "robot"]).unwrap_err(),
+            CommandError::UnknownClientType("robot".to_string())
+        );
+        assert_eq!(
+            command(&["CLIENT", "LIST", "ID", "x"]).unwrap_err(),
+            CommandError::InvalidClientId
+        );
+
+        assert!(matches!(
+            command(&["CLIENT", "KILL", "127.0.0.1:5000"]).unwrap(),
+            Command::Client(Client::Kill { filter, legacy: true })
+                if filter.addr.as_deref() == Some("127.0.0.1:5000")
+        ));
+        match command(&["CLIENT", "KILL", "USER", "default", "SKIPME", "no"]).unwrap() {
+            Command::Client(Client::Kill { filter, legacy }) => {
+                assert!(!legacy);
+                assert_eq!(filter.user.as_deref(), Some("default"));
+                assert!(!filter.skip_me);
+            }
+            _ => panic!("Expected Client"),
+        }
+        assert_eq!(
+            command(&["CLIENT", "KILL", "ID", "1", "SKIPME"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+
+        assert!(matches!(
+            command(&["CLIENT", "PAUSE", "100", "write"]).unwrap(),
+            Command::Client(Client::Pause { timeout, mode: PauseMode::Write })
+                if timeout == Duration::from_millis(100)
+        ));
+        assert!(matches!(
+            command(&["CLIENT", "PAUSE", "100"]).unwrap(),
+            Command::Client(Client::Pause {
+                mode: PauseMode::All,
+                ..
+            })
+        ));
+        assert_eq!(
+            command(&["CLIENT", "PAUSE", "-1"]).unwrap_err(),
+            CommandError::InvalidPauseTimeout
+        );
+        assert!(matches!(
+            command(&["CLIENT", "NO-EVICT", "on"]).unwrap(),
+            Command::Client(Client::NoEvict(true))
+        ));
+    }
+
+    #[test]
+    fn test_parse_tracking() {
+        let args = [
+            "CLIENT", "TRACKING", "on", "REDIRECT", "7", "BCAST", "PREFIX", "a:", "PREFIX", "b:",
+            "NOLOOP",
+        ];
+        match command(&args).unwrap() {
+            Command::Client(Client::Tracking(Some(tracking))) => assert_eq!(
+                tracking,
+                Tracking {
+                    redirect: Some(7),
+                    bcast: true,
+                    prefixes: vec!["a:".to_string(), "b:".to_string()],
+                    noloop: true,
+                    ..Default::default()
+                }
+            ),
+            _ => panic!("Expected Client"),
+        }
+        assert!(matches!(
+            command(&["CLIENT", "TRACKING", "OFF"]).unwrap(),
+            Command::Client(Client::Tracking(None))
+        ));
+        assert_eq!(
+            command(&["CLIENT", "TRACKING", "ON", "PREFIX", "a"]).unwrap_err(),
+            CommandError::PrefixWithoutBcast
+        );
+        assert_eq!(
+            command(&["CLIENT", "TRACKING", "ON", "OPTIN", "OPTOUT"]).unwrap_err(),
+            CommandError::OptInWithOptOut
+        );
+        assert_eq!(
+            command(&["CLIENT", "TRACKING", "ON", "BCAST", "OPTOUT"]).unwrap_err(),
+            CommandError::OptInWithBcast
+        );
+        assert_eq!(
+            command(&["CLIENT", "TRACKING", "maybe"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert!(matches!(
+            command(&["CLIENT", "CACHING", "yes"]).unwrap(),
+            Command::Client(Client::Caching(true))
+        ));
+    }
+}
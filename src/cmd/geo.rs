@@ -0,0 +1,401 @@
+use super::{
+    expect_min_args, extract_float, extract_integer, extract_key, extract_keys, extract_string,
+    CommandError, ZAddCondition,
+};
+use crate::resp::{Key, Resp};
+
+/// Latitudes beyond this can't be represented in a Web Mercator geohash.
+pub const GEO_LAT_LIMIT: f64 = 85.051_128_78;
+pub const GEO_LON_LIMIT: f64 = 180.0;
+
+/// `GEOADD key [NX|XX] [CH] longitude latitude member [...]`
+#[derive(Debug, Clone)]
+pub struct GeoAdd {
+    pub key: Key,
+    pub condition: Option<ZAddCondition>,
+    pub ch: bool,
+    pub points: Vec<(f64, f64, Key)>,
+}
+
+impl TryFrom<&[Resp]> for GeoAdd {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 4)?;
+        let (mut nx, mut xx, mut ch) = (false, false, false);
+        let mut rest = &args[1..];
+        while let Some((arg, tail)) = rest.split_first() {
+            match extract_string(arg)?.to_uppercase().as_str() {
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "CH" => ch = true,
+                _ => break,
+            }
+            rest = tail;
+        }
+        if rest.is_empty() || !rest.len().is_multiple_of(3) {
+            return Err(CommandError::SyntaxError);
+        }
+        if nx && xx {
+            return Err(CommandError::ZAddNxWithXx);
+        }
+        let points = rest
+            .chunks(3)
+            .map(|point| {
+                let (lon, lat) = extract_lonlat(&point[0], &point[1])?;
+                Ok((lon, lat, extract_key(&point[2])?))
+            })
+            .collect::<Result<_, CommandError>>()?;
+        Ok(GeoAdd {
+            key: extract_key(&args[0])?,
+            condition: match (nx, xx) {
+                (true, _) => Some(ZAddCondition::Nx),
+                (_, true) => Some(ZAddCondition::Xx),
+                _ => None,
+            },
+            ch,
+            points,
+        })
+    }
+}
+
+/// `GEODIST key member1 member2 [M|KM|FT|MI]`
+#[derive(Debug, Clone)]
+pub struct GeoDist {
+    pub key: Key,
+    pub member1: Key,
+    pub member2: Key,
+    /// Meters per unit of the reply.
+    pub unit: f64,
+}
+
+impl TryFrom<&[Resp]> for GeoDist {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        let unit = match &args[3..] {
+            [] => 1.0,
+            [unit] => extract_unit(unit)?,
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(GeoDist {
+            key: extract_key(&args[0])?,
+            member1: extract_key(&args[1])?,
+            member2: extract_key(&args[2])?,
+            unit,
+        })
+    }
+}
+
+/// A command taking a key and members: `GEOPOS`, `GEOHASH`.
+#[derive(Debug, Clone)]
+pub struct GeoMembers {
+    pub key: Key,
+    pub members: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for GeoMembers {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(GeoMembers {
+            key: extract_key(&args[0])?,
+            members: extract_keys(&args[1..])?,
+        })
+    }
+}
+
+/// Where a search is centered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoFrom {
+    Member(Key),
+    LonLat(f64, f64),
+}
+
+/// The area searched, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoOrder {
+    Asc,
+    Desc,
+}
+
+/// `GEOSEARCH` and `GEOSEARCHSTORE`.
+#[derive(Debug, Clone)]
+pub struct GeoSearch {
+    pub key: Key,
+    pub destination: Option<Key>,
+    pub from: GeoFrom,
+    pub shape: GeoShape,
+    /// Meters per unit of reported and stored distances.
+    pub unit: f64,
+    pub order: Option<GeoOrder>,
+    pub count: Option<usize>,
+    /// Return the first `count` matches found rather than the nearest.
+    pub any: bool,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+    /// Store distances rather than geohashes as scores.
+    pub store_dist: bool,
+}
+
+impl GeoSearch {
+    /// Parses `GEOSEARCH key ...`, or `GEOSEARCHSTORE destination key ...`
+    /// if `store` is set.
+    pub fn parse(args: &[Resp], store: bool) -> Result<Self, CommandError> {
+        let name = if store { "GEOSEARCHSTORE" } else { "GEOSEARCH" };
+        let (destination, args) = match store {
+            true => {
+                expect_min_args(args, 2)?;
+                (Some(extract_key(&args[0])?), &args[1..])
+            }
+            false => (None, args),
+        };
+        expect_min_args(args, 1)?;
+        let mut c = GeoSearch {
+            key: extract_key(&args[0])?,
+            destination,
+            from: GeoFrom::LonLat(0.0, 0.0),
+            shape: GeoShape::Radius(0.0),
+            unit: 1.0,
+            order: None,
+            count: None,
+            any: false,
+            with_coord: false,
+            with_dist: false,
+            with_hash: false,
+            store_dist: false,
+        };
+        let (mut from, mut shape) = (None, None);
+        let mut rest = &args[1..];
+        while let Some((arg, tail)) = rest.split_first() {
+            rest = tail;
+            match extract_string(arg)?.to_uppercase().as_str() {
+                "FROMMEMBER" if from.is_none() => {
+                    let (member, tail) = rest.split_first().ok_or(CommandError::SyntaxError)?;
+                    from = Some(GeoFrom::Member(extract_key(member)?));
+                    rest = tail;
+                }
+                "FROMLONLAT" if from.is_none() && rest.len() >= 2 => {
+                    let (lon, lat) = extract_lonlat(&rest[0], &rest[1])?;
+                    from = Some(GeoFrom::LonLat(lon, lat));
+                    rest = &rest[2..];
+                }
+                "FROMMEMBER" | "FROMLONLAT" if from.is_some() => {
+                    return Err(CommandError::GeoSearchFrom(name));
+                }
+                "BYRADIUS" if shape.is_none() && rest.len() >= 2 => {
+                    let radius = extract_float(&rest[0])?;
+                    if radius < 0.0 {
+                        return Err(CommandError::NegativeRadius);
+                    }
+                    c.unit = extract_unit(&rest[1])?;
+                    shape = Some(GeoShape::Radius(radius * c.unit));
+                    rest = &rest[2..];
+                }
+                "BYBOX" if shape.is_none() && rest.len() >= 3 => {
+                    let width = extract_float(&rest[0])?;
+                    let height = extract_float(&rest[1])?;
+                    if width < 0.0 || height < 0.0 {
+                        return Err(CommandError::NegativeBox);
+                    }
+                    c.unit = extract_unit(&rest[2])?;
+                    shape = Some(GeoShape::Box {
+                        width: width * c.unit,
+                        height: height * c.unit,
+                    });
+                    rest = &rest[3..];
+                }
+                "BYRADIUS" | "BYBOX" if shape.is_some() => {
+                    return Err(CommandError::GeoSearchShape(name));
+                }
+                "ASC" => c.order = Some(GeoOrder::Asc),
+                "DESC" => c.order = Some(GeoOrder::Desc),
+                "COUNT" => {
+                    let (count, tail) = rest.split_first().ok_or(CommandError::SyntaxError)?;
+                    let count = extract_integer(count)?;
+                    if count <= 0 {
+                        return Err(CommandError::GeoCountNotPositive);
+                    }
+                    c.count = Some(count as usize);
+                    rest = tail;
+                    if let Some((any, tail)) = rest.split_first() {
+                        if extract_string(any)?.eq_ignore_ascii_case("ANY") {
+                            c.any = true;
+                            rest = tail;
+                        }
+                    }
+                }
+                "ANY" => c.any = true,
+                "WITHCOORD" if !store => c.with_coord = true,
+                "WITHDIST" if !store => c.with_dist = true,
+                "WITHHASH" if !store => c.with_hash = true,
+                "STOREDIST" if store => c.store_dist = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        c.from = from.ok_or(CommandError::GeoSearchFrom(name))?;
+        c.shape = shape.ok_or(CommandError::GeoSearchShape(name))?;
+        if c.any && c.count.is_none() {
+            return Err(CommandError::GeoAnyWithoutCount);
+        }
+        Ok(c)
+    }
+}
+
+fn extract_lonlat(lon: &Resp, lat: &Resp) -> Result<(f64, f64), CommandError> {
+    let (lon, lat) = (extract_float(lon)?, extract_float(lat)?);
+    if lon.abs() > GEO_LON_LIMIT || lat.abs() > GEO_LAT_LIMIT {
+        return Err(CommandError::InvalidLonLat(lon, lat));
+    }
+    Ok((lon, lat))
+}
+
+/// Parses a distance unit into meters per unit.
+fn extract_unit(arg: &Resp) -> Result<f64, CommandError> {
+    match extract_string(arg)?.to_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(CommandError::UnsupportedUnit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{parse_command as command, Command},
+        resp::BulkString,
+    };
+
+    #[test]
+    fn test_parse_geoadd() {
+        match command(&["GEOADD", "g", "XX", "CH", "13.36", "38.11", "Palermo"]).unwrap() {
+            Command::GeoAdd(c) => {
+                assert_eq!(c.condition, Some(ZAddCondition::Xx));
+                assert!(c.ch);
+                assert_eq!(c.points.len(), 1);
+                assert_eq!((c.points[0].0, c.points[0].1), (13.36, 38.11));
+            }
+            _ => panic!("Expected GeoAdd"),
+        }
+        assert_eq!(
+            command(&["GEOADD", "g", "13.36", "86", "a"]).unwrap_err(),
+            CommandError::InvalidLonLat(13.36, 86.0)
+        );
+        assert_eq!(
+            command(&["GEOADD", "g", "1", "2", "a", "3"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["GEODIST", "g", "a", "b", "yd"]).unwrap_err(),
+            CommandError::UnsupportedUnit
+        );
+    }
+
+    #[test]
+    fn test_parse_geosearch() {
+        match command(&[
+            "GEOSEARCH",
+            "g",
+            "FROMLONLAT",
+            "15",
+            "37",
+            "BYBOX",
+            "400",
+            "200",
+            "km",
+            "DESC",
+            "COUNT",
+            "2",
+            "ANY",
+            "WITHDIST",
+            "WITHCOORD",
+        ])
+        .unwrap()
+        {
+            Command::GeoSearch(c) => {
+                assert_eq!(c.from, GeoFrom::LonLat(15.0, 37.0));
+                assert_eq!(
+                    c.shape,
+                    GeoShape::Box {
+                        width: 400_000.0,
+                        height: 200_000.0
+                    }
+                );
+                assert_eq!(c.unit, 1000.0);
+                assert_eq!(c.order, Some(GeoOrder::Desc));
+                assert_eq!(c.count, Some(2));
+                assert!(c.any && c.with_dist && c.with_coord && !c.with_hash);
+            }
+            _ => panic!("Expected GeoSearch"),
+        }
+        match command(&[
+            "GEOSEARCHSTORE",
+            "dst",
+            "g",
+            "FROMMEMBER",
+            "a",
+            "BYRADIUS",
+            "1",
+            "mi",
+            "STOREDIST",
+        ])
+        .unwrap()
+        {
+            Command::GeoSearchStore(c) => {
+                assert_eq!(
+                    c.destination,
+                    Some(Key::BulkString(BulkString::new("dst", false)))
+                );
+                assert_eq!(
+                    c.from,
+                    GeoFrom::Member(Key::BulkString(BulkString::new("a", false)))
+                );
+                assert_eq!(c.shape, GeoShape::Radius(1609.34));
+                assert!(c.store_dist);
+            }
+            _ => panic!("Expected GeoSearchStore"),
+        }
+        assert_eq!(
+            command(&["GEOSEARCH", "g", "BYRADIUS", "1", "m"]).unwrap_err(),
+            CommandError::GeoSearchFrom("GEOSEARCH")
+        );
+        assert_eq!(
+            command(&[
+                "GEOSEARCH",
+                "g",
+                "FROMMEMBER",
+                "a",
+                "BYRADIUS",
+                "1",
+                "m",
+                "ANY"
+            ])
+            .unwrap_err(),
+            CommandError::GeoAnyWithoutCount
+        );
+        assert_eq!(
+            command(&[
+                "GEOSEARCHSTORE",
+                "d",
+                "g",
+                "FROMMEMBER",
+                "a",
+                "BYRADIUS",
+                "1",
+                "m",
+                "WITHDIST"
+            ])
+            .unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use super::{
+    expect_args, expect_min_args, extract_float, extract_integer, extract_key, extract_keys,
+    extract_string, CommandError, ScanOptions,
+};
+use crate::resp::{Key, Resp};
+
+/// `HSET key field value [field value ...]`
+#[derive(Debug, Clone)]
+pub struct HSet {
+    pub key: Key,
+    pub pairs: Vec<(Key, Resp)>,
+}
+
+impl TryFrom<&[Resp]> for HSet {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        if args.len().is_multiple_of(2) {
+            return Err(CommandError::WrongNumberOfArguments(
+                args.len() + 1,
+                args.len(),
+            ));
+        }
+        let pairs = args[1..]
+            .chunks(2)
+            .map(|pair| Ok((extract_key(&pair[0])?, pair[1].clone())))
+            .collect::<Result<_, CommandError>>()?;
+        Ok(HSet {
+            key: extract_key(&args[0])?,
+            pairs,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HGet {
+    pub key: Key,
+    pub field: Key,
+}
+
+impl TryFrom<&[Resp]> for HGet {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(HGet {
+            key: extract_key(&args[0])?,
+            field: extract_key(&args[1])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HMGet {
+    pub key: Key,
+    pub fields: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for HMGet {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(HMGet {
+            key: extract_key(&args[0])?,
+            fields: extract_keys(&args[1..])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HDel {
+    pub key: Key,
+    pub fields: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for HDel {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(HDel {
+            key: extract_key(&args[0])?,
+            fields: extract_keys(&args[1..])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HIncrBy {
+    pub key: Key,
+    pub field: Key,
+    pub increment: i64,
+}
+
+impl TryFrom<&[Resp]> for HIncrBy {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(HIncrBy {
+            key: extract_key(&args[0])?,
+            field: extract_key(&args[1])?,
+            increment: extract_integer(&args[2])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HIncrByFloat {
+    pub key: Key,
+    pub field: Key,
+    pub increment: f64,
+}
+
+impl TryFrom<&[Resp]> for HIncrByFloat {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(HIncrByFloat {
+            key: extract_key(&args[0])?,
+            field: extract_key(&args[1])?,
+            increment: extract_float(&args[2])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HExists {
+    pub key: Key,
+    pub field: Key,
+}
+
+impl TryFrom<&[Resp]> for HExists {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(HExists {
+            key: extract_key(&args[0])?,
+            field: extract_key(&args[1])?,
+        })
+    }
+}
+
+/// A command taking a single hash key: `HGETALL`, `HLEN`, `HKEYS`, `HVALS`.
+#[derive(Debug, Clone)]
+pub struct HashKey {
+    pub key: Key,
+}
+
+impl TryFrom<&[Resp]> for HashKey {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(HashKey {
+            key: extract_key(&args[0])?,
+        })
+    }
+}
+
+/// `HRANDFIELD key [count [WITHVALUES]]`
+#[derive(Debug, Clone)]
+pub struct HRandField {
+    pub key: Key,
+    pub count: Option<i64>,
+    pub with_values: bool,
+}
+
+impl TryFrom<&[Resp]> for HRandField {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        if args.len() > 3 {
+            return Err(CommandError::SyntaxError);
+        }
+        let with_values = match args.get(2) {
+            Some(arg) if extract_string(arg)?.eq_ignore_ascii_case("WITHVALUES") => true,
+            Some(_) => return Err(CommandError::SyntaxError),
+            None => false,
+        };
+        let count = args.get(1).map(extract_integer).transpose()?;
+        // As in Redis, the number of replies a negative count asks for must
+        // not overflow.
+        let min = if with_values {
+            -i64::MAX / 2
+        } else {
+            -i64::MAX
+        };
+        if count.is_some_and(|n| n < min) {
+            return Err(CommandError::OutOfRange);
+        }
+        Ok(HRandField {
+            key: extract_key(&args[0])?,
+            count,
+            with_values,
+        })
+    }
+}
+
+/// `HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]`
+#[derive(Debug, Clone)]
+pub struct HScan {
+    pub key: Key,
+    pub cursor: u64,
+    pub options: ScanOptions,
+    pub no_values: bool,
+}
+
+impl TryFrom<&[Resp]> for HScan {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let mut rest = &args[2..];
+        let no_values = match rest.last() {
+            Some(arg) if extract_string(arg)?.eq_ignore_ascii_case("NOVALUES") => {
+                rest = &rest[..rest.len() - 1];
+                true
+            }
+            _ => false,
+        };
+        Ok(HScan {
+            key: extract_key(&args[0])?,
+            cursor: super::extract_cursor(&args[1])?,
+            options: ScanOptions::parse(rest, false)?,
+            no_values,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_hset() {
+        match command(&["HSET", "h", "f1", "v1", "f2", "v2"]).unwrap() {
+            Command::HSet(HSet { pairs, .. }) => assert_eq!(pairs.len(), 2),
+            _ => panic!("Expected HSet"),
+        }
+        assert!(command(&["HSET", "h", "f1"]).is_err());
+        assert!(command(&["HSET", "h", "f1", "v1", "f2"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_hincrby() {
+        match command(&["HINCRBYFLOAT", "h", "f", "1.5"]).unwrap() {
+            Command::HIncrByFloat(c) => assert_eq!(c.increment, 1.5),
+            _ => panic!("Expected HIncrByFloat"),
+        }
+        assert_eq!(
+            command(&["HINCRBY", "h", "f", "1.5"]).unwrap_err(),
+            CommandError::NotAnInteger
+        );
+        assert_eq!(
+            command(&["HINCRBYFLOAT", "h", "f", "x"]).unwrap_err(),
+            CommandError::NotAFloat
+        );
+    }
+
+    #[test]
+    fn test_parse_hrandfield() {
+        match command(&["HRANDFIELD", "h", "-5", "withvalues"]).unwrap() {
+            Command::HRandField(c) => {
+                assert_eq!(c.count, Some(-5));
+                assert!(c.with_values);
+            }
+            _ => panic!("Expected HRandField"),
+        }
+        assert_eq!(
+            command(&["HRANDFIELD", "h", "1", "x"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["HRANDFIELD", "h", "-9223372036854775808"]).unwrap_err(),
+            CommandError::OutOfRange
+        );
+        assert_eq!(
+            command(&["HRANDFIELD", "h", "-4611686018427387904", "WITHVALUES"]).unwrap_err(),
+            CommandError::OutOfRange
+        );
+        assert!(command(&["HRANDFIELD", "h", "-9223372036854775807"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_hscan() {
+        match command(&["HSCAN", "h", "0", "MATCH", "f*", "NOVALUES"]).unwrap() {
+            Command::HScan(c) => {
+                assert_eq!(c.options.pattern.as_deref(), Some("f*"));
+                assert!(c.no_values);
+            }
+            _ => panic!("Expected HScan"),
+        }
+        assert_eq!(
+            command(&["HSCAN", "h", "0", "TYPE", "string"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+}
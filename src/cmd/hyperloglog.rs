@@ -0,0 +1,57 @@
+use super::{expect_min_args, extract_bytes, extract_key, extract_keys, CommandError};
+use crate::resp::{Key, Resp};
+
+/// `PFADD key [element ...]`
+#[derive(Debug, Clone)]
+pub struct PfAdd {
+    pub key: Key,
+    pub elements: Vec<Vec<u8>>,
+}
+
+impl TryFrom<&[Resp]> for PfAdd {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(PfAdd {
+            key: extract_key(&args[0])?,
+            elements: args[1..]
+                .iter()
+                .map(extract_bytes)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// `PFCOUNT key [key ...]`
+#[derive(Debug, Clone)]
+pub struct PfCount {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for PfCount {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(PfCount {
+            keys: extract_keys(args)?,
+        })
+    }
+}
+
+/// `PFMERGE destkey [sourcekey ...]`
+#[derive(Debug, Clone)]
+pub struct PfMerge {
+    pub destination: Key,
+    pub sources: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for PfMerge {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(PfMerge {
+            destination: extract_key(&args[0])?,
+            sources: extract_keys(&args[1..])?,
+        })
+    }
+}
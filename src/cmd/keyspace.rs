@@ -0,0 +1,403 @@
+use super::CommandError;
+use super::{
+    expect_args, expect_min_args, extract_integer, extract_key, extract_keys, extract_string,
+};
+use crate::resp::{Key, Resp};
+
+#[derive(Debug, Clone)]
+pub struct Del {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for Del {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(Del {
+            keys: extract_keys(args)?,
+        })
+    }
+}
+
+/// Like `Del`, but large values are freed on a background task.
+#[derive(Debug, Clone)]
+pub struct Unlink {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for Unlink {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(Unlink {
+            keys: extract_keys(args)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Exists {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for Exists {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(Exists {
+            keys: extract_keys(args)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Type {
+    pub key: Key,
+}
+
+impl TryFrom<&[Resp]> for Type {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(Type {
+            key: extract_key(&args[0])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub key: Key,
+    pub new_key: Key,
+}
+
+impl TryFrom<&[Resp]> for Rename {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(Rename {
+            key: extract_key(&args[0])?,
+            new_key: extract_key(&args[1])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameNx {
+    pub key: Key,
+    pub new_key: Key,
+}
+
+impl TryFrom<&[Resp]> for RenameNx {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(RenameNx {
+            key: extract_key(&args[0])?,
+            new_key: extract_key(&args[1])?,
+        })
+    }
+}
+
+/// `COPY source destination [DB destination-db] [REPLACE]`
+#[derive(Debug, Clone)]
+pub struct Copy {
+    pub source: Key,
+    pub destination: Key,
+    pub db: Option<i64>,
+    pub replace: bool,
+}
+
+impl TryFrom<&[Resp]> for Copy {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let mut copy = Copy {
+            source: extract_key(&args[0])?,
+            destination: extract_key(&args[1])?,
+            db: None,
+            replace: false,
+        };
+        let mut iter = args[2..].iter();
+        while let Some(arg) = iter.next() {
+            match extract_string(arg)?.to_uppercase().as_str() {
+                "REPLACE" => copy.replace = true,
+                "DB" => {
+                    let db = iter.next().ok_or(CommandError::SyntaxError)?;
+                    copy.db = Some(extract_integer(db)?);
+                }
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(copy)
+    }
+}
+
+/// `MOVE key db`
+#[derive(Debug, Clone)]
+pub struct Move {
+    pub key: Key,
+    pub db: i64,
+}
+
+impl TryFrom<&[Resp]> for Move {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(Move {
+            key: extract_key(&args[0])?,
+            db: extract_integer(&args[1])?,
+        })
+    }
+}
+
+/// `SWAPDB index1 index2`
+#[derive(Debug, Clone)]
+pub struct SwapDb {
+    pub first: i64,
+    pub second: i64,
+}
+
+impl TryFrom<&[Resp]> for SwapDb {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(SwapDb {
+            first: extract_integer(&args[0]).map_err(|_| CommandError::InvalidFirstDb)?,
+            second: extract_integer(&args[1]).map_err(|_| CommandError::InvalidSecondDb)?,
+        })
+    }
+}
+
+/// `FLUSHDB [ASYNC | SYNC]` and `FLUSHALL [ASYNC | SYNC]`. `ASYNC` frees
+/// the flushed values on a background task.
+#[derive(Debug, Clone)]
+pub struct Flush {
+    pub lazy: bool,
+}
+
+impl TryFrom<&[Resp]> for Flush {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        let lazy = match args {
+            [] => false,
+            [mode] => match extract_string(mode)?.to_uppercase().as_str() {
+                "ASYNC" => true,
+                "SYNC" => false,
+                _ => return Err(CommandError::SyntaxError),
+            },
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(Flush { lazy })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Touch {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for Touch {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(Touch {
+            keys: extract_keys(args)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keys {
+    pub pattern: String,
+}
+
+impl TryFrom<&[Resp]> for Keys {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(Keys {
+            pattern: extract_string(&args[0])?,
+        })
+    }
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub cursor: u64,
+    pub options: ScanOptions,
+}
+
+impl TryFrom<&[Resp]> for Scan {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(Scan {
+            cursor: extract_cursor(&args[0])?,
+            options: ScanOptions::parse(&args[1..], true)?,
+        })
+    }
+}
+
+/// Options shared by the `SCAN` family of commands.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub pattern: Option<String>,
+    pub count: usize,
+    pub type_filter: Option<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            pattern: None,
+            count: 10,
+            type_filter: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// `TYPE` is only accepted when `allow_type` is set, i.e. for `SCAN`.
+    pub(super) fn parse(args: &[Resp], allow_type: bool) -> Result<Self, CommandError> {
+        let mut options = ScanOptions::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let opt = extract_string(arg)?.to_uppercase();
+            let value = iter.next().ok_or(CommandError::SyntaxError)?;
+            match opt.as_str() {
+                "MATCH" => options.pattern = Some(extract_string(value)?),
+                "COUNT" => {
+                    let count = extract_integer(value)?;
+                    if count < 1 {
+                        return Err(CommandError::SyntaxError);
+                    }
+                    options.count = count as usize;
+                }
+                "TYPE" if allow_type => options.type_filter = Some(extract_string(value)?),
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(options)
+    }
+}
+
+pub(super) fn extract_cursor(arg: &Resp) -> Result<u64, CommandError> {
+    extract_string(arg)?
+        .parse()
+        .map_err(|_| CommandError::InvalidCursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_del() {
+        match command(&["DEL", "a", "b"]).unwrap() {
+            Command::Del(Del { keys }) => assert_eq!(keys.len(), 2),
+            _ => panic!("Expected Del"),
+        }
+        assert_eq!(
+            command(&["DEL"]).unwrap_err(),
+            CommandError::NotEnoughArguments(1, 0)
+        );
+    }
+
+    #[test]
+    fn test_parse_copy() {
+        match command(&["COPY", "a", "b", "db", "0", "replace"]).unwrap() {
+            Command::Copy(c) => {
+                assert_eq!(c.db, Some(0));
+                assert!(c.replace);
+            }
+            _ => panic!("Expected Copy"),
+        }
+        assert_eq!(
+            command(&["COPY", "a", "b", "DB"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["COPY", "a", "b", "DB", "x"]).unwrap_err(),
+            CommandError::NotAnInteger
+        );
+    }
+
+    #[test]
+    fn test_parse_databases() {
+        assert!(matches!(
+            command(&["MOVE", "k", "3"]).unwrap(),
+            Command::Move(Move { db: 3, .. })
+        ));
+        assert!(matches!(
+            command(&["SWAPDB", "0", "1"]).unwrap(),
+            Command::SwapDb(SwapDb {
+                first: 0,
+                second: 1
+            })
+        ));
+        assert_eq!(
+            command(&["SWAPDB", "0", "x"]).unwrap_err(),
+            CommandError::InvalidSecondDb
+        );
+        assert!(matches!(
+            command(&["FLUSHALL", "async"]).unwrap(),
+            Command::FlushAll(Flush { lazy: true })
+        ));
+        assert!(matches!(
+            command(&["FLUSHDB"]).unwrap(),
+            Command::FlushDb(Flush { lazy: false })
+        ));
+        assert_eq!(
+            command(&["FLUSHDB", "now"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert!(matches!(
+            command(&["SELECT", "2"]).unwrap(),
+            Command::Select(c) if c.db == 2
+        ));
+    }
+
+    #[test]
+    fn test_parse_scan() {
+        match command(&[
+            "SCAN", "42", "match", "user:*", "COUNT", "100", "type", "hash",
+        ])
+        .unwrap()
+        {
+            Command::Scan(Scan { cursor, options }) => {
+                assert_eq!(cursor, 42);
+                assert_eq!(options.pattern.as_deref(), Some("user:*"));
+                assert_eq!(options.count, 100);
+                assert_eq!(options.type_filter.as_deref(), Some("hash"));
+            }
+            _ => panic!("Expected Scan"),
+        }
+        assert_eq!(
+            command(&["SCAN", "-1"]).unwrap_err(),
+            CommandError::InvalidCursor
+        );
+        assert_eq!(
+            command(&["SCAN", "0", "COUNT", "0"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["SCAN", "0", "MATCH"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+
+    #[test]
+    fn test_parse_randomkey() {
+        assert!(matches!(
+            command(&["RANDOMKEY"]).unwrap(),
+            Command::RandomKey
+        ));
+        assert_eq!(
+            command(&["DBSIZE", "x"]).unwrap_err(),
+            CommandError::WrongNumberOfArguments(0, 1)
+        );
+    }
+}
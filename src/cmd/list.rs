@@ -0,0 +1,438 @@
+use super::{
+    expect_args, expect_min_args, extract_integer, extract_key, extract_keys, extract_numkeys,
+    extract_positive, extract_string, extract_timeout, CommandError,
+};
+use crate::resp::{Key, Resp};
+use std::time::Duration;
+
+/// Which end of a list an operation works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl TryFrom<&Resp> for End {
+    type Error = CommandError;
+    fn try_from(arg: &Resp) -> Result<Self, Self::Error> {
+        match extract_string(arg)?.to_uppercase().as_str() {
+            "LEFT" => Ok(End::Left),
+            "RIGHT" => Ok(End::Right),
+            _ => Err(CommandError::SyntaxError),
+        }
+    }
+}
+
+/// `LPUSH key element [element ...]` and `RPUSH`.
+#[derive(Debug, Clone)]
+pub struct Push {
+    pub key: Key,
+    pub elements: Vec<Resp>,
+}
+
+impl TryFrom<&[Resp]> for Push {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(Push {
+            key: extract_key(&args[0])?,
+            elements: args[1..].to_vec(),
+        })
+    }
+}
+
+/// `LPOP key [count]` and `RPOP`.
+#[derive(Debug, Clone)]
+pub struct Pop {
+    pub key: Key,
+    pub count: Option<usize>,
+}
+
+impl TryFrom<&[Resp]> for Pop {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        if args.len() > 2 {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(Pop {
+            key: extract_key(&args[0])?,
+            count: args.get(1).map(extract_positive).transpose()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LRange {
+    pub key: Key,
+    pub start: i64,
+    pub stop: i64,
+}
+
+impl TryFrom<&[Resp]> for LRange {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(LRange {
+            key: extract_key(&args[0])?,
+            start: extract_integer(&args[1])?,
+            stop: extract_integer(&args[2])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LIndex {
+    pub key: Key,
+    pub index: i64,
+}
+
+impl TryFrom<&[Resp]> for LIndex {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(LIndex {
+            key: extract_key(&args[0])?,
+            index: extract_integer(&args[1])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LSet {
+    pub key: Key,
+    pub index: i64,
+    pub element: Resp,
+}
+
+impl TryFrom<&[Resp]> for LSet {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(LSet {
+            key: extract_key(&args[0])?,
+            index: extract_integer(&args[1])?,
+            element: args[2].clone(),
+        })
+    }
+}
+
+/// `LINSERT key BEFORE|AFTER pivot element`
+#[derive(Debug, Clone)]
+pub struct LInsert {
+    pub key: Key,
+    pub before: bool,
+    pub pivot: Resp,
+    pub element: Resp,
+}
+
+impl TryFrom<&[Resp]> for LInsert {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 4)?;
+        let before = match extract_string(&args[1])?.to_uppercase().as_str() {
+            "BEFORE" => true,
+            "AFTER" => false,
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(LInsert {
+            key: extract_key(&args[0])?,
+            before,
+            pivot: args[2].clone(),
+            element: args[3].clone(),
+        })
+    }
+}
+
+/// `LREM key count element`
+#[derive(Debug, Clone)]
+pub struct LRem {
+    pub key: Key,
+    pub count: i64,
+    pub element: Resp,
+}
+
+impl TryFrom<&[Resp]> for LRem {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(LRem {
+            key: extract_key(&args[0])?,
+            count: extract_integer(&args[1])?,
+            element: args[2].clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LTrim {
+    pub key: Key,
+    pub start: i64,
+    pub stop: i64,
+}
+
+impl TryFrom<&[Resp]> for LTrim {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(LTrim {
+            key: extract_key(&args[0])?,
+            start: extract_integer(&args[1])?,
+            stop: extract_integer(&args[2])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LLen {
+    pub key: Key,
+}
+
+impl TryFrom<&[Resp]> for LLen {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(LLen {
+            key: extract_key(&args[0])?,
+        })
+    }
+}
+
+/// `LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]`
+#[derive(Debug, Clone)]
+pub struct LPos {
+    pub key: Key,
+    pub element: Resp,
+    pub rank: i64,
+    pub count: Option<usize>,
+    pub max_len: usize,
+}
+
+impl TryFrom<&[Resp]> for LPos {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let mut lpos = LPos {
+            key: extract_key(&args[0])?,
+            element: args[1].clone(),
+            rank: 1,
+            count: None,
+            max_len: 0,
+        };
+        let mut iter = args[2..].iter();
+        while let Some(arg) = iter.next() {
+            let opt = extract_string(arg)?.to_uppercase();
+            let value = iter.next().ok_or(CommandError::SyntaxError)?;
+            match opt.as_str() {
+                "RANK" => {
+                    lpos.rank = extract_integer(value)?;
+                    if lpos.rank == 0 || lpos.rank == i64::MIN {
+                        return Err(CommandError::InvalidRank);
+                    }
+                }
+                "COUNT" => lpos.count = Some(extract_positive(value)?),
+                "MAXLEN" => lpos.max_len = extract_positive(value)?,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(lpos)
+    }
+}
+
+/// `LMOVE source destination LEFT|RIGHT LEFT|RIGHT`
+#[derive(Debug, Clone)]
+pub struct LMove {
+    pub source: Key,
+    pub destination: Key,
+    pub from: End,
+    pub to: End,
+}
+
+impl TryFrom<&[Resp]> for LMove {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 4)?;
+        Ok(LMove {
+            source: extract_key(&args[0])?,
+            destination: extract_key(&args[1])?,
+            from: End::try_from(&args[2])?,
+            to: End::try_from(&args[3])?,
+        })
+    }
+}
+
+/// `BLPOP key [key ...] timeout` and `BRPOP`.
+#[derive(Debug, Clone)]
+pub struct BPop {
+    pub keys: Vec<Key>,
+    pub timeout: Option<Duration>,
+}
+
+impl TryFrom<&[Resp]> for BPop {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let (timeout, keys) = args.split_last().ok_or(CommandError::WrongFormat)?;
+        Ok(BPop {
+            keys: extract_keys(keys)?,
+            timeout: extract_timeout(timeout)?,
+        })
+    }
+}
+
+/// `BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout`
+#[derive(Debug, Clone)]
+pub struct BLMove {
+    pub lmove: LMove,
+    pub timeout: Option<Duration>,
+}
+
+impl TryFrom<&[Resp]> for BLMove {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 5)?;
+        Ok(BLMove {
+            lmove: LMove::try_from(&args[..4])?,
+            timeout: extract_timeout(&args[4])?,
+        })
+    }
+}
+
+/// `BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]`
+#[derive(Debug, Clone)]
+pub struct BLMPop {
+    pub timeout: Option<Duration>,
+    pub keys: Vec<Key>,
+    pub end: End,
+    pub count: usize,
+}
+
+impl TryFrom<&[Resp]> for BLMPop {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 4)?;
+        let timeout = extract_timeout(&args[0])?;
+        let (keys, rest) = extract_numkeys(&args[1..])?;
+        let (end, rest) = rest.split_first().ok_or(CommandError::SyntaxError)?;
+        let count = match rest {
+            [] => 1,
+            [opt, count] if extract_string(opt)?.eq_ignore_ascii_case("COUNT") => {
+                match extract_positive(count)? {
+                    0 => return Err(CommandError::InvalidCount),
+                    n => n,
+                }
+            }
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(BLMPop {
+            timeout,
+            keys,
+            end: End::try_from(end)?,
+            count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_pop() {
+        match command(&["LPOP", "l", "3"]).unwrap() {
+            Command::LPop(Pop { count, .. }) => assert_eq!(count, Some(3)),
+            _ => panic!("Expected LPop"),
+        }
+        assert_eq!(
+            command(&["RPOP", "l", "-1"]).unwrap_err(),
+            CommandError::NotPositive
+        );
+    }
+
+    #[test]
+    fn test_parse_linsert() {
+        match command(&["LINSERT", "l", "before", "a", "b"]).unwrap() {
+            Command::LInsert(c) => assert!(c.before),
+            _ => panic!("Expected LInsert"),
+        }
+        assert_eq!(
+            command(&["LINSERT", "l", "x", "a", "b"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+
+    #[test]
+    fn test_parse_lpos() {
+        match command(&["LPOS", "l", "a", "RANK", "-2", "COUNT", "0", "MAXLEN", "10"]).unwrap() {
+            Command::LPos(c) => {
+                assert_eq!(c.rank, -2);
+                assert_eq!(c.count, Some(0));
+                assert_eq!(c.max_len, 10);
+            }
+            _ => panic!("Expected LPos"),
+        }
+        assert_eq!(
+            command(&["LPOS", "l", "a", "RANK", "0"]).unwrap_err(),
+            CommandError::InvalidRank
+        );
+    }
+
+    #[test]
+    fn test_parse_blocking() {
+        match command(&["BLPOP", "a", "b", "0.25"]).unwrap() {
+            Command::BLPop(c) => {
+                assert_eq!(c.keys.len(), 2);
+                assert_eq!(c.timeout, Some(Duration::from_millis(250)));
+            }
+            _ => panic!("Expected BLPop"),
+        }
+        match command(&["BRPOP", "a", "0"]).unwrap() {
+            Command::BRPop(c) => assert_eq!(c.timeout, None),
+            _ => panic!("Expected BRPop"),
+        }
+        assert_eq!(
+            command(&["BLPOP", "a", "-1"]).unwrap_err(),
+            CommandError::NegativeTimeout
+        );
+        assert_eq!(
+            command(&["BLPOP", "a", "x"]).unwrap_err(),
+            CommandError::InvalidTimeout
+        );
+        match command(&["BLMPOP", "1", "2", "a", "b", "RIGHT", "COUNT", "3"]).unwrap() {
+            Command::BLMPop(c) => {
+                assert_eq!(c.keys.len(), 2);
+                assert_eq!(c.end, End::Right);
+                assert_eq!(c.count, 3);
+            }
+            _ => panic!("Expected BLMPop"),
+        }
+        assert_eq!(
+            command(&["BLMPOP", "1", "0", "a", "LEFT"]).unwrap_err(),
+            CommandError::InvalidNumKeys
+        );
+        assert_eq!(
+            command(&["BLMPOP", "1", "3", "a", "LEFT"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        match command(&["BLMOVE", "a", "b", "LEFT", "LEFT", "1.5"]).unwrap() {
+            Command::BLMove(c) => assert_eq!(c.timeout, Some(Duration::from_millis(1500))),
+            _ => panic!("Expected BLMove"),
+        }
+    }
+
+    #[test]
+    fn test_parse_lmove() {
+        match command(&["LMOVE", "a", "b", "left", "RIGHT"]).unwrap() {
+            Command::LMove(c) => {
+                assert_eq!(c.from, End::Left);
+                assert_eq!(c.to, End::Right);
+            }
+            _ => panic!("Expected LMove"),
+        }
+        assert_eq!(
+            command(&["LMOVE", "a", "b", "up", "down"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+}
@@ -1,7 +1,35 @@
+mod config;
+mod connection;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod keyspace;
+mod list;
+mod pubsub;
+mod scripting;
+mod set;
+mod stream;
+mod transaction;
+mod zset;
+
 use crate::resp::{Key, Null, Resp, SimpleString};
 use anyhow::Result;
+pub use config::*;
+pub use connection::*;
+pub use geo::*;
+pub use hash::*;
+pub use hyperloglog::*;
+pub use keyspace::*;
+pub use list::*;
+pub use pubsub::*;
+pub use scripting::*;
+pub use set::*;
+use std::time::Duration;
+pub use stream::*;
 use thiserror::Error;
 use tracing::info;
+pub use transaction::*;
+pub use zset::*;
 
 #[derive(Debug, Error, PartialEq)]
 pub enum CommandError {
@@ -11,8 +39,116 @@ pub enum CommandError {
     WrongNumberOfArguments(usize, usize),
     #[error("Wrong format")]
     WrongFormat,
+    #[error("Wrong number of arguments, expected at least {0}, got {1}")]
+    NotEnoughArguments(usize, usize),
     #[error("Unsupported Key")]
     UnsupportedKey,
+    #[error("ERR syntax error")]
+    SyntaxError,
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR value is not a valid float")]
+    NotAFloat,
+    #[error("ERR invalid cursor")]
+    InvalidCursor,
+    #[error("ERR value is out of range, must be positive")]
+    NotPositive,
+    #[error("ERR value is out of range")]
+    OutOfRange,
+    #[error("ERR timeout is not a float or out of range")]
+    InvalidTimeout,
+    #[error("ERR timeout is negative")]
+    NegativeTimeout,
+    #[error("ERR numkeys should be greater than 0")]
+    InvalidNumKeys,
+    #[error("ERR count should be greater than 0")]
+    InvalidCount,
+    #[error("ERR LIMIT can't be negative")]
+    NegativeLimit,
+    #[error("ERR XX and NX options at the same time are not compatible")]
+    ZAddNxWithXx,
+    #[error("ERR GT, LT, and/or NX options at the same time are not compatible")]
+    ZAddNxWithComparison,
+    #[error("ERR INCR option supports a single increment-element pair")]
+    ZAddIncrPairs,
+    #[error("ERR min or max is not a float")]
+    InvalidScoreRange,
+    #[error("ERR min or max not valid string range item")]
+    InvalidLexRange,
+    #[error(
+        "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+    )]
+    LimitWithoutBy,
+    #[error("ERR syntax error, WITHSCORES not supported in combination with BYLEX")]
+    WithScoresByLex,
+    #[error("ERR weight value is not a float")]
+    InvalidWeight,
+    #[error("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the last match")]
+    InvalidRank,
+    #[error("ERR Invalid stream ID specified as stream command argument")]
+    InvalidStreamId,
+    #[error("ERR The ID specified in XADD must be greater than 0-0")]
+    XAddZeroId,
+    #[error("ERR syntax error, LIMIT cannot be used without the special ~ option")]
+    LimitWithoutApprox,
+    #[error("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.")]
+    UnbalancedStreams,
+    #[error("ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.")]
+    NewIdInXReadGroup,
+    #[error("ERR unknown subcommand '{0}'")]
+    UnknownSubcommand(String),
+    #[error("ERR invalid longitude,latitude pair {0:.6},{1:.6}")]
+    InvalidLonLat(f64, f64),
+    #[error("ERR unsupported unit provided. please use M, KM, FT, MI")]
+    UnsupportedUnit,
+    #[error("ERR radius cannot be negative")]
+    NegativeRadius,
+    #[error("ERR height or width cannot be negative")]
+    NegativeBox,
+    #[error("ERR COUNT must be > 0")]
+    GeoCountNotPositive,
+    #[error("ERR the ANY argument requires COUNT argument")]
+    GeoAnyWithoutCount,
+    #[error("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {0}")]
+    GeoSearchFrom(&'static str),
+    #[error("ERR exactly one of BYRADIUS and BYBOX can be specified for {0}")]
+    GeoSearchShape(&'static str),
+    #[error("ERR Can't execute '{0}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")]
+    NotAllowedWhenSubscribed(&'static str),
+    #[error("CROSSSLOT Keys in request don't hash to the same slot")]
+    CrossSlot,
+    #[error("ERR MULTI calls can not be nested")]
+    NestedMulti,
+    #[error("ERR EXEC without MULTI")]
+    ExecWithoutMulti,
+    #[error("ERR DISCARD without MULTI")]
+    DiscardWithoutMulti,
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+    #[error("ERR WATCH inside MULTI is not allowed")]
+    WatchInsideMulti,
+    #[error("ERR invalid first DB index")]
+    InvalidFirstDb,
+    #[error("ERR invalid second DB index")]
+    InvalidSecondDb,
+    #[error("ERR Client names cannot contain spaces, newlines or special characters.")]
+    InvalidClientName,
+    #[error("ERR Invalid client ID")]
+    InvalidClientId,
+    #[error("ERR Unknown client type '{0}'")]
+    UnknownClientType(String),
+    #[error("ERR timeout is not an integer or out of range")]
+    InvalidPauseTimeout,
+    #[error("ERR PREFIX option requires BCAST mode to be enabled")]
+    PrefixWithoutBcast,
+    #[error("ERR You can't use both OPTIN and OPTOUT")]
+    OptInWithOptOut,
+    #[error("ERR OPTIN and OPTOUT are not compatible with BCAST")]
+    OptInWithBcast,
+    #[error("ERR Number of keys can't be negative")]
+    NegativeNumKeys,
+    #[error("ERR Number of keys can't be greater than number of args")]
+    TooManyNumKeys,
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +157,140 @@ pub enum Command {
     Set(Set),
     Echo(Echo),
     Cmd,
+    Hello(Hello),
+    Ping(Ping),
+    Quit,
+    Reset,
+    Client(Client),
+    Del(Del),
+    Unlink(Unlink),
+    Exists(Exists),
+    Type(Type),
+    Rename(Rename),
+    RenameNx(RenameNx),
+    Copy(Copy),
+    Touch(Touch),
+    RandomKey,
+    DbSize,
+    Select(Select),
+    Move(Move),
+    SwapDb(SwapDb),
+    FlushDb(Flush),
+    FlushAll(Flush),
+    Multi,
+    Exec,
+    Discard,
+    Watch(Watch),
+    Unwatch,
+    Keys(Keys),
+    Scan(Scan),
+    HSet(HSet),
+    HGet(HGet),
+    HMGet(HMGet),
+    HGetAll(HashKey),
+    HDel(HDel),
+    HIncrBy(HIncrBy),
+    HIncrByFloat(HIncrByFloat),
+    HExists(HExists),
+    HLen(HashKey),
+    HKeys(HashKey),
+    HVals(HashKey),
+    HRandField(HRandField),
+    HScan(HScan),
+    LPush(Push),
+    RPush(Push),
+    LPop(Pop),
+    RPop(Pop),
+    LRange(LRange),
+    LIndex(LIndex),
+    LSet(LSet),
+    LInsert(LInsert),
+    LRem(LRem),
+    LTrim(LTrim),
+    LLen(LLen),
+    LPos(LPos),
+    LMove(LMove),
+    BLPop(BPop),
+    BRPop(BPop),
+    BLMove(BLMove),
+    BLMPop(BLMPop),
+    SAdd(Members),
+    SRem(Members),
+    SMembers(SetKey),
+    SIsMember(SIsMember),
+    SMIsMember(Members),
+    SCard(SetKey),
+    SPop(SPop),
+    SRandMember(SRandMember),
+    SMove(SMove),
+    SScan(SScan),
+    SInter(SetOp),
+    SUnion(SetOp),
+    SDiff(SetOp),
+    SInterStore(SetOpStore),
+    SUnionStore(SetOpStore),
+    SDiffStore(SetOpStore),
+    SInterCard(SInterCard),
+    ZAdd(ZAdd),
+    ZRange(ZRange),
+    ZRank(ZRank),
+    ZRevRank(ZRank),
+    ZScore(ZScore),
+    ZIncrBy(ZIncrBy),
+    ZRem(ZRem),
+    ZCount(ZCount),
+    ZCard(ZCard),
+    ZPopMin(ZPop),
+    ZPopMax(ZPop),
+    BZPopMin(BPop),
+    BZPopMax(BPop),
+    BZMPop(BZMPop),
+    XAdd(XAdd),
+    XRange(XRange),
+    XRevRange(XRange),
+    XLen(XLen),
+    XTrim(XTrim),
+    XDel(XDel),
+    XRead(XRead),
+    XReadGroup(XReadGroup),
+    XGroup(XGroup),
+    XAck(XAck),
+    XPending(XPending),
+    XClaim(XClaim),
+    XAutoClaim(XAutoClaim),
+    XInfo(XInfo),
+    ZScan(ZScan),
+    ZUnionStore(ZSetOp),
+    ZInterStore(ZSetOp),
+    ZDiffStore(ZSetOp),
+    ZUnion(ZSetOp),
+    ZInter(ZSetOp),
+    ZDiff(ZSetOp),
+    PfAdd(PfAdd),
+    PfCount(PfCount),
+    PfMerge(PfMerge),
+    GeoAdd(GeoAdd),
+    GeoDist(GeoDist),
+    GeoPos(GeoMembers),
+    GeoHash(GeoMembers),
+    GeoSearch(GeoSearch),
+    GeoSearchStore(GeoSearch),
+    Subscribe(Channels),
+    Unsubscribe(Channels),
+    PSubscribe(Channels),
+    PUnsubscribe(Channels),
+    Publish(Publish),
+    PubSub(PubSub),
+    SSubscribe(Channels),
+    SUnsubscribe(Channels),
+    SPublish(Publish),
+    Config(Config),
+    Eval(Eval),
+    EvalSha(Eval),
+    Script(Script),
+    FCall(Eval),
+    FCallRo(Eval),
+    Function(Function),
 }
 
 pub trait CommandExecutor {
@@ -28,12 +298,339 @@ pub trait CommandExecutor {
 }
 
 impl Command {
+    /// Whether the command runs a script, which may take long enough that
+    /// it shouldn't run on the connection's task.
+    pub fn runs_script(&self) -> bool {
+        matches!(
+            self,
+            Command::Eval(_) | Command::EvalSha(_) | Command::FCall(_) | Command::FCallRo(_)
+        )
+    }
+
+    /// Whether the command may wait for data to arrive. Blocking commands
+    /// are run with `Storage::execute_blocking`; run through `execute` they
+    /// don't wait and time out straight away.
+    pub fn is_blocking(&self) -> bool {
+        matches!(
+            self,
+            Command::BLPop(_)
+                | Command::BRPop(_)
+                | Command::BLMove(_)
+                | Command::BLMPop(_)
+                | Command::BZPopMin(_)
+                | Command::BZPopMax(_)
+                | Command::BZMPop(_)
+        ) || matches!(self, Command::XRead(c) | Command::XReadGroup(XReadGroup { read: c, .. }) if c.block)
+    }
+
+    /// Whether the command may run on a RESP2 connection in subscribed mode.
+    pub fn is_allowed_when_subscribed(&self) -> bool {
+        matches!(
+            self,
+            Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+                | Command::SSubscribe(_)
+                | Command::SUnsubscribe(_)
+                | Command::Ping(_)
+                | Command::Quit
+                | Command::Reset
+        )
+    }
+
+    /// Whether the command may change the keyspace.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::Del(_)
+                | Command::Unlink(_)
+                | Command::Rename(_)
+                | Command::RenameNx(_)
+                | Command::Copy(_)
+                | Command::Move(_)
+                | Command::SwapDb(_)
+                | Command::FlushDb(_)
+                | Command::FlushAll(_)
+                | Command::HSet(_)
+                | Command::HDel(_)
+                | Command::HIncrBy(_)
+                | Command::HIncrByFloat(_)
+                | Command::LPush(_)
+                | Command::RPush(_)
+                | Command::LPop(_)
+                | Command::RPop(_)
+                | Command::LSet(_)
+                | Command::LInsert(_)
+                | Command::LRem(_)
+                | Command::LTrim(_)
+                | Command::LMove(_)
+                | Command::BLPop(_)
+                | Command::BRPop(_)
+                | Command::BLMove(_)
+                | Command::BLMPop(_)
+                | Command::SAdd(_)
+                | Command::SRem(_)
+                | Command::SPop(_)
+                | Command::SMove(_)
+                | Command::SInterStore(_)
+                | Command::SUnionStore(_)
+                | Command::SDiffStore(_)
+                | Command::ZAdd(_)
+                | Command::ZIncrBy(_)
+                | Command::ZRem(_)
+                | Command::ZPopMin(_)
+                | Command::ZPopMax(_)
+                | Command::BZPopMin(_)
+                | Command::BZPopMax(_)
+                | Command::BZMPop(_)
+                | Command::ZUnionStore(_)
+                | Command::ZInterStore(_)
+                | Command::ZDiffStore(_)
+                | Command::XAdd(_)
+                | Command::XTrim(_)
+                | Command::XDel(_)
+                | Command::XReadGroup(_)
+                | Command::XGroup(_)
+                | Command::XAck(_)
+                | Command::XClaim(_)
+                | Command::XAutoClaim(_)
+                | Command::PfAdd(_)
+                | Command::PfMerge(_)
+                | Command::GeoAdd(_)
+                | Command::GeoSearchStore(_)
+        )
+    }
+
+    /// The keys a command that only reads looks at, which client-side
+    /// caching remembers. Empty for commands that write.
+    pub fn read_keys(&self) -> Vec<Key> {
+        match self {
+            Command::Get(Get { key })
+            | Command::Type(Type { key })
+            | Command::HGet(HGet { key, .. })
+            | Command::HMGet(HMGet { key, .. })
+            | Command::HGetAll(HashKey { key })
+            | Command::HExists(HExists { key, .. })
+            | Command::HLen(HashKey { key })
+            | Command::HKeys(HashKey { key })
+            | Command::HVals(HashKey { key })
+            | Command::HRandField(HRandField { key, .. })
+            | Command::HScan(HScan { key, .. })
+            | Command::LRange(LRange { key, .. })
+            | Command::LIndex(LIndex { key, .. })
+            | Command::LLen(LLen { key })
+            | Command::LPos(LPos { key, .. })
+            | Command::SMembers(SetKey { key })
+            | Command::SIsMember(SIsMember { key, .. })
+            | Command::SMIsMember(Members { key, .. })
+            | Command::SCard(SetKey { key })
+            | Command::SRandMember(SRandMember { key, .. })
+            | Command::SScan(SScan { key, .. })
+            | Command::ZRange(ZRange { key, .. })
+            | Command::ZRank(ZRank { key, .. })
+            | Command::ZRevRank(ZRank { key, .. })
+            | Command::ZScore(ZScore { key, .. })
+            | Command::ZCount(ZCount { key, .. })
+            | Command::ZCard(ZCard { key })
+            | Command::ZScan(ZScan { key, .. })
+            | Command::XRange(XRange { key, .. })
+            | Command::XRevRange(XRange { key, .. })
+            | Command::XLen(XLen { key })
+            | Command::XPending(XPending { key, .. })
+            | Command::XInfo(
+                XInfo::Stream { key } | XInfo::Groups { key } | XInfo::Consumers { key, .. },
+            )
+            | Command::GeoDist(GeoDist { key, .. })
+            | Command::GeoPos(GeoMembers { key, .. })
+            | Command::GeoHash(GeoMembers { key, .. })
+            | Command::GeoSearch(GeoSearch { key, .. }) => vec![key.clone()],
+            Command::Exists(Exists { keys })
+            | Command::Touch(Touch { keys })
+            | Command::SInter(SetOp { keys })
+            | Command::SUnion(SetOp { keys })
+            | Command::SDiff(SetOp { keys })
+            | Command::SInterCard(SInterCard { keys, .. })
+            | Command::ZUnion(ZSetOp { keys, .. })
+            | Command::ZInter(ZSetOp { keys, .. })
+            | Command::ZDiff(ZSetOp { keys, .. })
+            | Command::PfCount(PfCount { keys }) => keys.clone(),
+            Command::XRead(c) if !c.block => c.keys.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether `CLIENT PAUSE WRITE` holds the command: writes, scripts and
+    /// functions that may write, and commands a replica would have to see.
+    pub fn is_held_by_write_pause(&self) -> bool {
+        self.is_write()
+            || matches!(
+                self,
+                Command::Eval(_)
+                    | Command::EvalSha(_)
+                    | Command::FCall(_)
+                    | Command::Publish(_)
+                    | Command::SPublish(_)
+                    | Command::PfCount(_)
+            )
+    }
+
+    /// The lowercase command name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get(_) => "get",
+            Command::Set(_) => "set",
+            Command::Echo(_) => "echo",
+            Command::Cmd => "command",
+            Command::Hello(_) => "hello",
+            Command::Ping(_) => "ping",
+            Command::Quit => "quit",
+            Command::Reset => "reset",
+            Command::Client(_) => "client",
+            Command::Del(_) => "del",
+            Command::Unlink(_) => "unlink",
+            Command::Exists(_) => "exists",
+            Command::Type(_) => "type",
+            Command::Rename(_) => "rename",
+            Command::RenameNx(_) => "renamenx",
+            Command::Copy(_) => "copy",
+            Command::Touch(_) => "touch",
+            Command::RandomKey => "randomkey",
+            Command::DbSize => "dbsize",
+            Command::Select(_) => "select",
+            Command::Move(_) => "move",
+            Command::SwapDb(_) => "swapdb",
+            Command::FlushDb(_) => "flushdb",
+            Command::FlushAll(_) => "flushall",
+            Command::Multi => "multi",
+            Command::Exec => "exec",
+            Command::Discard => "discard",
+            Command::Watch(_) => "watch",
+            Command::Unwatch => "unwatch",
+            Command::Keys(_) => "keys",
+            Command::Scan(_) => "scan",
+            Command::HSet(_) => "hset",
+            Command::HGet(_) => "hget",
+            Command::HMGet(_) => "hmget",
+            Command::HGetAll(_) => "hgetall",
+            Command::HDel(_) => "hdel",
+            Command::HIncrBy(_) => "hincrby",
+            Command::HIncrByFloat(_) => "hincrbyfloat",
+            Command::HExists(_) => "hexists",
+            Command::HLen(_) => "hlen",
+            Command::HKeys(_) => "hkeys",
+            Command::HVals(_) => "hvals",
+            Command::HRandField(_) => "hrandfield",
+            Command::HScan(_) => "hscan",
+            Command::LPush(_) => "lpush",
+            Command::RPush(_) => "rpush",
+            Command::LPop(_) => "lpop",
+            Command::RPop(_) => "rpop",
+            Command::LRange(_) => "lrange",
+            Command::LIndex(_) => "lindex",
+            Command::LSet(_) => "lset",
+            Command::LInsert(_) => "linsert",
+            Command::LRem(_) => "lrem",
+            Command::LTrim(_) => "ltrim",
+            Command::LLen(_) => "llen",
+            Command::LPos(_) => "lpos",
+            Command::LMove(_) => "lmove",
+            Command::BLPop(_) => "blpop",
+            Command::BRPop(_) => "brpop",
+            Command::BLMove(_) => "blmove",
+            Command::BLMPop(_) => "blmpop",
+            Command::SAdd(_) => "sadd",
+            Command::SRem(_) => "srem",
+            Command::SMembers(_) => "smembers",
+            Command::SIsMember(_) => "sismember",
+            Command::SMIsMember(_) => "smismember",
+            Command::SCard(_) => "scard",
+            Command::SPop(_) => "spop",
+            Command::SRandMember(_) => "srandmember",
+            Command::SMove(_) => "smove",
+            Command::SScan(_) => "sscan",
+            Command::SInter(_) => "sinter",
+            Command::SUnion(_) => "sunion",
+            Command::SDiff(_) => "sdiff",
+            Command::SInterStore(_) => "sinterstore",
+            Command::SUnionStore(_) => "sunionstore",
+            Command::SDiffStore(_) => "sdiffstore",
+            Command::SInterCard(_) => "sintercard",
+            Command::ZAdd(_) => "zadd",
+            Command::ZRange(_) => "zrange",
+            Command::ZRank(_) => "zrank",
+            Command::ZRevRank(_) => "zrevrank",
+            Command::ZScore(_) => "zscore",
+            Command::ZIncrBy(_) => "zincrby",
+            Command::ZRem(_) => "zrem",
+            Command::ZCount(_) => "zcount",
+            Command::ZCard(_) => "zcard",
+            Command::ZPopMin(_) => "zpopmin",
+            Command::ZPopMax(_) => "zpopmax",
+            Command::BZPopMin(_) => "bzpopmin",
+            Command::BZPopMax(_) => "bzpopmax",
+            Command::BZMPop(_) => "bzmpop",
+            Command::XAdd(_) => "xadd",
+            Command::XRange(_) => "xrange",
+            Command::XRevRange(_) => "xrevrange",
+            Command::XLen(_) => "xlen",
+            Command::XTrim(_) => "xtrim",
+            Command::XDel(_) => "xdel",
+            Command::XRead(_) => "xread",
+            Command::XReadGroup(_) => "xreadgroup",
+            Command::XGroup(_) => "xgroup",
+            Command::XAck(_) => "xack",
+            Command::XPending(_) => "xpending",
+            Command::XClaim(_) => "xclaim",
+            Command::XAutoClaim(_) => "xautoclaim",
+            Command::XInfo(_) => "xinfo",
+            Command::ZScan(_) => "zscan",
+            Command::ZUnionStore(_) => "zunionstore",
+            Command::ZInterStore(_) => "zinterstore",
+            Command::ZDiffStore(_) => "zdiffstore",
+            Command::ZUnion(_) => "zunion",
+            Command::ZInter(_) => "zinter",
+            Command::ZDiff(_) => "zdiff",
+            Command::PfAdd(_) => "pfadd",
+            Command::PfCount(_) => "pfcount",
+            Command::PfMerge(_) => "pfmerge",
+            Command::GeoAdd(_) => "geoadd",
+            Command::GeoDist(_) => "geodist",
+            Command::GeoPos(_) => "geopos",
+            Command::GeoHash(_) => "geohash",
+            Command::GeoSearch(_) => "geosearch",
+            Command::GeoSearchStore(_) => "geosearchstore",
+            Command::Subscribe(_) => "subscribe",
+            Command::Unsubscribe(_) => "unsubscribe",
+            Command::PSubscribe(_) => "psubscribe",
+            Command::PUnsubscribe(_) => "punsubscribe",
+            Command::Publish(_) => "publish",
+            Command::PubSub(_) => "pubsub",
+            Command::SSubscribe(_) => "ssubscribe",
+            Command::SUnsubscribe(_) => "sunsubscribe",
+            Command::SPublish(_) => "spublish",
+            Command::Config(_) => "config",
+            Command::Eval(_) => "eval",
+            Command::EvalSha(_) => "evalsha",
+            Command::Script(_) => "script",
+            Command::FCall(_) => "fcall",
+            Command::FCallRo(_) => "fcall_ro",
+            Command::Function(_) => "function",
+        }
+    }
+
     pub fn execute(&self, executor: &dyn CommandExecutor) -> Result<Resp> {
         match self {
             Command::Get(c) => c.execute(executor),
             Command::Set(c) => c.execute(executor),
             Command::Echo(c) => c.execute(executor),
+            Command::Ping(c) => c.execute(),
             Command::Cmd => Ok(Resp::SimpleString(SimpleString::new("OK"))),
+            cmd => {
+                let res = executor.execute(cmd.clone())?;
+                Ok(res.unwrap_or(Resp::Null(Null)))
+            }
         }
     }
 }
@@ -46,7 +643,10 @@ impl TryFrom<Resp> for Command {
                 let mut iter = v.iter();
                 let cmd = iter.next().ok_or(CommandError::WrongFormat)?;
                 match cmd {
-                    Resp::BulkString(s) => match s.value.to_uppercase().as_str() {
+                    Resp::BulkString(s) => match String::from_utf8_lossy(&s.value)
+                        .to_uppercase()
+                        .as_str()
+                    {
                         "GET" => {
                             if iter.len() != 1 {
                                 return Err(CommandError::WrongNumberOfArguments(1, iter.len()));
@@ -81,6 +681,205 @@ impl TryFrom<Resp> for Command {
                             let msg = iter.next().ok_or(CommandError::WrongFormat)?;
                             Ok(Command::Echo(Echo { msg: msg.clone() }))
                         }
+                        "HELLO" => Ok(Command::Hello(iter.as_slice().try_into()?)),
+                        "PING" => Ok(Command::Ping(iter.as_slice().try_into()?)),
+                        "QUIT" => Ok(Command::Quit),
+                        "RESET" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::Reset)
+                        }
+                        "CLIENT" => Ok(Command::Client(iter.as_slice().try_into()?)),
+                        "DEL" => Ok(Command::Del(iter.as_slice().try_into()?)),
+                        "UNLINK" => Ok(Command::Unlink(iter.as_slice().try_into()?)),
+                        "EXISTS" => Ok(Command::Exists(iter.as_slice().try_into()?)),
+                        "TYPE" => Ok(Command::Type(iter.as_slice().try_into()?)),
+                        "RENAME" => Ok(Command::Rename(iter.as_slice().try_into()?)),
+                        "RENAMENX" => Ok(Command::RenameNx(iter.as_slice().try_into()?)),
+                        "COPY" => Ok(Command::Copy(iter.as_slice().try_into()?)),
+                        "TOUCH" => Ok(Command::Touch(iter.as_slice().try_into()?)),
+                        "KEYS" => Ok(Command::Keys(iter.as_slice().try_into()?)),
+                        "SCAN" => Ok(Command::Scan(iter.as_slice().try_into()?)),
+                        "HSET" => Ok(Command::HSet(iter.as_slice().try_into()?)),
+                        "HGET" => Ok(Command::HGet(iter.as_slice().try_into()?)),
+                        "HMGET" => Ok(Command::HMGet(iter.as_slice().try_into()?)),
+                        "HGETALL" => Ok(Command::HGetAll(iter.as_slice().try_into()?)),
+                        "HDEL" => Ok(Command::HDel(iter.as_slice().try_into()?)),
+                        "HINCRBY" => Ok(Command::HIncrBy(iter.as_slice().try_into()?)),
+                        "HINCRBYFLOAT" => Ok(Command::HIncrByFloat(iter.as_slice().try_into()?)),
+                        "HEXISTS" => Ok(Command::HExists(iter.as_slice().try_into()?)),
+                        "HLEN" => Ok(Command::HLen(iter.as_slice().try_into()?)),
+                        "HKEYS" => Ok(Command::HKeys(iter.as_slice().try_into()?)),
+                        "HVALS" => Ok(Command::HVals(iter.as_slice().try_into()?)),
+                        "HRANDFIELD" => Ok(Command::HRandField(iter.as_slice().try_into()?)),
+                        "HSCAN" => Ok(Command::HScan(iter.as_slice().try_into()?)),
+                        "LPUSH" => Ok(Command::LPush(iter.as_slice().try_into()?)),
+                        "RPUSH" => Ok(Command::RPush(iter.as_slice().try_into()?)),
+                        "LPOP" => Ok(Command::LPop(iter.as_slice().try_into()?)),
+                        "RPOP" => Ok(Command::RPop(iter.as_slice().try_into()?)),
+                        "LRANGE" => Ok(Command::LRange(iter.as_slice().try_into()?)),
+                        "LINDEX" => Ok(Command::LIndex(iter.as_slice().try_into()?)),
+                        "LSET" => Ok(Command::LSet(iter.as_slice().try_into()?)),
+                        "LINSERT" => Ok(Command::LInsert(iter.as_slice().try_into()?)),
+                        "LREM" => Ok(Command::LRem(iter.as_slice().try_into()?)),
+                        "LTRIM" => Ok(Command::LTrim(iter.as_slice().try_into()?)),
+                        "LLEN" => Ok(Command::LLen(iter.as_slice().try_into()?)),
+                        "LPOS" => Ok(Command::LPos(iter.as_slice().try_into()?)),
+                        "LMOVE" => Ok(Command::LMove(iter.as_slice().try_into()?)),
+                        "BLPOP" => Ok(Command::BLPop(iter.as_slice().try_into()?)),
+                        "BRPOP" => Ok(Command::BRPop(iter.as_slice().try_into()?)),
+                        "BLMOVE" => Ok(Command::BLMove(iter.as_slice().try_into()?)),
+                        "BLMPOP" => Ok(Command::BLMPop(iter.as_slice().try_into()?)),
+                        "SADD" => Ok(Command::SAdd(iter.as_slice().try_into()?)),
+                        "SREM" => Ok(Command::SRem(iter.as_slice().try_into()?)),
+                        "SMEMBERS" => Ok(Command::SMembers(iter.as_slice().try_into()?)),
+                        "SISMEMBER" => Ok(Command::SIsMember(iter.as_slice().try_into()?)),
+                        "SMISMEMBER" => Ok(Command::SMIsMember(iter.as_slice().try_into()?)),
+                        "SCARD" => Ok(Command::SCard(iter.as_slice().try_into()?)),
+                        "SPOP" => Ok(Command::SPop(iter.as_slice().try_into()?)),
+                        "SRANDMEMBER" => Ok(Command::SRandMember(iter.as_slice().try_into()?)),
+                        "SMOVE" => Ok(Command::SMove(iter.as_slice().try_into()?)),
+                        "SSCAN" => Ok(Command::SScan(iter.as_slice().try_into()?)),
+                        "SINTER" => Ok(Command::SInter(iter.as_slice().try_into()?)),
+                        "SUNION" => Ok(Command::SUnion(iter.as_slice().try_into()?)),
+                        "SDIFF" => Ok(Command::SDiff(iter.as_slice().try_into()?)),
+                        "SINTERSTORE" => Ok(Command::SInterStore(iter.as_slice().try_into()?)),
+                        "SUNIONSTORE" => Ok(Command::SUnionStore(iter.as_slice().try_into()?)),
+                        "SDIFFSTORE" => Ok(Command::SDiffStore(iter.as_slice().try_into()?)),
+                        "SINTERCARD" => Ok(Command::SInterCard(iter.as_slice().try_into()?)),
+                        "ZADD" => Ok(Command::ZAdd(iter.as_slice().try_into()?)),
+                        "ZRANGE" => Ok(Command::ZRange(iter.as_slice().try_into()?)),
+                        "ZRANK" => Ok(Command::ZRank(iter.as_slice().try_into()?)),
+                        "ZREVRANK" => Ok(Command::ZRevRank(iter.as_slice().try_into()?)),
+                        "ZSCORE" => Ok(Command::ZScore(iter.as_slice().try_into()?)),
+                        "ZINCRBY" => Ok(Command::ZIncrBy(iter.as_slice().try_into()?)),
+                        "ZREM" => Ok(Command::ZRem(iter.as_slice().try_into()?)),
+                        "ZCOUNT" => Ok(Command::ZCount(iter.as_slice().try_into()?)),
+                        "ZCARD" => Ok(Command::ZCard(iter.as_slice().try_into()?)),
+                        "ZPOPMIN" => Ok(Command::ZPopMin(iter.as_slice().try_into()?)),
+                        "ZPOPMAX" => Ok(Command::ZPopMax(iter.as_slice().try_into()?)),
+                        "BZPOPMIN" => Ok(Command::BZPopMin(iter.as_slice().try_into()?)),
+                        "BZPOPMAX" => Ok(Command::BZPopMax(iter.as_slice().try_into()?)),
+                        "BZMPOP" => Ok(Command::BZMPop(iter.as_slice().try_into()?)),
+                        "ZSCAN" => Ok(Command::ZScan(iter.as_slice().try_into()?)),
+                        "ZUNIONSTORE" => Ok(Command::ZUnionStore(ZSetOp::parse(
+                            iter.as_slice(),
+                            true,
+                            true,
+                        )?)),
+                        "ZINTERSTORE" => Ok(Command::ZInterStore(ZSetOp::parse(
+                            iter.as_slice(),
+                            true,
+                            true,
+                        )?)),
+                        "ZDIFFSTORE" => Ok(Command::ZDiffStore(ZSetOp::parse(
+                            iter.as_slice(),
+                            true,
+                            false,
+                        )?)),
+                        "ZUNION" => Ok(Command::ZUnion(ZSetOp::parse(
+                            iter.as_slice(),
+                            false,
+                            true,
+                        )?)),
+                        "ZINTER" => Ok(Command::ZInter(ZSetOp::parse(
+                            iter.as_slice(),
+                            false,
+                            true,
+                        )?)),
+                        "ZDIFF" => Ok(Command::ZDiff(ZSetOp::parse(
+                            iter.as_slice(),
+                            false,
+                            false,
+                        )?)),
+                        "XADD" => Ok(Command::XAdd(iter.as_slice().try_into()?)),
+                        "XRANGE" => Ok(Command::XRange(XRange::parse(iter.as_slice(), false)?)),
+                        "XREVRANGE" => {
+                            Ok(Command::XRevRange(XRange::parse(iter.as_slice(), true)?))
+                        }
+                        "XLEN" => Ok(Command::XLen(iter.as_slice().try_into()?)),
+                        "XTRIM" => Ok(Command::XTrim(iter.as_slice().try_into()?)),
+                        "XDEL" => Ok(Command::XDel(iter.as_slice().try_into()?)),
+                        "XREAD" => Ok(Command::XRead(iter.as_slice().try_into()?)),
+                        "XREADGROUP" => Ok(Command::XReadGroup(iter.as_slice().try_into()?)),
+                        "XGROUP" => Ok(Command::XGroup(iter.as_slice().try_into()?)),
+                        "XACK" => Ok(Command::XAck(iter.as_slice().try_into()?)),
+                        "XPENDING" => Ok(Command::XPending(iter.as_slice().try_into()?)),
+                        "XCLAIM" => Ok(Command::XClaim(iter.as_slice().try_into()?)),
+                        "XAUTOCLAIM" => Ok(Command::XAutoClaim(iter.as_slice().try_into()?)),
+                        "XINFO" => Ok(Command::XInfo(iter.as_slice().try_into()?)),
+                        "PFADD" => Ok(Command::PfAdd(iter.as_slice().try_into()?)),
+                        "PFCOUNT" => Ok(Command::PfCount(iter.as_slice().try_into()?)),
+                        "PFMERGE" => Ok(Command::PfMerge(iter.as_slice().try_into()?)),
+                        "GEOADD" => Ok(Command::GeoAdd(iter.as_slice().try_into()?)),
+                        "GEODIST" => Ok(Command::GeoDist(iter.as_slice().try_into()?)),
+                        "GEOPOS" => Ok(Command::GeoPos(iter.as_slice().try_into()?)),
+                        "GEOHASH" => Ok(Command::GeoHash(iter.as_slice().try_into()?)),
+                        "GEOSEARCH" => Ok(Command::GeoSearch(GeoSearch::parse(
+                            iter.as_slice(),
+                            false,
+                        )?)),
+                        "GEOSEARCHSTORE" => Ok(Command::GeoSearchStore(GeoSearch::parse(
+                            iter.as_slice(),
+                            true,
+                        )?)),
+                        "SUBSCRIBE" => Ok(Command::Subscribe(Channels::parse(iter.as_slice(), 1)?)),
+                        "UNSUBSCRIBE" => {
+                            Ok(Command::Unsubscribe(Channels::parse(iter.as_slice(), 0)?))
+                        }
+                        "PSUBSCRIBE" => {
+                            Ok(Command::PSubscribe(Channels::parse(iter.as_slice(), 1)?))
+                        }
+                        "PUNSUBSCRIBE" => {
+                            Ok(Command::PUnsubscribe(Channels::parse(iter.as_slice(), 0)?))
+                        }
+                        "PUBLISH" => Ok(Command::Publish(iter.as_slice().try_into()?)),
+                        "PUBSUB" => Ok(Command::PubSub(iter.as_slice().try_into()?)),
+                        "SSUBSCRIBE" => Ok(Command::SSubscribe(Channels::parse_sharded(
+                            iter.as_slice(),
+                            1,
+                        )?)),
+                        "SUNSUBSCRIBE" => Ok(Command::SUnsubscribe(Channels::parse_sharded(
+                            iter.as_slice(),
+                            0,
+                        )?)),
+                        "SPUBLISH" => Ok(Command::SPublish(iter.as_slice().try_into()?)),
+                        "CONFIG" => Ok(Command::Config(iter.as_slice().try_into()?)),
+                        "RANDOMKEY" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::RandomKey)
+                        }
+                        "DBSIZE" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::DbSize)
+                        }
+                        "SELECT" => Ok(Command::Select(iter.as_slice().try_into()?)),
+                        "MOVE" => Ok(Command::Move(iter.as_slice().try_into()?)),
+                        "SWAPDB" => Ok(Command::SwapDb(iter.as_slice().try_into()?)),
+                        "FLUSHDB" => Ok(Command::FlushDb(iter.as_slice().try_into()?)),
+                        "FLUSHALL" => Ok(Command::FlushAll(iter.as_slice().try_into()?)),
+                        "MULTI" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::Multi)
+                        }
+                        "EXEC" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::Exec)
+                        }
+                        "DISCARD" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::Discard)
+                        }
+                        "WATCH" => Ok(Command::Watch(iter.as_slice().try_into()?)),
+                        "EVAL" => Ok(Command::Eval(iter.as_slice().try_into()?)),
+                        "EVALSHA" => Ok(Command::EvalSha(iter.as_slice().try_into()?)),
+                        "SCRIPT" => Ok(Command::Script(iter.as_slice().try_into()?)),
+                        "FCALL" => Ok(Command::FCall(iter.as_slice().try_into()?)),
+                        "FCALL_RO" => Ok(Command::FCallRo(iter.as_slice().try_into()?)),
+                        "FUNCTION" => Ok(Command::Function(iter.as_slice().try_into()?)),
+                        "UNWATCH" => {
+                            expect_args(iter.as_slice(), 0)?;
+                            Ok(Command::Unwatch)
+                        }
                         cmd => Err(CommandError::UnsupportedCommand(cmd.to_string())),
                     },
                     _ => Err(CommandError::WrongFormat),
@@ -91,6 +890,117 @@ impl TryFrom<Resp> for Command {
     }
 }
 
+fn expect_args(args: &[Resp], n: usize) -> Result<(), CommandError> {
+    if args.len() != n {
+        return Err(CommandError::WrongNumberOfArguments(n, args.len()));
+    }
+    Ok(())
+}
+
+fn expect_min_args(args: &[Resp], n: usize) -> Result<(), CommandError> {
+    if args.len() < n {
+        return Err(CommandError::NotEnoughArguments(n, args.len()));
+    }
+    Ok(())
+}
+
+fn extract_key(arg: &Resp) -> Result<Key, CommandError> {
+    arg.clone()
+        .try_into()
+        .map_err(|_| CommandError::UnsupportedKey)
+}
+
+fn extract_keys(args: &[Resp]) -> Result<Vec<Key>, CommandError> {
+    args.iter().map(extract_key).collect()
+}
+
+/// Parses a blocking timeout in seconds, with sub-second precision. Zero
+/// means block forever.
+fn extract_timeout(arg: &Resp) -> Result<Option<Duration>, CommandError> {
+    let secs = extract_float(arg).map_err(|_| CommandError::InvalidTimeout)?;
+    if secs < 0.0 {
+        return Err(CommandError::NegativeTimeout);
+    }
+    if secs == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(secs)
+        .map(Some)
+        .map_err(|_| CommandError::InvalidTimeout)
+}
+
+/// Splits `numkeys key [key ...] rest...` into the keys and the rest.
+fn extract_numkeys(args: &[Resp]) -> Result<(Vec<Key>, &[Resp]), CommandError> {
+    let (numkeys, rest) = args.split_first().ok_or(CommandError::SyntaxError)?;
+    let numkeys = extract_integer(numkeys)?;
+    if numkeys <= 0 {
+        return Err(CommandError::InvalidNumKeys);
+    }
+    let numkeys = numkeys as usize;
+    if rest.len() < numkeys {
+        return Err(CommandError::SyntaxError);
+    }
+    Ok((extract_keys(&rest[..numkeys])?, &rest[numkeys..]))
+}
+
+fn extract_positive(arg: &Resp) -> Result<usize, CommandError> {
+    let n = extract_integer(arg)?;
+    if n < 0 {
+        return Err(CommandError::NotPositive);
+    }
+    Ok(n as usize)
+}
+
+fn extract_string(arg: &Resp) -> Result<String, CommandError> {
+    match arg {
+        Resp::BulkString(s) if !s.is_null => Ok(String::from_utf8_lossy(&s.value).into_owned()),
+        Resp::SimpleString(s) => Ok(s.as_str().to_string()),
+        Resp::Integer(i) => Ok(i.value().to_string()),
+        _ => Err(CommandError::WrongFormat),
+    }
+}
+
+/// Like `extract_string`, but keeps bulk strings byte for byte.
+fn extract_bytes(arg: &Resp) -> Result<Vec<u8>, CommandError> {
+    match arg {
+        Resp::BulkString(s) if !s.is_null => Ok(s.value.clone()),
+        _ => extract_string(arg).map(String::into_bytes),
+    }
+}
+
+fn extract_integer(arg: &Resp) -> Result<i64, CommandError> {
+    match arg {
+        Resp::Integer(i) => Ok(i.value()),
+        _ => extract_string(arg)?
+            .parse()
+            .map_err(|_| CommandError::NotAnInteger),
+    }
+}
+
+fn extract_float(arg: &Resp) -> Result<f64, CommandError> {
+    let f: f64 = match arg {
+        Resp::Integer(i) => i.value() as f64,
+        Resp::Double(d) => d.value(),
+        _ => extract_string(arg)?
+            .parse()
+            .map_err(|_| CommandError::NotAFloat)?,
+    };
+    if f.is_nan() {
+        return Err(CommandError::NotAFloat);
+    }
+    Ok(f)
+}
+
+#[cfg(test)]
+pub(crate) fn parse_command(args: &[&str]) -> Result<Command, CommandError> {
+    use crate::resp::{Array, BulkString};
+    let arr = args
+        .iter()
+        .map(|a| Resp::BulkString(BulkString::new(*a, false)))
+        .collect();
+    Command::try_from(Resp::Array(Array::new(arr, false)))
+}
+
 #[derive(Debug, Clone)]
 pub struct Get {
     pub key: Key,
@@ -149,7 +1059,7 @@ mod tests {
                 assert_eq!(
                     key,
                     Key::BulkString(BulkString {
-                        value: "key".to_string(),
+                        value: b"key".to_vec(),
                         is_null: false
                     })
                 );
@@ -168,7 +1078,7 @@ mod tests {
                 assert_eq!(
                     key,
                     Key::BulkString(BulkString {
-                        value: "key".to_string(),
+                        value: b"key".to_vec(),
                         is_null: false
                     })
                 );
@@ -191,6 +1101,16 @@ mod tests {
         assert_eq!(cmd.unwrap_err(), CommandError::WrongFormat);
     }
 
+    #[test]
+    fn test_name() {
+        let name = |args: &[&str]| parse_command(args).unwrap().name();
+        assert_eq!(name(&["SINTERSTORE", "d", "a"]), "sinterstore");
+        assert_eq!(name(&["COMMAND"]), "command");
+        assert_eq!(name(&["MULTI"]), "multi");
+        assert_eq!(name(&["CLIENT", "ID"]), "client");
+        assert_eq!(name(&["SET", "k", &"v".repeat(1 << 20)]), "set");
+    }
+
     #[test]
     fn test_parse_echo() {
         let mut arr = Array::default();
@@ -203,7 +1123,7 @@ mod tests {
                 assert_eq!(
                     msg,
                     Resp::BulkString(BulkString {
-                        value: "Hello World".to_string(),
+                        value: b"Hello World".to_vec(),
                         is_null: false
                     })
                 );
@@ -0,0 +1,146 @@
+use super::{
+    expect_args, expect_min_args, extract_key, extract_keys, extract_string, CommandError,
+};
+use crate::{
+    resp::{Key, Resp},
+    slot::key_slot,
+};
+
+/// A command taking channels or patterns: `SUBSCRIBE`, `UNSUBSCRIBE`,
+/// `PSUBSCRIBE`, `PUNSUBSCRIBE`, `SSUBSCRIBE`, `SUNSUBSCRIBE`.
+#[derive(Debug, Clone)]
+pub struct Channels {
+    pub channels: Vec<Key>,
+}
+
+impl Channels {
+    /// Parses at least `min` channels.
+    pub fn parse(args: &[Resp], min: usize) -> Result<Self, CommandError> {
+        expect_min_args(args, min)?;
+        Ok(Channels {
+            channels: extract_keys(args)?,
+        })
+    }
+
+    /// Parses at least `min` shard channels, which must all hash to the
+    /// same slot.
+    pub fn parse_sharded(args: &[Resp], min: usize) -> Result<Self, CommandError> {
+        let c = Channels::parse(args, min)?;
+        let mut slots = c
+            .channels
+            .iter()
+            .map(|c| key_slot(c.to_string().as_bytes()));
+        if let Some(first) = slots.next() {
+            if slots.any(|slot| slot != first) {
+                return Err(CommandError::CrossSlot);
+            }
+        }
+        Ok(c)
+    }
+}
+
+/// `PUBLISH channel message`
+#[derive(Debug, Clone)]
+pub struct Publish {
+    pub channel: Key,
+    pub message: Resp,
+}
+
+impl TryFrom<&[Resp]> for Publish {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(Publish {
+            channel: extract_key(&args[0])?,
+            message: args[1].clone(),
+        })
+    }
+}
+
+/// `PUBSUB CHANNELS [pattern]`, `PUBSUB NUMSUB [channel ...]`,
+/// `PUBSUB NUMPAT`, `PUBSUB SHARDCHANNELS [pattern]` and
+/// `PUBSUB SHARDNUMSUB [channel ...]`.
+#[derive(Debug, Clone)]
+pub enum PubSub {
+    Channels { pattern: Option<String> },
+    NumSub { channels: Vec<Key> },
+    NumPat,
+    ShardChannels { pattern: Option<String> },
+    ShardNumSub { channels: Vec<Key> },
+}
+
+impl TryFrom<&[Resp]> for PubSub {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let rest = &args[1..];
+        match subcommand.as_str() {
+            "CHANNELS" if rest.len() <= 1 => Ok(PubSub::Channels {
+                pattern: rest.first().map(extract_string).transpose()?,
+            }),
+            "NUMSUB" => Ok(PubSub::NumSub {
+                channels: extract_keys(rest)?,
+            }),
+            "NUMPAT" if rest.is_empty() => Ok(PubSub::NumPat),
+            "SHARDCHANNELS" if rest.len() <= 1 => Ok(PubSub::ShardChannels {
+                pattern: rest.first().map(extract_string).transpose()?,
+            }),
+            "SHARDNUMSUB" => Ok(PubSub::ShardNumSub {
+                channels: extract_keys(rest)?,
+            }),
+            "CHANNELS" | "NUMPAT" | "SHARDCHANNELS" => Err(CommandError::SyntaxError),
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_pubsub() {
+        match command(&["SUBSCRIBE", "a", "b"]).unwrap() {
+            Command::Subscribe(c) => assert_eq!(c.channels.len(), 2),
+            _ => panic!("Expected Subscribe"),
+        }
+        assert!(command(&["SUBSCRIBE"]).is_err());
+        match command(&["UNSUBSCRIBE"]).unwrap() {
+            Command::Unsubscribe(c) => assert!(c.channels.is_empty()),
+            _ => panic!("Expected Unsubscribe"),
+        }
+        match command(&["PUBSUB", "channels", "news.*"]).unwrap() {
+            Command::PubSub(PubSub::Channels { pattern }) => {
+                assert_eq!(pattern.as_deref(), Some("news.*"))
+            }
+            _ => panic!("Expected PubSub"),
+        }
+        assert!(matches!(
+            command(&["PUBSUB", "NUMPAT"]).unwrap(),
+            Command::PubSub(PubSub::NumPat)
+        ));
+        assert_eq!(
+            command(&["PUBSUB", "NUMPAT", "x"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(command(&["PUBSUB", "NUMPAT"]).unwrap().name(), "pubsub");
+        assert!(matches!(
+            command(&["PUBSUB", "SHARDNUMSUB", "a", "b"]).unwrap(),
+            Command::PubSub(PubSub::ShardNumSub { channels }) if channels.len() == 2
+        ));
+        assert!(command(&["SSUBSCRIBE", "{a}.1", "{a}.2"]).is_ok());
+        assert_eq!(
+            command(&["SSUBSCRIBE", "foo", "bar"]).unwrap_err(),
+            CommandError::CrossSlot
+        );
+        assert!(command(&["SUNSUBSCRIBE"]).is_ok());
+        assert_eq!(command(&["GET", "k"]).unwrap().name(), "get");
+        assert!(!command(&["GET", "k"]).unwrap().is_allowed_when_subscribed());
+        assert_eq!(
+            command(&["PUBLISH", "a"]).unwrap_err(),
+            CommandError::WrongNumberOfArguments(2, 1)
+        );
+    }
+}
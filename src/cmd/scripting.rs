@@ -0,0 +1,262 @@
+use super::{
+    expect_min_args, extract_bytes, extract_integer, extract_keys, extract_string, CommandError,
+};
+use crate::resp::{Key, Resp};
+
+/// `EVAL script numkeys [key ...] [arg ...]`, and `EVALSHA` with the SHA1
+/// digest of a cached script in place of its body. `FCALL` and `FCALL_RO`
+/// take the name of a function instead.
+#[derive(Debug, Clone)]
+pub struct Eval {
+    pub script: String,
+    pub keys: Vec<Key>,
+    pub args: Vec<Vec<u8>>,
+}
+
+impl TryFrom<&[Resp]> for Eval {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let numkeys = extract_integer(&args[1])?;
+        let rest = &args[2..];
+        if numkeys < 0 {
+            return Err(CommandError::NegativeNumKeys);
+        }
+        if numkeys as usize > rest.len() {
+            return Err(CommandError::TooManyNumKeys);
+        }
+        let (keys, rest) = rest.split_at(numkeys as usize);
+        Ok(Eval {
+            script: extract_string(&args[0])?,
+            keys: extract_keys(keys)?,
+            args: rest.iter().map(extract_bytes).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// `SCRIPT LOAD script`, `SCRIPT EXISTS sha1 [sha1 ...]`,
+/// `SCRIPT FLUSH [ASYNC | SYNC]` and `SCRIPT KILL`.
+#[derive(Debug, Clone)]
+pub enum Script {
+    Load { script: String },
+    Exists { shas: Vec<String> },
+    Flush,
+    Kill,
+}
+
+impl TryFrom<&[Resp]> for Script {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let rest = &args[1..];
+        match subcommand.as_str() {
+            "LOAD" if rest.len() == 1 => Ok(Script::Load {
+                script: extract_string(&rest[0])?,
+            }),
+            "EXISTS" if !rest.is_empty() => Ok(Script::Exists {
+                shas: rest.iter().map(extract_string).collect::<Result<_, _>>()?,
+            }),
+            "FLUSH" if rest.len() <= 1 => {
+                if let Some(mode) = rest.first() {
+                    let mode = extract_string(mode)?.to_uppercase();
+                    if mode != "ASYNC" && mode != "SYNC" {
+                        return Err(CommandError::SyntaxError);
+                    }
+                }
+                Ok(Script::Flush)
+            }
+            "KILL" if rest.is_empty() => Ok(Script::Kill),
+            "LOAD" | "EXISTS" | "FLUSH" | "KILL" => Err(CommandError::SyntaxError),
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+/// What `FUNCTION RESTORE` does with libraries that already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestorePolicy {
+    /// Fail if a restored library already exists.
+    #[default]
+    Append,
+    /// Replace existing libraries with restored ones of the same name.
+    Replace,
+    /// Delete every library first.
+    Flush,
+}
+
+/// `FUNCTION LOAD [REPLACE] code`, `FUNCTION LIST [LIBRARYNAME pattern]
+/// [WITHCODE]`, `FUNCTION DELETE library`, `FUNCTION DUMP`,
+/// `FUNCTION RESTORE payload [FLUSH | APPEND | REPLACE]` and
+/// `FUNCTION FLUSH [ASYNC | SYNC]`.
+#[derive(Debug, Clone)]
+pub enum Function {
+    Load {
+        code: String,
+        replace: bool,
+    },
+    List {
+        pattern: Option<String>,
+        with_code: bool,
+    },
+    Delete {
+        library: String,
+    },
+    Dump,
+    Restore {
+        payload: String,
+        policy: RestorePolicy,
+    },
+    Flush,
+}
+
+impl TryFrom<&[Resp]> for Function {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let rest = &args[1..];
+        match subcommand.as_str() {
+            "LOAD" => match rest {
+                [code] => Ok(Function::Load {
+                    code: extract_string(code)?,
+                    replace: false,
+                }),
+                [option, code] if extract_string(option)?.eq_ignore_ascii_case("REPLACE") => {
+                    Ok(Function::Load {
+                        code: extract_string(code)?,
+                        replace: true,
+                    })
+                }
+                _ => Err(CommandError::SyntaxError),
+            },
+            "LIST" => {
+                let mut pattern = None;
+                let mut with_code = false;
+                let mut rest = rest.iter();
+                while let Some(arg) = rest.next() {
+                    match extract_string(arg)?.to_uppercase().as_str() {
+                        "WITHCODE" => with_code = true,
+                        "LIBRARYNAME" => {
+                            let arg = rest.next().ok_or(CommandError::SyntaxError)?;
+                            pattern = Some(extract_string(arg)?);
+                        }
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                }
+                Ok(Function::List { pattern, with_code })
+            }
+            "DELETE" if rest.len() == 1 => Ok(Function::Delete {
+                library: extract_string(&rest[0])?,
+            }),
+            "DUMP" if rest.is_empty() => Ok(Function::Dump),
+            "RESTORE" if !rest.is_empty() && rest.len() <= 2 => {
+                let policy = match rest.get(1).map(extract_string).transpose()? {
+                    None => RestorePolicy::default(),
+                    Some(p) => match p.to_uppercase().as_str() {
+                        "APPEND" => RestorePolicy::Append,
+                        "REPLACE" => RestorePolicy::Replace,
+                        "FLUSH" => RestorePolicy::Flush,
+                        _ => return Err(CommandError::SyntaxError),
+                    },
+                };
+                Ok(Function::Restore {
+                    payload: extract_string(&rest[0])?,
+                    policy,
+                })
+            }
+            "FLUSH" if rest.len() <= 1 => {
+                if let Some(mode) = rest.first() {
+                    let mode = extract_string(mode)?.to_uppercase();
+                    if mode != "ASYNC" && mode != "SYNC" {
+                        return Err(CommandError::SyntaxError);
+                    }
+                }
+                Ok(Function::Flush)
+            }
+            "DELETE" | "DUMP" | "RESTORE" | "FLUSH" => Err(CommandError::SyntaxError),
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_eval() {
+        match command(&["EVAL", "return 1", "2", "a", "b", "c"]).unwrap() {
+            Command::Eval(c) => {
+                assert_eq!(c.script, "return 1");
+                assert_eq!(c.keys.len(), 2);
+                assert_eq!(c.args.len(), 1);
+            }
+            _ => panic!("Expected Eval"),
+        }
+        assert!(matches!(
+            command(&["EVALSHA", "abc", "0"]).unwrap(),
+            Command::EvalSha(Eval { keys, .. }) if keys.is_empty()
+        ));
+        assert_eq!(
+            command(&["EVAL", "return 1", "-1"]).unwrap_err(),
+            CommandError::NegativeNumKeys
+        );
+        assert_eq!(
+            command(&["EVAL", "return 1", "2", "a"]).unwrap_err(),
+            CommandError::TooManyNumKeys
+        );
+        assert!(matches!(
+            command(&["SCRIPT", "exists", "a", "b"]).unwrap(),
+            Command::Script(Script::Exists { shas }) if shas.len() == 2
+        ));
+        assert!(matches!(
+            command(&["SCRIPT", "FLUSH", "async"]).unwrap(),
+            Command::Script(Script::Flush)
+        ));
+        assert_eq!(
+            command(&["SCRIPT", "FLUSH", "now"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert!(matches!(
+            command(&["SCRIPT", "kill"]).unwrap(),
+            Command::Script(Script::Kill)
+        ));
+        assert_eq!(
+            command(&["SCRIPT", "KILL", "now"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+
+    #[test]
+    fn test_parse_function() {
+        assert!(matches!(
+            command(&["FUNCTION", "LOAD", "replace", "#!lua name=lib"]).unwrap(),
+            Command::Function(Function::Load { replace: true, .. })
+        ));
+        match command(&["FUNCTION", "LIST", "WITHCODE", "LIBRARYNAME", "l*"]).unwrap() {
+            Command::Function(Function::List { pattern, with_code }) => {
+                assert_eq!(pattern.as_deref(), Some("l*"));
+                assert!(with_code);
+            }
+            _ => panic!("Expected Function"),
+        }
+        assert_eq!(
+            command(&["FUNCTION", "LIST", "LIBRARYNAME"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert!(matches!(
+            command(&["FUNCTION", "RESTORE", "x", "flush"]).unwrap(),
+            Command::Function(Function::Restore {
+                policy: RestorePolicy::Flush,
+                ..
+            })
+        ));
+        assert!(matches!(
+            command(&["FCALL_RO", "f", "1", "k"]).unwrap(),
+            Command::FCallRo(Eval { keys, .. }) if keys.len() == 1
+        ));
+        assert_eq!(command(&["FCALL_RO", "f", "0"]).unwrap().name(), "fcall_ro");
+    }
+}
@@ -0,0 +1,264 @@
+use super::{
+    expect_args, expect_min_args, extract_integer, extract_key, extract_keys, extract_numkeys,
+    extract_positive, extract_string, CommandError, ScanOptions,
+};
+use crate::resp::{Key, Resp};
+
+/// A command taking a key and one or more members: `SADD`, `SREM`,
+/// `SMISMEMBER`.
+#[derive(Debug, Clone)]
+pub struct Members {
+    pub key: Key,
+    pub members: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for Members {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(Members {
+            key: extract_key(&args[0])?,
+            members: extract_keys(&args[1..])?,
+        })
+    }
+}
+
+/// A command taking a single set key: `SMEMBERS`, `SCARD`.
+#[derive(Debug, Clone)]
+pub struct SetKey {
+    pub key: Key,
+}
+
+impl TryFrom<&[Resp]> for SetKey {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(SetKey {
+            key: extract_key(&args[0])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SIsMember {
+    pub key: Key,
+    pub member: Key,
+}
+
+impl TryFrom<&[Resp]> for SIsMember {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(SIsMember {
+            key: extract_key(&args[0])?,
+            member: extract_key(&args[1])?,
+        })
+    }
+}
+
+/// `SPOP key [count]`
+#[derive(Debug, Clone)]
+pub struct SPop {
+    pub key: Key,
+    pub count: Option<usize>,
+}
+
+impl TryFrom<&[Resp]> for SPop {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        if args.len() > 2 {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(SPop {
+            key: extract_key(&args[0])?,
+            count: args.get(1).map(extract_positive).transpose()?,
+        })
+    }
+}
+
+/// `SRANDMEMBER key [count]`
+#[derive(Debug, Clone)]
+pub struct SRandMember {
+    pub key: Key,
+    pub count: Option<i64>,
+}
+
+impl TryFrom<&[Resp]> for SRandMember {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        if args.len() > 2 {
+            return Err(CommandError::SyntaxError);
+        }
+        let count = args.get(1).map(extract_integer).transpose()?;
+        // Redis takes counts down to -LONG_MAX, whose negation still fits.
+        if count == Some(i64::MIN) {
+            return Err(CommandError::OutOfRange);
+        }
+        Ok(SRandMember {
+            key: extract_key(&args[0])?,
+            count,
+        })
+    }
+}
+
+/// `SMOVE source destination member`
+#[derive(Debug, Clone)]
+pub struct SMove {
+    pub source: Key,
+    pub destination: Key,
+    pub member: Key,
+}
+
+impl TryFrom<&[Resp]> for SMove {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(SMove {
+            source: extract_key(&args[0])?,
+            destination: extract_key(&args[1])?,
+            member: extract_key(&args[2])?,
+        })
+    }
+}
+
+/// `SSCAN key cursor [MATCH pattern] [COUNT count]`
+#[derive(Debug, Clone)]
+pub struct SScan {
+    pub key: Key,
+    pub cursor: u64,
+    pub options: ScanOptions,
+}
+
+impl TryFrom<&[Resp]> for SScan {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(SScan {
+            key: extract_key(&args[0])?,
+            cursor: super::extract_cursor(&args[1])?,
+            options: ScanOptions::parse(&args[2..], false)?,
+        })
+    }
+}
+
+/// `SINTER key [key ...]`, `SUNION` and `SDIFF`.
+#[derive(Debug, Clone)]
+pub struct SetOp {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for SetOp {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(SetOp {
+            keys: extract_keys(args)?,
+        })
+    }
+}
+
+/// `SINTERSTORE destination key [key ...]`, `SUNIONSTORE` and `SDIFFSTORE`.
+#[derive(Debug, Clone)]
+pub struct SetOpStore {
+    pub destination: Key,
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for SetOpStore {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(SetOpStore {
+            destination: extract_key(&args[0])?,
+            keys: extract_keys(&args[1..])?,
+        })
+    }
+}
+
+/// `SINTERCARD numkeys key [key ...] [LIMIT limit]`. A limit of 0 means no
+/// limit.
+#[derive(Debug, Clone)]
+pub struct SInterCard {
+    pub keys: Vec<Key>,
+    pub limit: usize,
+}
+
+impl TryFrom<&[Resp]> for SInterCard {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        let (keys, rest) = extract_numkeys(args)?;
+        let limit = match rest {
+            [] => 0,
+            [opt, limit] if extract_string(opt)?.eq_ignore_ascii_case("LIMIT") => {
+                match extract_integer(limit)? {
+                    n if n < 0 => return Err(CommandError::NegativeLimit),
+                    n => n as usize,
+                }
+            }
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(SInterCard { keys, limit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_members() {
+        match command(&["SADD", "s", "a", "b"]).unwrap() {
+            Command::SAdd(c) => assert_eq!(c.members.len(), 2),
+            _ => panic!("Expected SAdd"),
+        }
+        assert!(command(&["SREM", "s"]).is_err());
+        assert!(command(&["SISMEMBER", "s", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_sintercard() {
+        match command(&["SINTERCARD", "2", "a", "b", "LIMIT", "5"]).unwrap() {
+            Command::SInterCard(c) => {
+                assert_eq!(c.keys.len(), 2);
+                assert_eq!(c.limit, 5);
+            }
+            _ => panic!("Expected SInterCard"),
+        }
+        assert_eq!(
+            command(&["SINTERCARD", "1", "a", "LIMIT", "-1"]).unwrap_err(),
+            CommandError::NegativeLimit
+        );
+        assert_eq!(
+            command(&["SINTERCARD", "1", "a", "b"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert!(command(&["SINTERSTORE", "dest"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_spop() {
+        match command(&["SPOP", "s", "3"]).unwrap() {
+            Command::SPop(c) => assert_eq!(c.count, Some(3)),
+            _ => panic!("Expected SPop"),
+        }
+        assert_eq!(
+            command(&["SPOP", "s", "-1"]).unwrap_err(),
+            CommandError::NotPositive
+        );
+        match command(&["SRANDMEMBER", "s", "-3"]).unwrap() {
+            Command::SRandMember(c) => assert_eq!(c.count, Some(-3)),
+            _ => panic!("Expected SRandMember"),
+        }
+        assert_eq!(
+            command(&["SRANDMEMBER", "s", "1", "2"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["SRANDMEMBER", "s", "-9223372036854775808"]).unwrap_err(),
+            CommandError::OutOfRange
+        );
+    }
+}
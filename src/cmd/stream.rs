@@ -0,0 +1,1016 @@
+use super::{
+    expect_args, expect_min_args, extract_integer, extract_key, extract_keys, extract_positive,
+    extract_string, CommandError,
+};
+use crate::resp::{Key, Resp};
+use std::fmt;
+use std::time::Duration;
+
+/// A stream entry ID: a millisecond timestamp and a sequence number telling
+/// apart entries added within the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// The smallest ID greater than this one.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The greatest ID smaller than this one.
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+
+    /// Parses `ms-seq`, or a bare `ms` with `seq` standing in for the
+    /// sequence number.
+    fn parse(s: &str, seq: u64) -> Result<StreamId, CommandError> {
+        let parse = |n: &str| n.parse().map_err(|_| CommandError::InvalidStreamId);
+        match s.split_once('-') {
+            Some((ms, s)) => Ok(StreamId::new(parse(ms)?, parse(s)?)),
+            None => Ok(StreamId::new(parse(s)?, seq)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl TryFrom<&Resp> for StreamId {
+    type Error = CommandError;
+    fn try_from(arg: &Resp) -> Result<Self, Self::Error> {
+        StreamId::parse(&extract_string(arg)?, 0)
+    }
+}
+
+/// The ID an `XADD` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XAddId {
+    /// `*`: the current time, or the last ID's millisecond if the clock is
+    /// behind it.
+    Auto,
+    /// `ms-*`: the next sequence number within the given millisecond.
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl TryFrom<&Resp> for XAddId {
+    type Error = CommandError;
+    fn try_from(arg: &Resp) -> Result<Self, Self::Error> {
+        let id = extract_string(arg)?;
+        if id == "*" {
+            return Ok(XAddId::Auto);
+        }
+        if let Some(ms) = id.strip_suffix("-*") {
+            let ms = ms.parse().map_err(|_| CommandError::InvalidStreamId)?;
+            return Ok(XAddId::AutoSeq(ms));
+        }
+        match StreamId::parse(&id, 0)? {
+            StreamId::MIN => Err(CommandError::XAddZeroId),
+            id => Ok(XAddId::Explicit(id)),
+        }
+    }
+}
+
+/// Which entries a trim keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Keep the newest `n` entries.
+    MaxLen(usize),
+    /// Keep the entries with IDs at or above this one.
+    MinId(StreamId),
+}
+
+/// `MAXLEN|MINID [=|~] threshold [LIMIT count]`, as taken by `XADD` and
+/// `XTRIM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trim {
+    pub strategy: TrimStrategy,
+    /// With `~`, only whole nodes are evicted, so a few more entries than
+    /// asked for may stay.
+    pub approximate: bool,
+    /// The most entries an approximate trim evicts. `None` means the
+    /// default, 0 means no limit.
+    pub limit: Option<usize>,
+}
+
+impl Trim {
+    /// Parses a trim off the front of `args`, returning the arguments that
+    /// follow it.
+    fn parse(args: &[Resp]) -> Result<(Trim, &[Resp]), CommandError> {
+        let (strategy, mut rest) = args.split_first().ok_or(CommandError::SyntaxError)?;
+        let strategy = extract_string(strategy)?.to_uppercase();
+        let mut approximate = false;
+        if let Some((op, tail)) = rest.split_first() {
+            match extract_string(op)?.as_str() {
+                "=" => rest = tail,
+                "~" => {
+                    approximate = true;
+                    rest = tail;
+                }
+                _ => {}
+            }
+        }
+        let (threshold, mut rest) = rest.split_first().ok_or(CommandError::SyntaxError)?;
+        let strategy = match strategy.as_str() {
+            "MAXLEN" => TrimStrategy::MaxLen(extract_positive(threshold)?),
+            "MINID" => TrimStrategy::MinId(threshold.try_into()?),
+            _ => return Err(CommandError::SyntaxError),
+        };
+        let mut limit = None;
+        if let [opt, count, tail @ ..] = rest {
+            if extract_string(opt)?.eq_ignore_ascii_case("LIMIT") {
+                if !approximate {
+                    return Err(CommandError::LimitWithoutApprox);
+                }
+                limit = Some(extract_positive(count)?);
+                rest = tail;
+            }
+        }
+        let trim = Trim {
+            strategy,
+            approximate,
+            limit,
+        };
+        Ok((trim, rest))
+    }
+}
+
+/// `XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]]
+/// *|id field value [field value ...]`
+#[derive(Debug, Clone)]
+pub struct XAdd {
+    pub key: Key,
+    pub nomkstream: bool,
+    pub trim: Option<Trim>,
+    pub id: XAddId,
+    /// Fields and values, interleaved.
+    pub fields: Vec<Resp>,
+}
+
+impl TryFrom<&[Resp]> for XAdd {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 4)?;
+        let key = extract_key(&args[0])?;
+        let mut nomkstream = false;
+        let mut trim = None;
+        let mut rest = &args[1..];
+        while let Some(opt) = rest.first() {
+            match extract_string(opt)?.to_uppercase().as_str() {
+                "NOMKSTREAM" => {
+                    nomkstream = true;
+                    rest = &rest[1..];
+                }
+                "MAXLEN" | "MINID" => {
+                    let (t, tail) = Trim::parse(rest)?;
+                    trim = Some(t);
+                    rest = tail;
+                }
+                _ => break,
+            }
+        }
+        let (id, fields) = rest.split_first().ok_or(CommandError::SyntaxError)?;
+        if fields.is_empty() || !fields.len().is_multiple_of(2) {
+            return Err(CommandError::WrongNumberOfArguments(
+                args.len() + 1,
+                args.len(),
+            ));
+        }
+        Ok(XAdd {
+            key,
+            nomkstream,
+            trim,
+            id: id.try_into()?,
+            fields: fields.to_vec(),
+        })
+    }
+}
+
+/// `XRANGE key start end [COUNT count]` and `XREVRANGE key end start`. Both
+/// bounds are resolved to inclusive IDs.
+#[derive(Debug, Clone)]
+pub struct XRange {
+    pub key: Key,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: Option<usize>,
+}
+
+impl XRange {
+    pub fn parse(args: &[Resp], rev: bool) -> Result<Self, CommandError> {
+        expect_min_args(args, 3)?;
+        let (start, end) = match rev {
+            false => (&args[1], &args[2]),
+            true => (&args[2], &args[1]),
+        };
+        let count = match &args[3..] {
+            [] => None,
+            [opt, count] if extract_string(opt)?.eq_ignore_ascii_case("COUNT") => {
+                Some(extract_positive(count)?)
+            }
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(XRange {
+            key: extract_key(&args[0])?,
+            start: range_bound(start, false)?,
+            end: range_bound(end, true)?,
+            count,
+        })
+    }
+}
+
+/// Parses `-`, `+`, an ID, or an ID prefixed with `(` to exclude it. A bare
+/// millisecond covers the whole millisecond.
+fn range_bound(arg: &Resp, end: bool) -> Result<StreamId, CommandError> {
+    let bound = extract_string(arg)?;
+    match bound.as_str() {
+        "-" => return Ok(StreamId::MIN),
+        "+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let seq = if end { u64::MAX } else { 0 };
+    match bound.strip_prefix('(') {
+        Some(id) => {
+            let id = StreamId::parse(id, seq)?;
+            match end {
+                false => id.next(),
+                true => id.prev(),
+            }
+            .ok_or(CommandError::InvalidStreamId)
+        }
+        None => StreamId::parse(&bound, seq),
+    }
+}
+
+/// `XLEN key`
+#[derive(Debug, Clone)]
+pub struct XLen {
+    pub key: Key,
+}
+
+impl TryFrom<&[Resp]> for XLen {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(XLen {
+            key: extract_key(&args[0])?,
+        })
+    }
+}
+
+/// `XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]`
+#[derive(Debug, Clone)]
+pub struct XTrim {
+    pub key: Key,
+    pub trim: Trim,
+}
+
+impl TryFrom<&[Resp]> for XTrim {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        let (trim, rest) = Trim::parse(&args[1..])?;
+        if !rest.is_empty() {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(XTrim {
+            key: extract_key(&args[0])?,
+            trim,
+        })
+    }
+}
+
+/// `XDEL key id [id ...]`
+#[derive(Debug, Clone)]
+pub struct XDel {
+    pub key: Key,
+    pub ids: Vec<StreamId>,
+}
+
+impl TryFrom<&[Resp]> for XDel {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(XDel {
+            key: extract_key(&args[0])?,
+            ids: args[1..]
+                .iter()
+                .map(StreamId::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Where an `XREAD` or `XREADGROUP` starts reading a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XReadId {
+    /// Entries after this ID. For `XREADGROUP`, the consumer's pending
+    /// entries after it.
+    After(StreamId),
+    /// `$`: only entries added from now on. `XREAD` only.
+    New,
+    /// `>`: entries never delivered to the group. `XREADGROUP` only.
+    Undelivered,
+}
+
+/// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`
+#[derive(Debug, Clone)]
+pub struct XRead {
+    pub count: Option<usize>,
+    pub block: bool,
+    /// How long to block for. `None` blocks forever.
+    pub timeout: Option<Duration>,
+    pub keys: Vec<Key>,
+    pub ids: Vec<XReadId>,
+}
+
+impl TryFrom<&[Resp]> for XRead {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        let (read, _) = XRead::parse(args, false)?;
+        Ok(read)
+    }
+}
+
+impl XRead {
+    /// Parses the options and streams shared by `XREAD` and `XREADGROUP`.
+    /// `NOACK` is only accepted for a group read and returned separately.
+    fn parse(args: &[Resp], group: bool) -> Result<(XRead, bool), CommandError> {
+        expect_min_args(args, 3)?;
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        let mut rest = args;
+        loop {
+            let [opt, tail @ ..] = rest else {
+                return Err(CommandError::SyntaxError);
+            };
+            match extract_string(opt)?.to_uppercase().as_str() {
+                "STREAMS" => {
+                    rest = tail;
+                    break;
+                }
+                "COUNT" => {
+                    let (n, tail) = tail.split_first().ok_or(CommandError::SyntaxError)?;
+                    count = Some(extract_positive(n)?).filter(|&n| n > 0);
+                    rest = tail;
+                }
+                "BLOCK" => {
+                    let (ms, tail) = tail.split_first().ok_or(CommandError::SyntaxError)?;
+                    match extract_integer(ms)? {
+                        ms if ms < 0 => return Err(CommandError::NegativeTimeout),
+                        0 => block = Some(None),
+                        ms => block = Some(Some(Duration::from_millis(ms as u64))),
+                    }
+                    rest = tail;
+                }
+                "NOACK" if group => {
+                    noack = true;
+                    rest = tail;
+                }
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        if rest.is_empty() || !rest.len().is_multiple_of(2) {
+            return Err(CommandError::UnbalancedStreams);
+        }
+        let (keys, ids) = rest.split_at(rest.len() / 2);
+        let ids = ids
+            .iter()
+            .map(|id| match extract_string(id)?.as_str() {
+                "$" if group => Err(CommandError::NewIdInXReadGroup),
+                "$" => Ok(XReadId::New),
+                ">" if group => Ok(XReadId::Undelivered),
+                id => StreamId::parse(id, 0).map(XReadId::After),
+            })
+            .collect::<Result<_, CommandError>>()?;
+        let read = XRead {
+            count,
+            block: block.is_some(),
+            timeout: block.flatten(),
+            keys: extract_keys(keys)?,
+            ids,
+        };
+        Ok((read, noack))
+    }
+}
+
+/// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds]
+/// [NOACK] STREAMS key [key ...] id [id ...]`
+#[derive(Debug, Clone)]
+pub struct XReadGroup {
+    pub group: Key,
+    pub consumer: Key,
+    /// Deliver without adding to the pending entries list.
+    pub noack: bool,
+    pub read: XRead,
+}
+
+impl TryFrom<&[Resp]> for XReadGroup {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 6)?;
+        if !extract_string(&args[0])?.eq_ignore_ascii_case("GROUP") {
+            return Err(CommandError::SyntaxError);
+        }
+        let (read, noack) = XRead::parse(&args[3..], true)?;
+        Ok(XReadGroup {
+            group: extract_key(&args[1])?,
+            consumer: extract_key(&args[2])?,
+            noack,
+            read,
+        })
+    }
+}
+
+/// `XGROUP CREATE|SETID|DESTROY|CREATECONSUMER|DELCONSUMER ...`
+#[derive(Debug, Clone)]
+pub enum XGroup {
+    /// `XGROUP CREATE key group id|$ [MKSTREAM] [ENTRIESREAD entries-read]`
+    Create {
+        key: Key,
+        group: Key,
+        /// The group's last delivered ID, or `New` for the stream's last ID.
+        id: XReadId,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    },
+    /// `XGROUP SETID key group id|$ [ENTRIESREAD entries-read]`
+    SetId {
+        key: Key,
+        group: Key,
+        id: XReadId,
+        entries_read: Option<u64>,
+    },
+    /// `XGROUP DESTROY key group`
+    Destroy { key: Key, group: Key },
+    /// `XGROUP CREATECONSUMER key group consumer`
+    CreateConsumer { key: Key, group: Key, consumer: Key },
+    /// `XGROUP DELCONSUMER key group consumer`
+    DelConsumer { key: Key, group: Key, consumer: Key },
+}
+
+impl TryFrom<&[Resp]> for XGroup {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let key = extract_key(&args[1])?;
+        let group = extract_key(&args[2])?;
+        let args = &args[3..];
+        match subcommand.as_str() {
+            "CREATE" | "SETID" => {
+                let create = subcommand == "CREATE";
+                let (id, mut rest) = args.split_first().ok_or(CommandError::SyntaxError)?;
+                let id = match extract_string(id)?.as_str() {
+                    "$" => XReadId::New,
+                    _ => XReadId::After(id.try_into()?),
+                };
+                let mut mkstream = false;
+                let mut entries_read = None;
+                while let [opt, tail @ ..] = rest {
+                    match extract_string(opt)?.to_uppercase().as_str() {
+                        "MKSTREAM" if create => {
+                            mkstream = true;
+                            rest = tail;
+                        }
+                        "ENTRIESREAD" => {
+                            let (n, tail) = tail.split_first().ok_or(CommandError::SyntaxError)?;
+                            entries_read = Some(extract_positive(n)? as u64);
+                            rest = tail;
+                        }
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                }
+                Ok(match create {
+                    true => XGroup::Create {
+                        key,
+                        group,
+                        id,
+                        mkstream,
+                        entries_read,
+                    },
+                    false => XGroup::SetId {
+                        key,
+                        group,
+                        id,
+                        entries_read,
+                    },
+                })
+            }
+            "DESTROY" => {
+                expect_args(args, 0)?;
+                Ok(XGroup::Destroy { key, group })
+            }
+            "CREATECONSUMER" | "DELCONSUMER" => {
+                expect_args(args, 1)?;
+                let consumer = extract_key(&args[0])?;
+                Ok(match subcommand.as_str() {
+                    "CREATECONSUMER" => XGroup::CreateConsumer {
+                        key,
+                        group,
+                        consumer,
+                    },
+                    _ => XGroup::DelConsumer {
+                        key,
+                        group,
+                        consumer,
+                    },
+                })
+            }
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+/// `XACK key group id [id ...]`
+#[derive(Debug, Clone)]
+pub struct XAck {
+    pub key: Key,
+    pub group: Key,
+    pub ids: Vec<StreamId>,
+}
+
+impl TryFrom<&[Resp]> for XAck {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        Ok(XAck {
+            key: extract_key(&args[0])?,
+            group: extract_key(&args[1])?,
+            ids: args[2..]
+                .iter()
+                .map(StreamId::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// The extended form of `XPENDING`: `[IDLE min-idle-time] start end count
+/// [consumer]`.
+#[derive(Debug, Clone)]
+pub struct XPendingRange {
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<Key>,
+}
+
+/// `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`
+#[derive(Debug, Clone)]
+pub struct XPending {
+    pub key: Key,
+    pub group: Key,
+    /// Without a range, `XPENDING` replies with a summary.
+    pub range: Option<XPendingRange>,
+}
+
+impl TryFrom<&[Resp]> for XPending {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let mut rest = &args[2..];
+        let mut min_idle = 0;
+        if let [opt, idle, tail @ ..] = rest {
+            if extract_string(opt)?.eq_ignore_ascii_case("IDLE") {
+                min_idle = extract_integer(idle)?.max(0) as u64;
+                rest = tail;
+                if rest.is_empty() {
+                    return Err(CommandError::SyntaxError);
+                }
+            }
+        }
+        let range = match rest {
+            [] => None,
+            [start, end, count, consumer @ ..] if consumer.len() <= 1 => Some(XPendingRange {
+                min_idle,
+                start: range_bound(start, false)?,
+                end: range_bound(end, true)?,
+                count: extract_integer(count)?.max(0) as usize,
+                consumer: consumer.first().map(extract_key).transpose()?,
+            }),
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(XPending {
+            key: extract_key(&args[0])?,
+            group: extract_key(&args[1])?,
+            range,
+        })
+    }
+}
+
+/// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+/// [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID]
+/// [LASTID lastid]`
+#[derive(Debug, Clone)]
+pub struct XClaim {
+    pub key: Key,
+    pub group: Key,
+    pub consumer: Key,
+    pub min_idle: u64,
+    pub ids: Vec<StreamId>,
+    /// Sets the idle time of the claimed entries instead of resetting it.
+    pub idle: Option<u64>,
+    /// Sets the delivery time of the claimed entries, as a Unix time in ms.
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    /// Claims IDs that exist in the stream but aren't pending.
+    pub force: bool,
+    /// Replies with IDs only and leaves delivery counts alone.
+    pub justid: bool,
+    pub last_id: Option<StreamId>,
+}
+
+impl TryFrom<&[Resp]> for XClaim {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 5)?;
+        let mut claim = XClaim {
+            key: extract_key(&args[0])?,
+            group: extract_key(&args[1])?,
+            consumer: extract_key(&args[2])?,
+            min_idle: extract_integer(&args[3])?.max(0) as u64,
+            ids: Vec::new(),
+            idle: None,
+            time: None,
+            retry_count: None,
+            force: false,
+            justid: false,
+            last_id: None,
+        };
+        let mut rest = &args[4..];
+        while let [id, tail @ ..] = rest {
+            match StreamId::try_from(id) {
+                Ok(id) => claim.ids.push(id),
+                Err(_) => break,
+            }
+            rest = tail;
+        }
+        if claim.ids.is_empty() {
+            return Err(CommandError::InvalidStreamId);
+        }
+        while let [opt, tail @ ..] = rest {
+            let opt = extract_string(opt)?.to_uppercase();
+            match opt.as_str() {
+                "FORCE" => claim.force = true,
+                "JUSTID" => claim.justid = true,
+                _ => {
+                    let (value, tail) = tail.split_first().ok_or(CommandError::SyntaxError)?;
+                    match opt.as_str() {
+                        "IDLE" => claim.idle = Some(extract_integer(value)?.max(0) as u64),
+                        "TIME" => claim.time = Some(extract_integer(value)?.max(0) as u64),
+                        "RETRYCOUNT" => claim.retry_count = Some(extract_positive(value)? as u64),
+                        "LASTID" => claim.last_id = Some(value.try_into()?),
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                    rest = tail;
+                    continue;
+                }
+            }
+            rest = tail;
+        }
+        Ok(claim)
+    }
+}
+
+/// `XAUTOCLAIM key group consumer min-idle-time start [COUNT count]
+/// [JUSTID]`
+#[derive(Debug, Clone)]
+pub struct XAutoClaim {
+    pub key: Key,
+    pub group: Key,
+    pub consumer: Key,
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub count: usize,
+    pub justid: bool,
+}
+
+impl TryFrom<&[Resp]> for XAutoClaim {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 5)?;
+        let mut count = 100;
+        let mut justid = false;
+        let mut rest = &args[5..];
+        while let [opt, tail @ ..] = rest {
+            match extract_string(opt)?.to_uppercase().as_str() {
+                "JUSTID" => {
+                    justid = true;
+                    rest = tail;
+                }
+                "COUNT" => {
+                    let (n, tail) = tail.split_first().ok_or(CommandError::SyntaxError)?;
+                    count = match extract_integer(n)? {
+                        n if n < 1 => return Err(CommandError::InvalidCount),
+                        n => n as usize,
+                    };
+                    rest = tail;
+                }
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(XAutoClaim {
+            key: extract_key(&args[0])?,
+            group: extract_key(&args[1])?,
+            consumer: extract_key(&args[2])?,
+            min_idle: extract_integer(&args[3])?.max(0) as u64,
+            start: range_bound(&args[4], false)?,
+            count,
+            justid,
+        })
+    }
+}
+
+/// `XINFO STREAM key`, `XINFO GROUPS key` and `XINFO CONSUMERS key group`.
+#[derive(Debug, Clone)]
+pub enum XInfo {
+    Stream { key: Key },
+    Groups { key: Key },
+    Consumers { key: Key, group: Key },
+}
+
+impl TryFrom<&[Resp]> for XInfo {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let subcommand = extract_string(&args[0])?.to_uppercase();
+        let key = extract_key(&args[1])?;
+        match subcommand.as_str() {
+            "STREAM" => {
+                expect_args(args, 2)?;
+                Ok(XInfo::Stream { key })
+            }
+            "GROUPS" => {
+                expect_args(args, 2)?;
+                Ok(XInfo::Groups { key })
+            }
+            "CONSUMERS" => {
+                expect_args(args, 3)?;
+                Ok(XInfo::Consumers {
+                    key,
+                    group: extract_key(&args[2])?,
+                })
+            }
+            _ => Err(CommandError::UnknownSubcommand(subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_xadd() {
+        match command(&[
+            "XADD",
+            "s",
+            "NOMKSTREAM",
+            "MAXLEN",
+            "~",
+            "10",
+            "LIMIT",
+            "5",
+            "1-*",
+            "f",
+            "v",
+        ])
+        .unwrap()
+        {
+            Command::XAdd(c) => {
+                assert!(c.nomkstream);
+                assert_eq!(
+                    c.trim,
+                    Some(Trim {
+                        strategy: TrimStrategy::MaxLen(10),
+                        approximate: true,
+                        limit: Some(5),
+                    })
+                );
+                assert_eq!(c.id, XAddId::AutoSeq(1));
+                assert_eq!(c.fields.len(), 2);
+            }
+            _ => panic!("Expected XAdd"),
+        }
+        match command(&["XADD", "s", "MINID", "5", "7", "f", "v"]).unwrap() {
+            Command::XAdd(c) => {
+                assert_eq!(
+                    c.trim.unwrap().strategy,
+                    TrimStrategy::MinId(StreamId::new(5, 0))
+                );
+                assert_eq!(c.id, XAddId::Explicit(StreamId::new(7, 0)));
+            }
+            _ => panic!("Expected XAdd"),
+        }
+        assert_eq!(
+            command(&["XADD", "s", "0-0", "f", "v"]).unwrap_err(),
+            CommandError::XAddZeroId
+        );
+        assert_eq!(
+            command(&["XADD", "s", "1-x", "f", "v"]).unwrap_err(),
+            CommandError::InvalidStreamId
+        );
+        assert_eq!(
+            command(&["XADD", "s", "MAXLEN", "1", "LIMIT", "5", "*", "f", "v"]).unwrap_err(),
+            CommandError::LimitWithoutApprox
+        );
+        assert!(command(&["XADD", "s", "*", "f"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_xrange() {
+        match command(&["XREVRANGE", "s", "+", "(5-3", "COUNT", "2"]).unwrap() {
+            Command::XRevRange(c) => {
+                assert_eq!(c.start, StreamId::new(5, 4));
+                assert_eq!(c.end, StreamId::MAX);
+                assert_eq!(c.count, Some(2));
+            }
+            _ => panic!("Expected XRevRange"),
+        }
+        match command(&["XRANGE", "s", "5", "(6-0"]).unwrap() {
+            Command::XRange(c) => {
+                assert_eq!(c.start, StreamId::new(5, 0));
+                assert_eq!(c.end, StreamId::new(5, u64::MAX));
+            }
+            _ => panic!("Expected XRange"),
+        }
+        assert_eq!(
+            command(&[
+                "XRANGE",
+                "s",
+                "(18446744073709551615-18446744073709551615",
+                "+"
+            ])
+            .unwrap_err(),
+            CommandError::InvalidStreamId
+        );
+    }
+
+    #[test]
+    fn test_parse_xread() {
+        match command(&[
+            "XREAD", "COUNT", "2", "BLOCK", "100", "STREAMS", "a", "b", "$", "1-1",
+        ])
+        .unwrap()
+        {
+            Command::XRead(c) => {
+                assert_eq!(c.count, Some(2));
+                assert!(c.block);
+                assert_eq!(c.timeout, Some(Duration::from_millis(100)));
+                assert_eq!(c.keys.len(), 2);
+                assert_eq!(
+                    c.ids,
+                    vec![XReadId::New, XReadId::After(StreamId::new(1, 1))]
+                );
+            }
+            _ => panic!("Expected XRead"),
+        }
+        match command(&["XREAD", "BLOCK", "0", "STREAMS", "a", "0"]).unwrap() {
+            Command::XRead(c) => assert!(c.block && c.timeout.is_none()),
+            _ => panic!("Expected XRead"),
+        }
+        assert_eq!(
+            command(&["XREAD", "STREAMS", "a", "b", "0"]).unwrap_err(),
+            CommandError::UnbalancedStreams
+        );
+        assert_eq!(
+            command(&["XREAD", "BLOCK", "-1", "STREAMS", "a", "0"]).unwrap_err(),
+            CommandError::NegativeTimeout
+        );
+    }
+
+    #[test]
+    fn test_parse_xreadgroup() {
+        match command(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "NOACK",
+            "COUNT",
+            "1",
+            "STREAMS",
+            "a",
+            "b",
+            ">",
+            "0",
+        ])
+        .unwrap()
+        {
+            Command::XReadGroup(c) => {
+                assert!(c.noack);
+                assert_eq!(c.read.count, Some(1));
+                assert_eq!(
+                    c.read.ids,
+                    vec![XReadId::Undelivered, XReadId::After(StreamId::MIN)]
+                );
+            }
+            _ => panic!("Expected XReadGroup"),
+        }
+        assert_eq!(
+            command(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "a", "$"]).unwrap_err(),
+            CommandError::NewIdInXReadGroup
+        );
+        assert_eq!(
+            command(&["XREAD", "NOACK", "STREAMS", "a", "0"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+
+    #[test]
+    fn test_parse_xgroup() {
+        match command(&[
+            "XGROUP",
+            "CREATE",
+            "s",
+            "g",
+            "$",
+            "MKSTREAM",
+            "ENTRIESREAD",
+            "3",
+        ])
+        .unwrap()
+        {
+            Command::XGroup(XGroup::Create {
+                id,
+                mkstream,
+                entries_read,
+                ..
+            }) => {
+                assert_eq!(id, XReadId::New);
+                assert!(mkstream);
+                assert_eq!(entries_read, Some(3));
+            }
+            _ => panic!("Expected XGROUP CREATE"),
+        }
+        assert_eq!(
+            command(&["XGROUP", "SETID", "s", "g", "0", "MKSTREAM"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["XGROUP", "NOPE", "s", "g"]).unwrap_err(),
+            CommandError::UnknownSubcommand("NOPE".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_claims() {
+        match command(&[
+            "XCLAIM", "s", "g", "c", "10", "1-0", "2", "IDLE", "5", "FORCE", "JUSTID", "LASTID",
+            "3-0",
+        ])
+        .unwrap()
+        {
+            Command::XClaim(c) => {
+                assert_eq!(c.ids, vec![StreamId::new(1, 0), StreamId::new(2, 0)]);
+                assert_eq!(c.idle, Some(5));
+                assert!(c.force && c.justid);
+                assert_eq!(c.last_id, Some(StreamId::new(3, 0)));
+            }
+            _ => panic!("Expected XClaim"),
+        }
+        assert_eq!(
+            command(&["XAUTOCLAIM", "s", "g", "c", "10", "0", "COUNT", "0"]).unwrap_err(),
+            CommandError::InvalidCount
+        );
+        match command(&["XPENDING", "s", "g", "IDLE", "9", "-", "+", "10", "c"]).unwrap() {
+            Command::XPending(c) => {
+                let range = c.range.unwrap();
+                assert_eq!(range.min_idle, 9);
+                assert_eq!(range.count, 10);
+                assert!(range.consumer.is_some());
+            }
+            _ => panic!("Expected XPending"),
+        }
+        assert_eq!(
+            command(&["XPENDING", "s", "g", "-", "+"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+}
@@ -0,0 +1,18 @@
+use super::{expect_min_args, extract_keys, CommandError};
+use crate::resp::{Key, Resp};
+
+/// `WATCH key [key ...]`
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub keys: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for Watch {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        Ok(Watch {
+            keys: extract_keys(args)?,
+        })
+    }
+}
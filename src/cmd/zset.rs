@@ -0,0 +1,661 @@
+use super::{
+    expect_args, expect_min_args, extract_float, extract_integer, extract_key, extract_keys,
+    extract_numkeys, extract_positive, extract_string, extract_timeout, CommandError, ScanOptions,
+};
+use crate::resp::{BulkString, Key, Resp};
+use std::time::Duration;
+
+/// `ZADD`'s NX and XX flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZAddCondition {
+    /// Only add new members.
+    Nx,
+    /// Only update existing members.
+    Xx,
+}
+
+/// `ZADD`'s GT and LT flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZAddComparison {
+    Gt,
+    Lt,
+}
+
+/// `ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]`
+#[derive(Debug, Clone)]
+pub struct ZAdd {
+    pub key: Key,
+    pub condition: Option<ZAddCondition>,
+    pub comparison: Option<ZAddComparison>,
+    pub ch: bool,
+    pub incr: bool,
+    pub pairs: Vec<(f64, Key)>,
+}
+
+impl TryFrom<&[Resp]> for ZAdd {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+            (false, false, false, false, false, false);
+        let mut rest = &args[1..];
+        while let Some((arg, tail)) = rest.split_first() {
+            match extract_string(arg)?.to_uppercase().as_str() {
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "GT" => gt = true,
+                "LT" => lt = true,
+                "CH" => ch = true,
+                "INCR" => incr = true,
+                _ => break,
+            }
+            rest = tail;
+        }
+        if rest.is_empty() || !rest.len().is_multiple_of(2) {
+            return Err(CommandError::SyntaxError);
+        }
+        if nx && xx {
+            return Err(CommandError::ZAddNxWithXx);
+        }
+        if (gt && lt) || (nx && (gt || lt)) {
+            return Err(CommandError::ZAddNxWithComparison);
+        }
+        let pairs: Vec<_> = rest
+            .chunks(2)
+            .map(|pair| Ok((extract_float(&pair[0])?, extract_key(&pair[1])?)))
+            .collect::<Result<_, CommandError>>()?;
+        if incr && pairs.len() > 1 {
+            return Err(CommandError::ZAddIncrPairs);
+        }
+        Ok(ZAdd {
+            key: extract_key(&args[0])?,
+            condition: match (nx, xx) {
+                (true, _) => Some(ZAddCondition::Nx),
+                (_, true) => Some(ZAddCondition::Xx),
+                _ => None,
+            },
+            comparison: match (gt, lt) {
+                (true, _) => Some(ZAddComparison::Gt),
+                (_, true) => Some(ZAddComparison::Lt),
+                _ => None,
+            },
+            ch,
+            incr,
+            pairs,
+        })
+    }
+}
+
+/// One end of a score range: `1.5`, `(1.5`, `-inf` or `+inf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(arg: &Resp) -> Result<Self, CommandError> {
+        let s = extract_string(arg).map_err(|_| CommandError::InvalidScoreRange)?;
+        let (s, exclusive) = match s.strip_prefix('(') {
+            Some(s) => (s, true),
+            None => (s.as_str(), false),
+        };
+        match s.parse::<f64>() {
+            Ok(score) if !score.is_nan() => Ok(ScoreBound { score, exclusive }),
+            _ => Err(CommandError::InvalidScoreRange),
+        }
+    }
+
+    /// Whether `score` lies below this bound taken as a minimum.
+    pub fn below_min(&self, score: f64) -> bool {
+        score < self.score || (self.exclusive && score == self.score)
+    }
+
+    /// Whether `score` lies within this bound taken as a maximum.
+    pub fn within_max(&self, score: f64) -> bool {
+        score < self.score || (!self.exclusive && score == self.score)
+    }
+}
+
+/// One end of a lexicographical range: `[a`, `(a`, `-` or `+`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    /// `-`, below every member.
+    Min,
+    /// `+`, above every member.
+    Max,
+    Inclusive(Key),
+    Exclusive(Key),
+}
+
+impl LexBound {
+    fn parse(arg: &Resp) -> Result<Self, CommandError> {
+        let s = extract_string(arg).map_err(|_| CommandError::InvalidLexRange)?;
+        let member = |s: &str| Key::BulkString(BulkString::new(s, false));
+        match s.split_at_checked(1) {
+            Some(("-", "")) => Ok(LexBound::Min),
+            Some(("+", "")) => Ok(LexBound::Max),
+            Some(("[", m)) => Ok(LexBound::Inclusive(member(m))),
+            Some(("(", m)) => Ok(LexBound::Exclusive(member(m))),
+            _ => Err(CommandError::InvalidLexRange),
+        }
+    }
+
+    /// Whether `member` lies below this bound taken as a minimum.
+    pub fn below_min(&self, member: &Key) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(m) => member < m,
+            LexBound::Exclusive(m) => member <= m,
+        }
+    }
+
+    /// Whether `member` lies within this bound taken as a maximum.
+    pub fn within_max(&self, member: &Key) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(m) => member <= m,
+            LexBound::Exclusive(m) => member < m,
+        }
+    }
+}
+
+/// What a `ZRANGE` selects on. Score and lex bounds are stored as
+/// `(min, max)` whatever order REV takes them in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZRangeBy {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// `ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count]
+/// [WITHSCORES]`
+#[derive(Debug, Clone)]
+pub struct ZRange {
+    pub key: Key,
+    pub by: ZRangeBy,
+    pub rev: bool,
+    pub limit: Option<(i64, i64)>,
+    pub with_scores: bool,
+}
+
+impl TryFrom<&[Resp]> for ZRange {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 3)?;
+        let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+        let mut limit = None;
+        let mut iter = args[3..].iter();
+        while let Some(arg) = iter.next() {
+            match extract_string(arg)?.to_uppercase().as_str() {
+                "BYSCORE" => by_score = true,
+                "BYLEX" => by_lex = true,
+                "REV" => rev = true,
+                "WITHSCORES" => with_scores = true,
+                "LIMIT" => {
+                    let offset = iter.next().ok_or(CommandError::SyntaxError)?;
+                    let count = iter.next().ok_or(CommandError::SyntaxError)?;
+                    limit = Some((extract_integer(offset)?, extract_integer(count)?));
+                }
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        let (start, stop) = match rev {
+            true if by_score || by_lex => (&args[2], &args[1]),
+            _ => (&args[1], &args[2]),
+        };
+        let by = match (by_score, by_lex) {
+            (true, true) => return Err(CommandError::SyntaxError),
+            (true, false) => ZRangeBy::Score(ScoreBound::parse(start)?, ScoreBound::parse(stop)?),
+            (false, true) => ZRangeBy::Lex(LexBound::parse(start)?, LexBound::parse(stop)?),
+            (false, false) => {
+                if limit.is_some() {
+                    return Err(CommandError::LimitWithoutBy);
+                }
+                ZRangeBy::Rank(extract_integer(start)?, extract_integer(stop)?)
+            }
+        };
+        if by_lex && with_scores {
+            return Err(CommandError::WithScoresByLex);
+        }
+        Ok(ZRange {
+            key: extract_key(&args[0])?,
+            by,
+            rev,
+            limit,
+            with_scores,
+        })
+    }
+}
+
+/// `ZRANK key member [WITHSCORE]` and `ZREVRANK`.
+#[derive(Debug, Clone)]
+pub struct ZRank {
+    pub key: Key,
+    pub member: Key,
+    pub with_score: bool,
+}
+
+impl TryFrom<&[Resp]> for ZRank {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        let with_score = match &args[2..] {
+            [] => false,
+            [arg] if extract_string(arg)?.eq_ignore_ascii_case("WITHSCORE") => true,
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(ZRank {
+            key: extract_key(&args[0])?,
+            member: extract_key(&args[1])?,
+            with_score,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZScore {
+    pub key: Key,
+    pub member: Key,
+}
+
+impl TryFrom<&[Resp]> for ZScore {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 2)?;
+        Ok(ZScore {
+            key: extract_key(&args[0])?,
+            member: extract_key(&args[1])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZIncrBy {
+    pub key: Key,
+    pub increment: f64,
+    pub member: Key,
+}
+
+impl TryFrom<&[Resp]> for ZIncrBy {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(ZIncrBy {
+            key: extract_key(&args[0])?,
+            increment: extract_float(&args[1])?,
+            member: extract_key(&args[2])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZRem {
+    pub key: Key,
+    pub members: Vec<Key>,
+}
+
+impl TryFrom<&[Resp]> for ZRem {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(ZRem {
+            key: extract_key(&args[0])?,
+            members: extract_keys(&args[1..])?,
+        })
+    }
+}
+
+/// `ZCOUNT key min max`
+#[derive(Debug, Clone)]
+pub struct ZCount {
+    pub key: Key,
+    pub min: ScoreBound,
+    pub max: ScoreBound,
+}
+
+impl TryFrom<&[Resp]> for ZCount {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 3)?;
+        Ok(ZCount {
+            key: extract_key(&args[0])?,
+            min: ScoreBound::parse(&args[1])?,
+            max: ScoreBound::parse(&args[2])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZCard {
+    pub key: Key,
+}
+
+impl TryFrom<&[Resp]> for ZCard {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_args(args, 1)?;
+        Ok(ZCard {
+            key: extract_key(&args[0])?,
+        })
+    }
+}
+
+/// `ZPOPMIN key [count]` and `ZPOPMAX`.
+#[derive(Debug, Clone)]
+pub struct ZPop {
+    pub key: Key,
+    pub count: Option<usize>,
+}
+
+impl TryFrom<&[Resp]> for ZPop {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 1)?;
+        if args.len() > 2 {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(ZPop {
+            key: extract_key(&args[0])?,
+            count: args.get(1).map(extract_positive).transpose()?,
+        })
+    }
+}
+
+/// `BZMPOP timeout numkeys key [key ...] MIN|MAX [COUNT count]`
+#[derive(Debug, Clone)]
+pub struct BZMPop {
+    pub timeout: Option<Duration>,
+    pub keys: Vec<Key>,
+    /// Pops the highest scores rather than the lowest.
+    pub max: bool,
+    pub count: usize,
+}
+
+impl TryFrom<&[Resp]> for BZMPop {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 4)?;
+        let timeout = extract_timeout(&args[0])?;
+        let (keys, rest) = extract_numkeys(&args[1..])?;
+        let (end, rest) = rest.split_first().ok_or(CommandError::SyntaxError)?;
+        let max = match extract_string(end)?.to_uppercase().as_str() {
+            "MIN" => false,
+            "MAX" => true,
+            _ => return Err(CommandError::SyntaxError),
+        };
+        let count = match rest {
+            [] => 1,
+            [opt, count] if extract_string(opt)?.eq_ignore_ascii_case("COUNT") => {
+                match extract_positive(count)? {
+                    0 => return Err(CommandError::InvalidCount),
+                    n => n,
+                }
+            }
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(BZMPop {
+            timeout,
+            keys,
+            max,
+            count,
+        })
+    }
+}
+
+/// `ZSCAN key cursor [MATCH pattern] [COUNT count]`
+#[derive(Debug, Clone)]
+pub struct ZScan {
+    pub key: Key,
+    pub cursor: u64,
+    pub options: ScanOptions,
+}
+
+impl TryFrom<&[Resp]> for ZScan {
+    type Error = CommandError;
+    fn try_from(args: &[Resp]) -> Result<Self, Self::Error> {
+        expect_min_args(args, 2)?;
+        Ok(ZScan {
+            key: extract_key(&args[0])?,
+            cursor: super::extract_cursor(&args[1])?,
+            options: ScanOptions::parse(&args[2..], false)?,
+        })
+    }
+}
+
+/// How `ZUNION` and `ZINTER` combine the scores of a member found in several
+/// inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+/// `ZUNIONSTORE destination numkeys key [key ...] [WEIGHTS weight ...]
+/// [AGGREGATE SUM|MIN|MAX]`, `ZINTERSTORE` and `ZDIFFSTORE`, and their
+/// variants without a destination, which take `WITHSCORES` instead.
+#[derive(Debug, Clone)]
+pub struct ZSetOp {
+    pub destination: Option<Key>,
+    pub keys: Vec<Key>,
+    pub weights: Vec<f64>,
+    pub aggregate: Aggregate,
+    pub with_scores: bool,
+}
+
+impl ZSetOp {
+    /// Parses the arguments of a command that stores its result if `store`
+    /// is set, and accepts `WEIGHTS` and `AGGREGATE` if `weighted` is.
+    pub fn parse(args: &[Resp], store: bool, weighted: bool) -> Result<Self, CommandError> {
+        let (destination, args) = match store {
+            true => {
+                let (destination, rest) = args.split_first().ok_or(CommandError::SyntaxError)?;
+                (Some(extract_key(destination)?), rest)
+            }
+            false => (None, args),
+        };
+        let (keys, rest) = extract_numkeys(args)?;
+        let mut op = ZSetOp {
+            destination,
+            weights: vec![1.0; keys.len()],
+            keys,
+            aggregate: Aggregate::Sum,
+            with_scores: false,
+        };
+        let mut iter = rest.iter();
+        while let Some(arg) = iter.next() {
+            match extract_string(arg)?.to_uppercase().as_str() {
+                "WEIGHTS" if weighted => {
+                    for weight in op.weights.iter_mut() {
+                        let arg = iter.next().ok_or(CommandError::SyntaxError)?;
+                        *weight = extract_float(arg).map_err(|_| CommandError::InvalidWeight)?;
+                    }
+                }
+                "AGGREGATE" if weighted => {
+                    let arg = iter.next().ok_or(CommandError::SyntaxError)?;
+                    op.aggregate = match extract_string(arg)?.to_uppercase().as_str() {
+                        "SUM" => Aggregate::Sum,
+                        "MIN" => Aggregate::Min,
+                        "MAX" => Aggregate::Max,
+                        _ => return Err(CommandError::SyntaxError),
+                    };
+                }
+                "WITHSCORES" if !store => op.with_scores = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{parse_command as command, Command};
+
+    #[test]
+    fn test_parse_zadd() {
+        match command(&["ZADD", "z", "XX", "GT", "CH", "1", "a", "-inf", "b"]).unwrap() {
+            Command::ZAdd(c) => {
+                assert_eq!(c.condition, Some(ZAddCondition::Xx));
+                assert_eq!(c.comparison, Some(ZAddComparison::Gt));
+                assert!(c.ch && !c.incr);
+                assert_eq!(c.pairs.len(), 2);
+                assert_eq!(c.pairs[1].0, f64::NEG_INFINITY);
+            }
+            _ => panic!("Expected ZAdd"),
+        }
+        assert_eq!(
+            command(&["ZADD", "z", "NX", "XX", "1", "a"]).unwrap_err(),
+            CommandError::ZAddNxWithXx
+        );
+        assert_eq!(
+            command(&["ZADD", "z", "NX", "LT", "1", "a"]).unwrap_err(),
+            CommandError::ZAddNxWithComparison
+        );
+        assert_eq!(
+            command(&["ZADD", "z", "INCR", "1", "a", "2", "b"]).unwrap_err(),
+            CommandError::ZAddIncrPairs
+        );
+        assert_eq!(
+            command(&["ZADD", "z", "1", "a", "2"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["ZADD", "z", "nan", "a"]).unwrap_err(),
+            CommandError::NotAFloat
+        );
+    }
+
+    #[test]
+    fn test_parse_zrange() {
+        match command(&[
+            "ZRANGE", "z", "(5", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2",
+        ])
+        .unwrap()
+        {
+            Command::ZRange(c) => {
+                let ZRangeBy::Score(min, max) = c.by else {
+                    panic!("Expected a score range");
+                };
+                assert_eq!(min.score, f64::NEG_INFINITY);
+                assert_eq!(max.score, 5.0);
+                assert!(max.exclusive);
+                assert!(c.rev);
+                assert_eq!(c.limit, Some((1, 2)));
+            }
+            _ => panic!("Expected ZRange"),
+        }
+        match command(&["ZRANGE", "z", "[a", "+", "BYLEX"]).unwrap() {
+            Command::ZRange(c) => assert!(matches!(
+                c.by,
+                ZRangeBy::Lex(LexBound::Inclusive(_), LexBound::Max)
+            )),
+            _ => panic!("Expected ZRange"),
+        }
+        assert_eq!(
+            command(&["ZRANGE", "z", "a", "b", "BYLEX"]).unwrap_err(),
+            CommandError::InvalidLexRange
+        );
+        assert_eq!(
+            command(&["ZRANGE", "z", "x", "1", "BYSCORE"]).unwrap_err(),
+            CommandError::InvalidScoreRange
+        );
+        assert_eq!(
+            command(&["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"]).unwrap_err(),
+            CommandError::LimitWithoutBy
+        );
+        assert_eq!(
+            command(&["ZRANGE", "z", "-", "+", "BYLEX", "WITHSCORES"]).unwrap_err(),
+            CommandError::WithScoresByLex
+        );
+    }
+
+    #[test]
+    fn test_parse_zsetop() {
+        let args = [
+            "ZUNIONSTORE",
+            "dest",
+            "2",
+            "a",
+            "b",
+            "WEIGHTS",
+            "2",
+            "0.5",
+            "AGGREGATE",
+            "max",
+        ];
+        match command(&args).unwrap() {
+            Command::ZUnionStore(c) => {
+                assert_eq!(
+                    c.destination,
+                    Some(Key::BulkString(BulkString::new("dest", false)))
+                );
+                assert_eq!(c.weights, vec![2.0, 0.5]);
+                assert_eq!(c.aggregate, Aggregate::Max);
+            }
+            _ => panic!("Expected ZUnionStore"),
+        }
+        match command(&["ZINTER", "1", "a", "WITHSCORES"]).unwrap() {
+            Command::ZInter(c) => assert!(c.destination.is_none() && c.with_scores),
+            _ => panic!("Expected ZInter"),
+        }
+        assert_eq!(
+            command(&["ZUNION", "2", "a", "b", "WEIGHTS", "1"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["ZUNION", "1", "a", "WEIGHTS", "x"]).unwrap_err(),
+            CommandError::InvalidWeight
+        );
+        assert_eq!(
+            command(&["ZDIFF", "1", "a", "AGGREGATE", "MIN"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["ZINTERSTORE", "dest", "1", "a", "WITHSCORES"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+    }
+
+    #[test]
+    fn test_parse_bzmpop() {
+        match command(&["BZMPOP", "0.5", "2", "a", "b", "max", "COUNT", "3"]).unwrap() {
+            Command::BZMPop(c) => {
+                assert_eq!(c.timeout, Some(Duration::from_millis(500)));
+                assert_eq!(c.keys.len(), 2);
+                assert!(c.max);
+                assert_eq!(c.count, 3);
+            }
+            _ => panic!("Expected BZMPop"),
+        }
+        assert_eq!(
+            command(&["BZMPOP", "0", "1", "a", "LEFT"]).unwrap_err(),
+            CommandError::SyntaxError
+        );
+        assert_eq!(
+            command(&["BZMPOP", "0", "1", "a", "MIN", "COUNT", "0"]).unwrap_err(),
+            CommandError::InvalidCount
+        );
+        assert_eq!(
+            command(&["BZPOPMIN", "a", "-1"]).unwrap_err(),
+            CommandError::NegativeTimeout
+        );
+    }
+
+    #[test]
+    fn test_score_bounds() {
+        let min = ScoreBound {
+            score: 1.0,
+            exclusive: true,
+        };
+        assert!(min.below_min(1.0));
+        assert!(!min.below_min(1.5));
+        assert!(min.within_max(0.5));
+        assert!(!min.within_max(1.0));
+    }
+}
@@ -1,31 +1,26 @@
 use crate::{
-    cmd::Command,
-    resp::{Resp, RespDeserializeError, Serialize},
+    cmd::{Command, CommandError},
+    resp::{Protocol, Resp, Serialize},
 };
 use bytes::BytesMut;
 use std::io;
 use tokio_util::codec::{Decoder, Encoder};
 
-pub struct Codec;
+#[derive(Debug, Default)]
+pub struct Codec {
+    pub protocol: Protocol,
+}
 
+/// Decodes commands. A frame that isn't a valid command still decodes, to
+/// the error to reply with, so the connection can carry on.
 impl Decoder for Codec {
-    type Item = Command;
+    type Item = Result<Command, CommandError>;
     type Error = io::Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let resp = Resp::try_from(buf);
-        match resp {
-            Ok(resp) => {
-                let cmd = Command::try_from(resp);
-                match cmd {
-                    Ok(cmd) => Ok(Some(cmd)),
-                    Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
-                }
-            }
-            Err(e) => match e {
-                RespDeserializeError::NotComplete => Ok(None),
-                _ => Err(io::Error::new(io::ErrorKind::Other, e)),
-            },
+        match Resp::decode(buf) {
+            Ok(resp) => Ok(resp.map(Command::try_from)),
+            Err(e) => Err(io::Error::other(e)),
         }
     }
 }
@@ -34,6 +29,10 @@ impl Encoder<Resp> for Codec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Resp, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let item = match self.protocol {
+            Protocol::Resp2 => item.into_resp2(),
+            Protocol::Resp3 => item,
+        };
         buf.extend(item.serialize());
         Ok(())
     }
@@ -0,0 +1,153 @@
+/// Redis-style glob matching, as used by `KEYS`, `SCAN ... MATCH` and
+/// pattern subscriptions.
+///
+/// Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` escapes.
+pub fn matches(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume when the rest fails to match: just past the last `*`,
+    // with that `*` swallowing one more byte. Later stars supersede earlier
+    // ones, so one point is enough and matching takes O(pattern * string).
+    let mut backtrack = None;
+    while p < pattern.len() || s < string.len() {
+        if p < pattern.len() {
+            if pattern[p] == b'*' {
+                p += 1;
+                backtrack = Some((p, s + 1));
+                continue;
+            }
+            if s < string.len() {
+                if let Some(next) = match_one(pattern, p, string[s], nocase) {
+                    p = next;
+                    s += 1;
+                    continue;
+                }
+            }
+        }
+        match backtrack {
+            Some((star, next)) if next <= string.len() => {
+                p = star;
+                s = next;
+                backtrack = Some((star, next + 1));
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Matches `c` against the pattern element at `p` other than `*`, returning
+/// where the next element starts.
+fn match_one(pattern: &[u8], mut p: usize, c: u8, nocase: bool) -> Option<usize> {
+    match pattern[p] {
+        b'?' => Some(p + 1),
+        b'[' => {
+            p += 1;
+            let not = p < pattern.len() && pattern[p] == b'^';
+            if not {
+                p += 1;
+            }
+            let mut matched = false;
+            loop {
+                if p >= pattern.len() {
+                    // Unterminated class: treat the end of the pattern as `]`.
+                    break;
+                }
+                match pattern[p] {
+                    b'\\' if p + 1 < pattern.len() => {
+                        p += 1;
+                        matched |= eq(pattern[p], c, nocase);
+                    }
+                    b']' => {
+                        p += 1;
+                        break;
+                    }
+                    start if p + 2 < pattern.len() && pattern[p + 1] == b'-' => {
+                        let (mut start, mut end) = (start, pattern[p + 2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        let mut c = c;
+                        if nocase {
+                            start = start.to_ascii_lowercase();
+                            end = end.to_ascii_lowercase();
+                            c = c.to_ascii_lowercase();
+                        }
+                        p += 2;
+                        matched |= start <= c && c <= end;
+                    }
+                    other => matched |= eq(other, c, nocase),
+                }
+                p += 1;
+            }
+            (matched != not).then_some(p)
+        }
+        b'\\' if p + 1 < pattern.len() => eq(pattern[p + 1], c, nocase).then_some(p + 2),
+        other => eq(other, c, nocase).then_some(p + 1),
+    }
+}
+
+fn eq(a: u8, b: u8, nocase: bool) -> bool {
+    if nocase {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a == b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(pattern: &str, string: &str) -> bool {
+        matches(pattern.as_bytes(), string.as_bytes(), false)
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(m("*", ""));
+        assert!(m("*", "hello"));
+        assert!(m("h*o", "hello"));
+        assert!(m("h**o", "hello"));
+        assert!(!m("h*x", "hello"));
+        assert!(m("h?llo", "hallo"));
+        assert!(!m("h?llo", "hllo"));
+        assert!(m("user:*:name", "user:42:name"));
+        assert!(!m("user:*:name", "user:42:email"));
+    }
+
+    #[test]
+    fn test_backtracking() {
+        assert!(m("*a*b", "xaxxb"));
+        assert!(m("a*b*c", "abbbc"));
+        assert!(!m("a*b*c", "abbbcd"));
+        assert!(m("*[0-9]", "key9"));
+        assert!(!m("*a*a*b", "aaaa"));
+        // Would take exponential time with a recursive matcher.
+        let string = "a".repeat(10_000);
+        assert!(!m("*a*a*a*a*a*a*a*a*a*a*b", &string));
+        assert!(m("*a*a*a*a*a*a*a*a*a*a*", &string));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(m("h[ae]llo", "hello"));
+        assert!(m("h[ae]llo", "hallo"));
+        assert!(!m("h[ae]llo", "hillo"));
+        assert!(m("h[^e]llo", "hallo"));
+        assert!(!m("h[^e]llo", "hello"));
+        assert!(m("h[a-b]llo", "hbllo"));
+        assert!(m("h[b-a]llo", "hbllo"));
+        assert!(!m("h[a-b]llo", "hcllo"));
+        assert!(m("[\\]]", "]"));
+        assert!(m("a[bc", "ab"));
+    }
+
+    #[test]
+    fn test_escapes_and_case() {
+        assert!(m("h\\*llo", "h*llo"));
+        assert!(!m("h\\*llo", "hello"));
+        assert!(m("h\\?", "h?"));
+        assert!(!matches(b"HELLO", b"hello", false));
+        assert!(matches(b"HE[k-m]LO", b"hello", true));
+    }
+}
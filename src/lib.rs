@@ -1,4 +1,6 @@
 pub mod backend;
-mod cmd;
+pub mod cmd;
 pub mod codec;
-mod resp;
+mod glob;
+pub mod resp;
+mod slot;
@@ -1,7 +1,10 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use futures::SinkExt;
-use my_redis::backend::Storage;
+use my_redis::backend::{error_reply, Client, Queued, Storage, DATABASES};
+use my_redis::cmd::{Command, CommandError, Script};
 use my_redis::codec::Codec;
+use my_redis::resp::{Protocol, Resp, SimpleString};
+use std::collections::VecDeque;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
@@ -12,7 +15,7 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let addr = "0.0.0.0:6379";
-    let storage = Storage::new();
+    let storage = Storage::with_databases(databases()?);
     let listener = TcpListener::bind(addr).await?;
 
     info!("Listening on: {}", addr);
@@ -26,19 +29,157 @@ async fn main() -> Result<()> {
     }
 }
 
+/// The number of databases, from `--databases N` on the command line.
+fn databases() -> Result<usize> {
+    let mut args = std::env::args().skip(1);
+    let mut databases = DATABASES;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--databases" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--databases needs a value"))?;
+                databases =
+                    n.parse().ok().filter(|n| *n > 0).ok_or_else(|| {
+                        anyhow!("--databases must be a positive integer, got {n:?}")
+                    })?;
+            }
+            _ => bail!("unknown argument {arg:?}"),
+        }
+    }
+    Ok(databases)
+}
+
 async fn process(socket: TcpStream, storage: &Storage) {
-    let mut frame = Framed::new(socket, Codec);
+    let (Ok(addr), Ok(laddr)) = (socket.peer_addr(), socket.local_addr()) else {
+        return;
+    };
+    let mut client = Client::new(storage, addr, laddr);
+    let mut frame = Framed::new(socket, Codec::default());
+    // Commands that arrived while the connection was blocked.
+    let mut pending = VecDeque::new();
     loop {
-        match frame.next().await {
-            Some(Ok(cmd)) => {
-                let res = cmd.execute(storage);
-                match res {
-                    Ok(resp) => {
-                        if let Err(e) = frame.send(resp).await {
+        let next = match pending.pop_front() {
+            Some(cmd) => Some(Ok(cmd)),
+            None => {
+                let killed = client.killed();
+                tokio::select! {
+                    Some(message) = client.messages.recv() => {
+                        if let Err(e) = frame.send(message).await {
                             error!("Error: {:?}", e);
                         }
+                        continue;
+                    }
+                    _ = killed => {
+                        info!("Connection killed");
+                        break;
+                    }
+                    next = frame.next() => next,
+                }
+            }
+        };
+        match next {
+            Some(Ok(cmd)) => {
+                if let Ok(cmd) = &cmd {
+                    client.interact(cmd);
+                    if let Some(write) = held_by_pause(cmd, &client) {
+                        tokio::select! {
+                            _ = client.storage.clients().wait_unpaused(write) => {}
+                            _ = client.killed() => {
+                                info!("Connection killed");
+                                break;
+                            }
+                        }
+                    }
+                }
+                // Wait out a script here rather than on the storage lock,
+                // which would hold up the runtime thread.
+                let busy = match &cmd {
+                    Ok(Command::Script(Script::Kill) | Command::Quit | Command::Reset) => Ok(()),
+                    _ => client.storage.wait_for_script().await,
+                };
+                let mut quit = false;
+                let res = match cmd {
+                    Ok(cmd)
+                        if client.subscriber.is_subscribed()
+                            && frame.codec().protocol == Protocol::Resp2
+                            && !cmd.is_allowed_when_subscribed() =>
+                    {
+                        Err(CommandError::NotAllowedWhenSubscribed(cmd.name()).into())
+                    }
+                    Ok(Command::Quit) => {
+                        quit = true;
+                        Ok(vec![simple("OK")])
+                    }
+                    Ok(Command::Reset) => {
+                        client.reset();
+                        frame.codec_mut().protocol = Protocol::default();
+                        Ok(vec![simple("RESET")])
+                    }
+                    _ if busy.is_err() => busy.map(|()| Vec::new()).map_err(Into::into),
+                    cmd => match client.queue(cmd, &mut frame.codec_mut().protocol) {
+                        Queued::Reply(res) => res,
+                        Queued::Run(cmd) if cmd.is_blocking() => {
+                            let blocked = client.storage.execute_blocking(cmd);
+                            tokio::pin!(blocked);
+                            loop {
+                                let killed = client.killed();
+                                tokio::select! {
+                                    res = &mut blocked => break res.map(|resp| vec![resp]),
+                                    Some(message) = client.messages.recv() => {
+                                        if let Err(e) = frame.send(message).await {
+                                            error!("Error: {:?}", e);
+                                        }
+                                    }
+                                    _ = killed => {
+                                        info!("Connection killed");
+                                        return;
+                                    }
+                                    next = frame.next() => match next {
+                                        Some(Ok(cmd)) => pending.push_back(cmd),
+                                        Some(Err(e)) => info!("Error: {:?}", e),
+                                        // Dropping the blocked command takes the
+                                        // connection off the wait queues.
+                                        None => {
+                                            info!("Connection closed");
+                                            return;
+                                        }
+                                    },
+                                }
+                            }
+                        }
+                        Queued::Run(cmd) if cmd.runs_script() => {
+                            client.track(&cmd);
+                            let storage = client.storage.clone();
+                            tokio::task::spawn_blocking(move || cmd.execute(&storage))
+                                .await
+                                .map_err(Into::into)
+                                .and_then(|res| res.map(|resp| vec![resp]))
+                        }
+                        Queued::Run(cmd) => {
+                            let storage = client.storage.clone();
+                            client.run(cmd, &storage, &mut frame.codec_mut().protocol)
+                        }
+                    },
+                };
+                // (Un)subscribing confirms each channel with a reply of its own.
+                let replies = res.unwrap_or_else(|e| vec![error_reply(e)]);
+                for resp in replies {
+                    if let Err(e) = frame.feed(resp).await {
+                        error!("Error: {:?}", e);
                     }
-                    Err(e) => error!("Error: {:?}", e),
+                }
+                if let Err(e) = frame.flush().await {
+                    error!("Error: {:?}", e);
+                }
+                client.sync(
+                    frame.codec().protocol,
+                    frame.read_buffer(),
+                    frame.write_buffer(),
+                );
+                if quit {
+                    info!("Connection closed");
+                    break;
                 }
             }
             Some(Err(e)) => {
@@ -51,3 +192,24 @@ async fn process(socket: TcpStream, storage: &Storage) {
         }
     }
 }
+
+/// Whether `CLIENT PAUSE` may hold `cmd`, and if so whether it counts as a
+/// write. Commands queued in a transaction are held at `EXEC` instead, and
+/// `CLIENT UNPAUSE` is never held so that a pause can always be lifted.
+fn held_by_pause(cmd: &Command, client: &Client) -> Option<bool> {
+    match (cmd, &client.transaction) {
+        (Command::Client(my_redis::cmd::Client::Unpause), _) => None,
+        (Command::Exec, Some(transaction)) => Some(
+            transaction
+                .commands
+                .iter()
+                .any(Command::is_held_by_write_pause),
+        ),
+        (_, Some(_)) => None,
+        (cmd, None) => Some(cmd.is_held_by_write_pause()),
+    }
+}
+
+fn simple(s: &str) -> Resp {
+    Resp::SimpleString(SimpleString::new(s))
+}
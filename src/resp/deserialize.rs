@@ -19,15 +19,33 @@ impl TryFrom<&mut BytesMut> for Resp {
     type Error = RespDeserializeError;
 
     fn try_from(buf: &mut BytesMut) -> Result<Resp, RespDeserializeError> {
-        let resp = _try_from(buf)?;
-        if !buf.is_empty() {
+        let mut rest = &buf[..];
+        let resp = _try_from(&mut rest)?;
+        if !rest.is_empty() {
             return Err(RespDeserializeError::WrongFormat);
         }
+        buf.clear();
         Ok(resp)
     }
 }
 
-fn _try_from(buf: &mut BytesMut) -> Result<Resp, RespDeserializeError> {
+impl Resp {
+    /// Takes the first frame off `buf`, leaving any that follow. Until the
+    /// whole frame has arrived this returns `None` and `buf` is left as is.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Resp>, RespDeserializeError> {
+        let mut rest = &buf[..];
+        let resp = match _try_from(&mut rest) {
+            Ok(resp) => resp,
+            Err(RespDeserializeError::NotComplete) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let used = buf.len() - rest.len();
+        buf.advance(used);
+        Ok(Some(resp))
+    }
+}
+
+fn _try_from(buf: &mut &[u8]) -> Result<Resp, RespDeserializeError> {
     if buf.len() < 3 {
         return Err(RespDeserializeError::NotComplete);
     }
@@ -83,6 +101,11 @@ fn _try_from(buf: &mut BytesMut) -> Result<Resp, RespDeserializeError> {
             let a = deserialize_array(buf)?;
             Ok(Resp::Array(a))
         }
+        b'>' => {
+            buf.advance(1);
+            let a = deserialize_array(buf)?;
+            Ok(Resp::Push(a))
+        }
         b'~' => {
             buf.advance(1);
             let s = deserialize_set(buf)?;
@@ -92,29 +115,25 @@ fn _try_from(buf: &mut BytesMut) -> Result<Resp, RespDeserializeError> {
     }
 }
 
-trait Deserialize {
-    fn deserialize<'a>(&'a mut self, buf: &'a [u8]) -> Result<&[u8], RespDeserializeError>;
-}
-
-fn deserialize_simple_string(buf: &mut BytesMut) -> Result<SimpleString, RespDeserializeError> {
+fn deserialize_simple_string(buf: &mut &[u8]) -> Result<SimpleString, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    match from_utf8(bytes.as_ref()) {
+    match from_utf8(bytes) {
         Ok(s) => Ok(SimpleString::new(s)),
         Err(e) => Err(RespDeserializeError::Utf8Error(e)),
     }
 }
 
-fn deserialize_simple_error(buf: &mut BytesMut) -> Result<SimpleError, RespDeserializeError> {
+fn deserialize_simple_error(buf: &mut &[u8]) -> Result<SimpleError, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    match from_utf8(bytes.as_ref()) {
+    match from_utf8(bytes) {
         Ok(s) => Ok(SimpleError::new(s)),
         Err(e) => Err(RespDeserializeError::Utf8Error(e)),
     }
 }
 
-fn deserialize_map(buf: &mut BytesMut) -> Result<Map, RespDeserializeError> {
+fn deserialize_map(buf: &mut &[u8]) -> Result<Map, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    let len = match from_utf8(bytes.as_ref()) {
+    let len = match from_utf8(bytes) {
         Ok(s) => s
             .parse::<usize>()
             .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -131,9 +150,9 @@ fn deserialize_map(buf: &mut BytesMut) -> Result<Map, RespDeserializeError> {
     Ok(map)
 }
 
-fn deserialize_integer(buf: &mut BytesMut) -> Result<Integer, RespDeserializeError> {
+fn deserialize_integer(buf: &mut &[u8]) -> Result<Integer, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    match from_utf8(bytes.as_ref()) {
+    match from_utf8(bytes) {
         Ok(s) => Ok(Integer::new(
             s.parse::<i64>()
                 .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -142,9 +161,9 @@ fn deserialize_integer(buf: &mut BytesMut) -> Result<Integer, RespDeserializeErr
     }
 }
 
-fn deserialize_bulk_string(buf: &mut BytesMut) -> Result<BulkString, RespDeserializeError> {
+fn deserialize_bulk_string(buf: &mut &[u8]) -> Result<BulkString, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    let len = match from_utf8(bytes.as_ref()) {
+    let len = match from_utf8(bytes) {
         Ok(s) => s
             .parse::<i64>()
             .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -159,18 +178,15 @@ fn deserialize_bulk_string(buf: &mut BytesMut) -> Result<BulkString, RespDeseria
     if (buf.len() as i64) < len + 2 {
         return Err(RespDeserializeError::NotComplete);
     }
-    let res = buf.split_to(len as usize);
+    let res = split_to(buf, len as usize);
     if buf[0] != b'\r' || buf[1] != b'\n' {
         return Err(RespDeserializeError::WrongFormat);
     }
     buf.advance(2);
-    match from_utf8(res.as_ref()) {
-        Ok(s) => Ok(BulkString::new(s, false)),
-        Err(e) => Err(RespDeserializeError::Utf8Error(e)),
-    }
+    Ok(BulkString::new(res.to_vec(), false))
 }
 
-fn deserialize_null(buf: &mut BytesMut) -> Result<Null, RespDeserializeError> {
+fn deserialize_null(buf: &mut &[u8]) -> Result<Null, RespDeserializeError> {
     if buf.len() < 2 {
         return Err(RespDeserializeError::NotComplete);
     }
@@ -181,7 +197,7 @@ fn deserialize_null(buf: &mut BytesMut) -> Result<Null, RespDeserializeError> {
     Ok(Null {})
 }
 
-fn deserialize_boolean(buf: &mut BytesMut) -> Result<Boolean, RespDeserializeError> {
+fn deserialize_boolean(buf: &mut &[u8]) -> Result<Boolean, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
     if bytes.len() != 1 {
         return Err(RespDeserializeError::WrongFormat);
@@ -193,9 +209,9 @@ fn deserialize_boolean(buf: &mut BytesMut) -> Result<Boolean, RespDeserializeErr
     }
 }
 
-fn deserialize_double(buf: &mut BytesMut) -> Result<Double, RespDeserializeError> {
+fn deserialize_double(buf: &mut &[u8]) -> Result<Double, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    match from_utf8(bytes.as_ref()) {
+    match from_utf8(bytes) {
         Ok(s) => Ok(Double::new(
             s.parse::<f64>()
                 .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -204,9 +220,9 @@ fn deserialize_double(buf: &mut BytesMut) -> Result<Double, RespDeserializeError
     }
 }
 
-fn deserialize_bulk_error(buf: &mut BytesMut) -> Result<BulkError, RespDeserializeError> {
+fn deserialize_bulk_error(buf: &mut &[u8]) -> Result<BulkError, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    let len = match from_utf8(bytes.as_ref()) {
+    let len = match from_utf8(bytes) {
         Ok(s) => s
             .parse::<usize>()
             .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -215,20 +231,20 @@ fn deserialize_bulk_error(buf: &mut BytesMut) -> Result<BulkError, RespDeseriali
     if buf.len() < len + 2 {
         return Err(RespDeserializeError::NotComplete);
     }
-    let res = buf.split_to(len);
+    let res = split_to(buf, len);
     if buf[0] != b'\r' || buf[1] != b'\n' {
         return Err(RespDeserializeError::WrongFormat);
     }
     buf.advance(2);
-    match from_utf8(res.as_ref()) {
+    match from_utf8(res) {
         Ok(s) => Ok(BulkError::new(s)),
         Err(e) => Err(RespDeserializeError::Utf8Error(e)),
     }
 }
 
-fn deserialize_array(buf: &mut BytesMut) -> Result<Array, RespDeserializeError> {
+fn deserialize_array(buf: &mut &[u8]) -> Result<Array, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    let len = match from_utf8(bytes.as_ref()) {
+    let len = match from_utf8(bytes) {
         Ok(s) => s
             .parse::<i64>()
             .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -248,9 +264,9 @@ fn deserialize_array(buf: &mut BytesMut) -> Result<Array, RespDeserializeError>
     Ok(array)
 }
 
-fn deserialize_set(buf: &mut BytesMut) -> Result<Set, RespDeserializeError> {
+fn deserialize_set(buf: &mut &[u8]) -> Result<Set, RespDeserializeError> {
     let bytes = find_crlf(buf)?;
-    let len = match from_utf8(bytes.as_ref()) {
+    let len = match from_utf8(bytes) {
         Ok(s) => s
             .parse::<usize>()
             .map_err(|_| RespDeserializeError::WrongFormat)?,
@@ -268,7 +284,7 @@ fn deserialize_set(buf: &mut BytesMut) -> Result<Set, RespDeserializeError> {
     Ok(set)
 }
 
-fn find_crlf(buf: &mut BytesMut) -> Result<BytesMut, RespDeserializeError> {
+fn find_crlf<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], RespDeserializeError> {
     let i = buf
         .iter()
         .position(|&c| c == b'\r')
@@ -279,11 +295,17 @@ fn find_crlf(buf: &mut BytesMut) -> Result<BytesMut, RespDeserializeError> {
     if buf[i + 1] != b'\n' {
         return Err(RespDeserializeError::WrongFormat);
     }
-    let res = buf.split_to(i);
+    let res = split_to(buf, i);
     buf.advance(2);
     Ok(res)
 }
 
+fn split_to<'a>(buf: &mut &'a [u8], at: usize) -> &'a [u8] {
+    let (head, rest) = buf.split_at(at);
+    *buf = rest;
+    head
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -376,6 +398,14 @@ mod tests {
         let r = Resp::try_from(&mut bytes).unwrap();
         assert_eq!(r, Resp::BulkString(BulkString::new("", true)));
 
+        let buf: &[u8] = b"$4\r\n\xff\r\n\0\r\n";
+        let mut bytes = BytesMut::from(buf);
+        let r = Resp::try_from(&mut bytes).unwrap();
+        assert_eq!(
+            r,
+            Resp::BulkString(BulkString::new(b"\xff\r\n\0".as_slice(), false))
+        );
+
         let buf: &[u8] = b"$6\r\nfoobar\r";
         let mut bytes = BytesMut::from(buf);
         let r = Resp::try_from(&mut bytes);
@@ -387,6 +417,31 @@ mod tests {
         assert!(r.is_err());
     }
 
+    #[test]
+    fn test_decode() {
+        let mut buf = BytesMut::new();
+        let frame = b"*2\r\n$4\r\nECHO\r\n$6\r\nfoobar\r\n";
+        for (i, &b) in frame.iter().enumerate() {
+            assert_eq!(Resp::decode(&mut buf).unwrap(), None);
+            assert_eq!(buf.len(), i, "an incomplete frame is left alone");
+            buf.extend([b]);
+        }
+        buf.extend(b":1\r\n:2");
+        let mut echo = Array::default();
+        echo.push(Resp::BulkString(BulkString::new("ECHO", false)));
+        echo.push(Resp::BulkString(BulkString::new("foobar", false)));
+        assert_eq!(Resp::decode(&mut buf).unwrap(), Some(Resp::Array(echo)));
+        assert_eq!(
+            Resp::decode(&mut buf).unwrap(),
+            Some(Resp::Integer(Integer::new(1)))
+        );
+        assert_eq!(Resp::decode(&mut buf).unwrap(), None);
+        assert_eq!(&buf[..], b":2");
+
+        let mut buf = BytesMut::from(&b"?\r\n"[..]);
+        assert!(Resp::decode(&mut buf).is_err());
+    }
+
     #[test]
     fn test_deserialize_null() {
         let buf: &[u8] = b"_\r\n";
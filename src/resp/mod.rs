@@ -5,6 +5,7 @@ pub use deserialize::RespDeserializeError;
 pub use serialize::Serialize;
 use std::{
     collections::{BTreeMap, BTreeSet},
+    fmt,
     ops::{Deref, DerefMut},
 };
 use thiserror::Error;
@@ -55,6 +56,20 @@ impl From<Key> for Resp {
     }
 }
 
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::SimpleString(v) => write!(f, "{}", v.value),
+            Key::SimpleError(v) => write!(f, "{}", v.value),
+            Key::Integer(v) => write!(f, "{}", v.value),
+            Key::BulkString(v) => write!(f, "{}", String::from_utf8_lossy(&v.value)),
+            Key::BulkError(v) => write!(f, "{}", v.value),
+            Key::Null(_) => Ok(()),
+            Key::Boolean(v) => write!(f, "{}", v.value),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Resp {
@@ -69,6 +84,49 @@ pub enum Resp {
     BulkError(BulkError),
     Map(Box<Map>),
     Set(Set),
+    /// Out-of-band data such as pub/sub messages.
+    Push(Array),
+}
+
+/// The protocol version a connection speaks, negotiated with `HELLO`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Resp {
+    /// Downgrades RESP3-only types to their RESP2 equivalents: maps become
+    /// flat arrays of keys and values, sets and pushes become arrays,
+    /// doubles become bulk strings and booleans become integers.
+    pub fn into_resp2(self) -> Resp {
+        match self {
+            Resp::Array(a) | Resp::Push(a) => Resp::Array(Array::new(
+                a.value.into_iter().map(Resp::into_resp2).collect(),
+                a.is_null,
+            )),
+            Resp::Null(_) => Resp::BulkString(BulkString::new("", true)),
+            Resp::Boolean(b) => Resp::Integer(Integer::new(b.value as i64)),
+            Resp::Double(d) => Resp::BulkString(BulkString::new(d.value.to_string(), false)),
+            Resp::BulkError(e) => Resp::SimpleError(SimpleError::new(e.value)),
+            Resp::Map(m) => Resp::Array(Array::new(
+                m.value
+                    .into_iter()
+                    .flat_map(|(k, v)| [Resp::from(k).into_resp2(), v.into_resp2()])
+                    .collect(),
+                false,
+            )),
+            Resp::Set(s) => Resp::Array(Array::new(
+                s.value
+                    .into_iter()
+                    .map(|k| Resp::from(k).into_resp2())
+                    .collect(),
+                false,
+            )),
+            resp => resp,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -83,6 +141,10 @@ impl SimpleString {
             value: value.into(),
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -97,6 +159,10 @@ impl SimpleError {
             value: value.into(),
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -109,22 +175,32 @@ impl Integer {
     pub fn new(value: i64) -> Self {
         Integer { value }
     }
+
+    pub fn value(&self) -> i64 {
+        self.value
+    }
 }
 
+/// A binary-safe string: its bytes are kept as they came over the wire.
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BulkString {
-    pub value: String,
+    pub value: Vec<u8>,
     pub is_null: bool,
 }
 
 impl BulkString {
     #[allow(dead_code)]
-    pub fn new<T: Into<String>>(value: T, is_null: bool) -> Self {
+    pub fn new<T: Into<Vec<u8>>>(value: T, is_null: bool) -> Self {
         BulkString {
             is_null,
             value: value.into(),
         }
     }
+
+    /// The value as text, if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.value).ok()
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -137,6 +213,10 @@ impl Array {
     pub fn new(value: Vec<Resp>, is_null: bool) -> Self {
         Array { value, is_null }
     }
+
+    pub fn is_null(&self) -> bool {
+        self.is_null
+    }
 }
 
 impl Deref for Array {
@@ -178,6 +258,10 @@ impl Double {
     pub fn new(value: f64) -> Self {
         Double { value }
     }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -199,6 +283,14 @@ pub struct Map {
     value: BTreeMap<Key, Resp>,
 }
 
+impl FromIterator<(Key, Resp)> for Map {
+    fn from_iter<T: IntoIterator<Item = (Key, Resp)>>(iter: T) -> Self {
+        Map {
+            value: iter.into_iter().collect(),
+        }
+    }
+}
+
 impl Deref for Map {
     type Target = BTreeMap<Key, Resp>;
 
@@ -218,6 +310,14 @@ pub struct Set {
     value: BTreeSet<Key>,
 }
 
+impl FromIterator<Key> for Set {
+    fn from_iter<T: IntoIterator<Item = Key>>(iter: T) -> Self {
+        Set {
+            value: iter.into_iter().collect(),
+        }
+    }
+}
+
 impl Deref for Set {
     type Target = BTreeSet<Key>;
 
@@ -231,3 +331,52 @@ impl DerefMut for Set {
         &mut self.value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_resp2() {
+        let mut map = Map::default();
+        map.insert(
+            Key::BulkString(BulkString::new("a", false)),
+            Resp::Double(Double::new(1.5)),
+        );
+        map.insert(
+            Key::BulkString(BulkString::new("b", false)),
+            Resp::Boolean(Boolean::new(true)),
+        );
+        let resp = Resp::Array(Array::new(
+            vec![Resp::Map(Box::new(map)), Resp::Null(Null)],
+            false,
+        ));
+        assert_eq!(
+            resp.into_resp2(),
+            Resp::Array(Array::new(
+                vec![
+                    Resp::Array(Array::new(
+                        vec![
+                            Resp::BulkString(BulkString::new("a", false)),
+                            Resp::BulkString(BulkString::new("1.5", false)),
+                            Resp::BulkString(BulkString::new("b", false)),
+                            Resp::Integer(Integer::new(1)),
+                        ],
+                        false
+                    )),
+                    Resp::BulkString(BulkString::new("", true)),
+                ],
+                false
+            ))
+        );
+
+        let push = Resp::Push(Array::new(vec![Resp::Null(Null)], false));
+        assert_eq!(
+            push.into_resp2(),
+            Resp::Array(Array::new(
+                vec![Resp::BulkString(BulkString::new("", true))],
+                false
+            ))
+        );
+    }
+}
@@ -23,8 +23,14 @@ impl Serialize for Resp {
             Resp::Double(s) => s.serialize(),
             Resp::BulkError(s) => s.serialize(),
             Resp::Map(s) => s.serialize(),
-            // Resp::Set(s) => s.serialize(),
-            _ => Vec::new(),
+            Resp::Set(s) => s.serialize(),
+            Resp::Push(s) => {
+                let mut result = format!(">{}\r\n", s.len()).as_bytes().to_vec();
+                for item in s.iter() {
+                    result.extend(item.serialize());
+                }
+                result
+            }
         }
     }
 }
@@ -52,9 +58,10 @@ impl Serialize for BulkString {
         if self.is_null {
             return b"$-1\r\n".to_vec();
         }
-        format!("${}\r\n{}\r\n", self.value.len(), self.value)
-            .as_bytes()
-            .to_vec()
+        let mut result = format!("${}\r\n", self.value.len()).into_bytes();
+        result.extend(&self.value);
+        result.extend(b"\r\n");
+        result
     }
 }
 
@@ -168,6 +175,9 @@ mod tests {
 
         let s = BulkString::new("", true);
         assert_eq!(s.serialize(), "$-1\r\n".as_bytes());
+
+        let s = BulkString::new(b"\xff\r\n\0".as_slice(), false);
+        assert_eq!(s.serialize(), b"$4\r\n\xff\r\n\0\r\n");
     }
 
     #[test]
@@ -227,6 +237,18 @@ mod tests {
         assert_eq!(s.serialize(), "*-1\r\n".as_bytes());
     }
 
+    #[test]
+    fn test_serialize_push() {
+        let s = Resp::Push(Array::new(
+            vec![
+                Resp::BulkString(BulkString::new("message", false)),
+                Resp::Integer(Integer::new(1)),
+            ],
+            false,
+        ));
+        assert_eq!(s.serialize(), ">2\r\n$7\r\nmessage\r\n:1\r\n".as_bytes());
+    }
+
     #[test]
     fn test_serialize_map() {
         let mut s = Map::default();
@@ -0,0 +1,46 @@
+/// The number of hash slots keys are sharded over in a cluster.
+pub const SLOTS: u16 = 16384;
+
+/// The cluster hash slot of a key or shard channel name. If the name holds a
+/// non-empty `{tag}`, only the tag is hashed, so related names can be kept
+/// in the same slot.
+pub fn key_slot(name: &[u8]) -> u16 {
+    let hashed = match name.iter().position(|&b| b == b'{') {
+        Some(open) => match name[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &name[open + 1..open + 1 + len],
+            _ => name,
+        },
+        None => name,
+    };
+    crc16(hashed) % SLOTS
+}
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"bar"), 5061);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_ne!(key_slot(b"{}a"), key_slot(b"a"));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+}